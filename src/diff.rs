@@ -0,0 +1,345 @@
+//! Structural comparison of two versions of a diagram, for reviewing changes.
+//!
+//! Nodes are paired by their [`Node::id`], so a renamed node shows up as renamed instead of
+//! removed and added again. Comments are left out, they hold no structure.
+
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use egui_snarl::NodeId;
+
+use crate::model::{DataType, Node, Subsystem, Wire};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A difference found in one level.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub message: String,
+}
+
+/// Differences of a level and of the subsystems nested inside it.
+#[derive(Clone, Debug, Default)]
+pub struct LevelDiff {
+    /// Name of the subsystem node holding the level in the newer version, `Top` for the top
+    /// level.
+    pub name: String,
+    pub changes: Vec<Change>,
+    /// Nested levels with differences, by subsystem node.
+    pub levels: Vec<LevelDiff>,
+}
+
+impl LevelDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.levels.is_empty()
+    }
+
+    /// Number of differences of the level and the levels nested inside it.
+    pub fn count(&self) -> usize {
+        self.changes.len() + self.levels.iter().map(LevelDiff::count).sum::<usize>()
+    }
+}
+
+/// Differences going from `old` to `new`.
+pub fn diff(old: &Subsystem, new: &Subsystem) -> LevelDiff {
+    diff_level("Top".to_string(), old, new)
+}
+
+fn diff_level(name: String, old: &Subsystem, new: &Subsystem) -> LevelDiff {
+    let mut diff = LevelDiff {
+        name,
+        ..LevelDiff::default()
+    };
+    let pairs = pair_nodes(old, new);
+    let mut change = |kind, message| diff.changes.push(Change { kind, message });
+
+    let mut new_nodes = blocks(new);
+    new_nodes.sort_by_key(|(node_id, _)| *node_id);
+    for &(node_id, node) in &new_nodes {
+        let Some(&old_id) = pairs.get(&node_id) else {
+            change(ChangeKind::Added, format!("Node \"{}\"", node.name));
+            continue;
+        };
+        let old_node = &old.snarl[old_id];
+        if old_node.name != node.name {
+            change(
+                ChangeKind::Changed,
+                format!("Renamed \"{}\" to \"{}\"", old_node.name, node.name),
+            );
+        }
+        diff_ports(
+            &mut change,
+            &node.name,
+            "input",
+            &inputs(old_node),
+            &inputs(node),
+        );
+        diff_ports(
+            &mut change,
+            &node.name,
+            "output",
+            &outputs(old_node),
+            &outputs(node),
+        );
+    }
+
+    let paired = pairs.values().copied().collect::<HashSet<_>>();
+    let mut old_nodes = blocks(old);
+    old_nodes.sort_by_key(|(node_id, _)| *node_id);
+    for (node_id, node) in old_nodes {
+        if !paired.contains(&node_id) {
+            change(ChangeKind::Removed, format!("Node \"{}\"", node.name));
+        }
+    }
+
+    // Old wires are described with the names of the newer version, so renames alone change none
+    let old_names = pairs
+        .iter()
+        .map(|(&new_id, &old_id)| (old_id, new_id))
+        .collect::<HashMap<_, _>>();
+    let old_wires = old
+        .snarl
+        .wires()
+        .map(|wire| {
+            describe_wire(old, wire, |node_id| {
+                old_names.get(&node_id).map(|&id| &new.snarl[id])
+            })
+        })
+        .collect::<HashSet<_>>();
+    let new_wires = new
+        .snarl
+        .wires()
+        .map(|wire| describe_wire(new, wire, |_| None))
+        .collect::<HashSet<_>>();
+    let mut added = new_wires.difference(&old_wires).collect::<Vec<_>>();
+    let mut removed = old_wires.difference(&new_wires).collect::<Vec<_>>();
+    added.sort();
+    removed.sort();
+    for wire in added {
+        change(ChangeKind::Added, format!("Wire {wire}"));
+    }
+    for wire in removed {
+        change(ChangeKind::Removed, format!("Wire {wire}"));
+    }
+
+    for (node_id, node) in new_nodes {
+        let (Some(old_id), Some(inner)) = (pairs.get(&node_id), node.subsystem.as_ref()) else {
+            continue;
+        };
+        let Some(old_inner) = old.snarl[*old_id].subsystem.as_ref() else {
+            continue;
+        };
+        if Rc::ptr_eq(old_inner, inner) {
+            continue;
+        }
+        let nested = diff_level(node.name.clone(), &old_inner.borrow(), &inner.borrow());
+        if !nested.is_empty() {
+            diff.levels.push(nested);
+        }
+    }
+    diff
+}
+
+/// Nodes of `subsystem` other than comments.
+fn blocks(subsystem: &Subsystem) -> Vec<(NodeId, &Node)> {
+    subsystem
+        .snarl
+        .node_ids()
+        .filter(|(_, node)| !node.is_comment())
+        .collect()
+}
+
+/// Pairs each node of `new` with the node of `old` with the same id, the one it is a version of.
+fn pair_nodes(old: &Subsystem, new: &Subsystem) -> HashMap<NodeId, NodeId> {
+    let mut new_nodes = blocks(new);
+    new_nodes.sort_by_key(|(node_id, _)| *node_id);
+
+    let old_ids = blocks(old)
+        .into_iter()
+        .map(|(node_id, node)| (node.id, node_id))
+        .collect::<HashMap<_, _>>();
+    let mut pairs = HashMap::new();
+    let mut taken = HashSet::new();
+    for (node_id, node) in new_nodes {
+        if let Some(&old_id) = old_ids.get(&node.id)
+            && taken.insert(old_id)
+        {
+            pairs.insert(node_id, old_id);
+        }
+    }
+    pairs
+}
+
+fn inputs(node: &Node) -> Vec<(&str, DataType)> {
+    node.inputs
+        .iter()
+        .map(|input| (input.name.as_str(), input.data_type))
+        .collect()
+}
+
+fn outputs(node: &Node) -> Vec<(&str, DataType)> {
+    node.outputs
+        .iter()
+        .map(|output| (output.name.as_str(), output.data_type))
+        .collect()
+}
+
+/// Reports the ports of one `side` of the node `name` that changed, comparing them by index.
+fn diff_ports(
+    change: &mut impl FnMut(ChangeKind, String),
+    name: &str,
+    side: &str,
+    old: &[(&str, DataType)],
+    new: &[(&str, DataType)],
+) {
+    for index in 0..old.len().max(new.len()) {
+        match (old.get(index), new.get(index)) {
+            (Some((port, _)), None) => {
+                change(
+                    ChangeKind::Removed,
+                    format!("\"{name}\": {side} \"{port}\""),
+                );
+            }
+            (None, Some((port, _))) => {
+                change(ChangeKind::Added, format!("\"{name}\": {side} \"{port}\""));
+            }
+            (Some((old_port, old_type)), Some((port, data_type))) => {
+                if old_port != port {
+                    change(
+                        ChangeKind::Changed,
+                        format!("\"{name}\": renamed {side} \"{old_port}\" to \"{port}\""),
+                    );
+                }
+                if old_type != data_type {
+                    change(
+                        ChangeKind::Changed,
+                        format!("\"{name}\": {side} \"{port}\" is now {data_type:?}"),
+                    );
+                }
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+/// `wire` as the names of the nodes and ports it joins, with the nodes `newer` gives the names
+/// of instead of their own.
+fn describe_wire<'a>(
+    subsystem: &'a Subsystem,
+    (from, to): Wire,
+    newer: impl Fn(NodeId) -> Option<&'a Node>,
+) -> String {
+    let node = |node_id| newer(node_id).unwrap_or(&subsystem.snarl[node_id]);
+    let (from_node, to_node) = (node(from.node), node(to.node));
+    let output = from_node
+        .outputs
+        .get(from.output)
+        .map_or("?", |output| output.name.as_str());
+    let input = to_node
+        .inputs
+        .get(to.input)
+        .map_or("?", |input| input.name.as_str());
+    format!(
+        "\"{}\".\"{output}\" → \"{}\".\"{input}\"",
+        from_node.name, to_node.name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use egui::Pos2;
+    use egui_snarl::{InPinId, OutPinId};
+
+    use super::*;
+    use crate::model::{self, Input, Output};
+
+    fn block(name: &str, inputs: usize, outputs: usize) -> Node {
+        Node {
+            name: name.to_string(),
+            inputs: vec![Input::default(); inputs],
+            outputs: vec![Output::default(); outputs],
+            ..Node::default()
+        }
+    }
+
+    fn connect(subsystem: &mut Subsystem, from: NodeId, to: NodeId) {
+        subsystem.snarl.connect(
+            OutPinId {
+                node: from,
+                output: 0,
+            },
+            InPinId { node: to, input: 0 },
+        );
+    }
+
+    fn changes(level: &LevelDiff) -> Vec<(ChangeKind, &str)> {
+        level
+            .changes
+            .iter()
+            .map(|change| (change.kind, change.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn nested_changes_are_found_by_node_id() {
+        let mut inner = Subsystem::new();
+        let a = inner.snarl.insert_node(Pos2::ZERO, block("a", 0, 1));
+        let b = inner.snarl.insert_node(Pos2::ZERO, block("b", 1, 0));
+        let c = inner.snarl.insert_node(Pos2::ZERO, block("c", 1, 0));
+        connect(&mut inner, a, b);
+        let mut old = Subsystem::new();
+        let sub = old.snarl.insert_node(
+            Pos2::ZERO,
+            Node {
+                subsystem: Some(Rc::new(RefCell::new(inner))),
+                ..block("sub", 0, 0)
+            },
+        );
+
+        let new = model::deep_clone(&old);
+        {
+            let mut inner = new.snarl[sub].subsystem.as_ref().unwrap().borrow_mut();
+            inner.snarl[b].name = "b2".to_string();
+            inner.snarl.remove_node(c);
+            let d = inner.snarl.insert_node(Pos2::ZERO, block("d", 1, 0));
+            inner.snarl.drop_inputs(InPinId { node: b, input: 0 });
+            connect(&mut inner, a, d);
+        }
+
+        let top = diff(&old, &new);
+        assert_eq!(top.name, "Top");
+        assert!(top.changes.is_empty());
+        assert_eq!(top.count(), 5);
+        let [level] = &top.levels[..] else {
+            panic!("one changed level");
+        };
+        assert_eq!(level.name, "sub");
+        assert!(level.levels.is_empty());
+        assert_eq!(
+            changes(level),
+            vec![
+                (ChangeKind::Changed, "Renamed \"b\" to \"b2\""),
+                (ChangeKind::Added, "Node \"d\""),
+                (ChangeKind::Removed, "Node \"c\""),
+                (ChangeKind::Added, "Wire \"a\".\"Output\" → \"d\".\"Input\""),
+                (
+                    ChangeKind::Removed,
+                    "Wire \"a\".\"Output\" → \"b2\".\"Input\""
+                ),
+            ]
+        );
+
+        // Both versions sharing the level have nothing to compare in it
+        assert!(diff(&old, &old).is_empty());
+    }
+}
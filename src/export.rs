@@ -0,0 +1,1167 @@
+//! Exporters turning a [`Subsystem`] into text formats consumed by other tools.
+//!
+//! Every graph exporter comes in two flavors: a plain one labelling nodes with their name, and a
+//! `_with` variant taking a labeler so callers can decide what text ends up on each node. The
+//! SVG drawing keeps the stored layout, the Rust skeleton follows the nesting of the subsystems,
+//! and the port listing can also be read back into nodes.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    rc::Rc,
+};
+
+use egui::{Color32, Rect, Vec2};
+use egui_snarl::{InPinId, NodeId, OutPinId, Snarl};
+
+use crate::model::{self, DataType, Input, InputKind, Node, Output, OutputKind, Subsystem};
+
+/// Labeler used by the exporters when none is provided.
+pub fn default_labeler(node: &Node) -> String {
+    node.name.clone()
+}
+
+/// Exports `subsystem` as a Graphviz `digraph`, labelling nodes with their name.
+pub fn to_dot(subsystem: &Subsystem) -> String {
+    to_dot_with(subsystem, default_labeler)
+}
+
+/// Exports `subsystem` as a Graphviz `digraph`, labelling nodes with `labeler`.
+///
+/// Subsystem nodes become `cluster_*` subgraphs containing an anchor node for the subsystem
+/// itself, which the wires of the enclosing level are attached to.
+pub fn to_dot_with(subsystem: &Subsystem, labeler: impl Fn(&Node) -> String) -> String {
+    let mut out = String::from("digraph diagram {\n    node [shape=box];\n");
+    write_dot_level(&mut out, subsystem, "n", 1, &labeler);
+    out.push_str("}\n");
+    out
+}
+
+fn write_dot_level(
+    out: &mut String,
+    subsystem: &Subsystem,
+    prefix: &str,
+    depth: usize,
+    labeler: &dyn Fn(&Node) -> String,
+) {
+    let indent = "    ".repeat(depth);
+
+    for (node_id, node) in subsystem.snarl.node_ids() {
+        if node.is_comment() {
+            continue;
+        }
+        let id = format!("{prefix}{}", node_id.0);
+        let label = dot_escape(&labeler(node));
+
+        if let Some(inner) = node.subsystem.as_ref() {
+            writeln!(out, "{indent}subgraph cluster_{id} {{").unwrap();
+            writeln!(out, "{indent}    label=\"{label}\";").unwrap();
+            writeln!(out, "{indent}    {id} [label=\"{label}\", style=bold];").unwrap();
+            write_dot_level(out, &inner.borrow(), &format!("{id}_"), depth + 1, labeler);
+            writeln!(out, "{indent}}}").unwrap();
+        } else {
+            writeln!(out, "{indent}{id} [label=\"{label}\"];").unwrap();
+        }
+    }
+
+    for (pin_out, pin_in) in subsystem.snarl.wires() {
+        let output_name = subsystem.snarl[pin_out.node]
+            .outputs
+            .get(pin_out.output)
+            .map_or("", |output| output.name.as_str());
+        let input_name = subsystem.snarl[pin_in.node]
+            .inputs
+            .get(pin_in.input)
+            .map_or("", |input| input.name.as_str());
+        // A named wire shows its name instead of the ports it joins
+        let label = match subsystem.wire_labels.get(&(pin_out, pin_in)) {
+            Some(label) => label.clone(),
+            None => format!("{output_name} -> {input_name}"),
+        };
+
+        writeln!(
+            out,
+            "{indent}{prefix}{} -> {prefix}{} [label=\"{}\"];",
+            pin_out.node.0,
+            pin_in.node.0,
+            dot_escape(&label),
+        )
+        .unwrap();
+    }
+}
+
+/// Escapes `text` for use inside a double-quoted DOT string.
+fn dot_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Exports `subsystem` as a Mermaid flowchart, labelling nodes with their name.
+pub fn to_mermaid(subsystem: &Subsystem) -> String {
+    to_mermaid_with(subsystem, default_labeler)
+}
+
+/// Exports `subsystem` as a Mermaid flowchart, labelling nodes with `labeler`.
+///
+/// Like in [`to_dot_with`], subsystem nodes become subgraphs containing an anchor node for the
+/// subsystem itself, which the wires of the enclosing level are attached to.
+pub fn to_mermaid_with(subsystem: &Subsystem, labeler: impl Fn(&Node) -> String) -> String {
+    let mut out = String::from("flowchart LR\n");
+    write_mermaid_level(&mut out, subsystem, "n", 1, &labeler);
+    out
+}
+
+fn write_mermaid_level(
+    out: &mut String,
+    subsystem: &Subsystem,
+    prefix: &str,
+    depth: usize,
+    labeler: &dyn Fn(&Node) -> String,
+) {
+    let indent = "    ".repeat(depth);
+    let mut nodes = subsystem.snarl.node_ids().collect::<Vec<_>>();
+    nodes.sort_by_key(|(node_id, _)| *node_id);
+
+    for (node_id, node) in nodes {
+        if node.is_comment() {
+            continue;
+        }
+        let id = format!("{prefix}{}", node_id.0);
+        let label = mermaid_escape(&labeler(node));
+
+        if let Some(inner) = node.subsystem.as_ref() {
+            writeln!(out, "{indent}subgraph cluster_{id} [\"{label}\"]").unwrap();
+            writeln!(out, "{indent}    {id}[[\"{label}\"]]").unwrap();
+            write_mermaid_level(out, &inner.borrow(), &format!("{id}_"), depth + 1, labeler);
+            writeln!(out, "{indent}end").unwrap();
+        } else {
+            writeln!(out, "{indent}{id}[\"{label}\"]").unwrap();
+        }
+    }
+
+    let mut wires = subsystem.snarl.wires().collect::<Vec<_>>();
+    wires.sort();
+    for (pin_out, pin_in) in wires {
+        let output_name = subsystem.snarl[pin_out.node]
+            .outputs
+            .get(pin_out.output)
+            .map_or("", |output| output.name.as_str());
+        let input_name = subsystem.snarl[pin_in.node]
+            .inputs
+            .get(pin_in.input)
+            .map_or("", |input| input.name.as_str());
+        // A named wire shows its name instead of the ports it joins
+        let label = match subsystem.wire_labels.get(&(pin_out, pin_in)) {
+            Some(label) => label.clone(),
+            None => format!("{output_name} -> {input_name}"),
+        };
+
+        writeln!(
+            out,
+            "{indent}{prefix}{} -->|\"{}\"| {prefix}{}",
+            pin_out.node.0,
+            mermaid_escape(&label),
+            pin_in.node.0,
+        )
+        .unwrap();
+    }
+}
+
+/// Escapes `text` for use inside a double-quoted Mermaid label.
+fn mermaid_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("#quot;"),
+            '#' => escaped.push_str("#35;"),
+            '\n' => escaped.push_str("<br>"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Height of the title bar of nodes in SVG exports.
+const SVG_HEADER: f32 = 24.0;
+
+/// Height of a row of ports in SVG exports.
+const SVG_ROW: f32 = 20.0;
+
+/// Space around the nodes of a level in SVG exports.
+const SVG_MARGIN: f32 = 20.0;
+
+/// Exports `subsystem` as a standalone SVG drawing, labelling nodes with their name, see
+/// [`to_svg_with`].
+pub fn to_svg(
+    subsystem: &Subsystem,
+    recursive: bool,
+    legend: bool,
+    wire_color: impl Fn(DataType) -> Color32,
+) -> String {
+    to_svg_with(subsystem, recursive, legend, wire_color, default_labeler)
+}
+
+/// Exports `subsystem` as a standalone SVG drawing, with nodes at their stored positions labelled
+/// with `labeler`, and wires colored by `wire_color` unless they have a color of their own.
+///
+/// With `recursive`, the nested subsystems are drawn below their level as framed groups,
+/// themselves containing the levels nested inside them. With `legend`, a footer lists the color
+/// of each data type.
+pub fn to_svg_with(
+    subsystem: &Subsystem,
+    recursive: bool,
+    legend: bool,
+    wire_color: impl Fn(DataType) -> Color32,
+    labeler: impl Fn(&Node) -> String,
+) -> String {
+    let mut body = String::new();
+    let mut size = write_svg_level(
+        &mut body,
+        subsystem,
+        &mut Vec::new(),
+        recursive,
+        &wire_color,
+        &labeler,
+    );
+    if legend {
+        let width = write_svg_legend(&mut body, size.y, &wire_color);
+        size.x = size.x.max(width);
+        size.y += SVG_ROW + SVG_MARGIN;
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {:.1} {:.1}\" width=\"{:.1}\" \
+         height=\"{:.1}\" font-family=\"sans-serif\" font-size=\"12\">",
+        size.x, size.y, size.x, size.y
+    )
+    .unwrap();
+    out.push_str(&body);
+    out.push_str("</svg>\n");
+    out
+}
+
+/// Writes a row naming each data type next to its color, `top` below the origin, returning the
+/// width it took.
+fn write_svg_legend(out: &mut String, top: f32, wire_color: &dyn Fn(DataType) -> Color32) -> f32 {
+    let y = top + SVG_ROW / 2.0;
+    let mut x = SVG_MARGIN;
+    out.push_str("<g>\n");
+    for data_type in DataType::ALL {
+        let name = format!("{data_type:?}");
+        writeln!(
+            out,
+            "  <circle cx=\"{:.1}\" cy=\"{y:.1}\" r=\"5\" fill=\"{}\"/>\
+             <text x=\"{:.1}\" y=\"{:.1}\">{name}</text>",
+            x + 5.0,
+            svg_color(wire_color(data_type)),
+            x + 14.0,
+            y + 4.0
+        )
+        .unwrap();
+        x += 14.0 + name.len() as f32 * 7.0 + SVG_MARGIN;
+    }
+    out.push_str("</g>\n");
+    x
+}
+
+/// Size of the box drawn for `node` in SVG exports.
+fn svg_node_size(node: &Node) -> Vec2 {
+    let rows = node.inputs.len().max(node.outputs.len()).max(1);
+    let text_rows = node.text.lines().count().max(1);
+    if node.is_comment() {
+        egui::vec2(node.width, text_rows as f32 * SVG_ROW + 8.0)
+    } else {
+        egui::vec2(node.width, SVG_HEADER + rows as f32 * SVG_ROW + 8.0)
+    }
+}
+
+/// Writes the level `subsystem` with its top-left corner at the origin, returning the size it
+/// took, nested levels included.
+fn write_svg_level(
+    out: &mut String,
+    subsystem: &Subsystem,
+    path: &mut Vec<String>,
+    recursive: bool,
+    wire_color: &dyn Fn(DataType) -> Color32,
+    labeler: &dyn Fn(&Node) -> String,
+) -> Vec2 {
+    let snarl = &subsystem.snarl;
+    let mut nodes = snarl.nodes_pos_ids().collect::<Vec<_>>();
+    nodes.sort_by_key(|(node_id, _, _)| *node_id);
+
+    let rects = nodes
+        .iter()
+        .map(|(node_id, pos, node)| (*node_id, Rect::from_min_size(*pos, svg_node_size(node))))
+        .collect::<HashMap<_, _>>();
+    let bounds = rects
+        .values()
+        .copied()
+        .reduce(|a, b| a.union(b))
+        .unwrap_or(Rect::ZERO);
+
+    let caption = if path.is_empty() { 0.0 } else { SVG_HEADER };
+    let mut size = egui::vec2(
+        bounds.width() + 2.0 * SVG_MARGIN,
+        caption + bounds.height() + 2.0 * SVG_MARGIN,
+    );
+
+    let mut level = String::new();
+    let offset = egui::vec2(SVG_MARGIN, caption + SVG_MARGIN) - bounds.min.to_vec2();
+    writeln!(
+        level,
+        "<g transform=\"translate({:.1} {:.1})\">",
+        offset.x, offset.y
+    )
+    .unwrap();
+
+    for (pin_out, pin_in) in snarl.wires() {
+        let (Some(from), Some(to)) = (rects.get(&pin_out.node), rects.get(&pin_in.node)) else {
+            continue;
+        };
+        let Some(output) = snarl[pin_out.node].outputs.get(pin_out.output) else {
+            continue;
+        };
+        let from_mirrored = subsystem.is_mirrored(&snarl[pin_out.node]);
+        let to_mirrored = subsystem.is_mirrored(&snarl[pin_in.node]);
+        let start_x = if from_mirrored {
+            from.left()
+        } else {
+            from.right()
+        };
+        let end_x = if to_mirrored { to.right() } else { to.left() };
+        let start = egui::pos2(start_x, svg_row_y(from, pin_out.output));
+        let end = egui::pos2(end_x, svg_row_y(to, pin_in.input));
+
+        let wire = (pin_out, pin_in);
+        let color = subsystem
+            .wire_colors
+            .get(&wire)
+            .copied()
+            .unwrap_or_else(|| wire_color(output.data_type));
+        let points = std::iter::once(start)
+            .chain(
+                subsystem
+                    .waypoints
+                    .get(&wire)
+                    .into_iter()
+                    .flatten()
+                    .copied(),
+            )
+            .chain(std::iter::once(end))
+            .collect::<Vec<_>>();
+        let d = match subsystem.waypoints.get(&wire) {
+            Some(waypoints) if !waypoints.is_empty() => points
+                .iter()
+                .enumerate()
+                .map(|(n, p)| format!("{}{:.1} {:.1}", if n == 0 { "M" } else { " L" }, p.x, p.y))
+                .collect::<String>(),
+            _ => {
+                let bend = ((end.x - start.x).abs() / 2.0).max(30.0);
+                let start_bend = if from_mirrored { -bend } else { bend };
+                let end_bend = if to_mirrored { -bend } else { bend };
+                format!(
+                    "M{:.1} {:.1} C{:.1} {:.1} {:.1} {:.1} {:.1} {:.1}",
+                    start.x,
+                    start.y,
+                    start.x + start_bend,
+                    start.y,
+                    end.x - end_bend,
+                    end.y,
+                    end.x,
+                    end.y
+                )
+            }
+        };
+        writeln!(
+            level,
+            "  <path d=\"{d}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>",
+            svg_color(color)
+        )
+        .unwrap();
+
+        if let Some(label) = subsystem.wire_labels.get(&wire) {
+            // Halfway along the route, between its two middle points
+            let middle = points[(points.len() - 1) / 2].lerp(points[points.len() / 2], 0.5);
+            writeln!(
+                level,
+                "  <text x=\"{:.1}\" y=\"{:.1}\" font-size=\"10\" text-anchor=\"middle\">{}</text>",
+                middle.x,
+                middle.y - 4.0,
+                xml_escape(label)
+            )
+            .unwrap();
+        }
+    }
+
+    for (node_id, _, node) in &nodes {
+        let mirrored = subsystem.is_mirrored(node);
+        write_svg_node(
+            &mut level,
+            node,
+            rects[node_id],
+            mirrored,
+            wire_color,
+            labeler,
+        );
+    }
+    level.push_str("</g>\n");
+
+    if recursive {
+        for (_, _, node) in &nodes {
+            let Some(inner) = node.subsystem.as_ref() else {
+                continue;
+            };
+            // The captions follow the names, whatever the labeler shows on the nodes
+            path.push(node.name.clone());
+            let mut nested = String::new();
+            let inner_size = write_svg_level(
+                &mut nested,
+                &inner.borrow(),
+                path,
+                true,
+                wire_color,
+                labeler,
+            );
+            path.pop();
+
+            writeln!(
+                level,
+                "<g transform=\"translate({:.1} {:.1})\">",
+                SVG_MARGIN, size.y
+            )
+            .unwrap();
+            level.push_str(&nested);
+            level.push_str("</g>\n");
+            size.x = size.x.max(inner_size.x + 2.0 * SVG_MARGIN);
+            size.y += inner_size.y + SVG_MARGIN;
+        }
+    }
+
+    if !path.is_empty() {
+        writeln!(
+            out,
+            "<g><rect width=\"{:.1}\" height=\"{:.1}\" fill=\"none\" stroke=\"#999\" \
+             stroke-dasharray=\"4 3\"/>",
+            size.x, size.y
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "<text x=\"8\" y=\"16\" font-weight=\"bold\">{}</text>",
+            xml_escape(&path.join(" / "))
+        )
+        .unwrap();
+    } else {
+        out.push_str("<g>\n");
+    }
+    out.push_str(&level);
+    out.push_str("</g>\n");
+    size
+}
+
+/// Vertical position of the `index`-th row of ports of the node drawn in `rect`.
+fn svg_row_y(rect: &Rect, index: usize) -> f32 {
+    rect.top() + SVG_HEADER + (index as f32 + 0.5) * SVG_ROW
+}
+
+fn write_svg_node(
+    out: &mut String,
+    node: &Node,
+    rect: Rect,
+    mirrored: bool,
+    wire_color: &dyn Fn(DataType) -> Color32,
+    labeler: &dyn Fn(&Node) -> String,
+) {
+    let fill = node.custom_color().map_or_else(
+        || {
+            if node.is_comment() {
+                "#fff7c0".to_string()
+            } else {
+                "#f4f4f4".to_string()
+            }
+        },
+        svg_color,
+    );
+    writeln!(
+        out,
+        "  <rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" rx=\"4\" fill=\"{fill}\" \
+         stroke=\"#333\"/>",
+        rect.left(),
+        rect.top(),
+        rect.width(),
+        rect.height()
+    )
+    .unwrap();
+
+    if node.is_comment() {
+        for (n, line) in node.text.lines().enumerate() {
+            writeln!(
+                out,
+                "  <text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+                rect.left() + 6.0,
+                rect.top() + (n as f32 + 1.0) * SVG_ROW,
+                xml_escape(line)
+            )
+            .unwrap();
+        }
+        return;
+    }
+
+    let weight = if node.subsystem.is_some() {
+        " font-weight=\"bold\""
+    } else {
+        ""
+    };
+    writeln!(
+        out,
+        "  <text x=\"{:.1}\" y=\"{:.1}\"{weight}>{}</text>",
+        rect.left() + 6.0,
+        rect.top() + 16.0,
+        xml_escape(&labeler(node))
+    )
+    .unwrap();
+
+    // Mirrored nodes swap the sides of their inputs and outputs
+    let (input_side, output_side) = if mirrored {
+        ((rect.right(), -8.0, "end"), (rect.left(), 8.0, "start"))
+    } else {
+        ((rect.left(), 8.0, "start"), (rect.right(), -8.0, "end"))
+    };
+    let ports = node
+        .inputs
+        .iter()
+        .map(|input| (&input.name, input.data_type, input_side))
+        .enumerate()
+        .chain(
+            node.outputs
+                .iter()
+                .map(|output| (&output.name, output.data_type, output_side))
+                .enumerate(),
+        );
+    for (index, (name, data_type, (x, inset, anchor))) in ports {
+        let y = svg_row_y(&rect, index);
+        writeln!(
+            out,
+            "  <circle cx=\"{x:.1}\" cy=\"{y:.1}\" r=\"4\" fill=\"{}\"/>\
+             <text x=\"{:.1}\" y=\"{:.1}\" font-size=\"10\" text-anchor=\"{anchor}\">{}</text>",
+            svg_color(wire_color(data_type)),
+            x + inset,
+            y + 4.0,
+            xml_escape(name)
+        )
+        .unwrap();
+    }
+}
+
+/// `color` as an SVG color, with its opacity dropped.
+fn svg_color(color: Color32) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r(), color.g(), color.b())
+}
+
+/// Escapes `text` for use in SVG text and attributes.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Generates Rust skeleton code from `subsystem`: a struct per node with a field per port, and
+/// a struct per subsystem holding its nodes, whose `connect` copies the values along its wires.
+/// The top level becomes a `Diagram` struct.
+///
+/// Nodes holding the same shared subsystem use the same struct. The result is meant as a
+/// starting point and may need touching up to compile.
+pub fn to_rust(subsystem: &Subsystem) -> String {
+    let mut generator = RustGenerator::default();
+    generator.type_names.insert("Diagram".to_string());
+    generator.write_subsystem("Diagram".to_string(), subsystem, &[], &[]);
+
+    let mut out = String::from("//! Generated from a diagram.\n");
+    for item in &generator.items {
+        out.push('\n');
+        out.push_str(item);
+    }
+    out
+}
+
+/// Struct generated for a node, with the fields of its ports.
+#[derive(Clone)]
+struct RustStruct {
+    name: String,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+}
+
+#[derive(Default)]
+struct RustGenerator {
+    items: Vec<String>,
+    type_names: HashSet<String>,
+    /// Structs already generated for shared subsystems.
+    shared: HashMap<*const RefCell<Subsystem>, RustStruct>,
+}
+
+impl RustGenerator {
+    /// A struct name based on `name` that is not taken yet.
+    fn type_name(&mut self, name: &str) -> String {
+        let base = rust_type_ident(name);
+        let name = (1..)
+            .map(|n| match n {
+                1 => base.clone(),
+                n => format!("{base}{n}"),
+            })
+            .find(|name| !self.type_names.contains(name))
+            .unwrap();
+        self.type_names.insert(name.clone());
+        name
+    }
+
+    fn write_node(&mut self, node: &Node) -> RustStruct {
+        let Some(inner) = node.subsystem.as_ref() else {
+            let name = self.type_name(&node.name);
+            return self.write_leaf(name, node);
+        };
+
+        if let Some(found) = self.shared.get(&Rc::as_ptr(inner)) {
+            return found.clone();
+        }
+        let name = self.type_name(&node.name);
+        let generated = self.write_subsystem(name, &inner.borrow(), &node.inputs, &node.outputs);
+        self.shared.insert(Rc::as_ptr(inner), generated.clone());
+        generated
+    }
+
+    fn write_leaf(&mut self, name: String, node: &Node) -> RustStruct {
+        let mut fields = Vec::new();
+        let (inputs, outputs) = port_fields(&node.inputs, &node.outputs, &mut fields);
+
+        let mut item = String::new();
+        writeln!(item, "/// Node \"{}\".", node.name).unwrap();
+        write_rust_struct(&mut item, &name, &fields);
+        self.items.push(item);
+        RustStruct {
+            name,
+            inputs,
+            outputs,
+        }
+    }
+
+    /// Writes the struct of `subsystem`, whose ports as seen from the level above are `inputs`
+    /// and `outputs`, after the structs of its nodes.
+    fn write_subsystem(
+        &mut self,
+        name: String,
+        subsystem: &Subsystem,
+        inputs: &[Input],
+        outputs: &[Output],
+    ) -> RustStruct {
+        let snarl = &subsystem.snarl;
+        let mut fields = Vec::new();
+        let (input_fields, output_fields) = port_fields(inputs, outputs, &mut fields);
+
+        let mut nodes = snarl.node_ids().collect::<Vec<_>>();
+        nodes.sort_by_key(|(node_id, _)| *node_id);
+
+        let mut children = HashMap::new();
+        for (node_id, node) in nodes {
+            if node.is_comment() || node.is_boundary() {
+                continue;
+            }
+            let generated = self.write_node(node);
+            let field = unique_field(&fields, &node.name);
+            fields.push((field.clone(), generated.name.clone()));
+            children.insert(node_id, (field, generated, node.subsystem.is_some()));
+        }
+
+        // Values flow from the ports of the subsystem, then from node to node
+        let boundary_inputs = subsystem.boundary_inputs();
+        let boundary_outputs = subsystem.boundary_outputs();
+        let source = |pin: OutPinId| match boundary_inputs.iter().position(|p| *p == pin) {
+            Some(n) => Some(format!("self.{}", input_fields.get(n)?)),
+            None => {
+                let (field, generated, _) = children.get(&pin.node)?;
+                Some(format!(
+                    "self.{field}.{}",
+                    generated.outputs.get(pin.output)?
+                ))
+            }
+        };
+        let target = |pin: InPinId| match boundary_outputs.iter().position(|p| *p == pin) {
+            Some(n) => Some(format!("self.{}", output_fields.get(n)?)),
+            None => {
+                let (field, generated, _) = children.get(&pin.node)?;
+                Some(format!("self.{field}.{}", generated.inputs.get(pin.input)?))
+            }
+        };
+
+        let mut wires = snarl.wires().collect::<Vec<_>>();
+        wires.sort();
+        let mut body = Vec::new();
+        let mut order = boundary_inputs
+            .iter()
+            .map(|pin| pin.node)
+            .collect::<Vec<_>>();
+        order.dedup();
+        order.extend(flow_order(snarl));
+        let mut done = HashSet::new();
+        for node_id in order {
+            if !done.insert(node_id) {
+                continue;
+            }
+            if let Some((field, _, true)) = children.get(&node_id) {
+                body.push(format!("self.{field}.connect();"));
+            }
+            for (pin_out, pin_in) in wires.iter().filter(|(pin_out, _)| pin_out.node == node_id) {
+                if let (Some(source), Some(target)) = (source(*pin_out), target(*pin_in)) {
+                    body.push(format!("{target} = {source};"));
+                }
+            }
+        }
+
+        let mut item = String::new();
+        if name == "Diagram" {
+            writeln!(item, "/// Top level of the diagram.").unwrap();
+        } else {
+            writeln!(item, "/// Subsystem \"{name}\".").unwrap();
+        }
+        write_rust_struct(&mut item, &name, &fields);
+        writeln!(item, "\nimpl {name} {{").unwrap();
+        writeln!(
+            item,
+            "    /// Copies the values along the wires of this level, descending into subsystems."
+        )
+        .unwrap();
+        writeln!(item, "    pub fn connect(&mut self) {{").unwrap();
+        for line in body {
+            writeln!(item, "        {line}").unwrap();
+        }
+        writeln!(item, "    }}\n}}").unwrap();
+        self.items.push(item);
+
+        RustStruct {
+            name,
+            inputs: input_fields,
+            outputs: output_fields,
+        }
+    }
+}
+
+/// Adds a field per port to `fields`, returning the names of the ones of `inputs` and
+/// `outputs`.
+fn port_fields(
+    inputs: &[Input],
+    outputs: &[Output],
+    fields: &mut Vec<(String, String)>,
+) -> (Vec<String>, Vec<String>) {
+    let ports = inputs
+        .iter()
+        .map(|input| (&input.name, input.data_type))
+        .chain(
+            outputs
+                .iter()
+                .map(|output| (&output.name, output.data_type)),
+        )
+        .collect::<Vec<_>>();
+
+    let mut names = Vec::new();
+    for (name, data_type) in ports {
+        let field = unique_field(fields, name);
+        fields.push((field.clone(), rust_type(data_type).to_string()));
+        names.push(field);
+    }
+    let outputs = names.split_off(inputs.len());
+    (names, outputs)
+}
+
+/// A field name based on `name` that is not in `fields` yet.
+fn unique_field(fields: &[(String, String)], name: &str) -> String {
+    let base = rust_field_ident(name);
+    (1..)
+        .map(|n| match n {
+            1 => base.clone(),
+            n => format!("{base}_{n}"),
+        })
+        .find(|name| fields.iter().all(|(field, _)| field != name))
+        .unwrap()
+}
+
+fn write_rust_struct(out: &mut String, name: &str, fields: &[(String, String)]) {
+    writeln!(out, "#[derive(Debug, Default)]").unwrap();
+    if fields.is_empty() {
+        writeln!(out, "pub struct {name};").unwrap();
+        return;
+    }
+    writeln!(out, "pub struct {name} {{").unwrap();
+    for (field, field_type) in fields {
+        writeln!(out, "    pub {field}: {field_type},").unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+}
+
+/// Nodes of `snarl` sorted so that each comes after the ones driving it, as far as loops
+/// allow. The nodes stuck in loops come last, by id.
+fn flow_order(snarl: &Snarl<Node>) -> Vec<NodeId> {
+    model::topo_order(snarl).unwrap_or_else(|e| [e.sorted, e.stuck].concat())
+}
+
+fn rust_type(data_type: DataType) -> &'static str {
+    match data_type {
+        DataType::Bool => "bool",
+        DataType::Int => "i64",
+        DataType::Float | DataType::Signal | DataType::Any => "f64",
+    }
+}
+
+/// `name` as a `PascalCase` identifier.
+fn rust_type_ident(name: &str) -> String {
+    let ident = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            std::iter::once(first).chain(chars).collect::<String>()
+        })
+        .collect::<String>();
+    match ident.chars().next() {
+        None => "Node".to_string(),
+        Some(c) if c.is_ascii_digit() => format!("N{ident}"),
+        Some(_) => ident,
+    }
+}
+
+/// `name` as a `snake_case` identifier.
+fn rust_field_ident(name: &str) -> String {
+    let ident = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("_");
+    match ident.chars().next() {
+        None => "port".to_string(),
+        Some(c) if c.is_ascii_digit() => format!("_{ident}"),
+        Some(_) if RUST_KEYWORDS.contains(&ident.as_str()) => format!("{ident}_"),
+        Some(_) => ident,
+    }
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while",
+];
+
+/// Lists every port of the nodes of `subsystem` as CSV: node name, direction, index, name and
+/// kind. With `recursive`, the nodes of nested subsystems are listed too, after a first column
+/// holding the path of their level.
+pub fn to_port_csv(subsystem: &Subsystem, recursive: bool) -> String {
+    let mut out = String::new();
+    if recursive {
+        out.push_str("path,");
+    }
+    out.push_str("node,direction,index,port,kind\n");
+    write_port_rows(&mut out, subsystem, recursive.then(Vec::new).as_mut());
+    out
+}
+
+/// Writes the port rows of `subsystem`, prefixed by `path` if given and then descending into
+/// the nested subsystems.
+fn write_port_rows(out: &mut String, subsystem: &Subsystem, path: Option<&mut Vec<String>>) {
+    let mut nodes = subsystem.snarl.node_ids().collect::<Vec<_>>();
+    nodes.sort_by_key(|(node_id, _)| *node_id);
+
+    for (_, node) in &nodes {
+        let inputs = node.inputs.iter().enumerate().map(|(index, input)| {
+            (
+                "in",
+                index,
+                input.name.as_str(),
+                input_kind_name(input.kind),
+            )
+        });
+        let outputs = node.outputs.iter().enumerate().map(|(index, output)| {
+            (
+                "out",
+                index,
+                output.name.as_str(),
+                output_kind_name(output.kind),
+            )
+        });
+
+        for (direction, index, name, kind) in inputs.chain(outputs) {
+            let mut fields = Vec::new();
+            if let Some(path) = path.as_deref() {
+                fields.push(csv_field(&path.join("/")));
+            }
+            fields.extend([
+                csv_field(&node.name),
+                direction.to_string(),
+                index.to_string(),
+                csv_field(name),
+                kind.to_string(),
+            ]);
+            writeln!(out, "{}", fields.join(",")).unwrap();
+        }
+    }
+
+    let Some(path) = path else {
+        return;
+    };
+    for (_, node) in nodes {
+        if let Some(inner) = node.subsystem.as_ref() {
+            path.push(node.name.clone());
+            write_port_rows(out, &inner.borrow(), Some(path));
+            path.pop();
+        }
+    }
+}
+
+/// Creates the nodes described by a port listing made by [`to_port_csv`], one per node name and
+/// path, with their ports in index order.
+pub fn nodes_from_port_csv(text: &str) -> Result<Vec<Node>, String> {
+    let mut records = parse_csv(text).into_iter();
+    let header = records.next().ok_or("The file is empty")?;
+    let has_path = header.first().is_some_and(|field| field == "path");
+    let columns = if has_path { 6 } else { 5 };
+
+    let mut nodes: Vec<((String, String), Node)> = Vec::new();
+    for (line, record) in records.enumerate() {
+        let row = line + 2;
+        if record.iter().all(String::is_empty) {
+            continue;
+        }
+        if record.len() != columns {
+            return Err(format!(
+                "Row {row} has {} fields instead of {columns}",
+                record.len()
+            ));
+        }
+        let (path, fields) = if has_path {
+            (record[0].clone(), &record[1..])
+        } else {
+            (String::new(), &record[..])
+        };
+        let [name, direction, index, port, kind] = fields else {
+            unreachable!("the field count is checked above");
+        };
+        let index = index
+            .parse::<usize>()
+            .map_err(|_| format!("Row {row} has an invalid port index \"{index}\""))?;
+
+        let key = (path, name.clone());
+        let node = match nodes.iter().position(|(other, _)| *other == key) {
+            Some(position) => &mut nodes[position].1,
+            None => {
+                let node = Node {
+                    name: name.clone(),
+                    ..Node::default()
+                };
+                nodes.push((key, node));
+                &mut nodes.last_mut().unwrap().1
+            }
+        };
+
+        match direction.as_str() {
+            "in" => {
+                let kind = parse_input_kind(kind)
+                    .ok_or_else(|| format!("Row {row} has an unknown port kind \"{kind}\""))?;
+                let input = Input {
+                    name: port.clone(),
+                    kind,
+                    ..Input::default()
+                };
+                let at = index.min(node.inputs.len());
+                node.inputs.insert(at, input);
+            }
+            "out" => {
+                let kind = parse_output_kind(kind)
+                    .ok_or_else(|| format!("Row {row} has an unknown port kind \"{kind}\""))?;
+                let output = Output {
+                    name: port.clone(),
+                    kind,
+                    ..Output::default()
+                };
+                let at = index.min(node.outputs.len());
+                node.outputs.insert(at, output);
+            }
+            _ => {
+                return Err(format!(
+                    "Row {row} has an unknown direction \"{direction}\""
+                ));
+            }
+        }
+    }
+
+    Ok(nodes.into_iter().map(|(_, node)| node).collect())
+}
+
+fn input_kind_name(kind: InputKind) -> &'static str {
+    match kind {
+        InputKind::Normal => "Normal",
+        InputKind::External => "External",
+        InputKind::Internal => "Internal",
+    }
+}
+
+fn output_kind_name(kind: OutputKind) -> &'static str {
+    match kind {
+        OutputKind::Normal => "Normal",
+        OutputKind::External => "External",
+        OutputKind::Internal => "Internal",
+    }
+}
+
+fn parse_input_kind(name: &str) -> Option<InputKind> {
+    match name {
+        "Normal" => Some(InputKind::Normal),
+        "External" => Some(InputKind::External),
+        "Internal" => Some(InputKind::Internal),
+        _ => None,
+    }
+}
+
+fn parse_output_kind(name: &str) -> Option<OutputKind> {
+    match name {
+        "Normal" => Some(OutputKind::Normal),
+        "External" => Some(OutputKind::External),
+        "Internal" => Some(OutputKind::Internal),
+        _ => None,
+    }
+}
+
+/// Quotes `text` for use as a CSV field if it holds a comma, a quote or a line break.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Splits `text` into records of fields, following the quoting of [`csv_field`].
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            '\r' if !quoted => {}
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use egui::Pos2;
+
+    /// A level holding a block wired into a subsystem node, which holds a block of its own.
+    fn sample() -> Subsystem {
+        let mut inner = Subsystem::new();
+        inner.snarl.insert_node(
+            Pos2::ZERO,
+            Node {
+                name: "inner".to_string(),
+                ..Node::default()
+            },
+        );
+
+        let mut top = Subsystem::new();
+        let source = top.snarl.insert_node(
+            Pos2::ZERO,
+            Node {
+                name: "source".to_string(),
+                outputs: vec![Output::default()],
+                ..Node::default()
+            },
+        );
+        let holder = top.snarl.insert_node(
+            Pos2::new(300.0, 0.0),
+            Node {
+                name: "holder".to_string(),
+                inputs: vec![Input::default()],
+                subsystem: Some(Rc::new(RefCell::new(inner))),
+                ..Node::default()
+            },
+        );
+        top.snarl.connect(
+            OutPinId {
+                node: source,
+                output: 0,
+            },
+            InPinId {
+                node: holder,
+                input: 0,
+            },
+        );
+        top
+    }
+
+    fn shouting(node: &Node) -> String {
+        format!("{}!", node.name.to_uppercase())
+    }
+
+    #[test]
+    fn labelers_name_every_node() {
+        let top = sample();
+        let dot = to_dot_with(&top, shouting);
+        let mermaid = to_mermaid_with(&top, shouting);
+        let svg = to_svg_with(&top, true, false, |_| Color32::BLACK, shouting);
+        for export in [dot, mermaid, svg.clone()] {
+            for name in ["SOURCE!", "HOLDER!", "INNER!"] {
+                assert!(export.contains(name), "{name} missing from {export}");
+            }
+            assert!(!export.contains("source"));
+        }
+
+        // Level captions keep the names
+        assert!(svg.contains(">holder</text>"));
+
+        // Without one, nodes go by their name
+        assert!(to_dot(&top).contains("label=\"source\""));
+        assert!(to_mermaid(&top).contains("[\"source\"]"));
+        assert!(to_svg(&top, true, false, |_| Color32::BLACK).contains(">source<"));
+    }
+}
@@ -0,0 +1,542 @@
+//! Translations of the strings shown in the interface.
+//!
+//! Strings are looked up by their English text, so a string without a translation, or one not
+//! routed through [`tr`] yet, simply shows in English.
+
+use std::{cell::RefCell, collections::HashMap};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Language {
+    #[default]
+    English,
+    French,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::French];
+
+    /// Name of the language, in that language.
+    pub fn label(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::French => "Français",
+        }
+    }
+
+    fn strings(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::English => &[],
+            Language::French => FRENCH,
+        }
+    }
+}
+
+thread_local! {
+    /// Translations of the current language, by English text.
+    static STRINGS: RefCell<HashMap<&'static str, &'static str>> = RefCell::default();
+}
+
+/// Switches the interface to `language`, from the next string looked up.
+pub fn set_language(language: Language) {
+    STRINGS.with_borrow_mut(|strings| *strings = language.strings().iter().copied().collect());
+    log::info!("Switched the interface to {language:?}");
+}
+
+/// `key`, an English string, in the current language.
+pub fn tr(key: &str) -> &str {
+    STRINGS
+        .with_borrow(|strings| strings.get(key).copied())
+        .unwrap_or(key)
+}
+
+/// `key`, an English template, in the current language with each `{name}` in it replaced by
+/// the value given for `name` in `args`.
+pub fn tr_args(key: &str, args: &[(&str, &dyn std::fmt::Display)]) -> String {
+    args.iter()
+        .fold(tr(key).to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{name}}}"), &value.to_string())
+        })
+}
+
+const FRENCH: &[(&str, &str)] = &[
+    // Top menu
+    ("File", "Fichier"),
+    ("New", "Nouveau"),
+    ("New Tab", "Nouvel onglet"),
+    ("Open…", "Ouvrir…"),
+    ("Recent", "Récents"),
+    ("No recent files", "Aucun fichier récent"),
+    ("The file no longer exists", "Le fichier n'existe plus"),
+    ("Clear Recent", "Effacer les récents"),
+    ("Save", "Enregistrer"),
+    ("Save As…", "Enregistrer sous…"),
+    ("Compare…", "Comparer…"),
+    ("Import", "Importer"),
+    ("Export", "Exporter"),
+    ("Graphviz…", "Graphviz…"),
+    ("Mermaid…", "Mermaid…"),
+    ("Rust…", "Rust…"),
+    ("SVG…", "SVG…"),
+    ("PNG", "PNG"),
+    ("Current View…", "Vue actuelle…"),
+    ("Whole Diagram…", "Diagramme entier…"),
+    ("Port CSV…", "Ports en CSV…"),
+    ("Recursive", "Récursif"),
+    ("Include Legend", "Inclure la légende"),
+    (
+        "Adds the legend to SVG and PNG exports",
+        "Ajoute la légende aux exports SVG et PNG",
+    ),
+    ("Quit", "Quitter"),
+    ("Edit", "Édition"),
+    ("Undo", "Annuler"),
+    ("Redo", "Rétablir"),
+    ("Presentation Mode", "Mode présentation"),
+    (
+        "Turn editing off, for showing finished diagrams",
+        "Désactive l'édition, pour montrer des diagrammes terminés",
+    ),
+    (
+        "Presentation mode, editing is off",
+        "Mode présentation, l'édition est désactivée",
+    ),
+    ("Exit", "Sortir"),
+    ("Language", "Langue"),
+    (
+        "Size of the text and controls, the canvas zooms on its own",
+        "Taille du texte et des contrôles, le canevas a son propre zoom",
+    ),
+    ("Search nodes", "Chercher des nœuds"),
+    ("No matching node", "Aucun nœud correspondant"),
+    (
+        "Match node names with a regular expression, to rename them",
+        "Chercher les noms de nœuds par expression régulière, pour les renommer",
+    ),
+    ("Replace", "Remplacer"),
+    ("Current subsystem only", "Sous-système actuel seulement"),
+    ("Go to", "Aller à"),
+    ("Double-click to rename", "Double-cliquer pour renommer"),
+    ("Top", "Haut"),
+    ("{count} nodes selected", "{count} nœuds sélectionnés"),
+    (
+        "{nodes} node(s), {wires} wire(s)",
+        "{nodes} nœud(s), {wires} fil(s)",
+    ),
+    ("{count} selected", "{count} sélectionné(s)"),
+    (
+        "Drop diagrams to open them",
+        "Déposer des diagrammes pour les ouvrir",
+    ),
+    (
+        "{path}: not a file that can be opened",
+        "{path} : pas un fichier qui peut être ouvert",
+    ),
+    ("Rename {count} node(s)", "Renommer {count} nœud(s)"),
+    ("Renamed {count} node(s)", "{count} nœud(s) renommé(s)"),
+    (
+        "{count} duplicate name(s) after renaming",
+        "{count} nom(s) en double après le renommage",
+    ),
+    (
+        "The subsystem of this change no longer exists",
+        "Le sous-système de cette modification n'existe plus",
+    ),
+    (
+        "Maximum nesting depth of {depth} reached",
+        "Profondeur d'imbrication maximale de {depth} atteinte",
+    ),
+    // Diagram menu
+    ("Diagram Menu", "Menu du diagramme"),
+    ("Add Node", "Ajouter un nœud"),
+    ("Add Comment", "Ajouter un commentaire"),
+    ("Connect By Name", "Connecter par nom"),
+    ("Selection", "Sélection"),
+    ("Invert Selection", "Inverser la sélection"),
+    ("Grow Selection", "Étendre la sélection"),
+    (
+        "Add the nodes wired to the selected ones",
+        "Ajoute les nœuds reliés aux nœuds sélectionnés",
+    ),
+    ("Shrink Selection", "Réduire la sélection"),
+    (
+        "Leave out the selected nodes wired to unselected ones",
+        "Retire les nœuds sélectionnés reliés à des nœuds non sélectionnés",
+    ),
+    ("Create Group", "Créer un groupe"),
+    (
+        "Box the selected nodes together, without changing the diagram",
+        "Encadre les nœuds sélectionnés ensemble, sans modifier le diagramme",
+    ),
+    ("Convert To Subsystem", "Convertir en sous-système"),
+    ("Insert Node", "Insérer un nœud"),
+    ("Insert Instance", "Insérer une instance"),
+    ("Auto Layout", "Disposition automatique"),
+    ("Layered", "En couches"),
+    ("Force-Directed", "Par forces"),
+    ("Layout Direction", "Sens de la disposition"),
+    ("Left to Right", "De gauche à droite"),
+    ("Right to Left", "De droite à gauche"),
+    ("Top to Bottom", "De haut en bas"),
+    ("Check Connections", "Vérifier les connexions"),
+    ("Fix Duplicate Names", "Corriger les noms en double"),
+    (
+        "Find Unused Subsystems",
+        "Trouver les sous-systèmes inutilisés",
+    ),
+    ("Statistics", "Statistiques"),
+    ("Properties", "Propriétés"),
+    ("Problems", "Problèmes"),
+    ("Simulate", "Simuler"),
+    ("Test Vectors", "Vecteurs de test"),
+    ("Go Up One Level", "Remonter d'un niveau"),
+    (
+        "Wire the outputs of the left node to the like-named inputs of the right one",
+        "Relie les sorties du nœud de gauche aux entrées de même nom du nœud de droite",
+    ),
+    (
+        "Connected {count} port(s) by name",
+        "{count} port(s) connecté(s) par nom",
+    ),
+    (
+        "Renamed {count} duplicate name(s)",
+        "{count} nom(s) en double renommé(s)",
+    ),
+    (
+        "\"{name}\" contains this level, an instance here would nest itself",
+        "\"{name}\" contient ce niveau, une instance ici s'imbriquerait en elle-même",
+    ),
+    // Node menu
+    ("Node menu", "Menu du nœud"),
+    (
+        "Id of the node across versions, click to copy",
+        "Identifiant du nœud d'une version à l'autre, cliquer pour copier",
+    ),
+    ("Add Input", "Ajouter une entrée"),
+    ("Add Output", "Ajouter une sortie"),
+    ("Add", "Ajouter"),
+    ("Remove Input", "Supprimer l'entrée"),
+    ("Remove Output", "Supprimer la sortie"),
+    (
+        "The subsystem is shared with other nodes, remove ports from a deep copy instead",
+        "Le sous-système est partagé avec d'autres nœuds, supprimer les ports d'une copie profonde",
+    ),
+    ("Remove Node", "Supprimer le nœud"),
+    ("Remove Comment", "Supprimer le commentaire"),
+    ("Remove", "Supprimer"),
+    ("Disconnect", "Déconnecter"),
+    ("Disconnect All Wires", "Déconnecter tous les fils"),
+    ("Select Upstream", "Sélectionner en amont"),
+    ("Select Downstream", "Sélectionner en aval"),
+    ("Select Connected", "Sélectionner les nœuds reliés"),
+    ("Enter Subsystem", "Entrer dans le sous-système"),
+    ("Show every port", "Afficher tous les ports"),
+    ("Collapse to the port counts", "Réduire au nombre de ports"),
+    ("Flatten Subsystem", "Aplatir le sous-système"),
+    (
+        "Move its contents to this level in its place",
+        "Déplace son contenu à ce niveau, à sa place",
+    ),
+    ("Sync Interface", "Synchroniser l'interface"),
+    ("Split Node", "Scinder le nœud"),
+    ("Inputs kept", "Entrées gardées"),
+    ("Outputs kept", "Sorties gardées"),
+    ("Wire halves together", "Relier les deux moitiés"),
+    ("Split", "Scinder"),
+    ("Reverse", "Inverser"),
+    (
+        "Make the signal flow the other way",
+        "Fait circuler le signal dans l'autre sens",
+    ),
+    (
+        "An end is bound to a subsystem boundary, which sets its direction",
+        "Une extrémité est liée à la frontière d'un sous-système, qui fixe son sens",
+    ),
+    (
+        "Reversed, the wire would close a loop",
+        "Inversé, le fil fermerait une boucle",
+    ),
+    (
+        "The ports at the reversed ends carry incompatible types",
+        "Les ports aux extrémités inversées portent des types incompatibles",
+    ),
+    (
+        "The reversed wire already exists",
+        "Le fil inversé existe déjà",
+    ),
+    (
+        "An end of the reversed wire already has all the wires it takes",
+        "Une extrémité du fil inversé a déjà tous les fils qu'elle accepte",
+    ),
+    (
+        "Rebuild the ports from the boundary nodes inside the subsystem",
+        "Reconstruit les ports depuis les nœuds de frontière du sous-système",
+    ),
+    (
+        "Ports out of sync with the subsystem boundary",
+        "Ports désynchronisés de la frontière du sous-système",
+    ),
+    ("Duplicate (Deep Copy)", "Dupliquer (copie profonde)"),
+    (
+        "Edits inside this subsystem show up in all of them",
+        "Les modifications de ce sous-système apparaissent dans toutes ses instances",
+    ),
+    ("Mirror", "Miroir"),
+    ("Lock Position", "Verrouiller la position"),
+    (
+        "Keep it in place when dragged",
+        "Le garder en place quand il est glissé",
+    ),
+    ("Position locked", "Position verrouillée"),
+    (
+        "Inputs on the right, outputs on the left",
+        "Entrées à droite, sorties à gauche",
+    ),
+    ("Logic", "Logique"),
+    ("Pass-Through", "Transmission"),
+    ("AND", "ET"),
+    ("OR", "OU"),
+    ("NOT", "NON"),
+    ("Constant 0", "Constante 0"),
+    ("Constant 1", "Constante 1"),
+    ("Custom", "Personnalisée"),
+    ("Color", "Couleur"),
+    ("Reset Color", "Réinitialiser la couleur"),
+    ("Kind", "Type"),
+    ("Name", "Nom"),
+    ("Width", "Largeur"),
+    ("Remove Group", "Supprimer le groupe"),
+    ("Most Wires", "Fils au plus"),
+    (
+        "Limit the wires this port can carry",
+        "Limite les fils que ce port peut porter",
+    ),
+    ("Tags", "Étiquettes"),
+    ("New tag", "Nouvelle étiquette"),
+    ("Remove Tag", "Supprimer l'étiquette"),
+    ("No node is tagged here", "Aucun nœud n'est étiqueté ici"),
+    ("Comment", "Commentaire"),
+    ("Duplicate name", "Nom en double"),
+    ("Evaluation order", "Ordre d'évaluation"),
+    (
+        "Waits on a loop, so it has no place in the evaluation order",
+        "Attend une boucle, il n'a donc pas de place dans l'ordre d'évaluation",
+    ),
+    ("Add {count} Inputs", "Ajouter {count} entrées"),
+    ("Add {count} Outputs", "Ajouter {count} sorties"),
+    ("In: {name}", "Entrée : {name}"),
+    ("Out: {name}", "Sortie : {name}"),
+    (
+        "{inputs} in, {outputs} out",
+        "{inputs} entrée(s), {outputs} sortie(s)",
+    ),
+    (
+        "Shared by {count} instances",
+        "Partagé par {count} instances",
+    ),
+    (
+        "Dropped {count} wire(s) from removed ports",
+        "{count} fil(s) des ports supprimés retiré(s)",
+    ),
+    ("Collapse {pins} pins", "Réduire les {pins} broches"),
+    (
+        "{pins} pins (click to expand)",
+        "{pins} broches (cliquer pour développer)",
+    ),
+    ("{nodes}n {wires}w", "{nodes}n {wires}f"),
+    (
+        "{nodes} node(s) and {wires} wire(s) inside",
+        "{nodes} nœud(s) et {wires} fil(s) à l'intérieur",
+    ),
+    (
+        "{op}, computed when simulating",
+        "{op}, calculé pendant la simulation",
+    ),
+    (
+        "{name}\n{kind} input, {wires} wire(s)",
+        "{name}\nEntrée {kind}, {wires} fil(s)",
+    ),
+    (
+        "{name}\n{kind} output, {wires} wire(s)",
+        "{name}\nSortie {kind}, {wires} fil(s)",
+    ),
+    ("Inside: {node} › {port}", "À l'intérieur : {node} › {port}"),
+    (
+        "No boundary port inside",
+        "Aucun port de frontière à l'intérieur",
+    ),
+    (
+        "The boundary of this subsystem changed, its node above no longer matches it",
+        "La frontière de ce sous-système a changé, son nœud au-dessus ne lui correspond plus",
+    ),
+    (
+        "\"{name}\" no longer matches the boundary of its subsystem",
+        "\"{name}\" ne correspond plus à la frontière de son sous-système",
+    ),
+    (
+        "\"{name}\" holds no subsystem to bind this port to",
+        "\"{name}\" ne contient aucun sous-système auquel lier ce port",
+    ),
+    // Wires
+    (
+        "Double-click to add a waypoint, right-click for more options",
+        "Double-cliquer pour ajouter un point de passage, clic droit pour plus d'options",
+    ),
+    ("Rename Wire", "Renommer le fil"),
+    ("Delete Wire", "Supprimer le fil"),
+    ("Remove Waypoint", "Supprimer le point de passage"),
+    ("Drag to another pin", "Glisser vers une autre broche"),
+    ("Add to Watch", "Surveiller"),
+    ("Remove from Watch", "Ne plus surveiller"),
+    // Panels
+    ("Outline", "Plan"),
+    ("Checkpoints", "Points de sauvegarde"),
+    ("Checkpoint {number}", "Point de sauvegarde {number}"),
+    ("Save Checkpoint", "Créer un point de sauvegarde"),
+    ("No checkpoints yet", "Aucun point de sauvegarde"),
+    ("Restore", "Restaurer"),
+    ("Forget", "Oublier"),
+    ("No subsystems yet", "Aucun sous-système"),
+    ("Legend", "Légende"),
+    (
+        "Wires take the color of the output driving them.",
+        "Les fils prennent la couleur de la sortie qui les alimente.",
+    ),
+    ("Presets", "Préréglages"),
+    ("Wires", "Fils"),
+    ("Curved", "Courbes"),
+    ("Orthogonal", "Orthogonaux"),
+    ("Straight", "Droits"),
+    ("Preset", "Préréglage"),
+    ("Save Preset", "Enregistrer le préréglage"),
+    ("Delete Preset", "Supprimer le préréglage"),
+    ("Reset to Default", "Rétablir par défaut"),
+    (
+        "Name the preset, built-in ones cannot be replaced",
+        "Nommez le préréglage, ceux fournis ne peuvent pas être remplacés",
+    ),
+    ("Normal port", "Port normal"),
+    (
+        "External: boundary of this subsystem",
+        "External : frontière de ce sous-système",
+    ),
+    (
+        "Internal: bound to the subsystem inside",
+        "Internal : lié au sous-système intérieur",
+    ),
+    ("Unconnected Ports", "Ports non connectés"),
+    ("Every port is connected.", "Tous les ports sont connectés."),
+    ("Unused Subsystems", "Sous-systèmes inutilisés"),
+    (
+        "Every subsystem is in use.",
+        "Tous les sous-systèmes sont utilisés.",
+    ),
+    ("No problems found.", "Aucun problème trouvé."),
+    ("{name} (empty)", "{name} (vide)"),
+    ("{name} (not wired)", "{name} (non relié)"),
+    ("Nodes", "Nœuds"),
+    ("Subsystems", "Sous-systèmes"),
+    ("Nesting depth", "Profondeur d'imbrication"),
+    ("Unconnected ports", "Ports non connectés"),
+    (
+        "{inputs} boundary input(s), {outputs} boundary output(s)",
+        "{inputs} entrée(s) de frontière, {outputs} sortie(s) de frontière",
+    ),
+    ("Whole diagram", "Diagramme entier"),
+    ("This subsystem", "Ce sous-système"),
+    (
+        "Counts include nested subsystems, once per instance.",
+        "Les comptes incluent les sous-systèmes imbriqués, une fois par instance.",
+    ),
+    ("Close", "Fermer"),
+    ("Comparison", "Comparaison"),
+    ("Changes since {name}", "Modifications depuis {name}"),
+    ("No differences.", "Aucune différence."),
+    // Simulation
+    ("Simulation", "Simulation"),
+    ("Inputs of the top level", "Entrées du niveau supérieur"),
+    (
+        "The top level has no External output to drive it.",
+        "Le niveau supérieur n'a aucune sortie externe pour le piloter.",
+    ),
+    ("Step", "Pas"),
+    ("Reset", "Réinitialiser"),
+    (
+        "Wires are green while on and gray while off.",
+        "Les fils sont verts à 1 et gris à 0.",
+    ),
+    ("Watch", "Surveillance"),
+    (
+        "Click a wire, or right-click it, to watch it.",
+        "Cliquer sur un fil, ou clic droit, pour le surveiller.",
+    ),
+    (
+        "This subsystem has no boundary ports to test.",
+        "Ce sous-système n'a aucun port de frontière à tester.",
+    ),
+    ("Result", "Résultat"),
+    ("{name} (expected)", "{name} (attendu)"),
+    ("not run", "non exécuté"),
+    (
+        "Click to cycle between don't care, 0 and 1",
+        "Cliquer pour alterner entre indifférent, 0 et 1",
+    ),
+    ("Remove Row", "Supprimer la ligne"),
+    ("Add Row", "Ajouter une ligne"),
+    ("Run All", "Tout exécuter"),
+    // Dialogs
+    ("Unsaved changes", "Modifications non enregistrées"),
+    ("Zoom of the canvas", "Zoom du canevas"),
+    (
+        "Position of the pointer on the canvas, and where it snaps to",
+        "Position du pointeur sur le canevas, et où elle s'aligne",
+    ),
+    (
+        "The diagram has unsaved changes that will be lost.",
+        "Le diagramme a des modifications non enregistrées qui seront perdues.",
+    ),
+    ("Saved {ago}", "Enregistré {ago}"),
+    ("Autosaved {ago}", "Enregistré automatiquement {ago}"),
+    (
+        "Could not autosave: {error}",
+        "Échec de l'enregistrement automatique : {error}",
+    ),
+    ("just now", "à l'instant"),
+    ("{count} s ago", "il y a {count} s"),
+    ("{count} min ago", "il y a {count} min"),
+    ("{count} h ago", "il y a {count} h"),
+    ("Discard Changes", "Abandonner les modifications"),
+    ("Cancel", "Annuler"),
+    ("Delete nodes", "Supprimer des nœuds"),
+    ("Delete", "Supprimer"),
+    (
+        "Delete the {count} selected nodes and their wires?",
+        "Supprimer les {count} nœuds sélectionnés et leurs fils ?",
+    ),
+    ("Remove subsystem node", "Supprimer le nœud de sous-système"),
+    (
+        "\"{name}\" shares its subsystem with {others} other instance(s). Only this instance is removed, the subsystem stays in the others.",
+        "\"{name}\" partage son sous-système avec {others} autre(s) instance(s). Seule cette instance est supprimée, le sous-système reste dans les autres.",
+    ),
+    (
+        "\"{name}\" holds a subsystem of {nodes} node(s), counting the nested ones. They will be lost along with it.",
+        "\"{name}\" contient un sous-système de {nodes} nœud(s), imbriqués compris. Ils seront perdus avec lui.",
+    ),
+    ("Flatten Instead", "Aplatir plutôt"),
+    (
+        "Recover unsaved work",
+        "Récupérer le travail non enregistré",
+    ),
+    (
+        "The editor closed without storing its diagrams. A copy of them was kept.",
+        "L'éditeur s'est fermé sans conserver ses diagrammes. Une copie en a été gardée.",
+    ),
+    (
+        "The recovery file holds no readable diagram",
+        "Le fichier de récupération ne contient aucun diagramme lisible",
+    ),
+    (
+        "{count} diagram(s), copied {ago}",
+        "{count} diagramme(s), copié(s) {ago}",
+    ),
+    ("Discard", "Abandonner"),
+];
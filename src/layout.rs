@@ -0,0 +1,429 @@
+//! Automatic placement of the nodes of a subsystem.
+//!
+//! Layouts only move the nodes of the level they are given, nested subsystems are left as they
+//! are. They need the size of each node, which only the UI knows, and fall back to
+//! `default_size` for the nodes it has not measured.
+
+use std::collections::{HashMap, HashSet};
+
+use egui::{Pos2, Rect, Vec2};
+use egui_snarl::{NodeId, Snarl};
+
+use crate::model::{InputKind, LayoutDirection, Node, OutputKind};
+
+/// Space kept between nodes.
+const MARGIN: f32 = 20.0;
+
+/// Number of simulation steps of [`force_directed`].
+const FORCE_ITERATIONS: usize = 200;
+
+/// Lays out the nodes of `snarl` with a Fruchterman-Reingold simulation: wires pull the nodes
+/// they connect together while all nodes push each other away. The diagram stays centered where
+/// it was.
+pub fn force_directed(snarl: &mut Snarl<Node>, sizes: &HashMap<NodeId, Vec2>, default_size: Vec2) {
+    let nodes = snarl
+        .nodes_pos_ids()
+        .map(|(node_id, pos, _)| {
+            let size = sizes.get(&node_id).copied().unwrap_or(default_size);
+            (node_id, pos + size / 2.0, size)
+        })
+        .collect::<Vec<_>>();
+    if nodes.len() < 2 {
+        return;
+    }
+
+    let index = nodes
+        .iter()
+        .enumerate()
+        .map(|(n, (node_id, _, _))| (*node_id, n))
+        .collect::<HashMap<_, _>>();
+    let springs = snarl
+        .wires()
+        .map(|(pin_out, pin_in)| (index[&pin_out.node], index[&pin_in.node]))
+        .filter(|(a, b)| a != b)
+        .collect::<HashSet<_>>();
+
+    let mut centers = nodes
+        .iter()
+        .map(|(_, center, _)| *center)
+        .collect::<Vec<_>>();
+    let origin = bounding_center(&centers);
+
+    // Ideal distance between connected nodes, from the average node size
+    let average_size = nodes
+        .iter()
+        .fold(Vec2::ZERO, |total, (_, _, size)| total + *size)
+        / nodes.len() as f32;
+    let k = average_size.length() + MARGIN;
+    let mut temperature = k * 2.0;
+    let cooling = temperature / FORCE_ITERATIONS as f32;
+
+    for _ in 0..FORCE_ITERATIONS {
+        let mut displacement = vec![Vec2::ZERO; centers.len()];
+
+        for a in 0..centers.len() {
+            for b in (a + 1)..centers.len() {
+                let (direction, distance) = separation(centers[a], centers[b], a, b);
+                let force = direction * (k * k / distance);
+                displacement[a] += force;
+                displacement[b] -= force;
+            }
+        }
+
+        for &(a, b) in &springs {
+            let (direction, distance) = separation(centers[a], centers[b], a, b);
+            let force = direction * (distance * distance / k);
+            displacement[a] -= force;
+            displacement[b] += force;
+        }
+
+        for (center, displacement) in centers.iter_mut().zip(displacement) {
+            let length = displacement.length();
+            if length > 0.0 {
+                *center += displacement / length * length.min(temperature);
+            }
+        }
+        temperature = (temperature - cooling).max(1.0);
+    }
+
+    let sizes = nodes.iter().map(|(_, _, size)| *size).collect::<Vec<_>>();
+    remove_overlaps(&mut centers, &sizes);
+
+    let shift = origin - bounding_center(&centers);
+    for ((node_id, _, size), center) in nodes.iter().zip(centers) {
+        if let Some(info) = snarl.get_node_info_mut(*node_id) {
+            info.pos = center + shift - *size / 2.0;
+        }
+    }
+}
+
+/// Unit vector from `b` to `a` and the distance between them, never zero so that nodes piled
+/// on top of each other still get pushed apart, each in its own direction.
+fn separation(a: Pos2, b: Pos2, a_index: usize, b_index: usize) -> (Vec2, f32) {
+    let delta = a - b;
+    let distance = delta.length();
+    if distance > 0.01 {
+        (delta / distance, distance)
+    } else {
+        let angle = (a_index * 31 + b_index * 17) as f32;
+        (Vec2::angled(angle), 0.01)
+    }
+}
+
+fn bounding_center(centers: &[Pos2]) -> Pos2 {
+    Rect::from_points(centers).center()
+}
+
+/// Pushes apart the nodes centered on `centers` until none of them overlap, keeping [`MARGIN`]
+/// between them.
+fn remove_overlaps(centers: &mut [Pos2], sizes: &[Vec2]) {
+    for _ in 0..100 {
+        let mut moved = false;
+
+        for a in 0..centers.len() {
+            for b in (a + 1)..centers.len() {
+                let reach = (sizes[a] + sizes[b]) / 2.0 + Vec2::splat(MARGIN);
+                let delta = centers[a] - centers[b];
+                let overlap = reach - delta.abs();
+                if overlap.x <= 0.0 || overlap.y <= 0.0 {
+                    continue;
+                }
+
+                // Move along the axis needing the least correction
+                let push = if overlap.x < overlap.y {
+                    Vec2::new(overlap.x * if delta.x < 0.0 { -0.5 } else { 0.5 }, 0.0)
+                } else {
+                    Vec2::new(0.0, overlap.y * if delta.y < 0.0 { -0.5 } else { 0.5 })
+                };
+                centers[a] += push;
+                centers[b] -= push;
+                moved = true;
+            }
+        }
+
+        if !moved {
+            break;
+        }
+    }
+}
+
+/// Space between the layers of [`layered`].
+const LAYER_GAP: f32 = 80.0;
+
+/// Number of ordering passes of [`layered`], alternating between both directions.
+const ORDERING_SWEEPS: usize = 8;
+
+/// Lays out the nodes of `snarl` in layers following the wires in `direction`, the subsystem
+/// inputs on the first one and its outputs on the last one. Nodes are ordered within their layer
+/// to limit wire crossings. The diagram keeps its top-left corner.
+pub fn layered(
+    snarl: &mut Snarl<Node>,
+    sizes: &HashMap<NodeId, Vec2>,
+    default_size: Vec2,
+    direction: LayoutDirection,
+) {
+    let mut nodes = snarl
+        .nodes_pos_ids()
+        .map(|(node_id, pos, _)| (node_id, pos))
+        .collect::<Vec<_>>();
+    if nodes.is_empty() {
+        return;
+    }
+    // Extent of a node along the flow and across it
+    let horizontal = direction != LayoutDirection::TopToBottom;
+    let axes = |v: Vec2| if horizontal { (v.x, v.y) } else { (v.y, v.x) };
+
+    // Start from the current order across the flow, so nodes without wires keep their place
+    nodes.sort_by(|a, b| axes(a.1.to_vec2()).1.total_cmp(&axes(b.1.to_vec2()).1));
+    let size_of = |node_id: &NodeId| sizes.get(node_id).copied().unwrap_or(default_size);
+    let origin = nodes
+        .iter()
+        .map(|(node_id, pos)| Rect::from_min_size(*pos, size_of(node_id)))
+        .reduce(|a, b| a.union(b))
+        .unwrap()
+        .min;
+
+    let edges = acyclic_edges(snarl, &nodes);
+    let mut layer = longest_path_layers(&nodes, &edges);
+
+    // Pin the boundary of the subsystem to both ends
+    let is_input = |node: &Node| {
+        node.inputs.is_empty()
+            && !node.outputs.is_empty()
+            && node
+                .outputs
+                .iter()
+                .all(|output| output.kind == OutputKind::External)
+    };
+    let is_output = |node: &Node| {
+        node.outputs.is_empty()
+            && !node.inputs.is_empty()
+            && node
+                .inputs
+                .iter()
+                .all(|input| input.kind == InputKind::External)
+    };
+    let last = nodes
+        .iter()
+        .filter(|(node_id, _)| !is_output(&snarl[*node_id]))
+        .map(|(node_id, _)| layer[node_id] + 1)
+        .max()
+        .unwrap_or(0);
+    for (node_id, _) in &nodes {
+        if is_input(&snarl[*node_id]) {
+            layer.insert(*node_id, 0);
+        } else if is_output(&snarl[*node_id]) {
+            layer.insert(*node_id, last);
+        }
+    }
+
+    let layer_count = layer.values().max().unwrap() + 1;
+    let mut layers = vec![Vec::new(); layer_count];
+    for (node_id, _) in &nodes {
+        layers[layer[node_id]].push(*node_id);
+    }
+    order_layers(&mut layers, &edges);
+    if direction == LayoutDirection::RightToLeft {
+        layers.reverse();
+    }
+
+    // Layers one after the other along the flow, each centered across it
+    let mut along = 0.0;
+    let extents = layers
+        .iter()
+        .map(|nodes| {
+            nodes
+                .iter()
+                .map(|node_id| axes(size_of(node_id)).1)
+                .sum::<f32>()
+                + MARGIN * nodes.len().saturating_sub(1) as f32
+        })
+        .collect::<Vec<_>>();
+    let widest = extents.iter().copied().fold(0.0, f32::max);
+
+    for (nodes, extent) in layers.iter().zip(extents) {
+        let mut across = (widest - extent) / 2.0;
+        let mut thickness = 0.0f32;
+        for node_id in nodes {
+            let (size_along, size_across) = axes(size_of(node_id));
+            let offset = if horizontal {
+                Vec2::new(along, across)
+            } else {
+                Vec2::new(across, along)
+            };
+            if let Some(info) = snarl.get_node_info_mut(*node_id) {
+                info.pos = origin + offset;
+            }
+            across += size_across + MARGIN;
+            thickness = thickness.max(size_along);
+        }
+        along += thickness + LAYER_GAP;
+    }
+}
+
+/// The node-to-node connections of `snarl`, leaving out the ones closing a loop.
+fn acyclic_edges(snarl: &Snarl<Node>, nodes: &[(NodeId, Pos2)]) -> Vec<(NodeId, NodeId)> {
+    let mut successors = HashMap::<NodeId, Vec<NodeId>>::new();
+    for (pin_out, pin_in) in snarl.wires() {
+        let next = successors.entry(pin_out.node).or_default();
+        if !next.contains(&pin_in.node) {
+            next.push(pin_in.node);
+        }
+    }
+
+    // Depth-first search, dropping the edges going back to a node still being explored
+    let mut edges = Vec::new();
+    let mut done = HashSet::new();
+    let mut active = HashSet::new();
+    for (root, _) in nodes {
+        if done.contains(root) {
+            continue;
+        }
+        let mut stack = vec![(*root, 0)];
+        active.insert(*root);
+        while let Some((node_id, next)) = stack.pop() {
+            let Some(&successor) = successors.get(&node_id).and_then(|s| s.get(next)) else {
+                active.remove(&node_id);
+                done.insert(node_id);
+                continue;
+            };
+            stack.push((node_id, next + 1));
+
+            if active.contains(&successor) {
+                continue;
+            }
+            edges.push((node_id, successor));
+            if !done.contains(&successor) {
+                active.insert(successor);
+                stack.push((successor, 0));
+            }
+        }
+    }
+    edges
+}
+
+/// Layer of each node: the length of the longest path of `edges` leading to it.
+fn longest_path_layers(
+    nodes: &[(NodeId, Pos2)],
+    edges: &[(NodeId, NodeId)],
+) -> HashMap<NodeId, usize> {
+    let mut incoming = nodes
+        .iter()
+        .map(|(node_id, _)| (*node_id, 0usize))
+        .collect::<HashMap<_, _>>();
+    for (_, to) in edges {
+        *incoming.get_mut(to).unwrap() += 1;
+    }
+
+    let mut layer = nodes
+        .iter()
+        .map(|(node_id, _)| (*node_id, 0))
+        .collect::<HashMap<_, _>>();
+    let mut ready = nodes
+        .iter()
+        .map(|(node_id, _)| *node_id)
+        .filter(|node_id| incoming[node_id] == 0)
+        .collect::<Vec<_>>();
+    while let Some(node_id) = ready.pop() {
+        for (_, to) in edges.iter().filter(|(from, _)| *from == node_id) {
+            let next = layer[&node_id] + 1;
+            let to_layer = layer.get_mut(to).unwrap();
+            *to_layer = (*to_layer).max(next);
+
+            let count = incoming.get_mut(to).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.push(*to);
+            }
+        }
+    }
+    layer
+}
+
+/// Reorders the nodes within each layer by the median position of their neighbors, sweeping
+/// back and forth, and keeps the ordering with the fewest crossings.
+fn order_layers(layers: &mut [Vec<NodeId>], edges: &[(NodeId, NodeId)]) {
+    let mut best = layers.to_vec();
+    let mut best_crossings = crossings(layers, edges);
+
+    for sweep in 0..ORDERING_SWEEPS {
+        let downwards = sweep % 2 == 0;
+        let layer_count = layers.len();
+        let order = (1..layer_count).map(|n| if downwards { n } else { layer_count - 1 - n });
+
+        for n in order {
+            let fixed = if downwards { n - 1 } else { n + 1 };
+            let position = layers[fixed]
+                .iter()
+                .enumerate()
+                .map(|(index, node_id)| (*node_id, index as f32))
+                .collect::<HashMap<_, _>>();
+
+            let layer = &mut layers[n];
+            let keys = layer
+                .iter()
+                .enumerate()
+                .map(|(index, node_id)| {
+                    let mut neighbors = edges
+                        .iter()
+                        .filter_map(|(from, to)| match () {
+                            _ if to == node_id => position.get(from),
+                            _ if from == node_id => position.get(to),
+                            _ => None,
+                        })
+                        .copied()
+                        .collect::<Vec<_>>();
+                    neighbors.sort_by(f32::total_cmp);
+                    let key = match neighbors.len() {
+                        0 => index as f32,
+                        len if len % 2 == 1 => neighbors[len / 2],
+                        len => (neighbors[len / 2 - 1] + neighbors[len / 2]) / 2.0,
+                    };
+                    (*node_id, key)
+                })
+                .collect::<HashMap<_, _>>();
+            layer.sort_by(|a, b| keys[a].total_cmp(&keys[b]));
+        }
+
+        let count = crossings(layers, edges);
+        if count < best_crossings {
+            best_crossings = count;
+            best = layers.to_vec();
+        }
+    }
+
+    layers.clone_from_slice(&best);
+}
+
+/// Number of crossings between the edges joining adjacent layers.
+fn crossings(layers: &[Vec<NodeId>], edges: &[(NodeId, NodeId)]) -> usize {
+    let position = layers
+        .iter()
+        .enumerate()
+        .flat_map(|(layer, nodes)| {
+            nodes
+                .iter()
+                .enumerate()
+                .map(move |(index, node_id)| (*node_id, (layer, index)))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let spans = edges
+        .iter()
+        .filter_map(|(from, to)| {
+            let (from_layer, from_index) = position[from];
+            let (to_layer, to_index) = position[to];
+            (to_layer == from_layer + 1).then_some((from_layer, from_index, to_index))
+        })
+        .collect::<Vec<_>>();
+
+    let mut count = 0;
+    for (n, a) in spans.iter().enumerate() {
+        for b in &spans[n + 1..] {
+            if a.0 == b.0 && (a.1 < b.1) != (a.2 < b.2) && a.1 != b.1 && a.2 != b.2 {
+                count += 1;
+            }
+        }
+    }
+    count
+}
@@ -1,628 +1,4995 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+mod diff;
+mod export;
+mod i18n;
+mod layout;
+mod model;
+mod simulate;
+mod undo;
+mod validate;
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    rc::Rc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use eframe::{App, CreationContext};
 use egui::{Color32, Id, Ui};
 use egui_snarl::{
     InPin, InPinId, NodeId, OutPin, OutPinId, Snarl,
     ui::{
-        NodeLayout, PinInfo, PinPlacement, SnarlStyle, SnarlViewer, SnarlWidget, get_selected_nodes,
+        AnyPins, BackgroundPattern, NodeLayout, PinInfo, PinPlacement, PinShape, SelectionStyle,
+        SnarlStyle, SnarlViewer, SnarlWidget, WireStyle, get_selected_nodes,
     },
 };
+use i18n::{tr, tr_args};
+use model::{
+    DataType, Group, Input, InputKind, LayoutDirection, Node, Output, OutputKind, PortSide,
+    Subsystem, Wire,
+};
+use undo::{Snapshot, UndoStack};
 
-#[derive(Clone, serde::Serialize, serde::Deserialize, Copy, PartialEq, Eq)]
-enum InputKind {
-    Normal,
-    External,
-    Internal,
-}
-
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
-struct Input {
-    name: String,
-    kind: InputKind,
+#[derive(Clone, serde::Serialize, serde::Deserialize, egui_probe::EguiProbe)]
+#[serde(default)]
+struct Preferences {
+    /// Fit the view to the contents of a subsystem when entering it for the first time. It
+    /// comes back with the view it was left with afterwards.
+    recenter_on_enter: bool,
+    /// Deepest subsystem level that can be entered or created, the top level being 0.
+    max_depth: Option<usize>,
+    /// Nodes with more pins than this show a summary instead until expanded.
+    pin_render_limit: usize,
+    /// Number of edits that can be undone.
+    undo_depth: usize,
+    /// Allow wires closing a loop, for diagrams with feedback.
+    allow_cycles: bool,
+    /// When a new wire would carry a port past its most wires, remove its first wire to make
+    /// room instead of refusing the new one.
+    replace_at_limit: bool,
+    /// Align nodes on a grid when they are dropped or added.
+    snap_to_grid: bool,
+    /// Distance between the lines of the grid.
+    grid_spacing: f32,
+    /// Number the nodes in an order they can be evaluated in, outlining the ones stuck in loops.
+    show_evaluation_order: bool,
+    /// How close to a pin a wire must be dropped to land on it, in screen space.
+    pin_snap_radius: f32,
+    palette: Palette,
+    /// Dash the wires by data type, so they differ by more than their color.
+    wire_patterns: bool,
+    /// Write the number of wires next to each pin carrying some.
+    show_wire_counts: bool,
+    /// Sketch the contents of each subsystem node inside it.
+    subsystem_previews: bool,
+    /// Seconds between the copies of the open diagrams kept to recover from a crash, never
+    /// when 0. Only diagrams with unsaved changes are copied.
+    autosave_interval: u32,
+    /// Size of the text and widgets around the canvas, 1 being the native size. Picked in the top
+    /// panel or stepped through with the zoom shortcuts, see [`DiagramApp::apply_ui_scale`].
+    #[egui_probe(skip)]
+    ui_scale: f32,
+    /// Language of the interface, picked in the top panel.
+    #[egui_probe(skip)]
+    language: i18n::Language,
 }
 
-impl Default for Input {
+impl Default for Preferences {
     fn default() -> Self {
         Self {
-            name: "Input".to_string(),
-            kind: InputKind::Normal,
+            recenter_on_enter: true,
+            max_depth: None,
+            pin_render_limit: 256,
+            undo_depth: 64,
+            allow_cycles: false,
+            replace_at_limit: true,
+            snap_to_grid: false,
+            grid_spacing: 20.0,
+            show_evaluation_order: false,
+            pin_snap_radius: 12.0,
+            palette: Palette::Standard,
+            wire_patterns: false,
+            show_wire_counts: false,
+            subsystem_previews: false,
+            autosave_interval: 60,
+            ui_scale: 1.0,
+            language: i18n::Language::English,
         }
     }
 }
 
-#[derive(Clone, serde::Serialize, serde::Deserialize, Copy, PartialEq, Eq)]
-enum OutputKind {
-    Normal,
-    External,
-    Internal,
+#[derive(Clone, Copy)]
+enum ToastKind {
+    Info,
+    Warning,
+    Error,
 }
 
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
-struct Output {
-    name: String,
-    kind: OutputKind,
+/// Short-lived messages shown in the corner of the canvas.
+#[derive(Default)]
+struct Toasts {
+    toasts: Vec<(ToastKind, String, Option<f64>)>,
 }
 
-impl Default for Output {
-    fn default() -> Self {
-        Self {
-            name: "Output".to_string(),
-            kind: OutputKind::Normal,
-        }
+impl Toasts {
+    /// How long a toast stays on screen, in seconds.
+    const DURATION: f64 = 4.0;
+
+    fn push(&mut self, kind: ToastKind, message: impl Into<String>) {
+        self.toasts.push((kind, message.into(), None));
     }
-}
 
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
-struct Node {
-    name: String,
-    next_input_port: usize,
-    next_output_port: usize,
-    inputs: HashMap<usize, Input>,
-    outputs: HashMap<usize, Output>,
-    subsystem: Option<Rc<RefCell<Subsystem>>>,
-}
+    fn show(&mut self, ctx: &egui::Context) {
+        let now = ctx.input(|input| input.time);
+        self.toasts
+            .retain_mut(|(_, _, shown_at)| now - *shown_at.get_or_insert(now) < Self::DURATION);
 
-impl Default for Node {
-    fn default() -> Self {
-        Self {
-            name: "Node".to_string(),
-            next_input_port: 0,
-            next_output_port: 0,
-            inputs: HashMap::default(),
-            outputs: HashMap::default(),
-            subsystem: None,
+        if self.toasts.is_empty() {
+            return;
         }
-    }
-}
-
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
-struct Subsystem {
-    snarl: Snarl<Node>,
-}
 
-impl Default for Subsystem {
-    fn default() -> Self {
-        Self::new()
+        egui::Area::new(Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-16.0, -16.0])
+            .show(ctx, |ui| {
+                for (kind, message, _) in &self.toasts {
+                    let color = match kind {
+                        ToastKind::Info => ui.visuals().text_color(),
+                        ToastKind::Warning => ui.visuals().warn_fg_color,
+                        ToastKind::Error => ui.visuals().error_fg_color,
+                    };
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.colored_label(color, message);
+                    });
+                }
+            });
+        ctx.request_repaint_after(std::time::Duration::from_millis(250));
     }
 }
 
-impl Subsystem {
-    fn new() -> Self {
-        Self {
-            snarl: Snarl::new(),
-        }
-    }
-}
+/// Size of the pins drawn on the edges of nodes.
+const PIN_SIZE: f32 = 7.0;
 
-struct DiagramViewer {
-    toplevel: Rc<RefCell<Subsystem>>,
-    current: Rc<RefCell<Subsystem>>,
-    previous: Vec<Rc<RefCell<Subsystem>>>,
-}
+/// Size assumed for nodes that have not been laid out yet.
+const DEFAULT_NODE_SIZE: egui::Vec2 = egui::vec2(220.0, 60.0);
 
-impl SnarlViewer<Node> for DiagramViewer {
-    fn title(&mut self, node: &Node) -> String {
-        node.name.clone()
-    }
+/// Colors of the wires and pins.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    egui_probe::EguiProbe,
+)]
+enum Palette {
+    #[default]
+    Standard,
+    /// Colors told apart with the common kinds of color blindness, from the Okabe-Ito palette,
+    /// with the pins outlined.
+    ColorblindSafe,
+}
 
-    fn inputs(&mut self, node: &Node) -> usize {
-        node.inputs.len()
+impl Palette {
+    /// Color of the pins and wires carrying `data_type`.
+    fn data_type_color(self, data_type: DataType) -> Color32 {
+        match (self, data_type) {
+            (Palette::Standard, DataType::Bool) => Color32::from_rgb(230, 80, 80),
+            (Palette::Standard, DataType::Int) => Color32::from_rgb(80, 140, 255),
+            (Palette::Standard, DataType::Float) => Color32::from_rgb(80, 200, 120),
+            (Palette::Standard, DataType::Signal) => Color32::from_rgb(200, 120, 255),
+            (Palette::ColorblindSafe, DataType::Bool) => Color32::from_rgb(230, 159, 0),
+            (Palette::ColorblindSafe, DataType::Int) => Color32::from_rgb(86, 180, 233),
+            (Palette::ColorblindSafe, DataType::Float) => Color32::from_rgb(0, 158, 115),
+            (Palette::ColorblindSafe, DataType::Signal) => Color32::from_rgb(240, 228, 66),
+            (_, DataType::Any) => Color32::from_gray(160),
+        }
     }
 
-    fn outputs(&mut self, node: &Node) -> usize {
-        node.outputs.len()
+    /// Fill of the pins standing for a port of the enclosing subsystem.
+    fn external_pin_fill(self) -> Color32 {
+        match self {
+            Palette::Standard => Color32::from_rgb(80, 170, 255),
+            Palette::ColorblindSafe => Color32::from_rgb(0, 114, 178),
+        }
     }
 
-    fn show_input(
-        &mut self,
-        pin: &InPin,
-        ui: &mut Ui,
-        snarl: &mut Snarl<Node>,
-    ) -> impl egui_snarl::ui::SnarlPin + 'static {
-        let node = &mut snarl[pin.id.node];
-        if let Some(input) = node.inputs.get_mut(&pin.id.input) {
-            ui.add_sized([200.0, 20.0], egui::TextEdit::singleline(&mut input.name));
-            PinInfo::square().with_wire_color(Color32::from_rgb(255, 0, 0))
-        } else {
-            PinInfo::star()
+    /// Fill of the pins of a subsystem node bound to its boundary.
+    fn internal_pin_fill(self) -> Color32 {
+        match self {
+            Palette::Standard => Color32::from_rgb(200, 120, 255),
+            Palette::ColorblindSafe => Color32::from_rgb(213, 94, 0),
         }
     }
 
-    fn show_output(
-        &mut self,
-        pin: &OutPin,
-        ui: &mut Ui,
-        snarl: &mut Snarl<Node>,
-    ) -> impl egui_snarl::ui::SnarlPin + 'static {
-        let node = &mut snarl[pin.id.node];
-        if let Some(output) = node.outputs.get_mut(&pin.id.output) {
-            ui.add_sized([200.0, 20.0], egui::TextEdit::singleline(&mut output.name));
-            PinInfo::square().with_wire_color(Color32::from_rgb(0, 0, 255))
-        } else {
-            PinInfo::star()
+    /// Outline of every pin, if any.
+    fn pin_stroke(self) -> Option<egui::Stroke> {
+        match self {
+            Palette::Standard => None,
+            Palette::ColorblindSafe => Some(egui::Stroke::new(1.5, Color32::WHITE)),
         }
     }
+}
 
-    fn show_header(
-        &mut self,
-        node_id: NodeId,
-        _inputs: &[InPin],
-        _outputs: &[OutPin],
-        ui: &mut Ui,
-        snarl: &mut Snarl<Node>,
-    ) {
-        let node = &mut snarl[node_id];
-        ui.add_sized([200.0, 20.0], egui::TextEdit::singleline(&mut node.name));
+/// Dash and gap lengths of the wires carrying `data_type` when wires are patterned, in graph
+/// space. `None` for a solid line.
+fn data_type_dashes(data_type: DataType) -> Option<(f32, f32)> {
+    match data_type {
+        DataType::Bool => None,
+        DataType::Int => Some((10.0, 4.0)),
+        DataType::Float => Some((4.0, 4.0)),
+        DataType::Signal => Some((1.5, 3.5)),
+        DataType::Any => Some((16.0, 4.0)),
     }
+}
 
-    fn drop_inputs(&mut self, pin: &InPin, snarl: &mut Snarl<Node>) {
-        if snarl.drop_inputs(pin.id) == 0
-            && let Some(node) = snarl.get_node_mut(pin.id.node)
-        {
-            node.inputs.remove(&pin.id.input);
-        }
+/// Shapes drawing a wire through `points`, dashed if `dashes` gives the dash and gap lengths.
+fn wire_shapes(
+    points: Vec<egui::Pos2>,
+    stroke: egui::Stroke,
+    dashes: Option<(f32, f32)>,
+) -> Vec<egui::Shape> {
+    match dashes {
+        Some((dash, gap)) => egui::Shape::dashed_line(&points, stroke, dash, gap),
+        None => vec![egui::Shape::line(points, stroke)],
     }
+}
 
-    fn drop_outputs(&mut self, pin: &OutPin, snarl: &mut Snarl<Node>) {
-        if snarl.drop_outputs(pin.id) == 0
-            && let Some(node) = snarl.get_node_mut(pin.id.node)
-        {
-            node.outputs.remove(&pin.id.output);
-        }
-    }
+/// Data type of the output behind `pin`, [`DataType::Any`] if there is none.
+fn output_type(snarl: &Snarl<Node>, pin: OutPinId) -> DataType {
+    snarl
+        .get_node(pin.node)
+        .and_then(|node| node.outputs.get(pin.output))
+        .map_or(DataType::Any, |output| output.data_type)
+}
 
-    fn has_node_menu(&mut self, _node: &Node) -> bool {
-        true
-    }
+/// Combo box picking the data type of a port. Returns `true` if it changed.
+fn data_type_combo(ui: &mut Ui, id_salt: impl std::hash::Hash, data_type: &mut DataType) -> bool {
+    let mut changed = false;
+    egui::ComboBox::from_id_salt(id_salt)
+        .width(60.0)
+        .selected_text(format!("{data_type:?}"))
+        .show_ui(ui, |ui| {
+            for option in DataType::ALL {
+                changed |= ui
+                    .selectable_value(data_type, option, format!("{option:?}"))
+                    .changed();
+            }
+        });
+    changed
+}
 
-    fn show_node_menu(
-        &mut self,
-        node_id: NodeId,
-        _inputs: &[InPin],
-        _outputs: &[OutPin],
-        ui: &mut Ui,
-        snarl: &mut Snarl<Node>,
-    ) {
-        let node = &mut snarl[node_id];
+/// Radius of the handles used to drag waypoints, in screen space.
+const WAYPOINT_RADIUS: f32 = 5.0;
 
-        ui.label("Node menu");
-        ui.separator();
+/// How far along its wire the handle to drag a wire end sits from its pin, in graph space.
+const WIRE_END_OFFSET: f32 = 14.0;
 
-        if ui.button("Add Input").clicked() {
-            node.inputs.insert(node.next_input_port, Input::default());
-            node.next_input_port += 1;
-            ui.close();
-        }
+/// Outline drawn around the node that has the keyboard focus.
+const FOCUS_STROKE: egui::Stroke = egui::Stroke {
+    width: 2.0,
+    color: Color32::from_rgb(255, 200, 0),
+};
 
-        if ui.button("Add Output").clicked() {
-            node.outputs
-                .insert(node.next_output_port, Output::default());
-            node.next_output_port += 1;
-            ui.close();
-        }
+/// How long a node gone to flashes, in seconds.
+const FLASH_DURATION: f32 = 1.0;
 
-        ui.separator();
+/// How long a pin refusing a wire stays red, in seconds.
+const REJECTED_PIN_DURATION: f64 = 0.5;
 
-        if ui.button("Enter Subsystem").clicked() {
-            self.previous.push(self.current.clone());
-            self.current = if let Some(subsystem) = node.subsystem.as_ref() {
-                subsystem.clone()
-            } else {
-                Rc::new(RefCell::new(Subsystem::default()))
-            };
-        }
+/// Opacity of the nodes carrying a hidden tag.
+const DIMMED_OPACITY: f32 = 0.25;
 
-        ui.separator();
-        ui.separator();
+/// How far from the original a duplicated node is placed.
+const DUPLICATE_OFFSET: egui::Vec2 = egui::vec2(40.0, 40.0);
 
-        if ui.button("Remove Node").clicked() {
-            snarl.remove_node(node_id);
-            ui.close();
-        }
-    }
+/// Why the ports of a node whose subsystem is shared with other nodes can't be removed.
+const SHARED_PORT_REASON: &str =
+    "The subsystem is shared with other nodes, remove ports from a deep copy instead";
 
-    fn has_graph_menu(&mut self, _pos: egui::Pos2, _snarl: &mut Snarl<Node>) -> bool {
-        true
-    }
+/// Background of comment nodes.
+const COMMENT_FILL: Color32 = Color32::from_rgb(96, 88, 48);
 
-    fn show_graph_menu(&mut self, pos: egui::Pos2, ui: &mut Ui, snarl: &mut Snarl<Node>) {
-        ui.label("Diagram Menu");
-        ui.separator();
+/// Width of the area along the right edge of nodes that resizes them.
+const RESIZE_HANDLE_WIDTH: f32 = 8.0;
 
-        if ui.button("Add Node").clicked() {
-            snarl.insert_node(pos, Node::default());
-            ui.close();
-        }
+/// Outline drawn around nodes with unconnected ports while connections are checked.
+const UNCONNECTED_STROKE: egui::Stroke = egui::Stroke {
+    width: 2.0,
+    color: Color32::from_rgb(255, 110, 40),
+};
 
-        let selected = get_selected_nodes(Id::new("diagram"), ui.ctx());
+/// Outline drawn around the nodes on a loop while the evaluation order is shown.
+const CYCLE_STROKE: egui::Stroke = egui::Stroke {
+    width: 2.0,
+    color: Color32::from_rgb(230, 60, 60),
+};
 
-        if ui
-            .add_enabled(
-                !selected.is_empty(),
-                egui::Button::new("Convert To Subsystem"),
-            )
-            .clicked()
-        {
-            // Ports that are not connected internally become part of the subsytem ports
-            // and are internally connected to an "external" port.
-            // If they were connected externally, we re-create this connection once again.
-            // If they were unconnected, we leave them unconnected externally.
-
-            let mut subsystem = Subsystem::default();
-
-            // List all the relevant connections
-            let wires = snarl
-                .wires()
-                .filter(|(pin_out, pin_in)| {
-                    selected.contains(&pin_in.node) || selected.contains(&pin_out.node)
-                })
-                .collect::<Vec<_>>();
+/// Color of wires carrying an on signal while simulating.
+const SIGNAL_ON: Color32 = Color32::from_rgb(80, 220, 100);
 
-            let internal_wires = wires
-                .iter()
-                .filter(|(pin_out, pin_in)| {
-                    selected.contains(&pin_in.node) && selected.contains(&pin_out.node)
-                })
-                .collect::<Vec<_>>();
-            let external_inputs = wires
-                .iter()
-                .filter(|(pin_out, pin_in)| {
-                    selected.contains(&pin_in.node) && !selected.contains(&pin_out.node)
-                })
-                .collect::<Vec<_>>();
-            let external_outputs = wires
-                .iter()
-                .filter(|(pin_out, pin_in)| {
-                    !selected.contains(&pin_in.node) && selected.contains(&pin_out.node)
-                })
-                .collect::<Vec<_>>();
+/// Color of wires carrying an off signal while simulating.
+const SIGNAL_OFF: Color32 = Color32::from_gray(110);
 
-            // Create external input nodes internally
-            let external_input_names = external_inputs
-                .iter()
-                .filter_map(|(_, pin_in)| {
-                    snarl[pin_in.node]
-                        .inputs
-                        .get(&pin_in.input)
-                        .map(|n| n.name.clone())
-                })
-                .collect::<Vec<_>>();
+/// Width of a step in the value history of watched wires.
+const SPARKLINE_STEP: f32 = 4.0;
 
-            let external_input_nodes = external_input_names
-                .iter()
-                .map(|name| Output {
-                    name: name.clone(),
-                    kind: OutputKind::External,
-                })
-                .enumerate()
-                .map(|(n, output)| {
-                    subsystem.snarl.insert_node(
-                        [0.0, n as f32 * 50.0].into(),
-                        Node {
-                            name: format!("Ext{}", n + 1),
-                            next_input_port: 0,
-                            next_output_port: 1,
-                            inputs: HashMap::default(),
-                            outputs: HashMap::from_iter([(0, output)]),
-                            subsystem: None,
-                        },
-                    )
-                })
-                .collect::<Vec<_>>();
+/// Outline drawn around highlighted nodes.
+const HIGHLIGHT_STROKE: egui::Stroke = egui::Stroke {
+    width: 2.0,
+    color: Color32::from_rgb(0, 200, 255),
+};
 
-            // Create external output nodes internally
-            let external_output_names = external_outputs
-                .iter()
-                .filter_map(|(pin_out, _)| {
-                    snarl[pin_out.node]
-                        .outputs
-                        .get(&pin_out.output)
-                        .map(|n| n.name.clone())
-                })
-                .collect::<Vec<_>>();
+/// Outline drawn around the selected nodes while the canvas selection is outdated, see
+/// [`DiagramViewer::merge_selection`].
+const SELECTED_STROKE: egui::Stroke = egui::Stroke {
+    width: 2.0,
+    color: Color32::from_rgb(90, 170, 255),
+};
 
-            let external_output_nodes = external_output_names
-                .iter()
-                .map(|name| Input {
-                    name: name.clone(),
-                    kind: InputKind::External,
-                })
-                .enumerate()
-                .map(|(n, input)| {
-                    subsystem.snarl.insert_node(
-                        [100.0, n as f32 * 50.0].into(),
-                        Node {
-                            name: format!("Ext{}", n + 1),
-                            next_input_port: 1,
-                            next_output_port: 0,
-                            inputs: HashMap::from_iter([(0, input)]),
-                            outputs: HashMap::default(),
-                            subsystem: None,
-                        },
-                    )
-                })
-                .collect::<Vec<_>>();
+/// Height of the sketch of the contents of a subsystem node.
+const PREVIEW_HEIGHT: f32 = 60.0;
 
-            // Map the old node IDs to the new ones
-            let mut node_map: HashMap<NodeId, NodeId> = HashMap::default();
-            for node_id in selected {
-                let Some(node) = snarl.get_node_info(node_id) else {
-                    continue;
-                };
-                let new_node_id = subsystem
-                    .snarl
-                    .insert_node(node.pos, snarl.remove_node(node_id));
-                node_map.insert(node_id, new_node_id);
-            }
+/// Height of the row of each pin of a collapsed subsystem node.
+const COLLAPSED_PIN_SPACING: f32 = 8.0;
 
-            // Re-create the internal connections
-            internal_wires
-                .into_iter()
-                .filter_map(|(pin_out, pin_in)| {
-                    Some((
-                        OutPinId {
-                            node: *node_map.get(&pin_out.node)?,
-                            output: pin_out.output,
-                        },
-                        InPinId {
-                            node: *node_map.get(&pin_in.node)?,
-                            input: pin_in.input,
-                        },
-                    ))
-                })
-                .for_each(|(pin_out, pin_in)| {
-                    subsystem.snarl.connect(pin_out, pin_in);
-                });
+/// Color of what was added, when comparing diagrams.
+const ADDED_COLOR: Color32 = Color32::from_rgb(80, 200, 120);
 
-            // Create the external input connections internally
-            external_inputs
-                .iter()
-                .enumerate()
-                .map(|(n, (_, pin_in))| {
-                    (
-                        OutPinId {
-                            node: external_input_nodes[n],
-                            output: 0,
-                        },
-                        InPinId {
-                            node: *node_map
-                                .get(&pin_in.node)
-                                .expect("Old input pin node is mapped to new node"),
-                            input: pin_in.input,
-                        },
-                    )
-                })
-                .for_each(|(pin_out, pin_in)| {
-                    subsystem.snarl.connect(pin_out, pin_in);
-                });
+/// Color of new groups.
+const GROUP_COLOR: Color32 = Color32::from_rgb(90, 140, 200);
 
-            // Create the external output connections internally
-            external_outputs
-                .iter()
-                .enumerate()
-                .map(|(n, (pin_out, _))| {
-                    (
-                        OutPinId {
-                            node: *node_map
-                                .get(&pin_out.node)
-                                .expect("Old output pin node is mapped to new node"),
-                            output: pin_out.output,
-                        },
-                        InPinId {
-                            node: external_output_nodes[n],
-                            input: 0,
-                        },
-                    )
-                })
-                .for_each(|(pin_out, pin_in)| {
-                    subsystem.snarl.connect(pin_out, pin_in);
-                });
+/// Space left between a group and its members.
+const GROUP_MARGIN: f32 = 12.0;
 
-            // Create the external subsystem node
-            let mut new_node = Node {
-                name: "Subsystem".to_string(),
-                inputs: external_input_names
-                    .iter()
-                    .map(|name| Input {
-                        name: name.clone(),
-                        kind: InputKind::Internal,
-                    })
-                    .collect(),
-                outputs: external_output_names
-                    .iter()
-                    .map(|name| Output {
-                        name: name.clone(),
-                        kind: OutputKind::Internal,
-                    })
-                    .collect(),
-                subsystem: None,
-            };
+/// Height of the strip holding the name of a group, dragged to move it.
+const GROUP_HEADER_HEIGHT: f32 = 20.0;
 
-            // Add the unconnected inputs
-            subsystem
-                .snarl
-                .node_ids()
-                .flat_map(|(node_id, node)| {
-                    node.inputs
-                        .iter()
-                        .enumerate()
-                        .filter_map(|(n, input)| {
-                            let pin = subsystem.snarl.in_pin(InPinId {
-                                node: node_id,
-                                input: n,
-                            });
-                            if !pin.remotes.is_empty() {
-                                None
-                            } else {
-                                Some((
-                                    node_id,
-                                    n,
-                                    Input {
-                                        name: input.name.clone(),
-                                        kind: InputKind::Internal,
-                                    },
-                                ))
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .collect::<Vec<_>>()
-                .into_iter()
-                .enumerate()
-                .for_each(|(n, (node_id, port, input))| {
-                    // Create new internal input nodes
-                    let input_node_id = subsystem.snarl.insert_node(
-                        [0.0, n as f32 * -150.0].into(),
-                        Node {
-                            name: format!("ExtUC{}", n + 1),
-                            inputs: Vec::default(),
-                            outputs: vec![Output {
-                                name: input.name.clone(),
-                                kind: OutputKind::External,
-                            }],
-                            subsystem: None,
-                        },
-                    );
+/// Strip at the top of the box `rect` of a group.
+fn group_header(rect: egui::Rect) -> egui::Rect {
+    egui::Rect::from_min_size(rect.min, egui::vec2(rect.width(), GROUP_HEADER_HEIGHT))
+}
 
-                    subsystem.snarl.connect(
-                        OutPinId {
-                            node: input_node_id,
-                            output: 0,
-                        },
-                        InPinId {
-                            node: node_id,
-                            input: port,
-                        },
-                    );
+/// Paints `group` as a translucent box over `rect`, with its name in the header.
+fn paint_group(painter: &egui::Painter, style: &egui::Style, group: &Group, rect: egui::Rect) {
+    painter.rect(
+        rect,
+        4.0,
+        group.color.gamma_multiply(0.12),
+        egui::Stroke::new(1.5, group.color),
+        egui::StrokeKind::Inside,
+    );
+    let header = group_header(rect);
+    painter.rect_filled(header, 4.0, group.color.gamma_multiply(0.5));
+    painter.text(
+        header.left_center() + egui::vec2(6.0, 0.0),
+        egui::Align2::LEFT_CENTER,
+        &group.name,
+        egui::FontId::proportional(12.0),
+        style.visuals.strong_text_color(),
+    );
+}
 
-                    // Add it to the subsystem block
-                    new_node.inputs.push(input);
-                });
+/// Shows the context menu of a pin on its name field, returning whether disconnecting it was
+/// picked. Picking another of the `kinds` of port sets `kind`, and the limit on its wires sets
+/// `max_connections`.
+fn pin_menu<K: Copy + PartialEq + std::fmt::Debug>(
+    response: &egui::Response,
+    connected: bool,
+    kind: &mut K,
+    kinds: &[K],
+    max_connections: &mut Option<usize>,
+) -> bool {
+    let mut disconnect = false;
+    response.context_menu(|ui| {
+        if ui
+            .add_enabled(connected, egui::Button::new(tr("Disconnect")))
+            .clicked()
+        {
+            disconnect = true;
+            ui.close();
+        }
 
-            // Add the unconnected outputs
-            subsystem
-                .snarl
-                .node_ids()
-                .flat_map(|(node_id, node)| {
-                    node.outputs
-                        .iter()
-                        .enumerate()
-                        .filter_map(|(n, output)| {
-                            let pin = subsystem.snarl.out_pin(OutPinId {
-                                node: node_id,
-                                output: n,
-                            });
-                            if !pin.remotes.is_empty() {
-                                None
-                            } else {
-                                Some((
-                                    node_id,
-                                    n,
-                                    Output {
-                                        name: output.name.clone(),
-                                        kind: OutputKind::Internal,
-                                    },
-                                ))
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .collect::<Vec<_>>()
-                .into_iter()
-                .enumerate()
-                .for_each(|(n, (node_id, port, output))| {
-                    // Create new internal output nodes
-                    let output_node_id = subsystem.snarl.insert_node(
-                        [300.0, n as f32 * -150.0].into(),
-                        Node {
-                            name: format!("ExtOutUC{}", n + 1),
-                            inputs: vec![Input {
-                                name: output.name.clone(),
-                                kind: InputKind::External,
-                            }],
-                            outputs: Vec::default(),
-                            subsystem: None,
-                        },
-                    );
+        ui.menu_button(tr("Kind"), |ui| {
+            for &option in kinds {
+                if ui
+                    .selectable_value(kind, option, format!("{option:?}"))
+                    .clicked()
+                {
+                    ui.close();
+                }
+            }
+        });
 
-                    subsystem.snarl.connect(
-                        OutPinId {
-                            node: node_id,
-                            output: port,
-                        },
-                        InPinId {
-                            node: output_node_id,
-                            input: 0,
-                        },
-                    );
+        ui.horizontal(|ui| {
+            let mut limited = max_connections.is_some();
+            if ui.checkbox(&mut limited, tr("Most Wires")).changed() {
+                *max_connections = limited.then_some(1);
+            }
+            if let Some(max) = max_connections.as_mut() {
+                ui.add(egui::DragValue::new(max).range(1..=usize::MAX));
+            }
+        })
+        .response
+        .on_hover_text(tr("Limit the wires this port can carry"));
+    });
+    disconnect
+}
 
-                    // Add it to the subsystem block
-                    new_node.outputs.push(output);
-                });
+/// Pin drawn for a port, a triangle if it is `external`, a circle if it is `internal` and a
+/// square otherwise.
+fn kind_pin(palette: Palette, external: bool, internal: bool) -> PinInfo {
+    let pin = if external {
+        PinInfo::triangle().with_fill(palette.external_pin_fill())
+    } else if internal {
+        PinInfo::circle().with_fill(palette.internal_pin_fill())
+    } else {
+        PinInfo::square()
+    };
+    match palette.pin_stroke() {
+        Some(stroke) => pin.with_stroke(stroke),
+        None => pin,
+    }
+}
 
-            new_node.subsystem = Some(Rc::new(RefCell::new(subsystem)));
-            let new_node_id = snarl.insert_node(pos, new_node);
+/// Draws a pin of `shape` filling `rect`.
+fn paint_pin(painter: &egui::Painter, rect: egui::Rect, shape: PinShape, color: Color32) {
+    match shape {
+        PinShape::Triangle => painter.add(egui::Shape::convex_polygon(
+            vec![rect.left_top(), rect.right_center(), rect.left_bottom()],
+            color,
+            egui::Stroke::NONE,
+        )),
+        PinShape::Circle => painter.circle_filled(rect.center(), rect.width() / 2.0, color),
+        _ => painter.rect_filled(rect.shrink(1.0), 0.0, color),
+    };
+}
 
-            // Connect the previously connected inputs and outputs to the new subsystem node
-            external_inputs
-                .iter()
-                .enumerate()
-                .map(|(n, (pin_out, _))| {
-                    (
-                        pin_out,
-                        InPinId {
-                            node: new_node_id,
-                            input: n,
-                        },
-                    )
-                })
-                .for_each(|(pin_out, pin_in)| {
-                    snarl.connect(*pin_out, pin_in);
-                });
-            external_outputs
-                .iter()
-                .enumerate()
-                .map(|(n, (_, pin_in))| {
-                    (
-                        OutPinId {
-                            node: new_node_id,
-                            output: n,
-                        },
-                        pin_in,
-                    )
-                })
-                .for_each(|(pin_out, pin_in)| {
-                    snarl.connect(pin_out, *pin_in);
-                });
+/// Draws `history` as a line high while on and low while off, the oldest value first.
+fn show_sparkline(ui: &mut Ui, history: &std::collections::VecDeque<bool>) {
+    let size = egui::vec2(simulate::WATCH_HISTORY as f32 * SPARKLINE_STEP, 14.0);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    ui.painter()
+        .rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
 
-            ui.close();
+    let y = |on: bool| {
+        if on {
+            rect.top() + 2.0
+        } else {
+            rect.bottom() - 2.0
         }
+    };
+    let points = history
+        .iter()
+        .enumerate()
+        .flat_map(|(n, on)| {
+            let x = rect.left() + n as f32 * SPARKLINE_STEP;
+            [
+                egui::pos2(x, y(*on)),
+                egui::pos2(x + SPARKLINE_STEP, y(*on)),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let color = match history.back() {
+        Some(true) => SIGNAL_ON,
+        _ => SIGNAL_OFF,
+    };
+    ui.painter()
+        .add(egui::Shape::line(points, egui::Stroke::new(1.5, color)));
+}
 
-        if !self.previous.is_empty() {
-            ui.separator();
-            ui.separator();
-            if ui.button("Go Up One Level").clicked() {
-                if let Some(previous) = self.previous.pop() {
-                    self.current = previous;
-                }
+/// Leaves `pin` invisible, for mirrored nodes whose pins are drawn on the other edge.
+fn hide_pin(pin: PinInfo) -> PinInfo {
+    pin.with_fill(Color32::TRANSPARENT)
+        .with_stroke(egui::Stroke::NONE)
+}
 
-                ui.close();
+/// Explains the colors of [`Palette::data_type_color`], the patterns of [`data_type_dashes`]
+/// if wires are patterned, and the pin shapes of [`kind_pin`].
+fn show_legend(ui: &mut Ui, preferences: &Preferences) {
+    let palette = preferences.palette;
+    ui.label(tr("Wires take the color of the output driving them."));
+    for data_type in DataType::ALL {
+        ui.horizontal(|ui| {
+            let color = palette.data_type_color(data_type);
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(12.0, 12.0), egui::Sense::hover());
+            ui.painter().circle_filled(rect.center(), 5.0, color);
+            if preferences.wire_patterns {
+                let (rect, _) =
+                    ui.allocate_exact_size(egui::vec2(40.0, 12.0), egui::Sense::hover());
+                ui.painter().extend(wire_shapes(
+                    vec![rect.left_center(), rect.right_center()],
+                    egui::Stroke::new(2.0, color),
+                    data_type_dashes(data_type),
+                ));
+            }
+            ui.label(format!("{data_type:?}"));
+        });
+    }
+
+    ui.separator();
+    let entries = [
+        (PinShape::Square, ui.visuals().text_color(), "Normal port"),
+        (
+            PinShape::Triangle,
+            palette.external_pin_fill(),
+            "External: boundary of this subsystem",
+        ),
+        (
+            PinShape::Circle,
+            palette.internal_pin_fill(),
+            "Internal: bound to the subsystem inside",
+        ),
+    ];
+
+    for (shape, color, text) in entries {
+        ui.horizontal(|ui| {
+            let (rect, _) = ui.allocate_exact_size(egui::vec2(12.0, 12.0), egui::Sense::hover());
+            paint_pin(ui.painter(), rect, shape, color);
+            ui.label(tr(text));
+        });
+    }
+}
+
+/// Describes the `index`-th port on `side` of `node`, carrying `wires` wires: its name, its kind
+/// and, on a subsystem node, the boundary port it stands for inside.
+fn pin_tooltip(node: &Node, side: PortSide, index: usize, wires: usize) -> String {
+    let (name, kind, template) = match side {
+        PortSide::Input => {
+            let input = &node.inputs[index];
+            (
+                &input.name,
+                format!("{:?}", input.kind),
+                "{name}\n{kind} input, {wires} wire(s)",
+            )
+        }
+        PortSide::Output => {
+            let output = &node.outputs[index];
+            (
+                &output.name,
+                format!("{:?}", output.kind),
+                "{name}\n{kind} output, {wires} wire(s)",
+            )
+        }
+    };
+    let mut text = tr_args(
+        template,
+        &[("name", name), ("kind", &kind), ("wires", &wires)],
+    );
+
+    if let Some(inner) = node.subsystem.as_ref() {
+        let inner = inner.borrow();
+        let boundary = match side {
+            PortSide::Input => inner.boundary_inputs().get(index).map(|pin| {
+                let node = &inner.snarl[pin.node];
+                (&node.name, &node.outputs[pin.output].name)
+            }),
+            PortSide::Output => inner.boundary_outputs().get(index).map(|pin| {
+                let node = &inner.snarl[pin.node];
+                (&node.name, &node.inputs[pin.input].name)
+            }),
+        };
+        match boundary {
+            Some((node, port)) => {
+                text += "\n";
+                text += &tr_args("Inside: {node} › {port}", &[("node", node), ("port", port)]);
+            }
+            None => {
+                text += "\n";
+                text += tr("No boundary port inside");
             }
         }
     }
+    text
 }
 
-struct DiagramApp {
-    viewer: DiagramViewer,
-    style: SnarlStyle,
+/// Shows `text` when the pin at `pos`, in the space of `ui`, is hovered. Only senses hovering,
+/// so the canvas still gets to drag wires from the pin.
+fn show_pin_tooltip(ui: &Ui, pos: egui::Pos2, id: Id, text: impl FnOnce() -> String) {
+    let rect = egui::Rect::from_center_size(pos, egui::Vec2::splat(PIN_SIZE + 4.0));
+    ui.interact(rect, id, egui::Sense::hover())
+        .on_hover_ui(|ui| {
+            ui.label(text());
+        });
 }
 
-const fn default_style() -> SnarlStyle {
+/// Writes `wires`, the number of wires on the pin at `pos`, just above it and off to the side
+/// the wires go, `direction` along the x axis.
+fn paint_wire_count(ui: &Ui, pos: egui::Pos2, direction: f32, wires: usize) {
+    let align = if direction < 0.0 {
+        egui::Align2::RIGHT_BOTTOM
+    } else {
+        egui::Align2::LEFT_BOTTOM
+    };
+    ui.painter().text(
+        pos + egui::vec2(direction * PIN_SIZE / 2.0, -PIN_SIZE / 2.0),
+        align,
+        wires.to_string(),
+        egui::FontId::proportional(9.0),
+        ui.visuals().weak_text_color(),
+    );
+}
+
+/// Sketches the nodes and wires of `subsystem` as boxes and lines, scaled to fit `width`.
+fn paint_subsystem_preview(ui: &mut Ui, subsystem: &Subsystem, width: f32) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(width, PREVIEW_HEIGHT), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+    let snarl = &subsystem.snarl;
+    let Some(bounds) = snarl
+        .nodes_pos_ids()
+        .map(|(_, pos, _)| egui::Rect::from_min_size(pos, DEFAULT_NODE_SIZE))
+        .reduce(egui::Rect::union)
+    else {
+        return;
+    };
+    let inner = rect.shrink(4.0);
+    let scale = (inner.width() / bounds.width()).min(inner.height() / bounds.height());
+    // Center the sketch in the space left by the scale that fits
+    let offset = inner.center() - bounds.center().to_vec2() * scale;
+    let place = |pos: egui::Pos2| offset + pos.to_vec2() * scale;
+    let node_rect = |node_id: NodeId| {
+        snarl.get_node_info(node_id).map(|info| {
+            egui::Rect::from_min_max(place(info.pos), place(info.pos + DEFAULT_NODE_SIZE))
+        })
+    };
+
+    let stroke = egui::Stroke::new(1.0, ui.visuals().weak_text_color());
+    for (from, to) in snarl.wires() {
+        if let (Some(from), Some(to)) = (node_rect(from.node), node_rect(to.node)) {
+            painter.line_segment([from.right_center(), to.left_center()], stroke);
+        }
+    }
+    for (node_id, node) in snarl.node_ids() {
+        if let Some(node_rect) = node_rect(node_id) {
+            let fill = node
+                .custom_color()
+                .unwrap_or(ui.visuals().widgets.inactive.bg_fill);
+            painter.rect(node_rect, 1.0, fill, stroke, egui::StrokeKind::Inside);
+        }
+    }
+}
+
+/// Lists the differences of a level `depth` levels deep, then the nested levels with theirs.
+fn show_level_diff(ui: &mut Ui, level: &diff::LevelDiff, depth: usize) {
+    egui::CollapsingHeader::new(format!("{} ({})", level.name, level.count()))
+        .id_salt(("level_diff", depth, &level.name))
+        .default_open(true)
+        .show(ui, |ui| {
+            for change in &level.changes {
+                let (sign, color) = match change.kind {
+                    diff::ChangeKind::Added => ("+", ADDED_COLOR),
+                    diff::ChangeKind::Removed => ("−", ui.visuals().error_fg_color),
+                    diff::ChangeKind::Changed => ("~", ui.visuals().warn_fg_color),
+                };
+                ui.colored_label(color, format!("{sign} {}", change.message));
+            }
+            for nested in &level.levels {
+                show_level_diff(ui, nested, depth + 1);
+            }
+        });
+}
+
+/// Outlines the name field of `response` in red when its name is already taken.
+fn mark_duplicate(ui: &Ui, response: &egui::Response, duplicate: bool) {
+    if duplicate {
+        ui.painter().rect_stroke(
+            response.rect,
+            2.0,
+            egui::Stroke::new(1.5, ui.visuals().error_fg_color),
+            egui::StrokeKind::Outside,
+        );
+        response.clone().on_hover_text(tr("Duplicate name"));
+    }
+}
+
+/// Width of the port name fields of a node `width` wide, leaving room for the type.
+fn port_field_width(width: f32) -> f32 {
+    (width - 60.0).max(60.0)
+}
+
+/// Id of the text field editing the name of `node_id`.
+fn node_name_id(node_id: NodeId) -> Id {
+    Id::new(("node_name", node_id))
+}
+
+/// Subsystems leading down to some level, with the name of the node entered from each.
+type Levels = Vec<(String, Rc<RefCell<Subsystem>>)>;
+
+/// Pins a new wire was dragged from before being dropped on the canvas.
+enum DroppedWire {
+    Outputs(Vec<OutPinId>),
+    Inputs(Vec<InPinId>),
+}
+
+struct DiagramViewer {
+    toplevel: Rc<RefCell<Subsystem>>,
+    /// Subsystem being shown. Its snarl is lent to the canvas while it is shown, so the viewer
+    /// callbacks must use the snarl they are given instead.
+    current: Rc<RefCell<Subsystem>>,
+    /// Levels above the current one.
+    previous: Levels,
+    /// Id of the canvas state of the diagram being edited.
+    diagram_id: Id,
+    preferences: Preferences,
+    viewport: egui::Rect,
+    node_rects: HashMap<NodeId, egui::Rect>,
+    /// Nodes drawn with their inputs on the right and outputs on the left.
+    mirrored_nodes: HashSet<NodeId>,
+    fit_pending: bool,
+    /// View to go back to on the next frame.
+    pending_view: Option<egui::emath::TSTransform>,
+    /// Transform from graph space to screen space, as of the last frame.
+    to_global: egui::emath::TSTransform,
+    /// Id of the canvas widget, as of the last frame.
+    canvas_id: Id,
+    /// Graph-space height of the row of each pin, as of the last frame.
+    input_rows: HashMap<InPinId, f32>,
+    output_rows: HashMap<OutPinId, f32>,
+    /// Inputs whose wire is drawn over the canvas, as of the last frame.
+    routed_inputs: HashSet<InPinId>,
+    /// Waypoints added this frame, inserted once the current subsystem is no longer borrowed.
+    new_waypoints: Vec<(Wire, egui::Pos2)>,
+    /// Number of ports added at once from the node menu.
+    port_batch_size: usize,
+    /// Number of inputs and outputs the node keeps when split from the node menu, the rest going
+    /// to the new node.
+    split_at: (usize, usize),
+    /// Wire the two halves of a split node together.
+    split_link: bool,
+    /// Ports to remove from the current subsystem before it is shown again.
+    pending_removals: Vec<(NodeId, PortSide, usize)>,
+    /// Subsystem node of the current subsystem waiting for the user to confirm its removal.
+    subsystem_removal: Option<NodeId>,
+    /// Wire whose label is being edited.
+    renaming_wire: Option<Wire>,
+    /// Wire whose end on the given side is being dragged to another pin.
+    wire_drag: Option<(Wire, PortSide)>,
+    /// Style of the wires the canvas draws, followed by the ones drawn over it.
+    wire_style: WireStyle,
+    /// New wire dropped on the canvas this frame, snapped to a pin once the pins are laid out.
+    dropped_wire: Option<DroppedWire>,
+    focused_node: Option<NodeId>,
+    /// Node just gone to, with the seconds left until it stops flashing.
+    flash: Option<(NodeId, f32)>,
+    /// Input that last refused a wire, along with when it was first shown refusing it.
+    rejected_pin: Option<(InPinId, Option<f64>)>,
+    /// Nodes standing out from the rest until the next click, like freshly pasted ones.
+    highlighted_nodes: HashSet<NodeId>,
+    /// Nodes of the current subsystem the editor acts on as selected. Follows the canvas,
+    /// except for the boxes drawn with Shift or Ctrl, see [`DiagramViewer::merge_selection`].
+    selection: Vec<NodeId>,
+    /// Graph position a box selection started from, while one is drawn.
+    box_origin: Option<egui::Pos2>,
+    /// Nodes of [`DiagramViewer::selection`] outlined by the editor, as the canvas can't show
+    /// them as selected.
+    merged_nodes: HashSet<NodeId>,
+    /// Whether the canvas selects other nodes than [`DiagramViewer::selection`], its frames are
+    /// then hidden.
+    canvas_outdated: bool,
+    /// Tags whose nodes are dimmed.
+    hidden_tags: HashSet<String>,
+    /// Tag being typed in the node menu.
+    new_tag: String,
+    /// Whether the unconnected ports of the current subsystem are listed.
+    checking_connections: bool,
+    /// Subsystem nodes found doing nothing, listed until the list is closed.
+    unused_subsystems: Option<Vec<(model::NodeMatch, model::Unused)>>,
+    /// Position of each node of the current subsystem in its evaluation order, while shown.
+    evaluation_order: HashMap<NodeId, usize>,
+    /// Nodes of the current subsystem on a loop, while the evaluation order is shown.
+    cycle_nodes: HashSet<NodeId>,
+    /// Boolean simulation of the diagram, while its panel is open.
+    simulation: Option<simulate::Simulation>,
+    /// Simulated values of the outputs of the current subsystem.
+    signal_values: HashMap<OutPinId, bool>,
+    /// Whether the test table of the current subsystem is shown.
+    showing_tests: bool,
+    /// Outputs of each row of the test table of the current subsystem, as of its last run.
+    test_results: Vec<Vec<bool>>,
+    /// Whether the problems found in the whole diagram are listed.
+    showing_problems: bool,
+    /// Whether the size figures of the diagram are shown.
+    showing_statistics: bool,
+    /// Whether the selected nodes, or the current subsystem, are shown as a form.
+    showing_properties: bool,
+    /// Whether editing is turned off, for showing finished diagrams. The view can still be
+    /// panned, zoomed and taken into subsystems.
+    presenting: bool,
+    /// Nodes of the current subsystem with unconnected ports, as of the last check.
+    unconnected_nodes: HashSet<NodeId>,
+    /// Nodes moved since the pointer was last released, snapped to the grid once it is.
+    moved_nodes: HashSet<NodeId>,
+    toasts: Toasts,
+    undo: UndoStack,
+    /// Whether the diagram changed since it was last saved or loaded.
+    dirty: bool,
+}
+
+/// Inserts `pos` into the route of a wire from `start` to `end` where it makes the shortest
+/// detour.
+fn insert_waypoint(
+    route: &mut Vec<egui::Pos2>,
+    start: egui::Pos2,
+    end: egui::Pos2,
+    pos: egui::Pos2,
+) {
+    let index = (0..=route.len())
+        .min_by(|&a, &b| {
+            let detour = |n: usize| {
+                let prev = if n == 0 { start } else { route[n - 1] };
+                let next = route.get(n).copied().unwrap_or(end);
+                prev.distance(pos) + pos.distance(next) - prev.distance(next)
+            };
+            detour(a).total_cmp(&detour(b))
+        })
+        .unwrap_or_default();
+    route.insert(index, pos);
+}
+
+enum OutlineClick {
+    /// Go to the level at the path and focus the node.
+    Select(Vec<NodeId>, NodeId),
+    /// Enter the subsystem at the path.
+    Enter(Vec<NodeId>),
+}
+
+/// Shows the nodes of `subsystem`, found at `path`, and the levels below them. `focused` is the
+/// path of the current level and the node focused in it.
+fn show_outline_level(
+    ui: &mut Ui,
+    subsystem: &Subsystem,
+    path: &mut Vec<NodeId>,
+    focused: (&[NodeId], Option<NodeId>),
+    clicked: &mut Option<OutlineClick>,
+) {
+    let mut nodes = subsystem.snarl.node_ids().collect::<Vec<_>>();
+    nodes.sort_by(|(a_id, a), (b_id, b)| a.name.cmp(&b.name).then(a_id.cmp(b_id)));
+
+    for (node_id, node) in nodes {
+        let selected = focused.0 == path.as_slice() && focused.1 == Some(node_id);
+        let Some(inner) = node.subsystem.as_ref() else {
+            if ui.selectable_label(selected, &node.name).clicked() {
+                *clicked = Some(OutlineClick::Select(path.clone(), node_id));
+            }
+            continue;
+        };
+
+        path.push(node_id);
+        let id = ui.make_persistent_id(("outline", path.as_slice()));
+        egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, false)
+            .show_header(ui, |ui| {
+                let response = ui.selectable_label(selected, &node.name);
+                if response.double_clicked() {
+                    *clicked = Some(OutlineClick::Enter(path.clone()));
+                } else if response.clicked() {
+                    *clicked = Some(OutlineClick::Select(
+                        path[..path.len() - 1].to_vec(),
+                        node_id,
+                    ));
+                }
+            })
+            .body(|ui| show_outline_level(ui, &inner.borrow(), path, focused, clicked));
+        path.pop();
+    }
+}
+
+impl DiagramViewer {
+    /// Removes the ports queued for removal during the last frame.
+    fn apply_pending_removals(&mut self) {
+        if self.pending_removals.is_empty() {
+            return;
+        }
+
+        let mut current = self.current.borrow_mut();
+        // Remove the highest indices first so the queued ones stay valid
+        self.pending_removals
+            .sort_by_key(|&(node_id, side, index)| (std::cmp::Reverse(index), node_id, side));
+        self.pending_removals.dedup();
+        let mut refused = false;
+        for (node_id, side, index) in self.pending_removals.drain(..) {
+            if let Some(remap) = model::remove_port(&mut current.snarl, node_id, side, index) {
+                current.rekey_wires(&remap);
+            } else {
+                refused |= current
+                    .snarl
+                    .get_node(node_id)
+                    .is_some_and(Node::shares_subsystem);
+            }
+        }
+        current.prune_wire_data();
+        drop(current);
+
+        if refused {
+            self.toasts.push(ToastKind::Warning, tr(SHARED_PORT_REASON));
+        }
+    }
+
+    /// Forgets where nodes and pins were drawn, when switching to another subsystem.
+    fn clear_layout(&mut self) {
+        self.node_rects.clear();
+        self.mirrored_nodes.clear();
+        self.test_results.clear();
+        self.input_rows.clear();
+        self.output_rows.clear();
+        self.highlighted_nodes.clear();
+        self.moved_nodes.clear();
+        self.selection.clear();
+        self.merged_nodes.clear();
+        self.canvas_outdated = false;
+    }
+
+    /// Graph-space size of the nodes drawn last frame.
+    fn node_sizes(&self) -> HashMap<NodeId, egui::Vec2> {
+        self.node_rects
+            .iter()
+            .map(|(node_id, rect)| (*node_id, rect.size()))
+            .collect()
+    }
+
+    fn in_pin_pos(&self, pin: InPinId) -> Option<egui::Pos2> {
+        let rect = self.node_rects.get(&pin.node)?;
+        let x = if self.mirrored_nodes.contains(&pin.node) {
+            rect.right()
+        } else {
+            rect.left()
+        };
+        Some(egui::pos2(x, *self.input_rows.get(&pin)?))
+    }
+
+    fn out_pin_pos(&self, pin: OutPinId) -> Option<egui::Pos2> {
+        let rect = self.node_rects.get(&pin.node)?;
+        let x = if self.mirrored_nodes.contains(&pin.node) {
+            rect.left()
+        } else {
+            rect.right()
+        };
+        Some(egui::pos2(x, *self.output_rows.get(&pin)?))
+    }
+
+    /// Direction a wire leaves `pin` in, or enters `pin` in for an input, along the x axis.
+    fn wire_direction(&self, node: NodeId) -> f32 {
+        if self.mirrored_nodes.contains(&node) {
+            -1.0
+        } else {
+            1.0
+        }
+    }
+
+    /// Runs `f` on the current subsystem while the canvas is shown, putting back the `snarl`
+    /// lent to it for the duration.
+    fn with_current<R>(&self, snarl: &mut Snarl<Node>, f: impl FnOnce(&mut Subsystem) -> R) -> R {
+        let mut current = self.current.borrow_mut();
+        std::mem::swap(&mut current.snarl, snarl);
+        let result = f(&mut current);
+        std::mem::swap(&mut current.snarl, snarl);
+        result
+    }
+
+    /// Warns when changing the kind of a port of `node` leaves a subsystem node out of step with
+    /// the boundary it stands for. `external` tells whether the port was or became External,
+    /// `internal` whether it became Internal.
+    fn warn_kind_change(&mut self, node: &Node, external: bool, internal: bool) {
+        if external && !self.previous.is_empty() {
+            self.toasts.push(
+                ToastKind::Warning,
+                tr("The boundary of this subsystem changed, its node above no longer matches it"),
+            );
+        }
+        if !internal && node.subsystem.is_some() {
+            self.toasts.push(
+                ToastKind::Warning,
+                tr_args(
+                    "\"{name}\" no longer matches the boundary of its subsystem",
+                    &[("name", &node.name)],
+                ),
+            );
+        } else if internal {
+            self.toasts.push(
+                ToastKind::Warning,
+                tr_args(
+                    "\"{name}\" holds no subsystem to bind this port to",
+                    &[("name", &node.name)],
+                ),
+            );
+        }
+    }
+
+    /// Every subsystem of the diagram while the canvas is shown, the nodes of the current one
+    /// being read from the `snarl` lent to it.
+    fn subsystem_uses(&self, snarl: &Snarl<Node>) -> Vec<model::SubsystemUse> {
+        let toplevel = self.toplevel.borrow();
+        let path = self.previous.iter().map(|(name, _)| name.clone()).collect();
+        model::subsystem_uses(&[(Vec::new(), &toplevel.snarl), (path, snarl)])
+    }
+
+    /// Graph-space box of `group` around its members as drawn last frame, header included.
+    fn group_rect(&self, group: &Group) -> Option<egui::Rect> {
+        let rect = group
+            .members
+            .iter()
+            .filter_map(|node| self.node_rects.get(node).copied())
+            .reduce(egui::Rect::union)?
+            .expand(GROUP_MARGIN);
+        Some(rect.with_min_y(rect.min.y - GROUP_HEADER_HEIGHT))
+    }
+
+    /// Lets the header of each group of the current subsystem be dragged to move its members
+    /// together, and right-clicked to rename, recolor or remove the group.
+    fn show_groups(&mut self, snarl: &mut Snarl<Node>, ctx: &egui::Context) {
+        let current = self.current.clone();
+        // The snarl is lent to the canvas, so the members are checked against it
+        model::prune_groups(&mut current.borrow_mut().groups, snarl);
+        if self.presenting {
+            return;
+        }
+
+        // Edited as a copy, so the current subsystem still has the groups from before the edit
+        // when it is recorded
+        let mut groups = current.borrow().groups.clone();
+        let to_global = self.to_global;
+        let mut removed = None;
+        for (n, group) in groups.iter_mut().enumerate() {
+            let Some(rect) = self.group_rect(group) else {
+                continue;
+            };
+            let header = (to_global * group_header(rect)).intersect(self.viewport);
+            if !header.is_positive() {
+                continue;
+            }
+
+            egui::Area::new(Id::new(("group", n)))
+                .fixed_pos(header.min)
+                .show(ctx, |ui| {
+                    let (_, response) =
+                        ui.allocate_exact_size(header.size(), egui::Sense::click_and_drag());
+                    let response = response.on_hover_cursor(egui::CursorIcon::Grab);
+
+                    if response.drag_started() {
+                        self.checkpoint(snarl);
+                    }
+                    if response.dragged() {
+                        let delta = response.drag_delta() / to_global.scaling;
+                        for &node in &group.members {
+                            if let Some(info) = snarl.get_node_info_mut(node)
+                                && !info.value.locked
+                            {
+                                info.pos += delta;
+                            }
+                        }
+                        self.dirty = true;
+                    }
+
+                    response.context_menu(|ui| {
+                        let name = ui
+                            .horizontal(|ui| {
+                                ui.label(tr("Name"));
+                                ui.text_edit_singleline(&mut group.name)
+                            })
+                            .inner;
+                        let color = ui
+                            .horizontal(|ui| {
+                                ui.label(tr("Color"));
+                                ui.color_edit_button_srgba(&mut group.color)
+                            })
+                            .inner;
+                        if name.changed() || color.changed() {
+                            // Typing the name or dragging through the picker is a single edit
+                            let key = Id::new(("group", n, name.changed()));
+                            if !self.undo.coalesces(key) {
+                                self.checkpoint(snarl);
+                                self.undo.start_coalescing(key);
+                            }
+                            self.dirty = true;
+                        }
+                        if ui.button(tr("Remove Group")).clicked() {
+                            removed = Some(n);
+                            ui.close();
+                        }
+                    });
+                });
+        }
+
+        if let Some(n) = removed {
+            self.checkpoint(snarl);
+            groups.remove(n);
+            self.dirty = true;
+        }
+        current.borrow_mut().groups = groups;
+    }
+
+    /// Draws the wires of the current subsystem that follow waypoints or have a color of their
+    /// own, with a handle to drag each waypoint around. Right-clicking a handle removes its
+    /// waypoint.
+    fn show_wire_overlays(&mut self, ctx: &egui::Context) {
+        let current = self.current.clone();
+        let mut current = current.borrow_mut();
+        current.prune_wire_data();
+
+        if !self.new_waypoints.is_empty() {
+            self.checkpoint_level(&current, &current.snarl);
+        }
+        for (wire, pos) in std::mem::take(&mut self.new_waypoints) {
+            let (Some(start), Some(end)) = (self.out_pin_pos(wire.0), self.in_pin_pos(wire.1))
+            else {
+                continue;
+            };
+            insert_waypoint(current.waypoints.entry(wire).or_default(), start, end, pos);
+            self.dirty = true;
+        }
+
+        let painter = ctx
+            .layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                Id::new("waypoints"),
+            ))
+            .with_clip_rect(self.viewport);
+        let to_global = self.to_global;
+
+        self.routed_inputs.clear();
+        // Edits are applied once the waypoints are no longer borrowed, so they can be recorded
+        let mut started = None;
+        let mut removed = None;
+
+        let current = &mut *current;
+        for (&wire, route) in &mut current.waypoints {
+            let (Some(start), Some(end)) = (self.out_pin_pos(wire.0), self.in_pin_pos(wire.1))
+            else {
+                continue;
+            };
+            self.routed_inputs.insert(wire.1);
+            let color = self.overlay_wire_color(&current.snarl, &current.wire_colors, wire);
+            let stroke = egui::Stroke::new(2.0 * to_global.scaling, color);
+
+            let points = std::iter::once(start)
+                .chain(route.iter().copied())
+                .chain(std::iter::once(end))
+                .map(|pos| to_global * pos)
+                .collect();
+            painter.extend(wire_shapes(
+                points,
+                stroke,
+                self.wire_dashes(&current.snarl, wire.0),
+            ));
+
+            // The handles would let the waypoints be moved
+            if self.presenting {
+                continue;
+            }
+            for (n, waypoint) in route.iter_mut().enumerate() {
+                let center = to_global * *waypoint;
+                egui::Area::new(Id::new(("waypoint", wire, n)))
+                    .order(egui::Order::Foreground)
+                    .fixed_pos(center - egui::Vec2::splat(WAYPOINT_RADIUS))
+                    .show(ctx, |ui| {
+                        let (rect, response) = ui.allocate_exact_size(
+                            egui::Vec2::splat(WAYPOINT_RADIUS * 2.0),
+                            egui::Sense::click_and_drag(),
+                        );
+                        ui.painter().circle(
+                            rect.center(),
+                            WAYPOINT_RADIUS,
+                            color,
+                            egui::Stroke::new(1.0, ui.visuals().strong_text_color()),
+                        );
+
+                        let delta = response.drag_delta() / to_global.scaling;
+                        if response.drag_started() {
+                            started = Some((wire, n, delta));
+                        } else if response.dragged() {
+                            *waypoint += delta;
+                            self.dirty = true;
+                        }
+
+                        response.context_menu(|ui| {
+                            if ui.button(tr("Remove Waypoint")).clicked() {
+                                removed = Some((wire, n));
+                                ui.close();
+                            }
+                        });
+                    });
+            }
+        }
+
+        if started.is_some() || removed.is_some() {
+            self.checkpoint_level(current, &current.snarl);
+            self.dirty = true;
+        }
+        if let Some((wire, n, delta)) = started
+            && let Some(waypoint) = current
+                .waypoints
+                .get_mut(&wire)
+                .and_then(|route| route.get_mut(n))
+        {
+            *waypoint += delta;
+        }
+        if let Some((wire, n)) = removed
+            && let Some(route) = current.waypoints.get_mut(&wire)
+        {
+            route.remove(n);
+            if route.is_empty() {
+                current.waypoints.remove(&wire);
+            }
+        }
+
+        // Colored wires, patterned wires and wires of mirrored nodes without waypoints keep the
+        // usual curve
+        for wire in current.snarl.wires() {
+            if current.waypoints.contains_key(&wire) {
+                continue;
+            }
+            let custom = current.wire_colors.contains_key(&wire);
+            let mirrored = self.mirrored_nodes.contains(&wire.0.node)
+                || self.mirrored_nodes.contains(&wire.1.node);
+            if !custom && !mirrored && !self.preferences.wire_patterns {
+                continue;
+            }
+            let color = self.overlay_wire_color(&current.snarl, &current.wire_colors, wire);
+            let (Some(start), Some(end)) = (self.out_pin_pos(wire.0), self.in_pin_pos(wire.1))
+            else {
+                continue;
+            };
+            self.routed_inputs.insert(wire.1);
+
+            let bend = ((end.x - start.x).abs() / 2.0).max(20.0);
+            let start_bend = egui::vec2(bend * self.wire_direction(wire.0.node), 0.0);
+            let end_bend = egui::vec2(bend * self.wire_direction(wire.1.node), 0.0);
+            let stroke = egui::Stroke::new(2.0 * to_global.scaling, color);
+            let dashes = self.wire_dashes(&current.snarl, wire.0);
+
+            // Follow the style of the wires the canvas draws
+            let points = match self.wire_style {
+                WireStyle::Line => vec![start, end],
+                WireStyle::AxisAligned { .. } => {
+                    let (out, into) = (start + start_bend, end - end_bend);
+                    let middle = (start.y + end.y) / 2.0;
+                    vec![
+                        start,
+                        out,
+                        egui::pos2(out.x, middle),
+                        egui::pos2(into.x, middle),
+                        into,
+                        end,
+                    ]
+                }
+                WireStyle::Bezier3 | WireStyle::Bezier5 => {
+                    let curve = egui::epaint::CubicBezierShape::from_points_stroke(
+                        [start, start + start_bend, end - end_bend, end].map(|pos| to_global * pos),
+                        false,
+                        Color32::TRANSPARENT,
+                        stroke,
+                    );
+                    if dashes.is_none() {
+                        painter.add(curve);
+                    } else {
+                        painter.extend(wire_shapes(curve.flatten(Some(0.5)), stroke, dashes));
+                    }
+                    continue;
+                }
+            };
+            let points = points.into_iter().map(|pos| to_global * pos).collect();
+            painter.extend(wire_shapes(points, stroke, dashes));
+        }
+
+        // The canvas keeps the pins of mirrored nodes where they were, hidden, and these stand in
+        let size = egui::Vec2::splat(PIN_SIZE * to_global.scaling);
+        let normal_fill = ctx.style().visuals.text_color();
+        for &node_id in &self.mirrored_nodes {
+            let Some(node) = current.snarl.get_node(node_id) else {
+                continue;
+            };
+            let inputs = node.inputs.iter().enumerate().map(|(input, port)| {
+                let pos = self.in_pin_pos(InPinId {
+                    node: node_id,
+                    input,
+                });
+                let external = port.kind == InputKind::External;
+                (
+                    pos,
+                    kind_pin(
+                        self.preferences.palette,
+                        external,
+                        port.kind == InputKind::Internal,
+                    ),
+                )
+            });
+            let outputs = node.outputs.iter().enumerate().map(|(output, port)| {
+                let pos = self.out_pin_pos(OutPinId {
+                    node: node_id,
+                    output,
+                });
+                let external = port.kind == OutputKind::External;
+                (
+                    pos,
+                    kind_pin(
+                        self.preferences.palette,
+                        external,
+                        port.kind == OutputKind::Internal,
+                    ),
+                )
+            });
+            for (pos, pin) in inputs.chain(outputs) {
+                let Some(pos) = pos else {
+                    continue;
+                };
+                let rect = egui::Rect::from_center_size(to_global * pos, size);
+                paint_pin(
+                    &painter,
+                    rect,
+                    pin.shape.unwrap_or(PinShape::Square),
+                    pin.fill.unwrap_or(normal_fill),
+                );
+            }
+        }
+    }
+
+    /// While Alt is held, shows a handle near both ends of every wire. Dragging one and dropping
+    /// it on another pin of the same side moves the wire there, dropping it anywhere else leaves
+    /// the wire as it was.
+    fn show_wire_ends(&mut self, ctx: &egui::Context) {
+        if self.presenting || self.wire_drag.is_none() && !ctx.input(|input| input.modifiers.alt) {
+            return;
+        }
+
+        let current = self.current.clone();
+        let mut current = current.borrow_mut();
+        let painter = ctx
+            .layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                Id::new("wire_ends"),
+            ))
+            .with_clip_rect(self.viewport);
+        let to_global = self.to_global;
+
+        let mut dropped = None;
+        for wire in current.snarl.wires().collect::<Vec<_>>() {
+            let (Some(start), Some(end)) = (self.out_pin_pos(wire.0), self.in_pin_pos(wire.1))
+            else {
+                continue;
+            };
+            let color = current.wire_colors.get(&wire).copied().unwrap_or_else(|| {
+                self.preferences
+                    .palette
+                    .data_type_color(output_type(&current.snarl, wire.0))
+            });
+
+            for (side, pin, other) in [
+                (PortSide::Input, end, start),
+                (PortSide::Output, start, end),
+            ] {
+                if self.wire_drag.is_some_and(|drag| drag != (wire, side)) {
+                    continue;
+                }
+
+                let handle = to_global * (pin + (other - pin).normalized() * WIRE_END_OFFSET);
+                egui::Area::new(Id::new(("wire_end", wire, side == PortSide::Input)))
+                    .order(egui::Order::Foreground)
+                    .fixed_pos(handle - egui::Vec2::splat(WAYPOINT_RADIUS))
+                    .show(ctx, |ui| {
+                        let (rect, response) = ui.allocate_exact_size(
+                            egui::Vec2::splat(WAYPOINT_RADIUS * 2.0),
+                            egui::Sense::drag(),
+                        );
+                        ui.painter().circle_stroke(
+                            rect.center(),
+                            WAYPOINT_RADIUS,
+                            egui::Stroke::new(2.0, color),
+                        );
+                        let response = response.on_hover_text(tr("Drag to another pin"));
+
+                        if response.drag_started() {
+                            self.wire_drag = Some((wire, side));
+                        }
+                        if let Some(pointer) = response.interact_pointer_pos() {
+                            painter.line_segment(
+                                [to_global * other, pointer],
+                                egui::Stroke::new(2.0 * to_global.scaling, color),
+                            );
+                        }
+                        if response.drag_stopped() {
+                            dropped = Some((wire, side, response.interact_pointer_pos()));
+                        }
+                    });
+            }
+        }
+
+        let Some((wire, side, pointer)) = dropped else {
+            return;
+        };
+        self.wire_drag = None;
+        let Some(pointer) = pointer else {
+            return;
+        };
+
+        // The closest pin within the snap radius of the pointer
+        let distance = |pos: egui::Pos2| (to_global * pos).distance(pointer);
+        let closest = |candidates: Vec<(f32, Wire)>| {
+            candidates
+                .into_iter()
+                .filter(|(distance, _)| *distance <= self.preferences.pin_snap_radius)
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, new)| new)
+        };
+        let new = match side {
+            PortSide::Input => closest(
+                self.input_rows
+                    .keys()
+                    .filter_map(|&pin| Some((distance(self.in_pin_pos(pin)?), (wire.0, pin))))
+                    .collect(),
+            ),
+            PortSide::Output => closest(
+                self.output_rows
+                    .keys()
+                    .filter_map(|&pin| Some((distance(self.out_pin_pos(pin)?), (pin, wire.1))))
+                    .collect(),
+            ),
+        };
+        let Some(new) = new.filter(|&new| new != wire) else {
+            return;
+        };
+
+        self.checkpoint_level(&current, &current.snarl);
+        if model::reconnect(
+            &mut current,
+            wire,
+            new,
+            self.preferences.allow_cycles,
+            self.preferences.replace_at_limit,
+        ) {
+            self.dirty = true;
+        } else {
+            self.undo.take_undo();
+            self.rejected_pin = Some((new.1, None));
+        }
+    }
+
+    /// Color of `wire` where it is drawn over the canvas, faded if one of its nodes is dimmed.
+    fn overlay_wire_color(
+        &self,
+        snarl: &Snarl<Node>,
+        wire_colors: &HashMap<Wire, Color32>,
+        wire: Wire,
+    ) -> Color32 {
+        let color = self
+            .signal_color(wire.0)
+            .or_else(|| wire_colors.get(&wire).copied())
+            .unwrap_or_else(|| {
+                self.preferences
+                    .palette
+                    .data_type_color(output_type(snarl, wire.0))
+            });
+        if self.is_dimmed(&snarl[wire.0.node]) || self.is_dimmed(&snarl[wire.1.node]) {
+            color.gamma_multiply(DIMMED_OPACITY)
+        } else {
+            color
+        }
+    }
+
+    /// Screen-space dash and gap lengths of the wires from `pin`, if wires are patterned and its
+    /// data type is dashed.
+    fn wire_dashes(&self, snarl: &Snarl<Node>, pin: OutPinId) -> Option<(f32, f32)> {
+        if !self.preferences.wire_patterns {
+            return None;
+        }
+        let (dash, gap) = data_type_dashes(output_type(snarl, pin))?;
+        let scaling = self.to_global.scaling;
+        Some((dash * scaling, gap * scaling))
+    }
+
+    /// Picks the simulated values of the current subsystem, before the canvas draws it.
+    fn update_signal_values(&mut self) {
+        self.signal_values = self
+            .simulation
+            .as_ref()
+            .and_then(|simulation| simulation.values(&self.current_path()))
+            .cloned()
+            .unwrap_or_default();
+    }
+
+    /// Color of the wires from `pin` for the value it carries, while simulating.
+    fn signal_color(&self, pin: OutPinId) -> Option<Color32> {
+        let on = *self.signal_values.get(&pin)?;
+        Some(if on { SIGNAL_ON } else { SIGNAL_OFF })
+    }
+
+    /// Lets the user drive the inputs of the top level and step the simulation.
+    fn show_simulation(&mut self, ui: &mut Ui) {
+        let Some(simulation) = self.simulation.as_mut() else {
+            return;
+        };
+        let toplevel = self.toplevel.borrow();
+
+        ui.label(tr("Inputs of the top level"));
+        let pins = toplevel.boundary_inputs();
+        if pins.is_empty() {
+            ui.weak(tr("The top level has no External output to drive it."));
+        }
+        for pin in pins {
+            let node = &toplevel.snarl[pin.node];
+            let text = format!("{} › {}", node.name, node.outputs[pin.output].name);
+            ui.checkbox(simulation.inputs.entry(pin).or_default(), text);
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button(tr("Step")).clicked() {
+                simulation.step(&toplevel);
+            }
+            if ui.button(tr("Reset")).clicked() {
+                simulation.levels.clear();
+                for watch in &mut simulation.watches {
+                    watch.history.clear();
+                }
+            }
+        });
+        ui.weak(tr("Wires are green while on and gray while off."));
+        drop(toplevel);
+
+        ui.separator();
+        ui.label(tr("Watch"));
+        self.show_watches(ui);
+    }
+
+    /// Lists the watched wires with the values they carried over the last steps, forgetting the
+    /// ones that no longer exist.
+    fn show_watches(&mut self, ui: &mut Ui) {
+        let Some(simulation) = self.simulation.as_ref() else {
+            return;
+        };
+        if simulation.watches.is_empty() {
+            ui.weak(tr("Click a wire, or right-click it, to watch it."));
+        }
+
+        let mut gone = Vec::new();
+        let mut removed = None;
+        for (n, watch) in simulation.watches.iter().enumerate() {
+            let Some((_, level)) = self.resolve_path(&watch.path) else {
+                gone.push(n);
+                continue;
+            };
+            let level = level.borrow();
+            let snarl = &level.snarl;
+            let (pin_out, pin_in) = watch.wire;
+            if !snarl.wires().any(|wire| wire == watch.wire) {
+                gone.push(n);
+                continue;
+            }
+            let (from, to) = (&snarl[pin_out.node], &snarl[pin_in.node]);
+            let output = from
+                .outputs
+                .get(pin_out.output)
+                .map_or("?", |port| &port.name);
+            let input = to.inputs.get(pin_in.input).map_or("?", |port| &port.name);
+            let text = format!("{}.{output} → {}.{input}", from.name, to.name);
+
+            ui.horizontal(|ui| {
+                ui.label(text);
+                if ui
+                    .small_button("✕")
+                    .on_hover_text(tr("Remove from Watch"))
+                    .clicked()
+                {
+                    removed = Some(n);
+                }
+            });
+            show_sparkline(ui, &watch.history);
+        }
+
+        let simulation = self.simulation.as_mut().unwrap();
+        for n in gone.into_iter().chain(removed).rev() {
+            simulation.watches.remove(n);
+        }
+    }
+
+    /// Shows the test table of the current subsystem: a row of boundary input values per test,
+    /// with the outputs expected, the ones found by the last run and whether they match.
+    fn show_test_vectors(&mut self, ui: &mut Ui) {
+        let current = self.current.clone();
+        let mut current = current.borrow_mut();
+        let inputs = current.boundary_inputs();
+        let outputs = current.boundary_outputs();
+        let input_names = inputs
+            .iter()
+            .map(|pin| current.snarl[pin.node].outputs[pin.output].name.clone())
+            .collect::<Vec<_>>();
+        let output_names = outputs
+            .iter()
+            .map(|pin| current.snarl[pin.node].inputs[pin.input].name.clone())
+            .collect::<Vec<_>>();
+        if inputs.is_empty() && outputs.is_empty() {
+            ui.weak(tr("This subsystem has no boundary ports to test."));
+            return;
+        }
+
+        // Kept to be recorded once the table is edited
+        let before = current.test_vectors.clone();
+        let mut edited = false;
+        let mut removed = None;
+        egui::ScrollArea::both().show(ui, |ui| {
+            egui::Grid::new("test_vectors")
+                .striped(true)
+                .show(ui, |ui| {
+                    for name in &input_names {
+                        ui.strong(name);
+                    }
+                    for name in &output_names {
+                        ui.strong(tr_args("{name} (expected)", &[("name", name)]));
+                    }
+                    ui.strong(tr("Result"));
+                    ui.end_row();
+
+                    for (n, vector) in current.test_vectors.iter_mut().enumerate() {
+                        vector.inputs.resize(inputs.len(), false);
+                        vector.expected.resize(outputs.len(), None);
+
+                        for value in &mut vector.inputs {
+                            if ui.button(if *value { "1" } else { "0" }).clicked() {
+                                *value = !*value;
+                                edited = true;
+                            }
+                        }
+                        for expected in &mut vector.expected {
+                            let text = match expected {
+                                None => "–",
+                                Some(false) => "0",
+                                Some(true) => "1",
+                            };
+                            let response = ui
+                                .button(text)
+                                .on_hover_text(tr("Click to cycle between don't care, 0 and 1"));
+                            if response.clicked() {
+                                *expected = match expected {
+                                    None => Some(false),
+                                    Some(false) => Some(true),
+                                    Some(true) => None,
+                                };
+                                edited = true;
+                            }
+                        }
+
+                        ui.horizontal(|ui| {
+                            match self.test_results.get(n) {
+                                Some(found) => {
+                                    let found_text = found
+                                        .iter()
+                                        .map(|on| if *on { "1" } else { "0" })
+                                        .collect::<String>();
+                                    if vector.passes(found) {
+                                        ui.colored_label(SIGNAL_ON, format!("✔ {found_text}"));
+                                    } else {
+                                        ui.colored_label(
+                                            ui.visuals().error_fg_color,
+                                            format!("✘ {found_text}"),
+                                        );
+                                    }
+                                }
+                                None => {
+                                    ui.weak(tr("not run"));
+                                }
+                            }
+                            if ui
+                                .small_button("✕")
+                                .on_hover_text(tr("Remove Row"))
+                                .clicked()
+                            {
+                                removed = Some(n);
+                            }
+                        });
+                        ui.end_row();
+                    }
+                });
+        });
+
+        if let Some(n) = removed {
+            current.test_vectors.remove(n);
+            edited = true;
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button(tr("Add Row")).clicked() {
+                current.test_vectors.push(model::TestVector {
+                    inputs: vec![false; inputs.len()],
+                    expected: vec![None; outputs.len()],
+                });
+                edited = true;
+            }
+            if ui.button(tr("Run All")).clicked() {
+                self.test_results = current
+                    .test_vectors
+                    .iter()
+                    .map(|vector| simulate::run_vector(&current, &vector.inputs))
+                    .collect();
+                let failed = current
+                    .test_vectors
+                    .iter()
+                    .zip(&self.test_results)
+                    .filter(|(vector, found)| !vector.passes(found))
+                    .count();
+                log::info!(
+                    "Ran {} test vector(s), {failed} failed",
+                    current.test_vectors.len()
+                );
+            }
+        });
+
+        if edited {
+            let after = std::mem::replace(&mut current.test_vectors, before);
+            self.checkpoint_level(&current, &current.snarl);
+            current.test_vectors = after;
+            self.test_results.clear();
+            self.dirty = true;
+        }
+    }
+
+    /// Works out the evaluation order of `snarl` if it is shown, before the canvas draws it.
+    fn update_evaluation_order(&mut self, snarl: &Snarl<Node>) {
+        self.evaluation_order.clear();
+        self.cycle_nodes.clear();
+        if !self.preferences.show_evaluation_order {
+            return;
+        }
+
+        let sorted = match model::topo_order(snarl) {
+            Ok(sorted) => sorted,
+            Err(e) => {
+                self.cycle_nodes = e.cycle.into_iter().collect();
+                e.sorted
+            }
+        };
+        self.evaluation_order = sorted
+            .into_iter()
+            .enumerate()
+            .map(|(n, node_id)| (node_id, n + 1))
+            .collect();
+    }
+
+    /// Lists the problems found in the whole diagram, errors first, kept up to date while shown.
+    /// Going to one focuses the node it is about, if any.
+    fn show_problems(&mut self, ui: &mut Ui) {
+        let mut problems = validate::validate(&self.toplevel.borrow());
+        problems.sort_by_key(|problem| std::cmp::Reverse(problem.severity));
+        if problems.is_empty() {
+            ui.label(tr("No problems found."));
+        }
+
+        let mut go_to = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for problem in &problems {
+                let (icon, color) = match problem.severity {
+                    validate::Severity::Error => ("⛔", ui.visuals().error_fg_color),
+                    validate::Severity::Warning => ("⚠", ui.visuals().warn_fg_color),
+                };
+                ui.horizontal(|ui| {
+                    ui.colored_label(color, icon);
+                    ui.label(&problem.message);
+                    if ui.small_button(tr("Go to")).clicked() {
+                        go_to = Some((problem.node_path.clone(), problem.node));
+                    }
+                });
+                let location = std::iter::once(tr("Top"))
+                    .chain(problem.path.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(" › ");
+                ui.label(egui::RichText::new(location).small().weak());
+            }
+        });
+
+        match go_to {
+            Some((path, Some(node_id))) => self.focus_node(&path, node_id),
+            Some((path, None)) => self.navigate_to(&path, None),
+            None => {}
+        }
+    }
+
+    /// Lists the subsystem nodes found doing nothing, each with a button to go to it and one to
+    /// remove it.
+    fn show_unused_subsystems(&mut self, ui: &mut Ui) {
+        let Some(unused) = self.unused_subsystems.as_ref() else {
+            return;
+        };
+        if unused.is_empty() {
+            ui.label(tr("Every subsystem is in use."));
+        }
+
+        let mut go_to = None;
+        let mut remove = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (found, reason) in unused {
+                let location = std::iter::once(tr("Top"))
+                    .chain(found.path_names.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(" › ");
+                let template = match reason {
+                    model::Unused::Empty => "{name} (empty)",
+                    model::Unused::Unwired => "{name} (not wired)",
+                };
+                ui.horizontal(|ui| {
+                    ui.label(tr_args(template, &[("name", &found.name)]));
+                    if ui.small_button(tr("Go to")).clicked() {
+                        go_to = Some(found.clone());
+                    }
+                    if ui.small_button(tr("Delete")).clicked() {
+                        remove = Some(found.clone());
+                    }
+                });
+                ui.label(egui::RichText::new(location).small().weak());
+            }
+        });
+
+        if let Some(found) = go_to {
+            self.focus_node(&found.path, found.node_id);
+        }
+        // Removing goes to the level of the node, so the edit can be seen and undone there
+        if let Some(found) = remove {
+            self.navigate_to(&found.path, None);
+            if self.current_path() == found.path {
+                self.remove_nodes(&[found.node_id]);
+                self.unused_subsystems =
+                    Some(model::find_unused_subsystems(&self.toplevel.borrow()));
+            }
+        }
+    }
+
+    /// Lists the size figures of the current subsystem next to the ones of the whole diagram.
+    fn show_statistics(&self, ui: &mut Ui) {
+        let current = self.current.borrow().statistics();
+        let whole = self.toplevel.borrow().statistics();
+        let rows = [
+            ("Nodes", current.nodes, whole.nodes),
+            ("Wires", current.wires, whole.wires),
+            ("Subsystems", current.subsystems, whole.subsystems),
+            ("Nesting depth", current.depth, whole.depth),
+            (
+                "Unconnected ports",
+                current.unconnected_ports,
+                whole.unconnected_ports,
+            ),
+        ];
+
+        egui::Grid::new("statistics")
+            .num_columns(3)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("");
+                ui.strong(tr("This subsystem"));
+                ui.strong(tr("Whole diagram"));
+                ui.end_row();
+
+                for (label, current, whole) in rows {
+                    ui.label(tr(label));
+                    ui.label(current.to_string());
+                    ui.label(whole.to_string());
+                    ui.end_row();
+                }
+            });
+        ui.weak(tr("Counts include nested subsystems, once per instance."));
+    }
+
+    /// Shows the selected node as a form, a form editing all the selected nodes at once when
+    /// there are several, or the current subsystem when none is.
+    fn show_properties(&mut self, ui: &mut Ui) {
+        let current = self.current.clone();
+        let selected = self
+            .selection
+            .iter()
+            .copied()
+            .filter(|&node_id| current.borrow().snarl.get_node(node_id).is_some())
+            .collect::<Vec<_>>();
+        if selected.is_empty() {
+            self.show_level_properties(ui, &mut current.borrow_mut());
+            return;
+        }
+
+        // Lent the way it is to the canvas, so the edits can record the current subsystem
+        let mut snarl = std::mem::take(&mut current.borrow_mut().snarl);
+        match selected[..] {
+            [node_id] => self.show_node_properties(ui, &mut snarl, node_id),
+            _ => {
+                ui.label(tr_args(
+                    "{count} nodes selected",
+                    &[("count", &selected.len())],
+                ));
+                ui.separator();
+                self.edit_node_color(ui, &mut snarl, &selected);
+                self.edit_node_tags(ui, &mut snarl, &selected);
+                ui.separator();
+                self.edit_node_behavior(ui, &mut snarl, &selected);
+            }
+        }
+        current.borrow_mut().snarl = snarl;
+    }
+
+    fn show_level_properties(&mut self, ui: &mut Ui, subsystem: &mut Subsystem) {
+        let name = self
+            .previous
+            .last()
+            .map_or(tr("Top"), |(name, _)| name.as_str());
+        ui.strong(name);
+        let (nodes, wires) = subsystem.total_counts();
+        ui.label(tr_args(
+            "{nodes} node(s) and {wires} wire(s) inside",
+            &[("nodes", &nodes), ("wires", &wires)],
+        ));
+        ui.label(tr_args(
+            "{inputs} boundary input(s), {outputs} boundary output(s)",
+            &[
+                ("inputs", &subsystem.boundary_inputs().len()),
+                ("outputs", &subsystem.boundary_outputs().len()),
+            ],
+        ));
+        ui.separator();
+
+        let before = subsystem.layout_dir;
+        let mut layout_dir = before;
+        egui::ComboBox::from_label(tr("Layout Direction"))
+            .selected_text(tr(layout_dir.label()))
+            .show_ui(ui, |ui| {
+                for direction in LayoutDirection::ALL {
+                    ui.selectable_value(&mut layout_dir, direction, tr(direction.label()));
+                }
+            });
+        if layout_dir != before {
+            self.checkpoint_level(subsystem, &subsystem.snarl);
+            subsystem.layout_dir = layout_dir;
+            self.dirty = true;
+        }
+    }
+
+    fn show_node_properties(&mut self, ui: &mut Ui, snarl: &mut Snarl<Node>, node_id: NodeId) {
+        if snarl[node_id].is_comment() {
+            let text_id = Id::new(("property_text", node_id));
+            let before = snarl[node_id].text.clone();
+            let response = ui.add(
+                egui::TextEdit::multiline(&mut snarl[node_id].text)
+                    .id(text_id)
+                    .desired_width(f32::INFINITY)
+                    .hint_text(tr("Comment")),
+            );
+            self.record_text_edit(snarl, node_id, text_id, &response, before, |node| {
+                &mut node.text
+            });
+            return;
+        }
+
+        egui::Grid::new("node_properties")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label(tr("Name"));
+                let name_id = Id::new(("property_name", node_id));
+                let before = snarl[node_id].name.clone();
+                let response = ui.add(
+                    egui::TextEdit::singleline(&mut snarl[node_id].name)
+                        .id(name_id)
+                        .desired_width(f32::INFINITY),
+                );
+                self.record_text_edit(snarl, node_id, name_id, &response, before, |node| {
+                    &mut node.name
+                });
+                ui.end_row();
+
+                ui.label(tr("Width"));
+                let mut width = snarl[node_id].width;
+                if ui
+                    .add(egui::DragValue::new(&mut width).range(Node::MIN_WIDTH..=f32::MAX))
+                    .changed()
+                {
+                    // Dragging the width is a single edit
+                    let key = Id::new(("property_width", node_id));
+                    if !self.undo.coalesces(key) {
+                        self.checkpoint(snarl);
+                        self.undo.start_coalescing(key);
+                    }
+                    snarl[node_id].width = width;
+                    self.dirty = true;
+                }
+                ui.end_row();
+            });
+        ui.separator();
+
+        self.edit_node_color(ui, snarl, &[node_id]);
+        self.edit_node_tags(ui, snarl, &[node_id]);
+        ui.separator();
+        self.edit_node_behavior(ui, snarl, &[node_id]);
+        if snarl[node_id].subsystem.is_some() {
+            let mut collapsed = snarl[node_id].collapsed;
+            if ui
+                .checkbox(&mut collapsed, tr("Collapse to the port counts"))
+                .changed()
+            {
+                self.checkpoint(snarl);
+                snarl[node_id].collapsed = collapsed;
+                self.dirty = true;
+            }
+        }
+        ui.separator();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("port_properties")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    for input in 0..snarl[node_id].inputs.len() {
+                        let pin = InPinId {
+                            node: node_id,
+                            input,
+                        };
+                        self.edit_input(ui, snarl, pin);
+                        ui.end_row();
+                    }
+                    for output in 0..snarl[node_id].outputs.len() {
+                        let pin = OutPinId {
+                            node: node_id,
+                            output,
+                        };
+                        self.edit_output(ui, snarl, pin);
+                        ui.end_row();
+                    }
+                });
+        });
+    }
+
+    /// Fields of the input `pin` as a row of the port grid of the properties.
+    fn edit_input(&mut self, ui: &mut Ui, snarl: &mut Snarl<Node>, pin: InPinId) {
+        let name_id = Id::new(("property_input_name", pin));
+        let before = snarl[pin.node].inputs[pin.input].name.clone();
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut snarl[pin.node].inputs[pin.input].name)
+                .id(name_id)
+                .desired_width(120.0),
+        );
+        self.record_text_edit(snarl, pin.node, name_id, &response, before, |node| {
+            &mut node.inputs[pin.input].name
+        });
+        let input = &snarl[pin.node].inputs[pin.input];
+
+        let old_kind = input.kind;
+        let mut kind = old_kind;
+        egui::ComboBox::from_id_salt(("property_input_kind", pin))
+            .width(70.0)
+            .selected_text(tr_args("In: {name}", &[("name", &format!("{kind:?}"))]))
+            .show_ui(ui, |ui| {
+                for option in InputKind::ALL {
+                    ui.selectable_value(&mut kind, option, format!("{option:?}"));
+                }
+            });
+        let mut data_type = input.data_type;
+        if data_type_combo(ui, ("property_input_type", pin), &mut data_type) {
+            self.checkpoint(snarl);
+            snarl[pin.node].inputs[pin.input].data_type = data_type;
+            self.dirty = true;
+        }
+        if kind != old_kind {
+            self.checkpoint(snarl);
+            snarl[pin.node].inputs[pin.input].kind = kind;
+            self.warn_kind_change(
+                &snarl[pin.node],
+                old_kind == InputKind::External || kind == InputKind::External,
+                kind == InputKind::Internal,
+            );
+            self.dirty = true;
+        }
+    }
+
+    /// Fields of the output `pin` as a row of the port grid of the properties.
+    fn edit_output(&mut self, ui: &mut Ui, snarl: &mut Snarl<Node>, pin: OutPinId) {
+        let name_id = Id::new(("property_output_name", pin));
+        let before = snarl[pin.node].outputs[pin.output].name.clone();
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut snarl[pin.node].outputs[pin.output].name)
+                .id(name_id)
+                .desired_width(120.0),
+        );
+        self.record_text_edit(snarl, pin.node, name_id, &response, before, |node| {
+            &mut node.outputs[pin.output].name
+        });
+        let output = &snarl[pin.node].outputs[pin.output];
+
+        let old_kind = output.kind;
+        let mut kind = old_kind;
+        egui::ComboBox::from_id_salt(("property_output_kind", pin))
+            .width(70.0)
+            .selected_text(tr_args("Out: {name}", &[("name", &format!("{kind:?}"))]))
+            .show_ui(ui, |ui| {
+                for option in OutputKind::ALL {
+                    ui.selectable_value(&mut kind, option, format!("{option:?}"));
+                }
+            });
+        let mut data_type = output.data_type;
+        if data_type_combo(ui, ("property_output_type", pin), &mut data_type) {
+            self.checkpoint(snarl);
+            snarl[pin.node].outputs[pin.output].data_type = data_type;
+            self.dirty = true;
+        }
+        if kind != old_kind {
+            self.checkpoint(snarl);
+            snarl[pin.node].outputs[pin.output].kind = kind;
+            self.warn_kind_change(
+                &snarl[pin.node],
+                old_kind == OutputKind::External || kind == OutputKind::External,
+                kind == OutputKind::Internal,
+            );
+            self.dirty = true;
+        }
+    }
+
+    /// Color picker setting the background of the nodes `node_ids`, showing the first one's.
+    fn edit_node_color(&mut self, ui: &mut Ui, snarl: &mut Snarl<Node>, node_ids: &[NodeId]) {
+        ui.horizontal(|ui| {
+            let [r, g, b, a] = snarl[node_ids[0]].color;
+            let mut color = Color32::from_rgba_unmultiplied(r, g, b, a);
+            ui.label(tr("Color"));
+            if egui::color_picker::color_edit_button_srgba(
+                ui,
+                &mut color,
+                egui::color_picker::Alpha::Opaque,
+            )
+            .changed()
+            {
+                // Dragging through the picker is a single edit
+                let key = Id::new(("node_color", node_ids));
+                if !self.undo.coalesces(key) {
+                    self.checkpoint(snarl);
+                    self.undo.start_coalescing(key);
+                }
+                for &node_id in node_ids {
+                    snarl[node_id].color = color.to_srgba_unmultiplied();
+                }
+                self.dirty = true;
+            }
+
+            let custom = node_ids
+                .iter()
+                .any(|&node_id| snarl[node_id].custom_color().is_some());
+            if ui
+                .add_enabled(custom, egui::Button::new(tr("Reset")))
+                .clicked()
+            {
+                self.checkpoint(snarl);
+                for &node_id in node_ids {
+                    snarl[node_id].color = Node::default_color();
+                }
+                self.dirty = true;
+            }
+        });
+    }
+
+    /// Tags of the nodes `node_ids`, any of them has, with a field to tag them all with another.
+    fn edit_node_tags(&mut self, ui: &mut Ui, snarl: &mut Snarl<Node>, node_ids: &[NodeId]) {
+        let mut tags = Vec::<String>::new();
+        for &node_id in node_ids {
+            for tag in &snarl[node_id].tags {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+        }
+
+        ui.label(tr("Tags"));
+        ui.horizontal_wrapped(|ui| {
+            let mut removed = None;
+            for tag in &tags {
+                if ui
+                    .small_button(format!("{tag} ✕"))
+                    .on_hover_text(tr("Remove Tag"))
+                    .clicked()
+                {
+                    removed = Some(tag);
+                }
+            }
+            if let Some(tag) = removed {
+                self.checkpoint(snarl);
+                for &node_id in node_ids {
+                    snarl[node_id].tags.retain(|other| other != tag);
+                }
+                self.dirty = true;
+            }
+        });
+        ui.horizontal(|ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.new_tag)
+                    .hint_text(tr("New tag"))
+                    .desired_width(100.0),
+            );
+            let submitted =
+                response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+            let tag = self.new_tag.trim().to_string();
+            let addable = !tag.is_empty()
+                && node_ids
+                    .iter()
+                    .any(|&node_id| !snarl[node_id].tags.contains(&tag));
+            if (ui
+                .add_enabled(addable, egui::Button::new(tr("Add")))
+                .clicked()
+                || submitted)
+                && addable
+            {
+                self.checkpoint(snarl);
+                for &node_id in node_ids {
+                    if !snarl[node_id].tags.contains(&tag) {
+                        snarl[node_id].tags.push(tag.clone());
+                    }
+                }
+                self.new_tag.clear();
+                self.dirty = true;
+            }
+        });
+    }
+
+    /// Mirroring and locking of the nodes `node_ids`, and what they compute when none holds a
+    /// subsystem, showing the first one's.
+    fn edit_node_behavior(&mut self, ui: &mut Ui, snarl: &mut Snarl<Node>, node_ids: &[NodeId]) {
+        let mut locked = snarl[node_ids[0]].locked;
+        if ui
+            .checkbox(&mut locked, tr("Lock Position"))
+            .on_hover_text(tr("Keep it in place when dragged"))
+            .changed()
+        {
+            self.checkpoint(snarl);
+            for &node_id in node_ids {
+                snarl[node_id].locked = locked;
+            }
+            self.dirty = true;
+        }
+
+        let mut mirrored = snarl[node_ids[0]].mirrored;
+        if ui
+            .checkbox(&mut mirrored, tr("Mirror"))
+            .on_hover_text(tr("Inputs on the right, outputs on the left"))
+            .changed()
+        {
+            self.checkpoint(snarl);
+            for &node_id in node_ids {
+                snarl[node_id].mirrored = mirrored;
+            }
+            self.dirty = true;
+        }
+
+        if node_ids
+            .iter()
+            .all(|&node_id| snarl[node_id].subsystem.is_none())
+        {
+            let old_op = snarl[node_ids[0]].op;
+            let mut op = old_op;
+            egui::ComboBox::from_label(tr("Logic"))
+                .selected_text(tr(op.label()))
+                .show_ui(ui, |ui| {
+                    for choice in model::LogicOp::ALL {
+                        ui.selectable_value(&mut op, choice, tr(choice.label()));
+                    }
+                });
+            if op != old_op {
+                self.checkpoint(snarl);
+                for &node_id in node_ids {
+                    snarl[node_id].op = op;
+                }
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Where to split `node_id` and whether to wire the halves together, see
+    /// [`model::split_node`]. The new node goes to the right of it.
+    fn show_split_menu(&mut self, node_id: NodeId, ui: &mut Ui, snarl: &mut Snarl<Node>) {
+        let node = &snarl[node_id];
+        let (inputs, outputs) = (node.inputs.len(), node.outputs.len());
+        egui::Grid::new("split").num_columns(2).show(ui, |ui| {
+            ui.label(tr("Inputs kept"));
+            ui.add(egui::DragValue::new(&mut self.split_at.0).range(0..=inputs));
+            ui.end_row();
+            ui.label(tr("Outputs kept"));
+            ui.add(egui::DragValue::new(&mut self.split_at.1).range(0..=outputs));
+            ui.end_row();
+        });
+        ui.checkbox(&mut self.split_link, tr("Wire halves together"));
+
+        let (kept_inputs, kept_outputs) = self.split_at;
+        let moves = kept_inputs.min(inputs) < inputs || kept_outputs.min(outputs) < outputs;
+        if ui
+            .add_enabled(moves, egui::Button::new(tr("Split")))
+            .clicked()
+        {
+            self.checkpoint(snarl);
+            let width = self.node_rects.get(&node_id).map_or(0.0, egui::Rect::width);
+            let pos = snarl
+                .get_node_info(node_id)
+                .map_or(egui::Pos2::ZERO, |info| info.pos)
+                + egui::vec2(width + DUPLICATE_OFFSET.x, 0.0);
+            let pos = self.snap_to_grid(pos);
+            if let Some((_, remap)) = model::split_node(
+                snarl,
+                node_id,
+                kept_inputs,
+                kept_outputs,
+                pos,
+                self.split_link,
+            ) {
+                self.current.borrow_mut().rekey_wires(&remap);
+            }
+            self.dirty = true;
+            ui.close();
+        }
+    }
+
+    /// Replaces the whole diagram by `toplevel`, back at the top level.
+    fn load(&mut self, toplevel: Subsystem) {
+        let system = Rc::new(RefCell::new(toplevel));
+        self.toplevel = system.clone();
+        self.current = system;
+        self.previous.clear();
+        self.clear_layout();
+        self.focused_node = None;
+        self.fit_pending = true;
+        self.show_view_of(&self.current.clone());
+        self.undo.clear();
+        self.dirty = false;
+    }
+
+    /// Subsystem nodes leading from the top level to the current subsystem.
+    fn current_path(&self) -> Vec<NodeId> {
+        let levels = self
+            .previous
+            .iter()
+            .map(|(_, level)| level)
+            .chain(std::iter::once(&self.current))
+            .collect::<Vec<_>>();
+        levels
+            .windows(2)
+            .filter_map(|pair| {
+                pair[0]
+                    .borrow()
+                    .snarl
+                    .node_ids()
+                    .find_map(|(node_id, node)| {
+                        node.subsystem
+                            .as_ref()
+                            .is_some_and(|subsystem| Rc::ptr_eq(subsystem, pair[1]))
+                            .then_some(node_id)
+                    })
+            })
+            .collect()
+    }
+
+    /// Subsystem reached by following the subsystem nodes of `path` from the top level, along
+    /// with the levels above it as kept in `previous`.
+    fn resolve_path(&self, path: &[NodeId]) -> Option<(Levels, Rc<RefCell<Subsystem>>)> {
+        let mut levels = Vec::new();
+        let mut level = self.toplevel.clone();
+        for &node_id in path {
+            let (name, inner) = level
+                .borrow()
+                .snarl
+                .get_node(node_id)
+                .and_then(|node| Some((node.name.clone(), node.subsystem.clone()?)))?;
+            levels.push((name, std::mem::replace(&mut level, inner)));
+        }
+        Some((levels, level))
+    }
+
+    /// Makes the subsystem at the end of `path` the current one and focuses `node_id` in it.
+    fn navigate_to(&mut self, path: &[NodeId], node_id: Option<NodeId>) {
+        let Some((levels, level)) = self.resolve_path(path) else {
+            return;
+        };
+        if !Rc::ptr_eq(&level, &self.current) {
+            if !self.check_depth(levels.len()) {
+                return;
+            }
+            self.show_view_of(&level);
+            self.current = level;
+            self.previous = levels;
+            self.clear_layout();
+            log::info!("Went to depth {}", self.previous.len());
+        }
+        self.focused_node = node_id;
+    }
+
+    /// Goes to the level at the end of `path`, centers the view on `node_id` and focuses it,
+    /// flashing its outline so it can be spotted.
+    fn focus_node(&mut self, path: &[NodeId], node_id: NodeId) {
+        self.navigate_to(path, Some(node_id));
+        if self.current_path() != path {
+            return;
+        }
+        let Some(pos) = self
+            .current
+            .borrow()
+            .snarl
+            .get_node_info(node_id)
+            .map(|info| info.pos)
+        else {
+            return;
+        };
+
+        // Keep the zoom the level is shown with
+        let center = self
+            .node_rects
+            .get(&node_id)
+            .map_or(pos + DEFAULT_NODE_SIZE / 2.0, |rect| rect.center());
+        let scaling = self
+            .pending_view
+            .map_or(self.to_global.scaling, |view| view.scaling);
+        self.pending_view = Some(egui::emath::TSTransform::new(
+            self.viewport.center().to_vec2() - center.to_vec2() * scaling,
+            scaling,
+        ));
+        self.fit_pending = false;
+        self.flash = Some((node_id, FLASH_DURATION));
+    }
+
+    /// Counts down the flash of the node last gone to, repainting until it is over.
+    fn decay_flash(&mut self, ctx: &egui::Context) {
+        let Some((_, remaining)) = self.flash.as_mut() else {
+            return;
+        };
+        *remaining -= ctx.input(|input| input.stable_dt);
+        if *remaining > 0.0 {
+            ctx.request_repaint();
+        } else {
+            self.flash = None;
+        }
+    }
+
+    /// Shows every node of the diagram nested under the subsystem node holding it. Clicking a
+    /// node goes to its level and focuses it, double-clicking a subsystem node enters it.
+    fn show_outline(&mut self, ui: &mut Ui) {
+        let current_path = self.current_path();
+        let mut clicked = None;
+        show_outline_level(
+            ui,
+            &self.toplevel.borrow(),
+            &mut Vec::new(),
+            (&current_path, self.focused_node),
+            &mut clicked,
+        );
+
+        match clicked {
+            Some(OutlineClick::Select(path, node_id)) => self.focus_node(&path, node_id),
+            Some(OutlineClick::Enter(path)) => self.navigate_to(&path, None),
+            None => {}
+        }
+    }
+
+    /// Lists the ports of the current subsystem left without a wire, kept up to date while the
+    /// check is on. Clicking one focuses its node.
+    fn show_unconnected_ports(&mut self, ui: &mut Ui) {
+        let mut clicked = None;
+        {
+            let current = self.current.borrow();
+            let snarl = &current.snarl;
+            let ports = model::unconnected_ports(snarl);
+            self.unconnected_nodes = ports.iter().map(|(node_id, _, _)| *node_id).collect();
+
+            if ports.is_empty() {
+                ui.label(tr("Every port is connected."));
+            }
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (node_id, side, index) in ports {
+                    let node = &snarl[node_id];
+                    let (direction, port) = match side {
+                        PortSide::Input => ("input", &node.inputs[index].name),
+                        PortSide::Output => ("output", &node.outputs[index].name),
+                    };
+                    let text = format!("{}: {direction} \"{port}\"", node.name);
+                    let selected = self.focused_node == Some(node_id);
+                    if ui.selectable_label(selected, text).clicked() {
+                        clicked = Some(node_id);
+                    }
+                }
+            });
+        }
+
+        if let Some(node_id) = clicked {
+            self.focus_node(&self.current_path(), node_id);
+        }
+    }
+
+    /// Records the edit of a text field of `node_id`, `field` being the edited text and
+    /// `before` what it held before this frame. Typing is a single edit, so the keystrokes are
+    /// merged until the field loses the focus.
+    fn record_text_edit(
+        &mut self,
+        snarl: &mut Snarl<Node>,
+        node_id: NodeId,
+        key: Id,
+        response: &egui::Response,
+        before: String,
+        field: impl Fn(&mut Node) -> &mut String,
+    ) {
+        if response.changed() {
+            // Record the text from before the first keystroke
+            if !self.undo.coalesces(key) {
+                let text = std::mem::replace(field(&mut snarl[node_id]), before);
+                self.checkpoint(snarl);
+                self.undo.start_coalescing(key);
+                *field(&mut snarl[node_id]) = text;
+            }
+            self.dirty = true;
+        }
+        if response.lost_focus() && self.undo.coalesces(key) {
+            self.undo.end_coalescing();
+        }
+    }
+
+    /// Records the state of the current subsystem, its nodes and wires passed as `snarl` since
+    /// they may be lent for showing, before it is edited.
+    fn checkpoint(&mut self, snarl: &Snarl<Node>) {
+        let current = self.current.clone();
+        self.checkpoint_level(&current.borrow(), snarl);
+    }
+
+    /// [`DiagramViewer::checkpoint`] for callers already borrowing the current subsystem as
+    /// `current`.
+    fn checkpoint_level(&mut self, current: &Subsystem, snarl: &Snarl<Node>) {
+        let snapshot = Snapshot::new(self.current_path(), current, snarl);
+        self.undo.record(snapshot, self.preferences.undo_depth);
+    }
+
+    /// Records the state of the whole diagram before an edit spanning several levels.
+    fn checkpoint_toplevel(&mut self) {
+        let toplevel = self.toplevel.borrow();
+        let snapshot = Snapshot::new(Vec::new(), &toplevel, &toplevel.snarl);
+        drop(toplevel);
+        self.undo.record(snapshot, self.preferences.undo_depth);
+    }
+
+    /// Puts back the subsystem saved in `snapshot` and navigates to it, returning what it
+    /// replaced.
+    fn restore(&mut self, snapshot: Snapshot) -> Option<Snapshot> {
+        let Some((levels, level)) = self.resolve_path(&snapshot.path) else {
+            self.toasts.push(
+                ToastKind::Warning,
+                tr("The subsystem of this change no longer exists"),
+            );
+            return None;
+        };
+
+        let replaced = {
+            let level = level.borrow();
+            Snapshot::new(snapshot.path.clone(), &level, &level.snarl)
+        };
+        // The snapshot holds copies of the subsystems shared with other nodes, share them again
+        let uses = model::subsystem_uses(&[(Vec::new(), &self.toplevel.borrow().snarl)]);
+        {
+            let mut subsystem = level.borrow_mut();
+            *subsystem = snapshot.subsystem();
+            // A snapshot of the top level holds every use of the shared subsystems, so its own
+            // copies are shared, leaving none of the replaced ones behind
+            if snapshot.path.is_empty() {
+                model::relink_instances(&mut subsystem);
+            } else {
+                model::relink_instances_to(&mut subsystem.snarl, &uses);
+            }
+        }
+
+        if !Rc::ptr_eq(&level, &self.current) {
+            self.show_view_of(&level);
+            self.current = level;
+            self.previous = levels;
+            self.clear_layout();
+        }
+        self.focused_node = None;
+        self.dirty = true;
+        Some(replaced)
+    }
+
+    fn undo(&mut self) {
+        if let Some(snapshot) = self.undo.take_undo()
+            && let Some(replaced) = self.restore(snapshot)
+        {
+            self.undo.undone(replaced);
+            log::info!("Undid the last change");
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(snapshot) = self.undo.take_redo()
+            && let Some(replaced) = self.restore(snapshot)
+        {
+            self.undo.redone(replaced);
+            log::info!("Redid the last undone change");
+        }
+    }
+
+    /// Whether `node` has too many pins to render them all.
+    fn pins_collapsed(&self, node: &Node) -> bool {
+        !node.expand_pins
+            && node.inputs.len() + node.outputs.len() > self.preferences.pin_render_limit
+    }
+
+    /// Whether a subsystem `depth` levels below the top level may exist, warning if not.
+    fn check_depth(&mut self, depth: usize) -> bool {
+        match self.preferences.max_depth {
+            Some(max_depth) if depth > max_depth => {
+                self.toasts.push(
+                    ToastKind::Warning,
+                    tr_args(
+                        "Maximum nesting depth of {depth} reached",
+                        &[("depth", &max_depth)],
+                    ),
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Makes the subsystem of `node_id` the current one, creating it if needed.
+    fn enter_subsystem(&mut self, node_id: NodeId, snarl: &mut Snarl<Node>) {
+        if !self.check_depth(self.previous.len() + 1) {
+            return;
+        }
+
+        let node = &mut snarl[node_id];
+        self.previous
+            .push((node.name.clone(), self.current.clone()));
+        // The node owns its subsystem from now on, so the edits made inside it are kept
+        self.dirty |= node.subsystem.is_none();
+        self.current = model::subsystem_of(node);
+        log::info!(
+            "Entered subsystem of node {node_id:?} (depth {})",
+            self.previous.len()
+        );
+        self.clear_layout();
+        self.focused_node = None;
+        self.show_view_of(&self.current.clone());
+    }
+
+    /// Sets the canvas up to show `level` with the view it was left with, or with its contents
+    /// fitted if it was never shown and the preferences ask for it.
+    fn show_view_of(&mut self, level: &Rc<RefCell<Subsystem>>) {
+        match level.borrow().view {
+            Some(view) => {
+                self.pending_view = Some(view);
+                self.fit_pending = false;
+            }
+            None => self.fit_pending |= self.preferences.recenter_on_enter,
+        }
+    }
+
+    /// Goes back up to the level `depth` levels below the top level.
+    fn go_to_depth(&mut self, depth: usize) {
+        if depth >= self.previous.len() {
+            return;
+        }
+
+        self.show_view_of(&self.previous[depth].1.clone());
+        self.current = self.previous[depth].1.clone();
+        self.previous.truncate(depth);
+        self.clear_layout();
+        self.focused_node = None;
+        log::info!("Went up to depth {depth}");
+    }
+
+    /// Goes back to the level above the current one, if any.
+    fn go_up(&mut self) {
+        if let Some(depth) = self.previous.len().checked_sub(1) {
+            self.go_to_depth(depth);
+        }
+    }
+
+    /// Enters the subsystem of the selected node, or goes up one level, on their shortcuts.
+    /// Entering needs a single subsystem node to be selected.
+    fn handle_navigation_keys(&mut self, ctx: &egui::Context) {
+        if self.text_input_focused(ctx) {
+            return;
+        }
+
+        if ctx.input_mut(|input| input.consume_shortcut(&GO_UP_SHORTCUT)) {
+            self.go_up();
+            return;
+        }
+
+        let selected = self.selection.clone();
+        let current = self.current.clone();
+        let mut current = current.borrow_mut();
+        if let [node_id] = selected[..]
+            && current
+                .snarl
+                .get_node(node_id)
+                .is_some_and(|node| node.subsystem.is_some())
+            && ctx.input_mut(|input| input.consume_shortcut(&ENTER_SUBSYSTEM_SHORTCUT))
+        {
+            self.enter_subsystem(node_id, &mut current.snarl);
+        }
+    }
+
+    /// Shows the path from the top level to the current subsystem, each level leading back to
+    /// it when clicked.
+    fn show_breadcrumbs(&mut self, ui: &mut Ui) {
+        let mut clicked = None;
+
+        ui.horizontal(|ui| {
+            let names = std::iter::once(tr("Top"))
+                .chain(self.previous.iter().map(|(name, _)| name.as_str()))
+                .collect::<Vec<_>>();
+            for (depth, name) in names.into_iter().enumerate() {
+                if depth > 0 {
+                    ui.label("›");
+                }
+                let is_current = depth == self.previous.len();
+                if ui.selectable_label(is_current, name).clicked() {
+                    clicked = Some(depth);
+                }
+            }
+        });
+
+        if let Some(depth) = clicked {
+            self.go_to_depth(depth);
+        }
+    }
+
+    /// Keyboard navigation of the canvas once it has the focus: Tab and Shift+Tab cycle through
+    /// the nodes, Enter enters the focused node's subsystem, F2 edits its name and Escape hands
+    /// the focus back to the rest of the interface.
+    fn handle_canvas_keyboard(&mut self, response: &egui::Response, ui: &mut Ui) {
+        self.canvas_id = response.id;
+
+        if response.clicked() {
+            response.request_focus();
+        }
+
+        if !response.has_focus() {
+            return;
+        }
+
+        ui.memory_mut(|memory| {
+            memory.set_focus_lock_filter(
+                response.id,
+                egui::EventFilter {
+                    tab: true,
+                    horizontal_arrows: true,
+                    vertical_arrows: true,
+                    escape: false,
+                },
+            );
+        });
+        ui.painter().rect_stroke(
+            response.rect.shrink(1.0),
+            0.0,
+            ui.visuals().selection.stroke,
+            egui::StrokeKind::Inside,
+        );
+
+        let current = self.current.clone();
+        let mut current = current.borrow_mut();
+        let snarl = &mut current.snarl;
+
+        if self
+            .focused_node
+            .is_some_and(|node_id| snarl.get_node(node_id).is_none())
+        {
+            self.focused_node = None;
+        }
+
+        // Reading order, top to bottom then left to right
+        let mut order = snarl
+            .nodes_pos_ids()
+            .map(|(node_id, pos, _)| (node_id, pos))
+            .collect::<Vec<_>>();
+        order.sort_by(|(_, a), (_, b)| a.y.total_cmp(&b.y).then(a.x.total_cmp(&b.x)));
+        let order = order
+            .into_iter()
+            .map(|(node_id, _)| node_id)
+            .collect::<Vec<_>>();
+
+        let (next, prev, enter, rename, escape) = ui.input_mut(|input| {
+            (
+                input.consume_key(egui::Modifiers::NONE, egui::Key::Tab),
+                input.consume_key(egui::Modifiers::SHIFT, egui::Key::Tab),
+                input.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                input.consume_key(egui::Modifiers::NONE, egui::Key::F2),
+                input.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+
+        if !order.is_empty() && (next || prev) {
+            let position = self
+                .focused_node
+                .and_then(|node_id| order.iter().position(|&n| n == node_id));
+            let index = match (position, next) {
+                (None, true) => 0,
+                (None, false) => order.len() - 1,
+                (Some(i), true) => (i + 1) % order.len(),
+                (Some(i), false) => (i + order.len() - 1) % order.len(),
+            };
+            self.focused_node = Some(order[index]);
+        }
+
+        if let Some(node_id) = self.focused_node {
+            if enter {
+                self.enter_subsystem(node_id, snarl);
+            } else if rename {
+                ui.memory_mut(|memory| memory.request_focus(node_name_id(node_id)));
+            }
+        }
+
+        if escape {
+            response.surrender_focus();
+        }
+    }
+
+    /// Removes `node_ids` from the current subsystem along with their wires.
+    fn remove_nodes(&mut self, node_ids: &[NodeId]) {
+        let current = self.current.clone();
+        let mut current = current.borrow_mut();
+        self.checkpoint_level(&current, &current.snarl);
+        for &node_id in node_ids {
+            if current.snarl.get_node(node_id).is_some() {
+                model::remove_node(&mut current.snarl, node_id);
+            }
+        }
+        current.prune_wire_data();
+
+        if self
+            .focused_node
+            .is_some_and(|node_id| node_ids.contains(&node_id))
+        {
+            self.focused_node = None;
+        }
+        self.dirty = true;
+    }
+
+    /// Asks for confirmation before removing the subsystem node waiting for it, telling how much
+    /// goes with it. Flattening the node instead keeps its contents.
+    fn show_subsystem_removal(&mut self, ctx: &egui::Context) {
+        let Some(node_id) = self.subsystem_removal else {
+            return;
+        };
+        let current = self.current.clone();
+        let Some(node) = current.borrow().snarl.get_node(node_id).cloned() else {
+            self.subsystem_removal = None;
+            return;
+        };
+        let Some(inner) = node.subsystem.as_ref() else {
+            self.subsystem_removal = None;
+            return;
+        };
+
+        let (nodes, _) = inner.borrow().total_counts();
+        let instances = model::subsystem_uses(&[(Vec::new(), &self.toplevel.borrow().snarl)])
+            .iter()
+            .find(|found| Rc::ptr_eq(found.subsystem(), inner))
+            .map_or(1, |found| found.instances);
+
+        let mut close = false;
+        let modal = egui::Modal::new(Id::new("remove_subsystem")).show(ctx, |ui| {
+            ui.heading(tr("Remove subsystem node"));
+            if instances > 1 {
+                ui.label(tr_args(
+                    "\"{name}\" shares its subsystem with {others} other instance(s). Only this \
+                     instance is removed, the subsystem stays in the others.",
+                    &[("name", &node.name), ("others", &(instances - 1))],
+                ));
+            } else {
+                ui.label(tr_args(
+                    "\"{name}\" holds a subsystem of {nodes} node(s), counting the nested ones. \
+                     They will be lost along with it.",
+                    &[("name", &node.name), ("nodes", &nodes)],
+                ));
+            }
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button(tr("Remove")).clicked() {
+                    self.remove_nodes(&[node_id]);
+                    close = true;
+                }
+                if ui
+                    .button(tr("Flatten Instead"))
+                    .on_hover_text(tr("Move its contents to this level in its place"))
+                    .clicked()
+                {
+                    let mut current = current.borrow_mut();
+                    self.checkpoint_level(&current, &current.snarl);
+                    model::flatten(&mut current, node_id);
+                    self.dirty = true;
+                    close = true;
+                }
+                if ui.button(tr("Cancel")).clicked() {
+                    close = true;
+                }
+            });
+        });
+
+        if close || modal.should_close() {
+            self.subsystem_removal = None;
+        }
+    }
+
+    /// Whether `node` carries a tag hidden by the user.
+    fn is_dimmed(&self, node: &Node) -> bool {
+        node.tags.iter().any(|tag| self.hidden_tags.contains(tag))
+    }
+
+    /// Lists the tags used in the current subsystem, each with a checkbox showing or dimming
+    /// the nodes carrying it.
+    fn show_tag_filter(&mut self, ui: &mut Ui) {
+        let mut tags = self
+            .current
+            .borrow()
+            .snarl
+            .nodes()
+            .flat_map(|node| node.tags.iter().cloned())
+            .collect::<Vec<_>>();
+        tags.sort();
+        tags.dedup();
+
+        if tags.is_empty() {
+            ui.weak(tr("No node is tagged here"));
+        }
+        for tag in tags {
+            let mut shown = !self.hidden_tags.contains(&tag);
+            if ui.checkbox(&mut shown, &tag).changed() {
+                if shown {
+                    self.hidden_tags.remove(&tag);
+                } else {
+                    self.hidden_tags.insert(tag);
+                }
+            }
+        }
+    }
+
+    /// `pos` moved to the closest grid intersection, if snapping is enabled.
+    fn snap_to_grid(&self, pos: egui::Pos2) -> egui::Pos2 {
+        if !self.preferences.snap_to_grid {
+            return pos;
+        }
+        let spacing = self.preferences.grid_spacing.max(1.0);
+        egui::pos2(
+            (pos.x / spacing).round() * spacing,
+            (pos.y / spacing).round() * spacing,
+        )
+    }
+
+    /// Brings [`DiagramViewer::selection`] up to date with the canvas, which had `before`
+    /// selected when it was shown.
+    ///
+    /// The canvas can be read but not told what is selected, and replaces its selection with
+    /// each box drawn. A box drawn holding Shift adds its nodes to the selection here instead,
+    /// and one drawn holding Ctrl takes them out, so a selection can be built piece by piece.
+    /// Clicks holding Shift or Ctrl likewise add or take out their node. Once the two
+    /// selections part ways, as after a paste, the canvas frames are hidden and the editor
+    /// outlines its own selection, which [`DiagramViewer::drag_selection`] moves as one.
+    fn merge_selection(
+        &mut self,
+        snarl: &Snarl<Node>,
+        response: &egui::Response,
+        before: &[NodeId],
+        contained: Option<bool>,
+    ) {
+        let ctx = &response.ctx;
+        let canvas = get_selected_nodes(self.diagram_id, ctx);
+        if response.drag_started_by(egui::PointerButton::Primary) {
+            self.box_origin = ctx
+                .input(|input| input.pointer.press_origin())
+                .map(|pos| self.to_global.inverse() * pos);
+        }
+
+        let modifiers = ctx.input(|input| input.modifiers);
+        let end = ctx.input(|input| input.pointer.latest_pos());
+        let boxed = match (self.box_origin, end) {
+            (Some(origin), Some(end)) if response.drag_stopped() => {
+                let area = egui::Rect::from_two_pos(origin, self.to_global.inverse() * end);
+                let inside = |rect: &egui::Rect| {
+                    if contained.unwrap_or(false) {
+                        area.contains_rect(*rect)
+                    } else {
+                        area.intersects(*rect)
+                    }
+                };
+                Some(
+                    self.node_rects
+                        .iter()
+                        .filter(|(_, rect)| inside(rect))
+                        .map(|(node_id, _)| *node_id)
+                        .collect::<HashSet<_>>(),
+                )
+            }
+            _ => None,
+        };
+        if response.drag_stopped() {
+            self.box_origin = None;
+        }
+
+        match boxed {
+            Some(boxed) if modifiers.command => {
+                self.selection.retain(|node_id| !boxed.contains(node_id));
+            }
+            Some(boxed) if modifiers.shift => {
+                let mut boxed = boxed.into_iter().collect::<Vec<_>>();
+                boxed.sort();
+                for node_id in boxed {
+                    if !self.selection.contains(&node_id) {
+                        self.selection.push(node_id);
+                    }
+                }
+            }
+            _ if canvas != before => {
+                if modifiers.shift && before.iter().all(|node_id| canvas.contains(node_id)) {
+                    for &node_id in &canvas {
+                        if !before.contains(&node_id) && !self.selection.contains(&node_id) {
+                            self.selection.push(node_id);
+                        }
+                    }
+                } else if modifiers.command && canvas.iter().all(|node_id| before.contains(node_id))
+                {
+                    self.selection
+                        .retain(|node_id| canvas.contains(node_id) || !before.contains(node_id));
+                } else {
+                    self.selection = canvas.clone();
+                }
+            }
+            _ => {}
+        }
+
+        self.selection
+            .retain(|&node_id| snarl.get_node(node_id).is_some());
+        self.canvas_outdated = canvas.len() != self.selection.len()
+            || canvas
+                .iter()
+                .any(|node_id| !self.selection.contains(node_id));
+        self.merged_nodes = if self.canvas_outdated {
+            self.selection.iter().copied().collect()
+        } else {
+            HashSet::default()
+        };
+    }
+
+    /// Moves the nodes of [`DiagramViewer::selection`] still at their position in `before` by
+    /// as much as a selected node dragged on the canvas, which only moves its own selection.
+    fn drag_selection(&self, snarl: &mut Snarl<Node>, before: &HashMap<NodeId, egui::Pos2>) {
+        let Some(delta) = self.selection.iter().find_map(|node_id| {
+            let pos = snarl.get_node_info(*node_id)?.pos;
+            Some(pos - *before.get(node_id)?).filter(|delta| *delta != egui::Vec2::ZERO)
+        }) else {
+            return;
+        };
+
+        for node_id in &self.selection {
+            if let Some(info) = snarl.get_node_info_mut(*node_id)
+                && before.get(node_id) == Some(&info.pos)
+            {
+                info.pos += delta;
+            }
+        }
+    }
+
+    /// Size of the current subsystem and of the selection, the zoom and the graph position of
+    /// the pointer over the canvas, laid out right to left for the status bar.
+    fn show_canvas_status(&self, ui: &mut Ui) {
+        let current = self.current.borrow();
+        ui.label(tr_args(
+            "{nodes} node(s), {wires} wire(s)",
+            &[
+                ("nodes", &current.snarl.nodes().count()),
+                ("wires", &current.snarl.wires().count()),
+            ],
+        ));
+        ui.separator();
+        let selected = self.selection.len();
+        ui.label(tr_args("{count} selected", &[("count", &selected)]));
+        ui.separator();
+        ui.label(format!("{:.0}%", self.to_global.scaling * 100.0))
+            .on_hover_text(tr("Zoom of the canvas"));
+
+        let Some(pointer) = ui
+            .ctx()
+            .pointer_latest_pos()
+            .filter(|pos| self.viewport.contains(*pos))
+        else {
+            return;
+        };
+        ui.separator();
+        let pos = self.to_global.inverse() * pointer;
+        let text = if self.preferences.snap_to_grid {
+            let snapped = self.snap_to_grid(pos);
+            format!(
+                "{:.0}, {:.0} → {:.0}, {:.0}",
+                pos.x, pos.y, snapped.x, snapped.y
+            )
+        } else {
+            format!("{:.0}, {:.0}", pos.x, pos.y)
+        };
+        ui.monospace(text).on_hover_text(tr(
+            "Position of the pointer on the canvas, and where it snaps to",
+        ));
+    }
+
+    /// Adds a node where empty canvas was double-clicked, with its name ready to be typed.
+    fn add_node_on_double_click(&mut self, response: &egui::Response) {
+        if !response.double_clicked() || self.presenting {
+            return;
+        }
+        let Some(pointer) = response.interact_pointer_pos() else {
+            return;
+        };
+        let pos = self.to_global.inverse() * pointer;
+
+        let current = self.current.clone();
+        let mut current = current.borrow_mut();
+        // Pins stick out of the sides of their node
+        let on_node = self.node_rects.iter().any(|(node_id, rect)| {
+            current.snarl.get_node(*node_id).is_some() && rect.expand(PIN_SIZE).contains(pos)
+        });
+        if on_node {
+            return;
+        }
+
+        self.checkpoint_level(&current, &current.snarl);
+        let snarl = &mut current.snarl;
+        let node = Node {
+            name: model::unique_name("Node", &model::node_names(snarl)),
+            ..Node::default()
+        };
+        let node_id = model::add_node(snarl, self.snap_to_grid(pos), node);
+        self.dirty = true;
+        response
+            .ctx
+            .memory_mut(|memory| memory.request_focus(node_name_id(node_id)));
+    }
+
+    /// Wires `from` to `to`, unless it would close a loop that is not allowed or join
+    /// incompatible types, in which case `to` shows it refused the wire.
+    fn connect_pins(&mut self, snarl: &mut Snarl<Node>, from: OutPinId, to: InPinId) {
+        if self.refuses_wire(snarl, from, to) {
+            return;
+        }
+        self.checkpoint(snarl);
+        self.dirty |= model::connect_within_limit(snarl, from, to, true);
+    }
+
+    /// Whether a wire from `from` to `to` is left out, because it exists or is refused. A
+    /// refused one is shown on `to`.
+    fn refuses_wire(&mut self, snarl: &Snarl<Node>, from: OutPinId, to: InPinId) -> bool {
+        if snarl.in_pin(to).remotes.contains(&from) {
+            return true;
+        }
+
+        if !self.preferences.allow_cycles && model::would_create_cycle(snarl, from, to) {
+            log::info!("Refused wire {from:?} -> {to:?} closing a loop");
+            self.rejected_pin = Some((to, None));
+            return true;
+        }
+
+        if !model::types_compatible(snarl, from, to) {
+            log::info!("Refused wire {from:?} -> {to:?} between incompatible types");
+            self.rejected_pin = Some((to, None));
+            return true;
+        }
+
+        let over_limit = model::wires_over_limit(snarl, from, to);
+        if !over_limit.is_empty() && !self.preferences.replace_at_limit {
+            log::info!("Refused wire {from:?} -> {to:?} past the most wires of a port");
+            self.rejected_pin = Some((to, None));
+            return true;
+        }
+        false
+    }
+
+    /// Whether [`DiagramViewer::connect_pins`] would add a wire from `from` to `to`.
+    fn accepts_wire(&self, snarl: &Snarl<Node>, from: OutPinId, to: InPinId) -> bool {
+        let exists = |node: NodeId| snarl.get_node(node).is_some();
+        exists(from.node)
+            && exists(to.node)
+            && from.output < snarl[from.node].outputs.len()
+            && to.input < snarl[to.node].inputs.len()
+            && !snarl.in_pin(to).remotes.contains(&from)
+            && (self.preferences.allow_cycles || !model::would_create_cycle(snarl, from, to))
+            && model::types_compatible(snarl, from, to)
+            && (self.preferences.replace_at_limit
+                || model::wires_over_limit(snarl, from, to).is_empty())
+    }
+
+    /// Connects a new wire dropped on the canvas to the closest pin accepting it within the snap
+    /// radius of the pointer, now that the pins of this frame are laid out.
+    fn snap_dropped_wire(&mut self, snarl: &mut Snarl<Node>, ctx: &egui::Context) {
+        let Some(dropped) = self.dropped_wire.take() else {
+            return;
+        };
+        let Some(pointer) = ctx.pointer_latest_pos() else {
+            return;
+        };
+        let distance = |pos: egui::Pos2| (self.to_global * pos).distance(pointer);
+
+        // Every pin the wire could land on, with the wires landing there would add
+        let candidates: Vec<(f32, Vec<Wire>)> = match dropped {
+            DroppedWire::Outputs(outputs) => self
+                .input_rows
+                .keys()
+                .filter_map(|&to| {
+                    let pos = self.in_pin_pos(to)?;
+                    Some((
+                        distance(pos),
+                        outputs.iter().map(|&from| (from, to)).collect(),
+                    ))
+                })
+                .collect(),
+            DroppedWire::Inputs(inputs) => self
+                .output_rows
+                .keys()
+                .filter_map(|&from| {
+                    let pos = self.out_pin_pos(from)?;
+                    Some((distance(pos), inputs.iter().map(|&to| (from, to)).collect()))
+                })
+                .collect(),
+        };
+        let closest = candidates
+            .into_iter()
+            .filter(|(distance, wires)| {
+                *distance <= self.preferences.pin_snap_radius
+                    && wires
+                        .iter()
+                        .all(|&(from, to)| self.accepts_wire(snarl, from, to))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+
+        // One undo step for the whole drop
+        if let Some((_, wires)) = closest {
+            self.checkpoint(snarl);
+            for (from, to) in wires {
+                if !self.refuses_wire(snarl, from, to) {
+                    self.dirty |= model::connect_within_limit(snarl, from, to, true);
+                }
+            }
+        }
+    }
+
+    /// Puts the locked nodes of `snarl` dragged away from their position in `before` back, or
+    /// all of them while presenting.
+    fn pin_locked_nodes(&self, snarl: &mut Snarl<Node>, before: &HashMap<NodeId, egui::Pos2>) {
+        for (&node_id, &pos) in before {
+            if let Some(info) = snarl.get_node_info_mut(node_id)
+                && (info.value.locked || self.presenting)
+            {
+                info.pos = pos;
+            }
+        }
+    }
+
+    /// Snaps the nodes of `snarl` that moved from their position in `before` to the grid, once
+    /// they are no longer being dragged.
+    fn snap_moved_nodes(
+        &mut self,
+        snarl: &mut Snarl<Node>,
+        before: &HashMap<NodeId, egui::Pos2>,
+        ctx: &egui::Context,
+    ) {
+        if !self.preferences.snap_to_grid {
+            self.moved_nodes.clear();
+            return;
+        }
+
+        self.moved_nodes.extend(
+            snarl
+                .nodes_pos_ids()
+                .filter(|(node_id, pos, _)| before.get(node_id).is_some_and(|old| old != pos))
+                .map(|(node_id, _, _)| node_id),
+        );
+        // Snapping during the drag would swallow the small moves of the pointer
+        if ctx.input(|input| input.pointer.any_down()) {
+            return;
+        }
+
+        for node_id in std::mem::take(&mut self.moved_nodes) {
+            if let Some(info) = snarl.get_node_info_mut(node_id) {
+                info.pos = self.snap_to_grid(info.pos);
+            }
+        }
+    }
+
+    /// Bounding box of all the nodes of `snarl`, in graph space.
+    fn bounding_box(&self, snarl: &Snarl<Node>) -> Option<egui::Rect> {
+        snarl
+            .nodes_pos_ids()
+            .map(|(node_id, pos, _)| {
+                self.node_rects
+                    .get(&node_id)
+                    .copied()
+                    .unwrap_or_else(|| egui::Rect::from_min_size(pos, DEFAULT_NODE_SIZE))
+            })
+            .reduce(|a, b| a.union(b))
+    }
+
+    /// Whether some widget other than the canvas has the keyboard focus, such as a text field.
+    fn text_input_focused(&self, ctx: &egui::Context) -> bool {
+        ctx.memory(|memory| memory.focused())
+            .is_some_and(|id| id != self.canvas_id)
+    }
+
+    /// Graph position under the pointer, or the center of the view if it is elsewhere.
+    fn pointer_graph_pos(&self, ctx: &egui::Context) -> egui::Pos2 {
+        let pos = ctx
+            .pointer_latest_pos()
+            .filter(|pos| self.viewport.contains(*pos))
+            .unwrap_or_else(|| self.viewport.center());
+        self.to_global.inverse() * pos
+    }
+
+    /// Transform that fits `bbox` into the viewport, never zooming in past 1:1.
+    fn fit_transform(&self, bbox: egui::Rect) -> egui::emath::TSTransform {
+        let margin = 40.0;
+        let available =
+            (self.viewport.size() - egui::vec2(margin, margin) * 2.0).max(egui::Vec2::splat(1.0));
+        let scaling = (available.x / bbox.width().max(1.0))
+            .min(available.y / bbox.height().max(1.0))
+            .min(1.0);
+        egui::emath::TSTransform::new(
+            self.viewport.center().to_vec2() - bbox.center().to_vec2() * scaling,
+            scaling,
+        )
+    }
+}
+
+impl SnarlViewer<Node> for DiagramViewer {
+    fn title(&mut self, node: &Node) -> String {
+        node.name.clone()
+    }
+
+    fn draw_background(
+        &mut self,
+        background: Option<&BackgroundPattern>,
+        viewport: &egui::Rect,
+        snarl_style: &SnarlStyle,
+        style: &egui::Style,
+        painter: &egui::Painter,
+        _snarl: &Snarl<Node>,
+    ) {
+        if let Some(background) = background {
+            background.draw(viewport, snarl_style, style, painter);
+        }
+        // Groups sit behind the nodes
+        for group in &self.current.borrow().groups {
+            if let Some(rect) = self.group_rect(group) {
+                paint_group(painter, style, group, rect);
+            }
+        }
+    }
+
+    fn current_transform(
+        &mut self,
+        to_global: &mut egui::emath::TSTransform,
+        snarl: &mut Snarl<Node>,
+    ) {
+        if let Some(view) = self.pending_view.take() {
+            *to_global = view;
+        } else if std::mem::take(&mut self.fit_pending)
+            && let Some(bbox) = self.bounding_box(snarl)
+        {
+            *to_global = self.fit_transform(bbox);
+        }
+
+        self.to_global = *to_global;
+        self.current.borrow_mut().view = Some(*to_global);
+    }
+
+    fn has_wire_widget(&mut self, _from: &OutPinId, _to: &InPinId, _snarl: &Snarl<Node>) -> bool {
+        true
+    }
+
+    fn show_wire_widget(
+        &mut self,
+        from: &OutPin,
+        to: &InPin,
+        ui: &mut Ui,
+        snarl: &mut Snarl<Node>,
+    ) {
+        let wire = (from.id, to.id);
+        let default_color = self
+            .preferences
+            .palette
+            .data_type_color(output_type(snarl, from.id));
+        let color = self.current.borrow().wire_colors.get(&wire).copied();
+
+        let (rect, response) = ui.allocate_exact_size(egui::Vec2::splat(8.0), egui::Sense::click());
+        let dot_color = if response.hovered() {
+            ui.visuals().strong_text_color()
+        } else {
+            color.unwrap_or(default_color)
+        };
+        ui.painter().circle_filled(rect.center(), 3.0, dot_color);
+
+        let label_id = Id::new(("wire_label", wire));
+        if self.renaming_wire == Some(wire) {
+            let current = self.current.clone();
+            let mut label = current
+                .borrow()
+                .wire_labels
+                .get(&wire)
+                .cloned()
+                .unwrap_or_default();
+            let edit = ui.add(
+                egui::TextEdit::singleline(&mut label)
+                    .id(label_id)
+                    .desired_width(100.0),
+            );
+            if edit.changed() {
+                // Typing the label is a single edit
+                if !self.undo.coalesces(label_id) {
+                    self.checkpoint(snarl);
+                    self.undo.start_coalescing(label_id);
+                }
+                self.dirty = true;
+            }
+            let mut current = current.borrow_mut();
+            if edit.lost_focus() {
+                self.renaming_wire = None;
+                self.undo.end_coalescing();
+            }
+            if edit.lost_focus() && label.trim().is_empty() {
+                current.wire_labels.remove(&wire);
+            } else {
+                current.wire_labels.insert(wire, label);
+            }
+        } else if let Some(label) = self.current.borrow().wire_labels.get(&wire) {
+            ui.painter().text(
+                rect.center() - egui::vec2(0.0, 6.0),
+                egui::Align2::CENTER_BOTTOM,
+                label,
+                egui::FontId::proportional(11.0),
+                ui.visuals().text_color(),
+            );
+        }
+
+        let response = response.on_hover_text(tr(
+            "Double-click to add a waypoint, right-click for more options",
+        ));
+        if response.double_clicked() && !self.presenting {
+            self.new_waypoints.push((wire, rect.center()));
+        }
+        // While simulating, a click watches the wire
+        if response.clicked() && self.simulation.is_some() {
+            let path = self.current_path();
+            if let Some(simulation) = self.simulation.as_mut() {
+                simulation.watch(path, wire);
+            }
+        }
+
+        response.context_menu(|ui| {
+            let path = self.current_path();
+            if let Some(simulation) = self.simulation.as_mut() {
+                if simulation.is_watched(&path, wire) {
+                    if ui.button(tr("Remove from Watch")).clicked() {
+                        simulation.unwatch(&path, wire);
+                        ui.close();
+                    }
+                } else if ui.button(tr("Add to Watch")).clicked() {
+                    simulation.watch(path, wire);
+                    ui.close();
+                }
+                ui.separator();
+            }
+            // Only watching is left while presenting
+            if self.presenting {
+                return;
+            }
+
+            if ui.button(tr("Rename Wire")).clicked() {
+                self.renaming_wire = Some(wire);
+                ui.memory_mut(|memory| memory.request_focus(label_id));
+                ui.close();
+            }
+
+            ui.horizontal(|ui| {
+                ui.label(tr("Color"));
+                let mut picked = color.unwrap_or(default_color);
+                if ui.color_edit_button_srgba(&mut picked).changed() {
+                    // Dragging through the picker is a single edit
+                    let key = Id::new(("wire_color", wire));
+                    if !self.undo.coalesces(key) {
+                        self.checkpoint(snarl);
+                        self.undo.start_coalescing(key);
+                    }
+                    self.current.borrow_mut().wire_colors.insert(wire, picked);
+                    self.dirty = true;
+                }
+            });
+
+            if ui
+                .add_enabled(color.is_some(), egui::Button::new(tr("Reset Color")))
+                .clicked()
+            {
+                self.checkpoint(snarl);
+                self.current.borrow_mut().wire_colors.remove(&wire);
+                self.dirty = true;
+                ui.close();
+            }
+
+            ui.separator();
+
+            if ui.button(tr("Insert Node")).clicked() {
+                self.checkpoint(snarl);
+                let pos = rect.center() - DEFAULT_NODE_SIZE / 2.0;
+                model::insert_node_on_wire(snarl, from.id, to.id, pos);
+                self.dirty = true;
+                ui.close();
+            }
+
+            if ui
+                .button(tr("Reverse"))
+                .on_hover_text(tr("Make the signal flow the other way"))
+                .clicked()
+            {
+                self.checkpoint(snarl);
+                match model::reverse_wire(
+                    snarl,
+                    wire,
+                    self.preferences.allow_cycles,
+                    self.preferences.replace_at_limit,
+                ) {
+                    Ok(reversed) => {
+                        // Keep how the wire looks, its route walked the other way
+                        let mut current = self.current.borrow_mut();
+                        if let Some(color) = current.wire_colors.remove(&wire) {
+                            current.wire_colors.insert(reversed, color);
+                        }
+                        if let Some(label) = current.wire_labels.remove(&wire) {
+                            current.wire_labels.insert(reversed, label);
+                        }
+                        if let Some(mut waypoints) = current.waypoints.remove(&wire) {
+                            waypoints.reverse();
+                            current.waypoints.insert(reversed, waypoints);
+                        }
+                        self.dirty = true;
+                    }
+                    Err(error) => {
+                        self.undo.take_undo();
+                        self.toasts.push(ToastKind::Warning, tr(error.message()));
+                    }
+                }
+                ui.close();
+            }
+
+            if ui.button(tr("Delete Wire")).clicked() {
+                self.checkpoint(snarl);
+                model::disconnect(snarl, from.id, to.id);
+                self.dirty = true;
+                ui.close();
+            }
+        });
+    }
+
+    fn final_node_rect(
+        &mut self,
+        node: NodeId,
+        rect: egui::Rect,
+        ui: &mut Ui,
+        snarl: &mut Snarl<Node>,
+    ) {
+        self.node_rects.insert(node, rect);
+        if self.current.borrow().is_mirrored(&snarl[node]) {
+            self.mirrored_nodes.insert(node);
+        } else {
+            self.mirrored_nodes.remove(&node);
+        }
+
+        if self.presenting {
+            return;
+        }
+
+        // Dragging the right edge resizes the node
+        let edge = egui::Rect::from_x_y_ranges(
+            rect.right() - RESIZE_HANDLE_WIDTH / 2.0..=rect.right() + RESIZE_HANDLE_WIDTH / 2.0,
+            rect.y_range(),
+        );
+        let response = ui
+            .interact(edge, Id::new(("node_resize", node)), egui::Sense::drag())
+            .on_hover_cursor(egui::CursorIcon::ResizeHorizontal);
+        if response.drag_started() {
+            self.checkpoint(snarl);
+        }
+        if response.dragged() {
+            let width = &mut snarl[node].width;
+            *width = (*width + response.drag_delta().x).max(Node::MIN_WIDTH);
+            self.dirty = true;
+        }
+    }
+
+    fn inputs(&mut self, node: &Node) -> usize {
+        if node.is_comment() || self.pins_collapsed(node) {
+            0
+        } else {
+            node.inputs.len()
+        }
+    }
+
+    fn outputs(&mut self, node: &Node) -> usize {
+        if node.is_comment() || self.pins_collapsed(node) {
+            0
+        } else {
+            node.outputs.len()
+        }
+    }
+
+    fn has_body(&mut self, node: &Node) -> bool {
+        node.is_comment()
+            || node.is_collapsed()
+            || self.preferences.subsystem_previews && node.subsystem.is_some()
+            || node.inputs.len() + node.outputs.len() > self.preferences.pin_render_limit
+    }
+
+    fn show_body(
+        &mut self,
+        node_id: NodeId,
+        _inputs: &[InPin],
+        _outputs: &[OutPin],
+        ui: &mut Ui,
+        snarl: &mut Snarl<Node>,
+    ) {
+        if self.is_dimmed(&snarl[node_id]) {
+            ui.set_opacity(DIMMED_OPACITY);
+        }
+
+        if snarl[node_id].is_comment() && self.presenting {
+            let node = &snarl[node_id];
+            ui.set_max_width(node.width);
+            ui.label(&node.text);
+            return;
+        }
+        if snarl[node_id].is_comment() {
+            let text_id = Id::new(("comment_text", node_id));
+            let before = snarl[node_id].text.clone();
+            let node = &mut snarl[node_id];
+            let response = ui.add(
+                egui::TextEdit::multiline(&mut node.text)
+                    .id(text_id)
+                    .desired_width(node.width)
+                    .desired_rows(3)
+                    .hint_text(tr("Comment")),
+            );
+            self.record_text_edit(snarl, node_id, text_id, &response, before, |node| {
+                &mut node.text
+            });
+            return;
+        }
+
+        let node = &snarl[node_id];
+        if self.preferences.subsystem_previews
+            && let Some(inner) = node.subsystem.as_ref()
+        {
+            paint_subsystem_preview(ui, &inner.borrow(), node.width);
+        }
+        if node.is_collapsed() {
+            ui.label(
+                egui::RichText::new(tr_args(
+                    "{inputs} in, {outputs} out",
+                    &[
+                        ("inputs", &node.inputs.len()),
+                        ("outputs", &node.outputs.len()),
+                    ],
+                ))
+                .small()
+                .weak(),
+            );
+        }
+        if node.inputs.len() + node.outputs.len() <= self.preferences.pin_render_limit {
+            return;
+        }
+
+        let node = &mut snarl[node_id];
+        let pins = node.inputs.len() + node.outputs.len();
+
+        if node.expand_pins {
+            if ui
+                .button(tr_args("Collapse {pins} pins", &[("pins", &pins)]))
+                .clicked()
+            {
+                node.expand_pins = false;
+            }
+        } else if ui
+            .button(
+                egui::RichText::new(tr_args("{pins} pins (click to expand)", &[("pins", &pins)]))
+                    .color(ui.visuals().warn_fg_color),
+            )
+            .clicked()
+        {
+            log::warn!("Rendering all {pins} pins of node {node_id:?}");
+            node.expand_pins = true;
+        }
+    }
+
+    fn show_input(
+        &mut self,
+        pin: &InPin,
+        ui: &mut Ui,
+        snarl: &mut Snarl<Node>,
+    ) -> impl egui_snarl::ui::SnarlPin + 'static {
+        let dimmed = self.is_dimmed(&snarl[pin.id.node]);
+        let wire_dimmed = dimmed
+            || pin
+                .remotes
+                .iter()
+                .any(|remote| self.is_dimmed(&snarl[remote.node]));
+        if dimmed {
+            ui.set_opacity(DIMMED_OPACITY);
+        }
+
+        let node = &mut snarl[pin.id.node];
+        let collapsed = node.is_collapsed();
+        if let Some(input) = node.inputs.get_mut(pin.id.input) {
+            if collapsed {
+                // Only the pin is left, stacked with the others along the edge
+                ui.allocate_space(egui::vec2(0.0, COLLAPSED_PIN_SPACING));
+            } else if self.presenting {
+                ui.label(input.name.as_str());
+            } else {
+                let name_id = Id::new(("input_name", pin.id));
+                let before = input.name.clone();
+                let response = ui.add_sized(
+                    [port_field_width(node.width), 20.0],
+                    egui::TextEdit::singleline(&mut input.name).id(name_id),
+                );
+                let index = pin.id.input;
+                self.record_text_edit(snarl, pin.id.node, name_id, &response, before, |node| {
+                    &mut node.inputs[index].name
+                });
+                let mut data_type = snarl[pin.id.node].inputs[index].data_type;
+                if data_type_combo(ui, ("input_type", pin.id), &mut data_type) {
+                    self.checkpoint(snarl);
+                    snarl[pin.id.node].inputs[index].data_type = data_type;
+                    self.dirty = true;
+                }
+                let node = &snarl[pin.id.node];
+
+                let name = &node.inputs[pin.id.input].name;
+                let duplicate = node
+                    .inputs
+                    .iter()
+                    .filter(|input| input.name == *name)
+                    .count()
+                    > 1;
+                mark_duplicate(ui, &response, duplicate);
+
+                let old_kind = node.inputs[pin.id.input].kind;
+                let mut kind = old_kind;
+                let old_max = node.inputs[pin.id.input].max_connections;
+                let mut max_connections = old_max;
+                if pin_menu(
+                    &response,
+                    !pin.remotes.is_empty(),
+                    &mut kind,
+                    &InputKind::ALL,
+                    &mut max_connections,
+                ) {
+                    self.checkpoint(snarl);
+                    let dropped = snarl.drop_inputs(pin.id);
+                    log::info!("Dropped {dropped} wire(s) from input {:?}", pin.id);
+                    self.dirty = true;
+                }
+                if kind != old_kind {
+                    self.checkpoint(snarl);
+                    snarl[pin.id.node].inputs[pin.id.input].kind = kind;
+                    self.warn_kind_change(
+                        &snarl[pin.id.node],
+                        old_kind == InputKind::External || kind == InputKind::External,
+                        kind == InputKind::Internal,
+                    );
+                    self.dirty = true;
+                }
+                if max_connections != old_max {
+                    // Dragging the limit is a single edit
+                    let key = Id::new(("input_max_connections", pin.id));
+                    if !self.undo.coalesces(key) {
+                        self.checkpoint(snarl);
+                        self.undo.start_coalescing(key);
+                    }
+                    snarl[pin.id.node].inputs[pin.id.input].max_connections = max_connections;
+                    self.dirty = true;
+                }
+            }
+            self.input_rows.insert(pin.id, ui.min_rect().center().y);
+            let data_type = snarl[pin.id.node].inputs[pin.id.input].data_type;
+
+            // Wires with a manual route are drawn separately, keep the direct one discreet
+            let color = pin
+                .remotes
+                .first()
+                .and_then(|remote| self.signal_color(*remote))
+                .unwrap_or_else(|| self.preferences.palette.data_type_color(data_type));
+            // Patterned wires are drawn over the canvas, hide the direct one under the gaps
+            let routed = self.routed_inputs.contains(&pin.id);
+            let color = if routed && self.preferences.wire_patterns {
+                Color32::TRANSPARENT
+            } else if routed || wire_dimmed {
+                color.gamma_multiply(0.25)
+            } else {
+                color
+            };
+            let kind = snarl[pin.id.node].inputs[pin.id.input].kind;
+            let pin_info = kind_pin(
+                self.preferences.palette,
+                kind == InputKind::External,
+                kind == InputKind::Internal,
+            )
+            .with_wire_color(color);
+            let pin_info = if self.current.borrow().is_mirrored(&snarl[pin.id.node]) {
+                hide_pin(pin_info)
+            } else {
+                pin_info
+            };
+            if let Some(pos) = self.in_pin_pos(pin.id) {
+                let node = &snarl[pin.id.node];
+                show_pin_tooltip(ui, pos, Id::new(("pin_tooltip", pin.id)), || {
+                    pin_tooltip(node, PortSide::Input, pin.id.input, pin.remotes.len())
+                });
+                if self.preferences.show_wire_counts && !pin.remotes.is_empty() {
+                    let outwards = -self.wire_direction(pin.id.node);
+                    paint_wire_count(ui, pos, outwards, pin.remotes.len());
+                }
+            }
+
+            if let Some((rejected, shown_at)) = self.rejected_pin.as_mut()
+                && *rejected == pin.id
+            {
+                let now = ui.input(|input| input.time);
+                if now - *shown_at.get_or_insert(now) < REJECTED_PIN_DURATION {
+                    ui.ctx().request_repaint();
+                    return pin_info.with_fill(Color32::RED);
+                }
+                self.rejected_pin = None;
+            }
+            pin_info
+        } else {
+            PinInfo::star()
+        }
+    }
+
+    fn show_output(
+        &mut self,
+        pin: &OutPin,
+        ui: &mut Ui,
+        snarl: &mut Snarl<Node>,
+    ) -> impl egui_snarl::ui::SnarlPin + 'static {
+        let dimmed = self.is_dimmed(&snarl[pin.id.node]);
+        let wire_dimmed = dimmed
+            || pin
+                .remotes
+                .iter()
+                .any(|remote| self.is_dimmed(&snarl[remote.node]));
+        if dimmed {
+            ui.set_opacity(DIMMED_OPACITY);
+        }
+
+        let node = &mut snarl[pin.id.node];
+        let collapsed = node.is_collapsed();
+        if let Some(output) = node.outputs.get_mut(pin.id.output) {
+            if collapsed {
+                // Only the pin is left, stacked with the others along the edge
+                ui.allocate_space(egui::vec2(0.0, COLLAPSED_PIN_SPACING));
+            } else if self.presenting {
+                ui.label(output.name.as_str());
+            } else {
+                let name_id = Id::new(("output_name", pin.id));
+                let before = output.name.clone();
+                let response = ui.add_sized(
+                    [port_field_width(node.width), 20.0],
+                    egui::TextEdit::singleline(&mut output.name).id(name_id),
+                );
+                let index = pin.id.output;
+                self.record_text_edit(snarl, pin.id.node, name_id, &response, before, |node| {
+                    &mut node.outputs[index].name
+                });
+                let mut data_type = snarl[pin.id.node].outputs[index].data_type;
+                if data_type_combo(ui, ("output_type", pin.id), &mut data_type) {
+                    self.checkpoint(snarl);
+                    snarl[pin.id.node].outputs[index].data_type = data_type;
+                    self.dirty = true;
+                }
+                let node = &snarl[pin.id.node];
+
+                let name = &node.outputs[pin.id.output].name;
+                let duplicate = node
+                    .outputs
+                    .iter()
+                    .filter(|output| output.name == *name)
+                    .count()
+                    > 1;
+                mark_duplicate(ui, &response, duplicate);
+
+                let old_kind = node.outputs[pin.id.output].kind;
+                let mut kind = old_kind;
+                let old_max = node.outputs[pin.id.output].max_connections;
+                let mut max_connections = old_max;
+                if pin_menu(
+                    &response,
+                    !pin.remotes.is_empty(),
+                    &mut kind,
+                    &OutputKind::ALL,
+                    &mut max_connections,
+                ) {
+                    self.checkpoint(snarl);
+                    let dropped = snarl.drop_outputs(pin.id);
+                    log::info!("Dropped {dropped} wire(s) from output {:?}", pin.id);
+                    self.dirty = true;
+                }
+                if kind != old_kind {
+                    self.checkpoint(snarl);
+                    snarl[pin.id.node].outputs[pin.id.output].kind = kind;
+                    self.warn_kind_change(
+                        &snarl[pin.id.node],
+                        old_kind == OutputKind::External || kind == OutputKind::External,
+                        kind == OutputKind::Internal,
+                    );
+                    self.dirty = true;
+                }
+                if max_connections != old_max {
+                    // Dragging the limit is a single edit
+                    let key = Id::new(("output_max_connections", pin.id));
+                    if !self.undo.coalesces(key) {
+                        self.checkpoint(snarl);
+                        self.undo.start_coalescing(key);
+                    }
+                    snarl[pin.id.node].outputs[pin.id.output].max_connections = max_connections;
+                    self.dirty = true;
+                }
+            }
+            self.output_rows.insert(pin.id, ui.min_rect().center().y);
+            let data_type = snarl[pin.id.node].outputs[pin.id.output].data_type;
+
+            let color = self
+                .signal_color(pin.id)
+                .unwrap_or_else(|| self.preferences.palette.data_type_color(data_type));
+            let color = if wire_dimmed {
+                color.gamma_multiply(DIMMED_OPACITY)
+            } else {
+                color
+            };
+            let kind = snarl[pin.id.node].outputs[pin.id.output].kind;
+            let pin_info = kind_pin(
+                self.preferences.palette,
+                kind == OutputKind::External,
+                kind == OutputKind::Internal,
+            )
+            .with_wire_color(color);
+            if let Some(pos) = self.out_pin_pos(pin.id) {
+                let node = &snarl[pin.id.node];
+                show_pin_tooltip(ui, pos, Id::new(("pin_tooltip", pin.id)), || {
+                    pin_tooltip(node, PortSide::Output, pin.id.output, pin.remotes.len())
+                });
+                if self.preferences.show_wire_counts && !pin.remotes.is_empty() {
+                    let outwards = self.wire_direction(pin.id.node);
+                    paint_wire_count(ui, pos, outwards, pin.remotes.len());
+                }
+            }
+            if self.current.borrow().is_mirrored(&snarl[pin.id.node]) {
+                hide_pin(pin_info)
+            } else {
+                pin_info
+            }
+        } else {
+            PinInfo::star()
+        }
+    }
+
+    fn show_header(
+        &mut self,
+        node_id: NodeId,
+        _inputs: &[InPin],
+        _outputs: &[OutPin],
+        ui: &mut Ui,
+        snarl: &mut Snarl<Node>,
+    ) {
+        if self.is_dimmed(&snarl[node_id]) {
+            ui.set_opacity(DIMMED_OPACITY);
+        }
+
+        if snarl[node_id].is_comment() {
+            ui.label(egui::RichText::new(tr("Comment")).small().weak());
+            return;
+        }
+
+        if snarl[node_id].subsystem.is_some() && !self.presenting {
+            let collapsed = snarl[node_id].collapsed;
+            let (caret, hint) = if collapsed {
+                ("⏵", tr("Show every port"))
+            } else {
+                ("⏷", tr("Collapse to the port counts"))
+            };
+            if ui.small_button(caret).on_hover_text(hint).clicked() {
+                self.checkpoint(snarl);
+                snarl[node_id].collapsed = !collapsed;
+                self.dirty = true;
+            }
+        }
+
+        if self.presenting {
+            let node = &snarl[node_id];
+            ui.add_sized(
+                [node.width, 20.0],
+                egui::Label::new(egui::RichText::new(&node.name).strong()),
+            );
+        } else {
+            let name_id = node_name_id(node_id);
+            let before = snarl[node_id].name.clone();
+            let response = ui.add_sized(
+                [snarl[node_id].width, 20.0],
+                egui::TextEdit::singleline(&mut snarl[node_id].name).id(name_id),
+            );
+            self.record_text_edit(snarl, node_id, name_id, &response, before, |node| {
+                &mut node.name
+            });
+
+            let node = &snarl[node_id];
+            let duplicate = snarl.node_ids().any(|(other_id, other)| {
+                other_id != node_id && !other.is_comment() && other.name == node.name
+            });
+            mark_duplicate(ui, &response, duplicate);
+        }
+
+        let node = &snarl[node_id];
+
+        if node.locked {
+            ui.label(egui::RichText::new("🔒").small().weak())
+                .on_hover_text(tr("Position locked"));
+        }
+
+        if node.subsystem.is_none() && node.op != model::LogicOp::Pass {
+            ui.label(egui::RichText::new(node.op.glyph()).monospace().weak())
+                .on_hover_text(tr_args(
+                    "{op}, computed when simulating",
+                    &[("op", &tr(node.op.label()))],
+                ));
+        }
+
+        if let Some(n) = self.evaluation_order.get(&node_id) {
+            ui.label(egui::RichText::new(format!("#{n}")).small().strong())
+                .on_hover_text(tr("Evaluation order"));
+        } else if self.preferences.show_evaluation_order && !self.cycle_nodes.is_empty() {
+            ui.label(egui::RichText::new("#?").small().weak())
+                .on_hover_text(tr(
+                    "Waits on a loop, so it has no place in the evaluation order",
+                ));
+        }
+
+        if let Some(subsystem) = node.subsystem.as_ref() {
+            let (nodes, wires) = subsystem.borrow().total_counts();
+            ui.label(
+                egui::RichText::new(tr_args(
+                    "{nodes}n {wires}w",
+                    &[("nodes", &nodes), ("wires", &wires)],
+                ))
+                .small()
+                .weak(),
+            )
+            .on_hover_text(tr_args(
+                "{nodes} node(s) and {wires} wire(s) inside",
+                &[("nodes", &nodes), ("wires", &wires)],
+            ));
+
+            if model::interface_drift(node) {
+                ui.colored_label(ui.visuals().warn_fg_color, "⚠")
+                    .on_hover_text(tr("Ports out of sync with the subsystem boundary"));
+            }
+        }
+    }
+
+    fn node_frame(
+        &mut self,
+        default: egui::Frame,
+        node: NodeId,
+        _inputs: &[InPin],
+        _outputs: &[OutPin],
+        snarl: &Snarl<Node>,
+    ) -> egui::Frame {
+        let default = match snarl[node].custom_color() {
+            Some(color) => default.fill(color),
+            None if snarl[node].is_comment() => default.fill(COMMENT_FILL),
+            None => default,
+        };
+        let default = if self.is_dimmed(&snarl[node]) {
+            default.multiply_with_opacity(DIMMED_OPACITY)
+        } else {
+            default
+        };
+
+        if let Some((flashed, remaining)) = self.flash
+            && flashed == node
+        {
+            let t = remaining / FLASH_DURATION;
+            default.stroke(egui::Stroke::new(
+                FOCUS_STROKE.width + 4.0 * t,
+                FOCUS_STROKE.color.lerp_to_gamma(Color32::WHITE, t),
+            ))
+        } else if self.focused_node == Some(node) {
+            default.stroke(FOCUS_STROKE)
+        } else if self.highlighted_nodes.contains(&node) {
+            default.stroke(HIGHLIGHT_STROKE)
+        } else if self.merged_nodes.contains(&node) {
+            default.stroke(SELECTED_STROKE)
+        } else if self.unconnected_nodes.contains(&node) {
+            default.stroke(UNCONNECTED_STROKE)
+        } else if self.cycle_nodes.contains(&node) {
+            default.stroke(CYCLE_STROKE)
+        } else {
+            default
+        }
+    }
+
+    fn connect(&mut self, from: &OutPin, to: &InPin, snarl: &mut Snarl<Node>) {
+        if !self.presenting {
+            self.connect_pins(snarl, from.id, to.id);
+        }
+    }
+
+    fn has_dropped_wire_menu(&mut self, src_pins: AnyPins, _snarl: &mut Snarl<Node>) -> bool {
+        if self.presenting {
+            return false;
+        }
+        self.dropped_wire = Some(match src_pins {
+            AnyPins::Out(pins) => DroppedWire::Outputs(pins.to_vec()),
+            AnyPins::In(pins) => DroppedWire::Inputs(pins.to_vec()),
+        });
+        false
+    }
+
+    fn disconnect(&mut self, from: &OutPin, to: &InPin, snarl: &mut Snarl<Node>) {
+        if self.presenting {
+            return;
+        }
+        self.checkpoint(snarl);
+        self.dirty |= model::disconnect(snarl, from.id, to.id);
+    }
+
+    fn drop_inputs(&mut self, pin: &InPin, snarl: &mut Snarl<Node>) {
+        if self.presenting {
+            return;
+        }
+        self.checkpoint(snarl);
+        let dropped = snarl.drop_inputs(pin.id);
+        log::info!("Dropped {dropped} wire(s) from input {:?}", pin.id);
+        self.dirty = true;
+
+        // The pins are still being laid out, remove the port once the frame is done
+        if dropped == 0 {
+            self.pending_removals
+                .push((pin.id.node, PortSide::Input, pin.id.input));
+        }
+    }
+
+    fn drop_outputs(&mut self, pin: &OutPin, snarl: &mut Snarl<Node>) {
+        if self.presenting {
+            return;
+        }
+        self.checkpoint(snarl);
+        let dropped = snarl.drop_outputs(pin.id);
+        log::info!("Dropped {dropped} wire(s) from output {:?}", pin.id);
+        self.dirty = true;
+
+        if dropped == 0 {
+            self.pending_removals
+                .push((pin.id.node, PortSide::Output, pin.id.output));
+        }
+    }
+
+    fn has_node_menu(&mut self, _node: &Node) -> bool {
+        !self.presenting
+    }
+
+    fn show_node_menu(
+        &mut self,
+        node_id: NodeId,
+        _inputs: &[InPin],
+        _outputs: &[OutPin],
+        ui: &mut Ui,
+        snarl: &mut Snarl<Node>,
+    ) {
+        ui.label(tr("Node menu"));
+        let id = snarl[node_id].id.to_string();
+        let response = ui
+            .add(
+                egui::Label::new(egui::RichText::new(&id).small().weak().monospace())
+                    .sense(egui::Sense::click()),
+            )
+            .on_hover_text(tr("Id of the node across versions, click to copy"));
+        if response.clicked() {
+            ui.ctx().copy_text(id);
+        }
+        ui.separator();
+
+        if snarl[node_id].is_comment() {
+            if ui.button(tr("Remove Comment")).clicked() {
+                self.checkpoint(snarl);
+                model::remove_node(snarl, node_id);
+                self.dirty = true;
+                ui.close();
+            }
+            return;
+        }
+
+        if ui.button(tr("Add Input")).clicked() {
+            self.checkpoint(snarl);
+            let names = snarl[node_id]
+                .inputs
+                .iter()
+                .map(|input| input.name.clone())
+                .collect();
+            let input = Input {
+                name: model::unique_name("Input", &names),
+                ..Input::default()
+            };
+            model::add_input(snarl, node_id, input);
+            self.dirty = true;
+            ui.close();
+        }
+
+        if ui.button(tr("Add Output")).clicked() {
+            self.checkpoint(snarl);
+            let names = snarl[node_id]
+                .outputs
+                .iter()
+                .map(|output| output.name.clone())
+                .collect();
+            let output = Output {
+                name: model::unique_name("Output", &names),
+                ..Output::default()
+            };
+            model::add_output(snarl, node_id, output);
+            self.dirty = true;
+            ui.close();
+        }
+
+        ui.horizontal(|ui| {
+            let count = self.port_batch_size;
+            ui.add(egui::DragValue::new(&mut self.port_batch_size).range(1..=256));
+
+            if ui
+                .button(tr_args("Add {count} Inputs", &[("count", &count)]))
+                .clicked()
+            {
+                self.checkpoint(snarl);
+                model::add_inputs(snarl, node_id, count);
+                self.dirty = true;
+                ui.close();
+            }
+
+            if ui
+                .button(tr_args("Add {count} Outputs", &[("count", &count)]))
+                .clicked()
+            {
+                self.checkpoint(snarl);
+                model::add_outputs(snarl, node_id, count);
+                self.dirty = true;
+                ui.close();
+            }
+        });
+
+        // Removing a port shifts the ones after it, so wait for the pins to be laid out
+        let mut removed = None;
+        let node = &snarl[node_id];
+        let shared = node.shares_subsystem();
+        egui::ScrollArea::vertical()
+            .max_height(200.0)
+            .show(ui, |ui| {
+                for (index, input) in node.inputs.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(!shared, egui::Button::new("✕").small())
+                            .on_hover_text(tr("Remove Input"))
+                            .on_disabled_hover_text(tr(SHARED_PORT_REASON))
+                            .clicked()
+                        {
+                            removed = Some((PortSide::Input, index));
+                        }
+                        ui.label(tr_args("In: {name}", &[("name", &input.name)]));
+                    });
+                }
+                for (index, output) in node.outputs.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(!shared, egui::Button::new("✕").small())
+                            .on_hover_text(tr("Remove Output"))
+                            .on_disabled_hover_text(tr(SHARED_PORT_REASON))
+                            .clicked()
+                        {
+                            removed = Some((PortSide::Output, index));
+                        }
+                        ui.label(tr_args("Out: {name}", &[("name", &output.name)]));
+                    });
+                }
+            });
+
+        if let Some((side, index)) = removed {
+            self.checkpoint(snarl);
+            self.pending_removals.push((node_id, side, index));
+            self.dirty = true;
+            ui.close();
+        }
+
+        ui.separator();
+
+        self.edit_node_color(ui, snarl, &[node_id]);
+        self.edit_node_tags(ui, snarl, &[node_id]);
+        ui.separator();
+        self.edit_node_behavior(ui, snarl, &[node_id]);
+
+        if ui.button(tr("Disconnect All Wires")).clicked() {
+            self.checkpoint(snarl);
+            self.dirty |= model::disconnect_all(snarl, node_id) > 0;
+            ui.close();
+        }
+
+        // The canvas offers no way to change its selection, so the reached nodes are highlighted
+        for (label, reach) in [
+            ("Select Upstream", model::Reach::Upstream),
+            ("Select Downstream", model::Reach::Downstream),
+            ("Select Connected", model::Reach::Connected),
+        ] {
+            if ui.button(tr(label)).clicked() {
+                self.highlighted_nodes = model::reachable_nodes(snarl, node_id, reach);
+                ui.close();
+            }
+        }
+
+        ui.separator();
+
+        if ui
+            .add(
+                egui::Button::new(tr("Enter Subsystem"))
+                    .shortcut_text(ui.ctx().format_shortcut(&ENTER_SUBSYSTEM_SHORTCUT)),
+            )
+            .clicked()
+        {
+            self.enter_subsystem(node_id, snarl);
+        }
+
+        if ui
+            .add_enabled(
+                snarl[node_id].subsystem.is_some(),
+                egui::Button::new(tr("Flatten Subsystem")),
+            )
+            .clicked()
+        {
+            self.checkpoint(snarl);
+            self.with_current(snarl, |current| model::flatten(current, node_id));
+            self.dirty = true;
+            ui.close();
+        }
+
+        if let Some(inner) = snarl[node_id].subsystem.as_ref() {
+            let instances = self
+                .subsystem_uses(snarl)
+                .iter()
+                .find(|found| Rc::ptr_eq(found.subsystem(), inner))
+                .map_or(1, |found| found.instances);
+            if instances > 1 {
+                ui.weak(tr_args(
+                    "Shared by {count} instances",
+                    &[("count", &instances)],
+                ))
+                .on_hover_text(tr("Edits inside this subsystem show up in all of them"));
+            }
+        }
+
+        if ui
+            .add_enabled(
+                snarl[node_id].subsystem.is_some(),
+                egui::Button::new(tr("Sync Interface")),
+            )
+            .on_hover_text(tr(
+                "Rebuild the ports from the boundary nodes inside the subsystem",
+            ))
+            .clicked()
+        {
+            self.checkpoint(snarl);
+            if let Some(remap) = model::sync_node_interface(snarl, node_id) {
+                self.current.borrow_mut().rekey_wires(&remap);
+                let dropped = remap.iter().filter(|(_, new)| new.is_none()).count();
+                if dropped > 0 {
+                    self.toasts.push(
+                        ToastKind::Warning,
+                        tr_args(
+                            "Dropped {count} wire(s) from removed ports",
+                            &[("count", &dropped)],
+                        ),
+                    );
+                }
+                self.dirty = true;
+            }
+            ui.close();
+        }
+
+        ui.add_enabled_ui(snarl[node_id].subsystem.is_none(), |ui| {
+            ui.menu_button(tr("Split Node"), |ui| {
+                self.show_split_menu(node_id, ui, snarl);
+            })
+        });
+
+        if ui.button(tr("Duplicate (Deep Copy)")).clicked() {
+            self.checkpoint(snarl);
+            let mut copy = snarl[node_id].clone();
+            model::unshare_subsystem(&mut copy);
+            copy.renew_id();
+            model::claim_unique_name(&mut copy, &mut model::node_names(snarl));
+            let pos = snarl
+                .get_node_info(node_id)
+                .map_or(egui::Pos2::ZERO, |info| info.pos);
+            model::add_node(snarl, self.snap_to_grid(pos + DUPLICATE_OFFSET), copy);
+            self.dirty = true;
+            ui.close();
+        }
+
+        ui.separator();
+        ui.separator();
+
+        if ui.button(tr("Remove Node")).clicked() {
+            // Everything nested inside would go with it, so ask first
+            if snarl[node_id].subsystem.is_some() {
+                self.subsystem_removal = Some(node_id);
+            } else {
+                self.checkpoint(snarl);
+                model::remove_node(snarl, node_id);
+                self.dirty = true;
+            }
+            ui.close();
+        }
+    }
+
+    fn has_graph_menu(&mut self, _pos: egui::Pos2, _snarl: &mut Snarl<Node>) -> bool {
+        !self.presenting
+    }
+
+    fn show_graph_menu(&mut self, pos: egui::Pos2, ui: &mut Ui, snarl: &mut Snarl<Node>) {
+        ui.label(tr("Diagram Menu"));
+        ui.separator();
+
+        if ui.button(tr("Add Node")).clicked() {
+            self.checkpoint(snarl);
+            let node = Node {
+                name: model::unique_name("Node", &model::node_names(snarl)),
+                ..Node::default()
+            };
+            model::add_node(snarl, self.snap_to_grid(pos), node);
+            self.dirty = true;
+            ui.close();
+        }
+
+        if ui.button(tr("Add Comment")).clicked() {
+            self.checkpoint(snarl);
+            model::add_node(snarl, self.snap_to_grid(pos), Node::comment());
+            self.dirty = true;
+            ui.close();
+        }
+
+        let selected = self.selection.clone();
+
+        if ui
+            .add_enabled(
+                selected.len() == 2,
+                egui::Button::new(tr("Connect By Name")),
+            )
+            .on_hover_text(tr(
+                "Wire the outputs of the left node to the like-named inputs of the right one",
+            ))
+            .clicked()
+        {
+            // Signals flow left to right, so the leftmost node is the source
+            let mut pair = selected
+                .iter()
+                .filter_map(|&node_id| Some((node_id, snarl.get_node_info(node_id)?.pos.x)))
+                .collect::<Vec<_>>();
+            pair.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            if let [(from, _), (to, _)] = pair[..] {
+                self.checkpoint(snarl);
+                let count = model::connect_by_name(
+                    snarl,
+                    from,
+                    to,
+                    self.preferences.allow_cycles,
+                    self.preferences.replace_at_limit,
+                );
+                self.dirty |= count > 0;
+                self.toasts.push(
+                    ToastKind::Info,
+                    tr_args("Connected {count} port(s) by name", &[("count", &count)]),
+                );
+            }
+            ui.close();
+        }
+
+        ui.menu_button(tr("Selection"), |ui| {
+            // The canvas keeps showing its own selection, the editor outlines the rest
+            if ui.button(tr("Invert Selection")).clicked() {
+                self.selection = model::invert_selection(snarl, &selected);
+                ui.close();
+            }
+            if ui
+                .add_enabled(
+                    !selected.is_empty(),
+                    egui::Button::new(tr("Grow Selection")),
+                )
+                .on_hover_text(tr("Add the nodes wired to the selected ones"))
+                .clicked()
+            {
+                self.selection = model::grow_selection(snarl, &selected);
+                ui.close();
+            }
+            if ui
+                .add_enabled(
+                    !selected.is_empty(),
+                    egui::Button::new(tr("Shrink Selection")),
+                )
+                .on_hover_text(tr("Leave out the selected nodes wired to unselected ones"))
+                .clicked()
+            {
+                self.selection = model::shrink_selection(snarl, &selected);
+                ui.close();
+            }
+        });
+
+        if ui
+            .add_enabled(!selected.is_empty(), egui::Button::new(tr("Create Group")))
+            .on_hover_text(tr(
+                "Box the selected nodes together, without changing the diagram",
+            ))
+            .clicked()
+        {
+            self.checkpoint(snarl);
+            let mut current = self.current.borrow_mut();
+            let name = format!("Group {}", current.groups.len() + 1);
+            current.groups.push(Group {
+                name,
+                color: GROUP_COLOR,
+                members: selected.clone(),
+            });
+            self.dirty = true;
+            ui.close();
+        }
+
+        if ui
+            .add_enabled(
+                !selected.is_empty(),
+                egui::Button::new(tr("Convert To Subsystem")),
+            )
+            .clicked()
+        {
+            // The selected nodes move one level down, along with everything nested inside them
+            let depth = self.previous.len()
+                + 1
+                + selected
+                    .iter()
+                    .filter_map(|&node_id| snarl.get_node(node_id))
+                    .map(Node::nesting_depth)
+                    .max()
+                    .unwrap_or(0);
+            if !self.check_depth(depth) {
+                ui.close();
+                return;
+            }
+
+            self.checkpoint(snarl);
+            self.with_current(snarl, |current| {
+                model::convert_to_subsystem(current, &selected, pos)
+            });
+            self.dirty = true;
+
+            ui.close();
+        }
+
+        ui.menu_button(tr("Insert Instance"), |ui| {
+            let uses = self.subsystem_uses(snarl);
+            if uses.is_empty() {
+                ui.weak(tr("No subsystems yet"));
+            }
+
+            for found in &uses {
+                if !ui.button(found.path.join(" / ")).clicked() {
+                    continue;
+                }
+                ui.close();
+
+                let subsystem = found.subsystem();
+                if model::contains_subsystem(&subsystem.borrow(), &self.current) {
+                    self.toasts.push(
+                        ToastKind::Warning,
+                        tr_args(
+                            "\"{name}\" contains this level, an instance here would nest itself",
+                            &[("name", &found.node.name)],
+                        ),
+                    );
+                    return;
+                }
+
+                let depth = self.previous.len() + found.node.nesting_depth();
+                if !self.check_depth(depth) {
+                    return;
+                }
+
+                self.checkpoint(snarl);
+                model::mark_instance(subsystem, &uses);
+                let mut node = found.node.clone();
+                node.renew_id();
+                model::add_node(snarl, self.snap_to_grid(pos), node);
+                self.dirty = true;
+                log::info!("Inserted an instance of \"{}\"", found.path.join(" / "));
+                return;
+            }
+        });
+
+        if ui.button(tr("Fix Duplicate Names")).clicked() {
+            self.checkpoint(snarl);
+            let count = model::fix_duplicate_names(snarl);
+            self.dirty |= count > 0;
+            self.toasts.push(
+                ToastKind::Info,
+                tr_args("Renamed {count} duplicate name(s)", &[("count", &count)]),
+            );
+            ui.close();
+        }
+
+        if ui.button(tr("Check Connections")).clicked() {
+            self.checking_connections = true;
+            ui.close();
+        }
+
+        if ui.button(tr("Simulate")).clicked() {
+            self.simulation.get_or_insert_with(Default::default);
+            ui.close();
+        }
+
+        if ui.button(tr("Test Vectors")).clicked() {
+            self.showing_tests = true;
+            ui.close();
+        }
+
+        if ui.button(tr("Problems")).clicked() {
+            self.showing_problems = true;
+            ui.close();
+        }
+
+        if ui.button(tr("Find Unused Subsystems")).clicked() {
+            self.unused_subsystems = Some(model::find_unused_subsystems(&self.toplevel.borrow()));
+            ui.close();
+        }
+
+        if ui.button(tr("Statistics")).clicked() {
+            self.showing_statistics = true;
+            ui.close();
+        }
+
+        if ui.button(tr("Properties")).clicked() {
+            self.showing_properties = true;
+            ui.close();
+        }
+
+        ui.menu_button(tr("Auto Layout"), |ui| {
+            if ui.button(tr("Force-Directed")).clicked() {
+                self.checkpoint(snarl);
+                layout::force_directed(snarl, &self.node_sizes(), DEFAULT_NODE_SIZE);
+                self.dirty = true;
+                ui.close();
+            }
+
+            if ui.button(tr("Layered")).clicked() {
+                self.checkpoint(snarl);
+                let direction = self.current.borrow().layout_dir;
+                layout::layered(snarl, &self.node_sizes(), DEFAULT_NODE_SIZE, direction);
+                self.dirty = true;
+                ui.close();
+            }
+        });
+
+        ui.menu_button(tr("Layout Direction"), |ui| {
+            let before = self.current.borrow().layout_dir;
+            for direction in LayoutDirection::ALL {
+                if ui
+                    .radio(before == direction, tr(direction.label()))
+                    .clicked()
+                {
+                    if direction != before {
+                        self.checkpoint(snarl);
+                        self.current.borrow_mut().layout_dir = direction;
+                        self.dirty = true;
+                    }
+                    ui.close();
+                }
+            }
+        });
+
+        if !self.previous.is_empty() {
+            ui.separator();
+            ui.separator();
+            if ui
+                .add(
+                    egui::Button::new(tr("Go Up One Level"))
+                        .shortcut_text(ui.ctx().format_shortcut(&GO_UP_SHORTCUT)),
+                )
+                .clicked()
+            {
+                self.go_up();
+                ui.close();
+            }
+        }
+    }
+}
+
+/// Actions that would lose unsaved changes, waiting for the user to confirm them.
+#[derive(Clone, Copy)]
+enum PendingAction {
+    NewDiagram,
+    OpenDiagram,
+    /// Opens [`DiagramApp::dropped_file`] in place of the current diagram.
+    OpenDropped,
+    /// Opens the file at this index of [`DiagramApp::recent_files`] in place of the current
+    /// diagram.
+    OpenRecent(usize),
+    CloseDocument(usize),
+}
+
+const NEW_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::N);
+const OPEN_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::O);
+const SAVE_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::S);
+const SAVE_AS_SHORTCUT: egui::KeyboardShortcut = egui::KeyboardShortcut::new(
+    egui::Modifiers::COMMAND.plus(egui::Modifiers::SHIFT),
+    egui::Key::S,
+);
+const UNDO_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+const REDO_SHORTCUT: egui::KeyboardShortcut = egui::KeyboardShortcut::new(
+    egui::Modifiers::COMMAND.plus(egui::Modifiers::SHIFT),
+    egui::Key::Z,
+);
+const ENTER_SUBSYSTEM_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::NONE, egui::Key::Enter);
+// Backspace alone already deletes the selection
+const GO_UP_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::ALT, egui::Key::ArrowUp);
+
+/// A diagram open in its own tab.
+///
+/// The state of the active document is lent to the viewer and the app while it is edited, see
+/// [`DiagramApp::swap_active`], so its own fields are stale until it is given back.
+struct Document {
+    name: String,
+    toplevel: Rc<RefCell<Subsystem>>,
+    current: Rc<RefCell<Subsystem>>,
+    previous: Levels,
+    /// Id of the canvas state of this document, so selections stay in their tab.
+    diagram_id: Id,
+    undo: UndoStack,
+    dirty: bool,
+    file_path: Option<std::path::PathBuf>,
+    /// When the diagram was last saved to its file during this session.
+    last_saved: Option<Instant>,
+}
+
+impl Document {
+    fn new(name: String, toplevel: Subsystem) -> Self {
+        let toplevel = Rc::new(RefCell::new(toplevel));
+        Self {
+            name,
+            diagram_id: Id::new(("diagram", Rc::as_ptr(&toplevel))),
+            current: toplevel.clone(),
+            toplevel,
+            previous: Vec::new(),
+            undo: UndoStack::default(),
+            dirty: false,
+            file_path: None,
+            last_saved: None,
+        }
+    }
+}
+
+struct DiagramApp {
+    viewer: DiagramViewer,
+    style: SnarlStyle,
+    /// Styles saved by the user, by name.
+    style_presets: BTreeMap<String, SnarlStyle>,
+    /// Name of the preset last picked, or to save the style under.
+    style_preset: String,
+    /// Open diagrams, one per tab.
+    documents: Vec<Document>,
+    /// Index of the document being edited.
+    active: usize,
+    /// Document whose tab name is being edited.
+    renaming: Option<usize>,
+    file_path: Option<std::path::PathBuf>,
+    pending_action: Option<PendingAction>,
+    /// Files last opened or saved, the latest first.
+    recent_files: Vec<std::path::PathBuf>,
+    /// Diagram file dropped on the window, waiting for [`PendingAction::OpenDropped`].
+    dropped_file: Option<std::path::PathBuf>,
+    title: String,
+    search: NodeSearch,
+    /// Nodes of the current subsystem waiting for the user to confirm their removal.
+    pending_deletion: Option<Vec<NodeId>>,
+    /// Whether the port listing export descends into nested subsystems.
+    port_csv_recursive: bool,
+    /// Whether the SVG export draws the nested subsystems too.
+    svg_recursive: bool,
+    /// Whether the SVG and PNG exports include the legend.
+    export_legend: bool,
+    png_export: Option<PngExport>,
+    /// Named states of the whole diagram saved during this session, as JSON.
+    checkpoints: Vec<(String, String)>,
+    /// Name given to the next checkpoint.
+    checkpoint_name: String,
+    /// Name of the file the diagram was last compared with, and what differs from it.
+    comparison: Option<(String, diff::LevelDiff)>,
+    /// When the active diagram was last saved to its file during this session.
+    last_saved: Option<Instant>,
+    /// When the open diagrams were last copied to the recovery file, or the editor started.
+    autosave_started: Instant,
+    /// When the open diagrams were last copied to the recovery file, if they were.
+    last_autosave: Option<Instant>,
+    /// Diagrams of the recovery file, found newer than the stored ones at startup and waiting
+    /// for the user to restore or discard them.
+    recovery: Option<Recovery>,
+    /// [`Preferences::ui_scale`] as last handed to egui.
+    applied_ui_scale: f32,
+}
+
+/// Name of the application, which sets where eframe keeps its storage.
+const APP_NAME: &str = "Diagram";
+
+/// Most files listed in the File > Recent menu.
+const RECENT_FILES: usize = 10;
+
+/// Copy of the open diagrams written every [`Preferences::autosave_interval`], for recovering
+/// from a crash.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Recovery {
+    /// Seconds since the Unix epoch when it was written.
+    saved_at: u64,
+    /// Name and saved diagram of each document, as kept in the storage.
+    documents: Vec<(String, String)>,
+}
+
+impl Recovery {
+    fn path() -> Option<std::path::PathBuf> {
+        eframe::storage_dir(APP_NAME).map(|dir| dir.join("recovery.json"))
+    }
+
+    /// Reads the recovery file, if there is one.
+    fn read() -> Option<Self> {
+        let json = std::fs::read_to_string(Self::path()?).ok()?;
+        serde_json::from_str(&json).ok()
+    }
+
+    fn write(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("No directory to keep the recovery file in")?;
+        std::fs::create_dir_all(path.parent().unwrap_or(&path)).map_err(|e| e.to_string())?;
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        std::fs::write(&path, json).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    fn remove() {
+        if let Some(path) = Self::path() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// How long ago `elapsed` is, roughly, for the status bar.
+fn format_ago(elapsed: Duration) -> String {
+    match elapsed.as_secs() {
+        0..10 => tr("just now").to_string(),
+        secs @ 10..60 => tr_args("{count} s ago", &[("count", &secs)]),
+        secs @ 60..3600 => tr_args("{count} min ago", &[("count", &(secs / 60))]),
+        secs => tr_args("{count} h ago", &[("count", &(secs / 3600))]),
+    }
+}
+
+/// Opens the documents kept as `stored`, the name and saved diagram of each, skipping the ones
+/// that cannot be read.
+fn load_documents(stored: Vec<(String, String)>) -> Vec<Document> {
+    stored
+        .into_iter()
+        .filter_map(|(name, json)| {
+            let mut toplevel = model::migrate(serde_json::from_str(&json).ok()?).ok()?;
+            model::relink_instances(&mut toplevel);
+            Some(Document::new(name, toplevel))
+        })
+        .collect()
+}
+
+/// Choices of [`Preferences::ui_scale`] offered in the top panel.
+const UI_SCALES: [f32; 8] = [0.75, 0.9, 1.0, 1.1, 1.25, 1.5, 1.75, 2.0];
+
+/// Seconds without typing before the search box runs its query.
+const SEARCH_DELAY: f64 = 0.3;
+
+/// Frames rendered before capturing the canvas for a PNG export, so the menu used to start it
+/// is gone and a fitted view is in place.
+const PNG_SETTLE_FRAMES: u32 = 2;
+
+/// A PNG export waiting for the canvas to be captured.
+struct PngExport {
+    path: std::path::PathBuf,
+    /// View to go back to once captured, if the whole diagram was fitted for the capture.
+    restore_view: Option<egui::emath::TSTransform>,
+    /// Frames left to render before requesting the screenshot.
+    wait_frames: u32,
+    requested: bool,
+    /// Whether the legend is drawn over the canvas for the capture.
+    legend: bool,
+}
+
+#[derive(Default)]
+struct NodeSearch {
+    query: String,
+    /// When the query was last edited, if it has not been run since.
+    edited_at: Option<f64>,
+    results: Vec<model::NodeMatch>,
+    /// Match node names with the query as a regular expression, which allows renaming them.
+    regex: bool,
+    /// The query compiled as of the last run, in regex mode.
+    pattern: Option<regex::Regex>,
+    /// Why the query is not a valid regular expression, as of the last run.
+    error: Option<String>,
+    /// What the names matched in regex mode are renamed to, with `$1` and the like standing
+    /// for their capture groups.
+    replacement: String,
+    /// Rename the matches of the current subsystem only, instead of the whole diagram.
+    current_level_only: bool,
+}
+
+impl NodeSearch {
+    /// Finds the nodes of `toplevel` and the levels nested inside it matching the query.
+    fn run(&mut self, toplevel: &Subsystem) {
+        self.edited_at = None;
+        self.error = None;
+        self.pattern = None;
+        if self.query.is_empty() {
+            self.results.clear();
+        } else if self.regex {
+            match regex::Regex::new(&self.query) {
+                Ok(pattern) => {
+                    self.results = model::find_nodes_regex(toplevel, &pattern);
+                    self.pattern = Some(pattern);
+                }
+                Err(e) => {
+                    self.results.clear();
+                    self.error = Some(e.to_string());
+                }
+            }
+        } else {
+            self.results = model::find_nodes(toplevel, &self.query);
+        }
+    }
+
+    /// New names of the matches the replacement changes, in the levels to rename.
+    fn renames(&self, current_path: &[NodeId]) -> Vec<(model::NodeMatch, String)> {
+        let Some(pattern) = self.pattern.as_ref() else {
+            return Vec::new();
+        };
+        self.results
+            .iter()
+            .filter(|found| !self.current_level_only || found.path == current_path)
+            .filter_map(|found| {
+                let name = pattern.replace_all(&found.name, self.replacement.as_str());
+                (name != found.name).then(|| (found.clone(), name.into_owned()))
+            })
+            .collect()
+    }
+}
+
+/// What copying nodes puts in the clipboard.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ClipboardContents {
+    /// Graph-space bounding box of the copied nodes, centered on the pointer when pasting.
+    bbox: egui::Rect,
+    fragment: model::Fragment,
+}
+
+const fn default_style() -> SnarlStyle {
     SnarlStyle {
         node_layout: Some(NodeLayout::coil()),
         pin_placement: Some(PinPlacement::Edge),
-        pin_size: Some(7.0),
+        pin_size: Some(PIN_SIZE),
         node_frame: Some(egui::Frame {
             inner_margin: egui::Margin::same(8),
             outer_margin: egui::Margin {
@@ -647,40 +5014,1565 @@ const fn default_style() -> SnarlStyle {
         }),
         ..SnarlStyle::new()
     }
-}
+}
+
+/// Wire styles offered in the style panel, by name.
+const WIRE_STYLES: [(&str, WireStyle); 3] = [
+    ("Curved", WireStyle::Bezier5),
+    (
+        "Orthogonal",
+        WireStyle::AxisAligned {
+            corner_radius: 10.0,
+        },
+    ),
+    ("Straight", WireStyle::Line),
+];
+
+/// Styles offered in the style panel next to the ones saved by the user.
+fn builtin_styles() -> [(&'static str, SnarlStyle); 2] {
+    let default = default_style();
+    let node_frame = default.node_frame.unwrap_or_default();
+    let bg_frame = default.bg_frame.unwrap_or_default();
+    [
+        (
+            "Dark Compact",
+            SnarlStyle {
+                pin_size: Some(5.0),
+                node_frame: Some(egui::Frame {
+                    inner_margin: egui::Margin::same(4),
+                    corner_radius: egui::CornerRadius::same(4),
+                    fill: Color32::from_gray(22),
+                    ..node_frame
+                }),
+                bg_frame: Some(egui::Frame {
+                    fill: Color32::from_gray(14),
+                    ..bg_frame
+                }),
+                ..default
+            },
+        ),
+        (
+            "High Contrast",
+            SnarlStyle {
+                pin_size: Some(9.0),
+                pin_stroke: Some(egui::Stroke::new(1.5, Color32::WHITE)),
+                wire_width: Some(3.0),
+                node_frame: Some(egui::Frame {
+                    fill: Color32::BLACK,
+                    stroke: egui::Stroke::new(1.5, Color32::WHITE),
+                    ..node_frame
+                }),
+                bg_frame: Some(egui::Frame {
+                    fill: Color32::from_gray(10),
+                    ..bg_frame
+                }),
+                bg_pattern_stroke: Some(egui::Stroke::new(1.0, Color32::from_gray(90))),
+                ..default
+            },
+        ),
+    ]
+}
+
+impl DiagramApp {
+    pub fn new(cx: &CreationContext) -> Self {
+        egui_extras::install_image_loaders(&cx.egui_ctx);
+
+        // Earlier versions kept a single diagram under "toplevel"
+        let stored = cx
+            .storage
+            .and_then(|storage| match storage.get_string("documents") {
+                Some(documents) => serde_json::from_str::<Vec<(String, String)>>(&documents).ok(),
+                None => storage
+                    .get_string("toplevel")
+                    .map(|toplevel| vec![("Untitled".to_string(), toplevel)]),
+            })
+            .unwrap_or_default();
+
+        let mut documents = load_documents(stored);
+        if documents.is_empty() {
+            documents.push(Document::new("Untitled".to_string(), Subsystem::new()));
+        }
+        let active = cx
+            .storage
+            .and_then(|storage| storage.get_string("active_document"))
+            .and_then(|active| active.parse::<usize>().ok())
+            .unwrap_or(0)
+            .min(documents.len() - 1);
+
+        let style = cx.storage.map_or_else(default_style, |storage| {
+            storage
+                .get_string("style")
+                .and_then(|style| serde_json::from_str(&style).ok())
+                .unwrap_or_else(default_style)
+        });
+        let style_presets = cx
+            .storage
+            .and_then(|storage| storage.get_string("style_presets"))
+            .and_then(|presets| serde_json::from_str(&presets).ok())
+            .unwrap_or_default();
+
+        let mut preferences = cx.storage.map_or_else(Preferences::default, |storage| {
+            storage
+                .get_string("preferences")
+                .and_then(|preferences| serde_json::from_str(&preferences).ok())
+                .unwrap_or_default()
+        });
+        i18n::set_language(preferences.language);
+        preferences.ui_scale = preferences.ui_scale.clamp(UI_SCALES[0], UI_SCALES[7]);
+        // The zoom shortcuts step the preference instead, which is then handed to egui
+        cx.egui_ctx
+            .options_mut(|options| options.zoom_with_keyboard = false);
+        cx.egui_ctx.set_zoom_factor(preferences.ui_scale);
+        let applied_ui_scale = preferences.ui_scale;
+
+        // Files gone since the last session are of no use in the menu
+        let recent_files = cx
+            .storage
+            .and_then(|storage| storage.get_string("recent_files"))
+            .and_then(|recent| serde_json::from_str::<Vec<std::path::PathBuf>>(&recent).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| path.exists())
+            .collect();
+
+        // A recovery file written after the storage was means the editor did not get to store
+        // its diagrams when it last closed
+        let stored_at = cx
+            .storage
+            .and_then(|storage| storage.get_string("saved_at"))
+            .and_then(|saved_at| saved_at.parse::<u64>().ok())
+            .unwrap_or(0);
+        let recovery = Recovery::read().filter(|recovery| recovery.saved_at > stored_at);
+        if recovery.is_some() {
+            log::warn!("Found a recovery file newer than the stored diagrams");
+        }
+
+        let mut app = Self {
+            viewer: DiagramViewer {
+                toplevel: Rc::default(),
+                current: Rc::default(),
+                previous: Vec::default(),
+                diagram_id: Id::NULL,
+                preferences,
+                viewport: egui::Rect::NOTHING,
+                node_rects: HashMap::default(),
+                mirrored_nodes: HashSet::default(),
+                fit_pending: false,
+                pending_view: None,
+                to_global: egui::emath::TSTransform::IDENTITY,
+                canvas_id: Id::NULL,
+                input_rows: HashMap::default(),
+                output_rows: HashMap::default(),
+                routed_inputs: HashSet::default(),
+                new_waypoints: Vec::default(),
+                renaming_wire: None,
+                port_batch_size: 4,
+                split_at: (1, 1),
+                split_link: false,
+                pending_removals: Vec::default(),
+                subsystem_removal: None,
+                wire_drag: None,
+                dropped_wire: None,
+                wire_style: WireStyle::Bezier5,
+                focused_node: None,
+                flash: None,
+                rejected_pin: None,
+                highlighted_nodes: HashSet::default(),
+                selection: Vec::new(),
+                box_origin: None,
+                merged_nodes: HashSet::default(),
+                canvas_outdated: false,
+                hidden_tags: HashSet::default(),
+                new_tag: String::new(),
+                checking_connections: false,
+                showing_statistics: false,
+                showing_properties: false,
+                presenting: false,
+                showing_problems: false,
+                showing_tests: false,
+                test_results: Vec::new(),
+                simulation: None,
+                signal_values: HashMap::default(),
+                evaluation_order: HashMap::default(),
+                cycle_nodes: HashSet::default(),
+                unused_subsystems: None,
+                unconnected_nodes: HashSet::default(),
+                moved_nodes: HashSet::default(),
+                toasts: Toasts::default(),
+                undo: UndoStack::default(),
+                dirty: false,
+            },
+            style,
+            style_presets,
+            style_preset: String::new(),
+            documents,
+            active,
+            renaming: None,
+            file_path: None,
+            recent_files,
+            pending_action: None,
+            dropped_file: None,
+            title: String::new(),
+            search: NodeSearch::default(),
+            pending_deletion: None,
+            port_csv_recursive: false,
+            svg_recursive: false,
+            export_legend: false,
+            png_export: None,
+            checkpoints: Vec::new(),
+            checkpoint_name: String::new(),
+            comparison: None,
+            last_saved: None,
+            autosave_started: Instant::now(),
+            last_autosave: None,
+            recovery,
+            applied_ui_scale,
+        };
+        app.swap_active();
+        app.viewer.show_view_of(&app.viewer.current.clone());
+        app
+    }
+
+    /// Swaps the state of the active document with the one the viewer and the app work on,
+    /// lending it to them or giving it back.
+    fn swap_active(&mut self) {
+        let document = &mut self.documents[self.active];
+        let viewer = &mut self.viewer;
+        std::mem::swap(&mut document.toplevel, &mut viewer.toplevel);
+        std::mem::swap(&mut document.current, &mut viewer.current);
+        std::mem::swap(&mut document.previous, &mut viewer.previous);
+        std::mem::swap(&mut document.diagram_id, &mut viewer.diagram_id);
+        std::mem::swap(&mut document.undo, &mut viewer.undo);
+        std::mem::swap(&mut document.dirty, &mut viewer.dirty);
+        std::mem::swap(&mut document.file_path, &mut self.file_path);
+        std::mem::swap(&mut document.last_saved, &mut self.last_saved);
+    }
+
+    /// Makes the `index`-th document the one being edited.
+    fn switch_document(&mut self, index: usize) {
+        if index == self.active || index >= self.documents.len() {
+            return;
+        }
+        self.swap_active();
+        self.active = index;
+        self.lend_active();
+    }
+
+    /// Lends the active document to the viewer, dropping what was tied to the previous one.
+    fn lend_active(&mut self) {
+        self.swap_active();
+        self.viewer.clear_layout();
+        self.viewer.focused_node = None;
+        self.viewer.highlighted_nodes.clear();
+        self.viewer.unconnected_nodes.clear();
+        self.viewer.show_view_of(&self.viewer.current.clone());
+        self.pending_deletion = None;
+        self.search.results.clear();
+        log::info!("Switched to \"{}\"", self.documents[self.active].name);
+    }
+
+    /// Opens a new empty diagram in its own tab.
+    fn add_document(&mut self) {
+        let names = self
+            .documents
+            .iter()
+            .map(|document| document.name.as_str())
+            .collect::<HashSet<_>>();
+        let name = (1..)
+            .map(|n| match n {
+                1 => "Untitled".to_string(),
+                n => format!("Untitled {n}"),
+            })
+            .find(|name| !names.contains(name.as_str()))
+            .unwrap();
+
+        self.documents.push(Document::new(name, Subsystem::new()));
+        self.switch_document(self.documents.len() - 1);
+    }
+
+    /// Closes the `index`-th document, leaving an empty one if it was the last.
+    fn close_document(&mut self, index: usize) {
+        if self.documents.len() == 1 {
+            self.documents[0].name = "Untitled".to_string();
+            self.perform(PendingAction::NewDiagram);
+            return;
+        }
+
+        log::info!("Closed \"{}\"", self.documents[index].name);
+        if index == self.active {
+            self.swap_active();
+            self.documents.remove(index);
+            self.active = index.min(self.documents.len() - 1);
+            self.lend_active();
+        } else {
+            self.documents.remove(index);
+            if index < self.active {
+                self.active -= 1;
+            }
+        }
+        self.renaming = None;
+    }
+
+    fn document_dirty(&self, index: usize) -> bool {
+        if index == self.active {
+            self.viewer.dirty
+        } else {
+            self.documents[index].dirty
+        }
+    }
+
+    /// Shows a tab per document to switch between them, with buttons to close them and to add
+    /// one. Double-clicking a tab renames it.
+    fn show_tabs(&mut self, ui: &mut Ui) {
+        let mut switch = None;
+        let mut close = None;
+
+        ui.horizontal(|ui| {
+            for n in 0..self.documents.len() {
+                let name_id = Id::new(("document_name", n));
+                if self.renaming == Some(n) {
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut self.documents[n].name)
+                            .id(name_id)
+                            .desired_width(120.0),
+                    );
+                    if response.lost_focus() {
+                        self.renaming = None;
+                    }
+                } else {
+                    let dirty = if self.document_dirty(n) { "*" } else { "" };
+                    let response = ui
+                        .selectable_label(
+                            n == self.active,
+                            format!("{}{dirty}", self.documents[n].name),
+                        )
+                        .on_hover_text(tr("Double-click to rename"));
+                    if response.clicked() {
+                        switch = Some(n);
+                    }
+                    if response.double_clicked() {
+                        self.renaming = Some(n);
+                        ui.memory_mut(|memory| memory.request_focus(name_id));
+                    }
+                }
+
+                if ui.small_button("✕").on_hover_text(tr("Close")).clicked() {
+                    close = Some(n);
+                }
+                ui.separator();
+            }
+
+            if ui.small_button("+").on_hover_text(tr("New Tab")).clicked() {
+                self.add_document();
+            }
+        });
+
+        if let Some(index) = switch {
+            self.switch_document(index);
+        }
+        if let Some(index) = close {
+            self.request(PendingAction::CloseDocument(index));
+        }
+    }
+
+    /// Copies the selected nodes and the wires between them on Ctrl+C, and pastes them back on
+    /// Ctrl+V centered on the pointer, keeping their relative positions.
+    ///
+    /// The nodes go through the system clipboard as JSON, since the integration only reports
+    /// Ctrl+V along with the text it holds. That also lets them be pasted into another window.
+    fn handle_clipboard(&mut self, ctx: &egui::Context) {
+        if self.viewer.text_input_focused(ctx) {
+            return;
+        }
+
+        let (copy, pasted_text) = ctx.input(|input| {
+            (
+                input.events.iter().any(|e| matches!(e, egui::Event::Copy)),
+                input.events.iter().find_map(|e| match e {
+                    egui::Event::Paste(text) => Some(text.clone()),
+                    _ => None,
+                }),
+            )
+        });
+
+        let current = self.viewer.current.clone();
+        let mut current = current.borrow_mut();
+
+        if copy {
+            let selected = self.viewer.selection.clone();
+            let fragment = model::copy_nodes(&current.snarl, &selected);
+            let bbox = fragment
+                .nodes
+                .iter()
+                .map(|(node_id, pos, _)| {
+                    self.viewer
+                        .node_rects
+                        .get(node_id)
+                        .copied()
+                        .unwrap_or_else(|| egui::Rect::from_min_size(*pos, DEFAULT_NODE_SIZE))
+                })
+                .reduce(|a, b| a.union(b));
+
+            if let Some(bbox) = bbox {
+                log::info!(
+                    "Copied {} node(s) and {} wire(s)",
+                    fragment.nodes.len(),
+                    fragment.wires.len()
+                );
+                ctx.copy_text(
+                    serde_json::to_string(&ClipboardContents { bbox, fragment }).unwrap(),
+                );
+            }
+        }
+
+        // Text copied from elsewhere is not for us
+        if !self.viewer.presenting
+            && let Some(text) = pasted_text
+            && let Ok(contents) = serde_json::from_str::<ClipboardContents>(&text)
+        {
+            let depth = self.viewer.previous.len()
+                + contents
+                    .fragment
+                    .nodes
+                    .iter()
+                    .map(|(_, _, node)| node.nesting_depth())
+                    .max()
+                    .unwrap_or(0);
+            if !self.viewer.check_depth(depth) {
+                return;
+            }
+            // Read back from JSON, its subsystems are all new and can't hold this level
+            self.viewer.checkpoint_level(&current, &current.snarl);
+            let offset = self.viewer.pointer_graph_pos(ctx) - contents.bbox.center();
+            let pasted = model::paste_fragment(&mut current.snarl, contents.fragment, offset);
+            self.viewer.highlighted_nodes = pasted.iter().copied().collect();
+            self.viewer.selection = pasted;
+            self.viewer.dirty = true;
+        }
+    }
+
+    /// Shows the node search box, listing the nodes matching its query anywhere in the diagram.
+    /// Picking one goes to it and highlights every match of its level.
+    fn show_search(&mut self, ui: &mut Ui) {
+        let search = &mut self.search;
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut search.query)
+                .hint_text(tr("Search nodes"))
+                .desired_width(200.0),
+        );
+        let toggled = ui
+            .selectable_label(search.regex, ".*")
+            .on_hover_text(tr(
+                "Match node names with a regular expression, to rename them",
+            ))
+            .clicked();
+        if toggled {
+            search.regex = !search.regex;
+        }
+        let now = ui.input(|input| input.time);
+        if response.changed() {
+            search.edited_at = Some(now);
+        }
+
+        // Wait for a pause in typing, since a large diagram takes a while to search
+        let submitted =
+            response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+        let run = match search.edited_at {
+            Some(edited_at) if submitted || now - edited_at >= SEARCH_DELAY => true,
+            Some(edited_at) => {
+                ui.ctx()
+                    .request_repaint_after_secs((edited_at + SEARCH_DELAY - now) as f32);
+                false
+            }
+            None => submitted || toggled,
+        };
+        if run {
+            search.run(&self.viewer.toplevel.borrow());
+        }
+
+        let renames = search.renames(&self.viewer.current_path());
+        let mut picked = None;
+        let mut rename = false;
+        egui::Popup::from_response(&response)
+            .open_memory(run.then_some(egui::SetOpenCommand::Bool(!search.query.is_empty())))
+            .close_behavior(egui::PopupCloseBehavior::CloseOnClickOutside)
+            .show(|ui| {
+                if let Some(error) = &search.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                } else if search.regex {
+                    ui.horizontal(|ui| {
+                        ui.label(tr("Replace"));
+                        ui.add(
+                            egui::TextEdit::singleline(&mut search.replacement)
+                                .hint_text("$1")
+                                .desired_width(150.0),
+                        );
+                    });
+                    ui.checkbox(&mut search.current_level_only, tr("Current subsystem only"));
+                    egui::ScrollArea::vertical()
+                        .id_salt("rename_preview")
+                        .max_height(150.0)
+                        .show(ui, |ui| {
+                            for (found, name) in &renames {
+                                ui.label(format!("{} → {name}", found.name));
+                            }
+                        });
+                    if ui
+                        .add_enabled(
+                            !renames.is_empty() && !self.viewer.presenting,
+                            egui::Button::new(tr_args(
+                                "Rename {count} node(s)",
+                                &[("count", &renames.len())],
+                            )),
+                        )
+                        .clicked()
+                    {
+                        rename = true;
+                        ui.close();
+                    }
+                    ui.separator();
+                }
+
+                if search.results.is_empty() {
+                    ui.weak(tr("No matching node"));
+                }
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        for found in &search.results {
+                            let location = std::iter::once(tr("Top"))
+                                .chain(found.path_names.iter().map(String::as_str))
+                                .collect::<Vec<_>>()
+                                .join(" › ");
+                            if ui.selectable_label(false, &found.name).clicked() {
+                                picked = Some(found.clone());
+                                ui.close();
+                            }
+                            ui.label(egui::RichText::new(location).small().weak());
+                        }
+                    });
+            });
+
+        if let Some(found) = picked {
+            let viewer = &mut self.viewer;
+            viewer.focus_node(&found.path, found.node_id);
+            if viewer.current_path() == found.path {
+                viewer.highlighted_nodes = self
+                    .search
+                    .results
+                    .iter()
+                    .filter(|other| other.path == found.path)
+                    .map(|other| other.node_id)
+                    .collect();
+            }
+        }
+        if rename {
+            self.rename_matches(&renames);
+        }
+    }
+
+    /// Gives the nodes of `renames` their new name as a single edit, then warns about the names
+    /// left clashing.
+    fn rename_matches(&mut self, renames: &[(model::NodeMatch, String)]) {
+        let viewer = &mut self.viewer;
+        if self.search.current_level_only {
+            let current = viewer.current.clone();
+            viewer.checkpoint(&current.borrow().snarl);
+        } else {
+            viewer.checkpoint_toplevel();
+        }
+
+        let mut renamed = HashSet::new();
+        for (found, name) in renames {
+            let Some((_, level)) = viewer.resolve_path(&found.path) else {
+                continue;
+            };
+            // Shared subsystems are found once per instance, their nodes are renamed once
+            if !renamed.insert((Rc::as_ptr(&level), found.node_id)) {
+                continue;
+            }
+            if let Some(node) = level.borrow_mut().snarl.get_node_mut(found.node_id) {
+                node.name = name.clone();
+            }
+        }
+        viewer.dirty = true;
+        log::info!(
+            "Renamed {} node(s) matching {:?}",
+            renamed.len(),
+            self.search.query
+        );
+        viewer.toasts.push(
+            ToastKind::Info,
+            tr_args("Renamed {count} node(s)", &[("count", &renamed.len())]),
+        );
+
+        let clashes =
+            validate::validate_with(&viewer.toplevel.borrow(), &[&validate::DuplicateNames]);
+        if !clashes.is_empty() {
+            viewer.toasts.push(
+                ToastKind::Warning,
+                tr_args(
+                    "{count} duplicate name(s) after renaming",
+                    &[("count", &clashes.len())],
+                ),
+            );
+            viewer.showing_problems = true;
+        }
+        self.search.run(&viewer.toplevel.borrow());
+    }
+
+    /// Removes the selected nodes when Delete or Backspace is pressed, asking first when there
+    /// are several of them.
+    fn handle_delete(&mut self, ctx: &egui::Context) {
+        if self.viewer.text_input_focused(ctx) || self.viewer.presenting {
+            return;
+        }
+
+        let pressed = ctx.input_mut(|input| {
+            input.consume_key(egui::Modifiers::NONE, egui::Key::Delete)
+                | input.consume_key(egui::Modifiers::NONE, egui::Key::Backspace)
+        });
+        if !pressed {
+            return;
+        }
+
+        let selected = {
+            let current = self.viewer.current.borrow();
+            self.viewer
+                .selection
+                .iter()
+                .copied()
+                .filter(|node_id| current.snarl.get_node(*node_id).is_some())
+                .collect::<Vec<_>>()
+        };
+        let holds_subsystem = |node_id: NodeId| {
+            let current = self.viewer.current.borrow();
+            current.snarl[node_id].subsystem.is_some()
+        };
+        match selected.len() {
+            0 => {}
+            1 if holds_subsystem(selected[0]) => {
+                self.viewer.subsystem_removal = Some(selected[0]);
+            }
+            1 => self.viewer.remove_nodes(&selected),
+            _ => self.pending_deletion = Some(selected),
+        }
+    }
+
+    fn show_pending_deletion(&mut self, ctx: &egui::Context) {
+        let Some(node_ids) = self.pending_deletion.as_ref() else {
+            return;
+        };
+        let count = node_ids.len();
+
+        let modal = egui::Modal::new(Id::new("delete_nodes")).show(ctx, |ui| {
+            ui.heading(tr("Delete nodes"));
+            ui.label(tr_args(
+                "Delete the {count} selected nodes and their wires?",
+                &[("count", &count)],
+            ));
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button(tr("Delete")).clicked()
+                    && let Some(node_ids) = self.pending_deletion.take()
+                {
+                    self.viewer.remove_nodes(&node_ids);
+                }
+                if ui.button(tr("Cancel")).clicked() {
+                    self.pending_deletion = None;
+                }
+            });
+        });
+
+        if modal.should_close() {
+            self.pending_deletion = None;
+        }
+    }
+
+    /// Runs `action` right away, or asks for confirmation first if it would lose changes.
+    fn request(&mut self, action: PendingAction) {
+        let dirty = match action {
+            PendingAction::CloseDocument(index) => self.document_dirty(index),
+            _ => self.viewer.dirty,
+        };
+        if dirty {
+            self.pending_action = Some(action);
+        } else {
+            self.perform(action);
+        }
+    }
+
+    fn perform(&mut self, action: PendingAction) {
+        match action {
+            PendingAction::NewDiagram => {
+                self.viewer.load(Subsystem::new());
+                self.file_path = None;
+                self.last_saved = None;
+                log::info!("Started a new diagram");
+            }
+            PendingAction::OpenDiagram => self.open(),
+            PendingAction::OpenDropped => {
+                if let Some(path) = self.dropped_file.take() {
+                    self.open_path(path);
+                }
+            }
+            PendingAction::OpenRecent(index) => {
+                if let Some(path) = self.recent_files.get(index).cloned() {
+                    self.open_path(path);
+                }
+            }
+            PendingAction::CloseDocument(index) => self.close_document(index),
+        }
+    }
+
+    /// Puts `path` at the top of the recent files.
+    fn remember_file(&mut self, path: &std::path::Path) {
+        self.recent_files.retain(|recent| recent != path);
+        self.recent_files.insert(0, path.to_path_buf());
+        self.recent_files.truncate(RECENT_FILES);
+    }
+
+    /// Lists the recent files, opening the one clicked in place of the current diagram. Files
+    /// gone since they were listed are grayed out.
+    fn show_recent_files(&mut self, ui: &mut Ui) {
+        if self.recent_files.is_empty() {
+            ui.weak(tr("No recent files"));
+            return;
+        }
+
+        let mut clicked = None;
+        for (index, path) in self.recent_files.iter().enumerate() {
+            let name = path.file_name().map_or_else(
+                || path.display().to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            );
+            let exists = path.exists();
+            let response = ui
+                .add_enabled(exists, egui::Button::new(name))
+                .on_hover_text(path.display().to_string())
+                .on_disabled_hover_text(tr("The file no longer exists"));
+            if response.clicked() {
+                clicked = Some(index);
+                ui.close();
+            }
+        }
+
+        ui.separator();
+        if ui.button(tr("Clear Recent")).clicked() {
+            self.recent_files.clear();
+            ui.close();
+        }
+
+        if let Some(index) = clicked {
+            self.request(PendingAction::OpenRecent(index));
+        }
+    }
+
+    /// Asks for a diagram file and opens it in place of the current diagram.
+    fn open(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Diagram", &["json"])
+            .pick_file()
+        else {
+            return;
+        };
+        self.open_path(path);
+    }
+
+    /// Opens the diagram at `path` in place of the current diagram.
+    fn open_path(&mut self, path: std::path::PathBuf) {
+        match read_diagram(&path) {
+            Ok(toplevel) => {
+                self.viewer.load(toplevel);
+                log::info!("Opened {}", path.display());
+                self.remember_file(&path);
+                self.file_path = Some(path);
+                self.last_saved = None;
+            }
+            Err(e) => self.viewer.toasts.push(ToastKind::Error, e),
+        }
+    }
+
+    /// Opens the diagram at `path` in a new tab named after the file.
+    fn open_in_new_tab(&mut self, path: std::path::PathBuf) {
+        match read_diagram(&path) {
+            Ok(toplevel) => {
+                let name = path
+                    .file_stem()
+                    .map_or("Untitled".into(), |name| name.to_string_lossy())
+                    .into_owned();
+                let mut document = Document::new(name, toplevel);
+                log::info!("Opened {} in a new tab", path.display());
+                self.remember_file(&path);
+                document.file_path = Some(path);
+                self.documents.push(document);
+                self.switch_document(self.documents.len() - 1);
+            }
+            Err(e) => self.viewer.toasts.push(ToastKind::Error, e),
+        }
+    }
+
+    /// Opens the files dropped on the window: a single diagram replaces the current one, once
+    /// the user agrees to lose its changes, while several each get their own tab. Port CSV files
+    /// are imported into the current subsystem.
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        if ctx.input(|input| !input.raw.hovered_files.is_empty()) {
+            let painter = ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                Id::new("file_drop"),
+            ));
+            let rect = ctx.screen_rect();
+            painter.rect_filled(rect, 0.0, Color32::from_black_alpha(160));
+            painter.text(
+                rect.center(),
+                egui::Align2::CENTER_CENTER,
+                tr("Drop diagrams to open them"),
+                egui::FontId::proportional(24.0),
+                Color32::WHITE,
+            );
+        }
+
+        let paths = ctx.input(|input| {
+            input
+                .raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect::<Vec<_>>()
+        });
+        if paths.is_empty() {
+            return;
+        }
+
+        let extension = |path: &std::path::Path| {
+            path.extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+        };
+        let diagrams = paths
+            .iter()
+            .filter(|path| extension(path).as_deref() == Some("json"))
+            .count();
+        for path in paths {
+            match extension(&path).as_deref() {
+                Some("json") if diagrams == 1 => {
+                    self.dropped_file = Some(path);
+                    self.request(PendingAction::OpenDropped);
+                }
+                Some("json") => self.open_in_new_tab(path),
+                Some("csv") => self.import_port_csv_from(&path),
+                _ => self.viewer.toasts.push(
+                    ToastKind::Warning,
+                    tr_args(
+                        "{path}: not a file that can be opened",
+                        &[("path", &path.display())],
+                    ),
+                ),
+            }
+        }
+    }
+
+    /// Saves the diagram to the file it was last saved to or opened from, asking for one if
+    /// there is none.
+    fn save_file(&mut self) {
+        match self.file_path.clone() {
+            Some(path) => self.save_file_to(path),
+            None => self.save_file_as(),
+        }
+    }
+
+    /// Asks for a file and saves the diagram to it.
+    fn save_file_as(&mut self) {
+        let mut dialog = rfd::FileDialog::new()
+            .add_filter("Diagram", &["json"])
+            .set_file_name("diagram.json");
+        if let Some(directory) = self.file_path.as_ref().and_then(|path| path.parent()) {
+            dialog = dialog.set_directory(directory);
+        }
+
+        if let Some(path) = dialog.save_file() {
+            self.save_file_to(path);
+        }
+    }
+
+    /// Steps [`Preferences::ui_scale`] through [`UI_SCALES`] with the zoom shortcuts of egui, and
+    /// hands it to egui once it changed. egui never changes it back, its own shortcuts being off.
+    fn apply_ui_scale(&mut self, ctx: &egui::Context) {
+        use egui::gui_zoom::kb_shortcuts;
+
+        let scale = &mut self.viewer.preferences.ui_scale;
+        ctx.input_mut(|input| {
+            if input.consume_shortcut(&kb_shortcuts::ZOOM_RESET) {
+                *scale = 1.0;
+            } else if input.consume_shortcut(&kb_shortcuts::ZOOM_IN)
+                || input.consume_shortcut(&kb_shortcuts::ZOOM_IN_SECONDARY)
+            {
+                *scale = UI_SCALES
+                    .into_iter()
+                    .find(|choice| choice > scale)
+                    .unwrap_or(UI_SCALES[7]);
+            } else if input.consume_shortcut(&kb_shortcuts::ZOOM_OUT) {
+                *scale = UI_SCALES
+                    .into_iter()
+                    .rev()
+                    .find(|choice| choice < scale)
+                    .unwrap_or(UI_SCALES[0]);
+            }
+        });
+
+        if *scale != self.applied_ui_scale {
+            ctx.set_zoom_factor(*scale);
+            self.applied_ui_scale = *scale;
+            log::info!("Scaled the interface to {:.0}%", *scale * 100.0);
+        }
+    }
+
+    /// Asks for a file and exports the diagram to it as Graphviz DOT.
+    fn export_dot(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Graphviz", &["dot", "gv"])
+            .set_file_name("diagram.dot")
+            .save_file()
+        else {
+            return;
+        };
+
+        let dot = export::to_dot(&self.viewer.toplevel.borrow());
+        match std::fs::write(&path, dot) {
+            Ok(()) => log::info!("Exported {}", path.display()),
+            Err(e) => self
+                .viewer
+                .toasts
+                .push(ToastKind::Error, format!("{}: {e}", path.display())),
+        }
+    }
+
+    /// Asks for a file and exports the diagram to it as a Mermaid flowchart.
+    fn export_mermaid(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Mermaid", &["mmd", "mermaid"])
+            .set_file_name("diagram.mmd")
+            .save_file()
+        else {
+            return;
+        };
+
+        let mermaid = export::to_mermaid(&self.viewer.toplevel.borrow());
+        match std::fs::write(&path, mermaid) {
+            Ok(()) => log::info!("Exported {}", path.display()),
+            Err(e) => self
+                .viewer
+                .toasts
+                .push(ToastKind::Error, format!("{}: {e}", path.display())),
+        }
+    }
+
+    /// Asks for a file and writes Rust skeleton code generated from the whole diagram to it.
+    fn export_rust(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Rust", &["rs"])
+            .set_file_name("diagram.rs")
+            .save_file()
+        else {
+            return;
+        };
+
+        let code = export::to_rust(&self.viewer.toplevel.borrow());
+        match std::fs::write(&path, code) {
+            Ok(()) => log::info!("Exported {}", path.display()),
+            Err(e) => self
+                .viewer
+                .toasts
+                .push(ToastKind::Error, format!("{}: {e}", path.display())),
+        }
+    }
 
-impl DiagramApp {
-    pub fn new(cx: &CreationContext) -> Self {
-        egui_extras::install_image_loaders(&cx.egui_ctx);
+    /// Asks for a file and draws the current subsystem to it as SVG, along with the ones nested
+    /// inside it if asked.
+    fn export_svg(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("SVG", &["svg"])
+            .set_file_name("diagram.svg")
+            .save_file()
+        else {
+            return;
+        };
 
-        let toplevel = cx.storage.map_or_else(Subsystem::new, |storage| {
-            storage
-                .get_string("toplevel")
-                .and_then(|subsystem| serde_json::from_str(&subsystem).ok())
-                .unwrap_or_default()
+        let svg = export::to_svg(
+            &self.viewer.current.borrow(),
+            self.svg_recursive,
+            self.export_legend,
+            |data_type| self.viewer.preferences.palette.data_type_color(data_type),
+        );
+        match std::fs::write(&path, svg) {
+            Ok(()) => log::info!("Exported {}", path.display()),
+            Err(e) => self
+                .viewer
+                .toasts
+                .push(ToastKind::Error, format!("{}: {e}", path.display())),
+        }
+    }
+
+    /// Lists the checkpoints of the session, with a field to save a new one. Restoring one
+    /// replaces the whole diagram and goes back to the top level.
+    fn show_checkpoints(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            let hint = tr_args(
+                "Checkpoint {number}",
+                &[("number", &(self.checkpoints.len() + 1))],
+            );
+            ui.add(
+                egui::TextEdit::singleline(&mut self.checkpoint_name)
+                    .hint_text(&hint)
+                    .desired_width(120.0),
+            );
+            if ui.button(tr("Save Checkpoint")).clicked() {
+                let name = match self.checkpoint_name.trim() {
+                    "" => hint,
+                    name => name.to_string(),
+                };
+                let json = serde_json::to_string(&*self.viewer.toplevel.borrow())
+                    .expect("diagrams are always serializable");
+                log::info!("Saved checkpoint \"{name}\"");
+                self.checkpoints.push((name, json));
+                self.checkpoint_name.clear();
+            }
         });
 
-        let style = cx.storage.map_or_else(default_style, |storage| {
-            storage
-                .get_string("style")
-                .and_then(|style| serde_json::from_str(&style).ok())
-                .unwrap_or_else(default_style)
+        if self.checkpoints.is_empty() {
+            ui.weak(tr("No checkpoints yet"));
+        }
+
+        let mut restore = None;
+        let mut remove = None;
+        for (n, (name, _)) in self.checkpoints.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(name);
+                if ui.small_button(tr("Restore")).clicked() {
+                    restore = Some(n);
+                }
+                if ui.small_button("✕").on_hover_text(tr("Forget")).clicked() {
+                    remove = Some(n);
+                }
+            });
+        }
+
+        if let Some(n) = restore {
+            let (name, json) = &self.checkpoints[n];
+            let mut toplevel = serde_json::from_str::<Subsystem>(json)
+                .expect("checkpoints are serialized diagrams");
+            model::relink_instances(&mut toplevel);
+            self.viewer.load(toplevel);
+            self.viewer.dirty = true;
+            log::info!("Restored checkpoint \"{name}\"");
+        }
+        if let Some(n) = remove {
+            self.checkpoints.remove(n);
+        }
+    }
+
+    /// Asks for a file and schedules a capture of the canvas to it, fitting the whole diagram in
+    /// view for the capture if `whole`.
+    fn export_png(&mut self, whole: bool) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("PNG", &["png"])
+            .set_file_name("diagram.png")
+            .save_file()
+        else {
+            return;
+        };
+
+        let restore_view = whole.then_some(self.viewer.to_global);
+        self.viewer.fit_pending |= whole;
+        self.png_export = Some(PngExport {
+            path,
+            restore_view,
+            wait_frames: PNG_SETTLE_FRAMES,
+            requested: false,
+            legend: self.export_legend,
         });
+    }
 
-        let system = Rc::new(RefCell::new(toplevel));
+    /// Drives the pending PNG export: requests the screenshot once the canvas settled, then
+    /// writes the part showing the canvas once it arrives.
+    fn capture_png(&mut self, ctx: &egui::Context) {
+        let Some(export) = self.png_export.as_mut() else {
+            return;
+        };
 
-        Self {
-            viewer: DiagramViewer {
-                toplevel: system.clone(),
-                current: system,
-                previous: Vec::default(),
-            },
-            style,
+        if !export.requested {
+            if export.wait_frames > 0 {
+                export.wait_frames -= 1;
+            } else {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Screenshot(Default::default()));
+                export.requested = true;
+            }
+            ctx.request_repaint();
+            return;
+        }
+
+        let screenshot = ctx.input(|input| {
+            input.raw.events.iter().find_map(|event| match event {
+                egui::Event::Screenshot { image, .. } => Some(image.clone()),
+                _ => None,
+            })
+        });
+        let Some(screenshot) = screenshot else {
+            return;
+        };
+        let Some(export) = self.png_export.take() else {
+            return;
+        };
+
+        self.viewer.pending_view = export.restore_view;
+        let image = screenshot.region(&self.viewer.viewport, Some(ctx.pixels_per_point()));
+        let [width, height] = image.size.map(|size| size as u32);
+        let written = image::save_buffer_with_format(
+            &export.path,
+            image.as_raw(),
+            width,
+            height,
+            image::ExtendedColorType::Rgba8,
+            image::ImageFormat::Png,
+        );
+        match written {
+            Ok(()) => log::info!("Exported {}", export.path.display()),
+            Err(e) => self
+                .viewer
+                .toasts
+                .push(ToastKind::Error, format!("{}: {e}", export.path.display())),
+        }
+    }
+
+    /// Writes the ports of the current subsystem, and of the ones nested inside it if asked, as
+    /// CSV.
+    fn export_port_csv(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("CSV", &["csv"])
+            .set_file_name("ports.csv")
+            .save_file()
+        else {
+            return;
+        };
+
+        let csv = export::to_port_csv(&self.viewer.current.borrow(), self.port_csv_recursive);
+        match std::fs::write(&path, csv) {
+            Ok(()) => log::info!("Exported {}", path.display()),
+            Err(e) => self
+                .viewer
+                .toasts
+                .push(ToastKind::Error, format!("{}: {e}", path.display())),
+        }
+    }
+
+    /// Asks for a diagram file and lists how the current diagram differs from it.
+    fn compare(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Diagram", &["json"])
+            .pick_file()
+        else {
+            return;
+        };
+        match read_diagram(&path) {
+            Ok(other) => {
+                let differences = diff::diff(&other, &self.viewer.toplevel.borrow());
+                log::info!(
+                    "Compared with {}: {} difference(s)",
+                    path.display(),
+                    differences.count()
+                );
+                let name = path
+                    .file_name()
+                    .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+                self.comparison = Some((name, differences));
+            }
+            Err(e) => self.viewer.toasts.push(ToastKind::Error, e),
+        }
+    }
+
+    /// Adds the nodes of a port listing to the current subsystem, in a row at the center of
+    /// the view.
+    fn import_port_csv(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("CSV", &["csv"])
+            .pick_file()
+        else {
+            return;
+        };
+        self.import_port_csv_from(&path);
+    }
+
+    /// Adds the nodes listed in the port CSV file at `path` to the current subsystem.
+    fn import_port_csv_from(&mut self, path: &std::path::Path) {
+        let nodes = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| export::nodes_from_port_csv(&text));
+        let nodes = match nodes {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                self.viewer
+                    .toasts
+                    .push(ToastKind::Error, format!("{}: {e}", path.display()));
+                return;
+            }
+        };
+
+        let viewer = &mut self.viewer;
+        let current = viewer.current.clone();
+        let mut current = current.borrow_mut();
+        viewer.checkpoint_level(&current, &current.snarl);
+        let mut pos = viewer.to_global.inverse() * viewer.viewport.center();
+        let count = nodes.len();
+        let mut names = model::node_names(&current.snarl);
+        for mut node in nodes {
+            model::claim_unique_name(&mut node, &mut names);
+            let width = node.width;
+            model::add_node(&mut current.snarl, viewer.snap_to_grid(pos), node);
+            pos.x += width + 80.0;
+        }
+        viewer.dirty = true;
+        log::info!("Imported {count} node(s) from {}", path.display());
+    }
+
+    fn save_file_to(&mut self, path: std::path::PathBuf) {
+        let toplevel = self.viewer.toplevel.borrow();
+        let json = serde_json::to_string_pretty(&model::SavedDocument::new(&toplevel)).unwrap();
+        drop(toplevel);
+        match std::fs::write(&path, json) {
+            Ok(()) => {
+                log::info!("Saved {}", path.display());
+                self.remember_file(&path);
+                self.file_path = Some(path);
+                self.last_saved = Some(Instant::now());
+                self.viewer.dirty = false;
+            }
+            Err(e) => self
+                .viewer
+                .toasts
+                .push(ToastKind::Error, format!("{}: {e}", path.display())),
+        }
+    }
+
+    /// Name and saved diagram of each open document.
+    fn stored_documents(&self) -> Vec<(String, String)> {
+        self.documents
+            .iter()
+            .enumerate()
+            .map(|(n, document)| {
+                let toplevel = if n == self.active {
+                    &self.viewer.toplevel
+                } else {
+                    &document.toplevel
+                };
+                let toplevel = toplevel.borrow();
+                let toplevel = model::SavedDocument::new(&toplevel);
+                (
+                    document.name.clone(),
+                    serde_json::to_string(&toplevel).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    /// Copies the open diagrams to the recovery file once [`Preferences::autosave_interval`]
+    /// has passed, if any has unsaved changes.
+    fn autosave(&mut self, ctx: &egui::Context) {
+        let interval = self.viewer.preferences.autosave_interval;
+        let dirty = self.viewer.dirty
+            || self
+                .documents
+                .iter()
+                .enumerate()
+                .any(|(n, document)| n != self.active && document.dirty);
+        if interval == 0 || !dirty || self.recovery.is_some() {
+            return;
+        }
+
+        let interval = Duration::from_secs(interval.into());
+        let elapsed = self.autosave_started.elapsed();
+        if elapsed < interval {
+            // Nothing else may wake the editor up in time
+            ctx.request_repaint_after(interval - elapsed);
+            return;
+        }
+
+        self.autosave_started = Instant::now();
+        let recovery = Recovery {
+            saved_at: unix_time(),
+            documents: self.stored_documents(),
+        };
+        match recovery.write() {
+            Ok(()) => {
+                self.last_autosave = Some(Instant::now());
+                log::info!("Autosaved {} diagram(s)", recovery.documents.len());
+            }
+            Err(e) => self.viewer.toasts.push(
+                ToastKind::Warning,
+                tr_args("Could not autosave: {error}", &[("error", &e)]),
+            ),
+        }
+    }
+
+    /// Offers to restore the diagrams of a recovery file found at startup.
+    fn show_recovery(&mut self, ctx: &egui::Context) {
+        let Some(recovery) = self.recovery.as_ref() else {
+            return;
+        };
+
+        let mut restore = false;
+        let mut discard = false;
+        egui::Modal::new(Id::new("recovery")).show(ctx, |ui| {
+            ui.heading(tr("Recover unsaved work"));
+            ui.label(tr(
+                "The editor closed without storing its diagrams. A copy of them was kept.",
+            ));
+            let age = unix_time().saturating_sub(recovery.saved_at);
+            ui.weak(tr_args(
+                "{count} diagram(s), copied {ago}",
+                &[
+                    ("count", &recovery.documents.len()),
+                    ("ago", &format_ago(Duration::from_secs(age))),
+                ],
+            ));
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                restore = ui.button(tr("Restore")).clicked();
+                discard = ui.button(tr("Discard")).clicked();
+            });
+        });
+
+        if restore && let Some(recovery) = self.recovery.take() {
+            let mut documents = load_documents(recovery.documents);
+            if documents.is_empty() {
+                self.viewer.toasts.push(
+                    ToastKind::Error,
+                    tr("The recovery file holds no readable diagram"),
+                );
+                // Kept, it would be offered again on every start
+                Recovery::remove();
+                return;
+            }
+            // None of them made it to a file
+            for document in &mut documents {
+                document.dirty = true;
+            }
+            self.swap_active();
+            self.documents = documents;
+            self.active = 0;
+            self.lend_active();
+            Recovery::remove();
+            log::info!(
+                "Restored {} diagram(s) from the recovery file",
+                self.documents.len()
+            );
+        } else if discard {
+            self.recovery = None;
+            Recovery::remove();
+            log::info!("Discarded the recovery file");
+        }
+    }
+
+    /// Whether the active diagram has unsaved changes, when it was last saved or copied, and
+    /// where the canvas stands.
+    fn show_status(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if self.viewer.dirty {
+                ui.label(tr("Unsaved changes"));
+            }
+            if let Some(saved) = self.last_saved {
+                ui.weak(tr_args(
+                    "Saved {ago}",
+                    &[("ago", &format_ago(saved.elapsed()))],
+                ));
+            }
+            if let Some(autosaved) = self.last_autosave {
+                ui.weak(tr_args(
+                    "Autosaved {ago}",
+                    &[("ago", &format_ago(autosaved.elapsed()))],
+                ));
+            }
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                self.viewer.show_canvas_status(ui);
+            });
+        });
+        if self.last_saved.is_some() || self.last_autosave.is_some() {
+            // Keep the times current
+            ui.ctx().request_repaint_after(Duration::from_secs(1));
+        }
+    }
+
+    fn show_pending_action(&mut self, ctx: &egui::Context) {
+        let Some(action) = self.pending_action else {
+            return;
+        };
+
+        let modal = egui::Modal::new(Id::new("discard_changes")).show(ctx, |ui| {
+            ui.heading(tr("Unsaved changes"));
+            ui.label(tr("The diagram has unsaved changes that will be lost."));
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                if ui.button(tr("Discard Changes")).clicked() {
+                    self.pending_action = None;
+                    self.perform(action);
+                }
+                if ui.button(tr("Cancel")).clicked() {
+                    self.pending_action = None;
+                }
+            });
+        });
+
+        if modal.should_close() {
+            self.pending_action = None;
+        }
+    }
+
+    /// Lets the user pick how the wires are routed between their pins.
+    fn show_wire_style(&mut self, ui: &mut Ui) {
+        let current = match self.style.wire_style {
+            Some(WireStyle::Line) => WIRE_STYLES[2].0,
+            Some(WireStyle::AxisAligned { .. }) => WIRE_STYLES[1].0,
+            Some(WireStyle::Bezier3 | WireStyle::Bezier5) | None => WIRE_STYLES[0].0,
+        };
+        egui::ComboBox::from_label(tr("Wires"))
+            .selected_text(tr(current))
+            .show_ui(ui, |ui| {
+                for (name, style) in WIRE_STYLES {
+                    if ui.selectable_label(current == name, tr(name)).clicked() {
+                        self.style.wire_style = Some(style);
+                    }
+                }
+            });
+    }
+
+    /// Lets the user pick a built-in or saved style, and save the current one under a name.
+    fn show_style_presets(&mut self, ui: &mut Ui) {
+        egui::ComboBox::from_label(tr("Preset"))
+            .selected_text(self.style_preset.as_str())
+            .show_ui(ui, |ui| {
+                for (name, style) in builtin_styles() {
+                    if ui
+                        .selectable_label(self.style_preset == name, name)
+                        .clicked()
+                    {
+                        self.style = style;
+                        self.style_preset = name.to_string();
+                    }
+                }
+                if !self.style_presets.is_empty() {
+                    ui.separator();
+                }
+                for (name, style) in &self.style_presets {
+                    if ui
+                        .selectable_label(self.style_preset == *name, name)
+                        .clicked()
+                    {
+                        self.style = *style;
+                        self.style_preset = name.clone();
+                    }
+                }
+            });
+
+        ui.text_edit_singleline(&mut self.style_preset);
+        let name = self.style_preset.trim().to_string();
+        let builtin = builtin_styles().iter().any(|(builtin, _)| *builtin == name);
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(
+                    !name.is_empty() && !builtin,
+                    egui::Button::new(tr("Save Preset")),
+                )
+                .on_disabled_hover_text(tr("Name the preset, built-in ones cannot be replaced"))
+                .clicked()
+            {
+                self.style_presets.insert(name.clone(), self.style);
+                log::info!("Saved style preset \"{name}\"");
+            }
+            if ui
+                .add_enabled(
+                    self.style_presets.contains_key(&name),
+                    egui::Button::new(tr("Delete Preset")),
+                )
+                .clicked()
+            {
+                self.style_presets.remove(&name);
+                log::info!("Deleted style preset \"{name}\"");
+            }
+        });
+        if ui.button(tr("Reset to Default")).clicked() {
+            self.style = default_style();
+            self.style_preset.clear();
+        }
+    }
+
+    /// Keeps the window title in sync with the open file and its unsaved changes.
+    fn update_title(&mut self, ctx: &egui::Context) {
+        let name = self
+            .file_path
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map_or("Untitled".into(), |name| name.to_string_lossy());
+        let title = format!(
+            "{name}{} - Diagram",
+            if self.viewer.dirty { "*" } else { "" }
+        );
+
+        if title != self.title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.title = title;
         }
     }
 }
 
+/// Reads a diagram saved as JSON.
+fn read_diagram(path: &std::path::Path) -> Result<Subsystem, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut subsystem = serde_json::from_str(&json)
+        .map_err(|e| e.to_string())
+        .and_then(model::migrate)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    model::relink_instances(&mut subsystem);
+    Ok(subsystem)
+}
+
+/// Runs the headless command line mode requested by `args`, if any.
+///
+/// Returns `None` when the editor should start normally.
+fn run_headless(args: &[String]) -> Option<Result<(), String>> {
+    let [_, mode, path] = args else {
+        return None;
+    };
+
+    let load = || read_diagram(path.as_ref());
+
+    match mode.as_str() {
+        "--validate" => Some(load().and_then(|subsystem| {
+            let diagnostics = validate::validate(&subsystem);
+            println!("{}", serde_json::to_string_pretty(&diagnostics).unwrap());
+            if validate::has_errors(&diagnostics) {
+                Err(format!("{path}: the diagram has errors"))
+            } else {
+                Ok(())
+            }
+        })),
+        _ => None,
+    }
+}
+
 fn main() -> eframe::Result<()> {
+    // Actions are logged through `log`; set `RUST_LOG=diagram_editor=info` (or `debug`) to see them.
+    env_logger::init();
+
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(result) = run_headless(&args) {
+        if let Err(e) = result {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([400.0, 300.0])
@@ -689,7 +6581,7 @@ fn main() -> eframe::Result<()> {
     };
 
     eframe::run_native(
-        "Diagram",
+        APP_NAME,
         native_options,
         Box::new(|cx| Ok(Box::new(DiagramApp::new(cx)))),
     )
@@ -697,42 +6589,467 @@ fn main() -> eframe::Result<()> {
 
 impl App for DiagramApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.viewer.apply_pending_removals();
+        self.apply_ui_scale(ctx);
+        if ctx.input(|input| input.pointer.any_pressed()) {
+            self.viewer.highlighted_nodes.clear();
+        }
+
+        if ctx.input_mut(|input| input.consume_shortcut(&NEW_SHORTCUT)) {
+            self.request(PendingAction::NewDiagram);
+        }
+        if ctx.input_mut(|input| input.consume_shortcut(&OPEN_SHORTCUT)) {
+            self.request(PendingAction::OpenDiagram);
+        }
+        // Save As first, since the Save shortcut also matches with Shift held
+        if ctx.input_mut(|input| input.consume_shortcut(&SAVE_AS_SHORTCUT)) {
+            self.save_file_as();
+        } else if ctx.input_mut(|input| input.consume_shortcut(&SAVE_SHORTCUT)) {
+            self.save_file();
+        }
+        self.handle_clipboard(ctx);
+        self.handle_delete(ctx);
+        self.viewer.handle_navigation_keys(ctx);
+
+        // Text fields have their own undo
+        if !self.viewer.text_input_focused(ctx) && !self.viewer.presenting {
+            // Redo first, since the undo shortcut also matches with Shift held
+            if ctx.input_mut(|input| input.consume_shortcut(&REDO_SHORTCUT)) {
+                self.viewer.redo();
+            } else if ctx.input_mut(|input| input.consume_shortcut(&UNDO_SHORTCUT)) {
+                self.viewer.undo();
+            }
+        }
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::MenuBar::new().ui(ui, |ui| {
-                ui.menu_button("File", |ui| {
-                    if ui.button("Quit").clicked() {
+                ui.menu_button(tr("File"), |ui| {
+                    if ui
+                        .add(
+                            egui::Button::new(tr("New"))
+                                .shortcut_text(ctx.format_shortcut(&NEW_SHORTCUT)),
+                        )
+                        .clicked()
+                    {
+                        self.request(PendingAction::NewDiagram);
+                        ui.close();
+                    }
+
+                    if ui
+                        .add(
+                            egui::Button::new(tr("Open…"))
+                                .shortcut_text(ctx.format_shortcut(&OPEN_SHORTCUT)),
+                        )
+                        .clicked()
+                    {
+                        self.request(PendingAction::OpenDiagram);
+                        ui.close();
+                    }
+
+                    ui.menu_button(tr("Recent"), |ui| self.show_recent_files(ui));
+
+                    ui.separator();
+
+                    if ui
+                        .add(
+                            egui::Button::new(tr("Save"))
+                                .shortcut_text(ctx.format_shortcut(&SAVE_SHORTCUT)),
+                        )
+                        .clicked()
+                    {
+                        self.save_file();
+                        ui.close();
+                    }
+
+                    if ui
+                        .add(
+                            egui::Button::new(tr("Save As…"))
+                                .shortcut_text(ctx.format_shortcut(&SAVE_AS_SHORTCUT)),
+                        )
+                        .clicked()
+                    {
+                        self.save_file_as();
+                        ui.close();
+                    }
+
+                    if ui.button(tr("Compare…")).clicked() {
+                        self.compare();
+                        ui.close();
+                    }
+
+                    ui.menu_button(tr("Import"), |ui| {
+                        if ui.button(tr("Port CSV…")).clicked() {
+                            self.import_port_csv();
+                            ui.close();
+                        }
+                    });
+
+                    ui.menu_button(tr("Export"), |ui| {
+                        if ui.button(tr("Graphviz…")).clicked() {
+                            self.export_dot();
+                            ui.close();
+                        }
+
+                        if ui.button(tr("Mermaid…")).clicked() {
+                            self.export_mermaid();
+                            ui.close();
+                        }
+
+                        if ui.button(tr("Rust…")).clicked() {
+                            self.export_rust();
+                            ui.close();
+                        }
+
+                        ui.menu_button(tr("PNG"), |ui| {
+                            if ui.button(tr("Current View…")).clicked() {
+                                ui.close();
+                                self.export_png(false);
+                            }
+                            if ui.button(tr("Whole Diagram…")).clicked() {
+                                ui.close();
+                                self.export_png(true);
+                            }
+                        });
+
+                        ui.horizontal(|ui| {
+                            if ui.button(tr("SVG…")).clicked() {
+                                self.export_svg();
+                                ui.close();
+                            }
+                            ui.checkbox(&mut self.svg_recursive, tr("Recursive"));
+                        });
+
+                        ui.checkbox(&mut self.export_legend, tr("Include Legend"))
+                            .on_hover_text(tr("Adds the legend to SVG and PNG exports"));
+
+                        ui.horizontal(|ui| {
+                            if ui.button(tr("Port CSV…")).clicked() {
+                                self.export_port_csv();
+                                ui.close();
+                            }
+                            ui.checkbox(&mut self.port_csv_recursive, tr("Recursive"));
+                        });
+                    });
+
+                    ui.separator();
+
+                    if ui.button(tr("Quit")).clicked() {
                         ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                     }
                 });
+
+                ui.menu_button(tr("Edit"), |ui| {
+                    if ui
+                        .add_enabled(
+                            self.viewer.undo.can_undo() && !self.viewer.presenting,
+                            egui::Button::new(tr("Undo"))
+                                .shortcut_text(ctx.format_shortcut(&UNDO_SHORTCUT)),
+                        )
+                        .clicked()
+                    {
+                        self.viewer.undo();
+                        ui.close();
+                    }
+
+                    if ui
+                        .add_enabled(
+                            self.viewer.undo.can_redo() && !self.viewer.presenting,
+                            egui::Button::new(tr("Redo"))
+                                .shortcut_text(ctx.format_shortcut(&REDO_SHORTCUT)),
+                        )
+                        .clicked()
+                    {
+                        self.viewer.redo();
+                        ui.close();
+                    }
+
+                    ui.separator();
+
+                    ui.checkbox(&mut self.viewer.presenting, tr("Presentation Mode"))
+                        .on_hover_text(tr("Turn editing off, for showing finished diagrams"));
+                });
+
+                ui.menu_button(tr("Language"), |ui| {
+                    for language in i18n::Language::ALL {
+                        let language_choice = &mut self.viewer.preferences.language;
+                        if ui
+                            .radio_value(language_choice, language, language.label())
+                            .clicked()
+                        {
+                            i18n::set_language(language);
+                            ui.close();
+                        }
+                    }
+                });
                 ui.add_space(16.0);
 
                 egui::widgets::global_theme_preference_switch(ui);
+                ui.add_space(16.0);
+
+                let scale = &mut self.viewer.preferences.ui_scale;
+                egui::ComboBox::from_id_salt("ui_scale")
+                    .selected_text(format!("{:.0}%", *scale * 100.0))
+                    .show_ui(ui, |ui| {
+                        for choice in UI_SCALES {
+                            let text = format!("{:.0}%", choice * 100.0);
+                            ui.selectable_value(scale, choice, text);
+                        }
+                    })
+                    .response
+                    .on_hover_text(tr(
+                        "Size of the text and controls, the canvas zooms on its own",
+                    ));
+                ui.add_space(16.0);
+
+                self.show_search(ui);
+            });
+        });
+
+        egui::TopBottomPanel::top("tabs").show(ctx, |ui| self.show_tabs(ui));
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| self.show_status(ui));
+
+        egui::TopBottomPanel::top("breadcrumbs").show(ctx, |ui| {
+            self.viewer.show_breadcrumbs(ui);
+        });
+
+        if self.viewer.presenting {
+            egui::TopBottomPanel::top("presentation").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        tr("Presentation mode, editing is off"),
+                    );
+                    if ui.small_button(tr("Exit")).clicked() {
+                        self.viewer.presenting = false;
+                    }
+                });
+            });
+        }
+
+        egui::SidePanel::left("outline").show(ctx, |ui| {
+            ui.heading(tr("Outline"));
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                self.viewer.show_outline(ui);
+                ui.separator();
+                egui::CollapsingHeader::new(tr("Tags"))
+                    .default_open(true)
+                    .show(ui, |ui| self.viewer.show_tag_filter(ui));
+                egui::CollapsingHeader::new(tr("Checkpoints"))
+                    .show(ui, |ui| self.show_checkpoints(ui));
             });
         });
 
         egui::SidePanel::left("style").show(ctx, |ui| {
             egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::CollapsingHeader::new(tr("Legend"))
+                    .show(ui, |ui| show_legend(ui, &self.viewer.preferences));
+                egui::CollapsingHeader::new(tr("Presets"))
+                    .show(ui, |ui| self.show_style_presets(ui));
+                self.show_wire_style(ui);
+                ui.separator();
                 egui_probe::Probe::new(&mut self.style).show(ui);
+                ui.separator();
+                egui_probe::Probe::new(&mut self.viewer.preferences).show(ui);
             });
         });
 
+        if self.viewer.checking_connections {
+            let mut close = false;
+            egui::SidePanel::right("connections").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading(tr("Unconnected Ports"));
+                    close = ui.small_button("✕").on_hover_text(tr("Close")).clicked();
+                });
+                ui.separator();
+                self.viewer.show_unconnected_ports(ui);
+            });
+
+            if close {
+                self.viewer.checking_connections = false;
+                self.viewer.unconnected_nodes.clear();
+            }
+        }
+
+        if self.viewer.simulation.is_some() {
+            egui::SidePanel::right("simulation").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading(tr("Simulation"));
+                    if ui.small_button("✕").on_hover_text(tr("Close")).clicked() {
+                        self.viewer.simulation = None;
+                    }
+                });
+                ui.separator();
+                self.viewer.show_simulation(ui);
+            });
+        }
+
+        if self.viewer.showing_tests {
+            egui::SidePanel::right("test_vectors").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading(tr("Test Vectors"));
+                    if ui.small_button("✕").on_hover_text(tr("Close")).clicked() {
+                        self.viewer.showing_tests = false;
+                    }
+                });
+                ui.separator();
+                ui.add_enabled_ui(!self.viewer.presenting, |ui| {
+                    self.viewer.show_test_vectors(ui);
+                });
+            });
+        }
+
+        if self.viewer.showing_problems {
+            egui::SidePanel::right("problems").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading(tr("Problems"));
+                    if ui.small_button("✕").on_hover_text(tr("Close")).clicked() {
+                        self.viewer.showing_problems = false;
+                    }
+                });
+                ui.separator();
+                self.viewer.show_problems(ui);
+            });
+        }
+
+        if self.viewer.unused_subsystems.is_some() {
+            egui::SidePanel::right("unused").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading(tr("Unused Subsystems"));
+                    if ui.small_button("✕").on_hover_text(tr("Close")).clicked() {
+                        self.viewer.unused_subsystems = None;
+                    }
+                });
+                ui.separator();
+                self.viewer.show_unused_subsystems(ui);
+            });
+        }
+
+        if let Some((name, differences)) = self.comparison.as_ref() {
+            let mut close = false;
+            egui::SidePanel::right("comparison").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading(tr("Comparison"));
+                    close = ui.small_button("✕").on_hover_text(tr("Close")).clicked();
+                });
+                ui.label(tr_args("Changes since {name}", &[("name", &name)]));
+                ui.separator();
+                if differences.is_empty() {
+                    ui.label(tr("No differences."));
+                }
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    show_level_diff(ui, differences, 0);
+                });
+            });
+            if close {
+                self.comparison = None;
+            }
+        }
+
+        if self.viewer.showing_statistics {
+            egui::SidePanel::right("statistics").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading(tr("Statistics"));
+                    if ui.small_button("✕").on_hover_text(tr("Close")).clicked() {
+                        self.viewer.showing_statistics = false;
+                    }
+                });
+                ui.separator();
+                self.viewer.show_statistics(ui);
+            });
+        }
+
+        if self.viewer.showing_properties {
+            egui::SidePanel::right("properties").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading(tr("Properties"));
+                    if ui.small_button("✕").on_hover_text(tr("Close")).clicked() {
+                        self.viewer.showing_properties = false;
+                    }
+                });
+                ui.separator();
+                ui.add_enabled_ui(!self.viewer.presenting, |ui| {
+                    self.viewer.show_properties(ui);
+                });
+            });
+        }
+
+        self.viewer.toasts.show(ctx);
+        self.handle_dropped_files(ctx);
+        self.show_pending_action(ctx);
+        self.show_recovery(ctx);
+        self.autosave(ctx);
+        self.show_pending_deletion(ctx);
+        self.viewer.show_subsystem_removal(ctx);
+        self.update_title(ctx);
+
         egui::CentralPanel::default().show(ctx, |ui| {
-            SnarlWidget::new()
-                .id(Id::new("diagram"))
-                .style(self.style)
-                .show(
-                    &mut self.viewer.current.clone().borrow_mut().snarl,
-                    &mut self.viewer,
-                    ui,
-                );
+            self.viewer.viewport = ui.max_rect();
+            // Lend the snarl to the canvas, the viewer may need the rest of the subsystem
+            let current = self.viewer.current.clone();
+            self.viewer.update_signal_values();
+            self.viewer.decay_flash(ctx);
+            self.viewer.wire_style = self.style.wire_style.unwrap_or(WireStyle::Bezier5);
+            let mut snarl = std::mem::take(&mut current.borrow_mut().snarl);
+            let positions = snarl
+                .nodes_pos_ids()
+                .map(|(node_id, pos, _)| (node_id, pos))
+                .collect::<HashMap<_, _>>();
+            self.viewer.update_evaluation_order(&snarl);
+            let selected = get_selected_nodes(self.viewer.diagram_id, ctx);
+            let mut style = self.style;
+            if self.viewer.canvas_outdated {
+                style.select_style = Some(SelectionStyle::default());
+            }
+            let response = SnarlWidget::new()
+                .id(self.viewer.diagram_id)
+                .style(style)
+                .show(&mut snarl, &mut self.viewer, ui);
+            self.viewer.drag_selection(&mut snarl, &positions);
+            self.viewer.merge_selection(
+                &snarl,
+                &response,
+                &selected,
+                self.style.select_rect_contained,
+            );
+            self.viewer.show_groups(&mut snarl, ctx);
+            self.viewer.pin_locked_nodes(&mut snarl, &positions);
+            self.viewer.snap_moved_nodes(&mut snarl, &positions, ctx);
+            self.viewer.snap_dropped_wire(&mut snarl, ctx);
+            current.borrow_mut().snarl = snarl;
+
+            self.viewer.handle_canvas_keyboard(&response, ui);
+            self.viewer.add_node_on_double_click(&response);
+            self.viewer.show_wire_overlays(ctx);
+            self.viewer.show_wire_ends(ctx);
         });
+
+        if self.png_export.as_ref().is_some_and(|export| export.legend) {
+            egui::Area::new(Id::new("png_legend"))
+                .order(egui::Order::Foreground)
+                .pivot(egui::Align2::LEFT_BOTTOM)
+                .fixed_pos(self.viewer.viewport.left_bottom() + egui::vec2(8.0, -8.0))
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style())
+                        .show(ui, |ui| show_legend(ui, &self.viewer.preferences));
+                });
+        }
+        self.capture_png(ctx);
     }
 
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
-        let system = serde_json::to_string(&self.viewer.toplevel).unwrap();
-        storage.set_string("toplevel", system);
+        let documents = self.stored_documents();
+        storage.set_string("documents", serde_json::to_string(&documents).unwrap());
+        storage.set_string("active_document", self.active.to_string());
+        storage.set_string("saved_at", unix_time().to_string());
+        let recent_files = serde_json::to_string(&self.recent_files).unwrap();
+        storage.set_string("recent_files", recent_files);
 
         let style = serde_json::to_string(&self.style).unwrap();
         storage.set_string("style", style);
+        let style_presets = serde_json::to_string(&self.style_presets).unwrap();
+        storage.set_string("style_presets", style_presets);
+
+        let preferences = serde_json::to_string(&self.viewer.preferences).unwrap();
+        storage.set_string("preferences", preferences);
     }
 }
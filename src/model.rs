@@ -0,0 +1,3701 @@
+//! The diagram model and the operations editing it.
+//!
+//! Nothing in here draws or reads input: every operation works directly on a [`Snarl<Node>`] or
+//! a [`Subsystem`], so diagrams can be built and transformed programmatically or headlessly.
+//! Some egui types are still borrowed as plain data, the positions, the wire colors and the
+//! pan and zoom a subsystem was last shown with, since they are saved along with the diagram.
+
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use egui::{Color32, emath::TSTransform};
+use egui_snarl::{InPinId, NodeId, OutPinId, Snarl};
+use regex::Regex;
+use uuid::Uuid;
+
+/// Positions and offsets taken by the operations below, so callers need not depend on egui.
+pub use egui::{Pos2, Vec2};
+
+/// Kind of data flowing through a port.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DataType {
+    Bool,
+    Int,
+    Float,
+    Signal,
+    /// Accepts and provides any kind of data.
+    #[default]
+    Any,
+}
+
+impl DataType {
+    pub const ALL: [DataType; 5] = [
+        DataType::Bool,
+        DataType::Int,
+        DataType::Float,
+        DataType::Signal,
+        DataType::Any,
+    ];
+
+    /// Whether an output of this type may drive an input of type `input`.
+    pub fn connects_to(self, input: DataType) -> bool {
+        self == DataType::Any || input == DataType::Any || self == input
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize, Copy, PartialEq, Eq, Debug)]
+pub enum InputKind {
+    Normal,
+    External,
+    Internal,
+}
+
+impl InputKind {
+    pub const ALL: [InputKind; 3] = [InputKind::Normal, InputKind::External, InputKind::Internal];
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Input {
+    pub name: String,
+    pub kind: InputKind,
+    #[serde(default)]
+    pub data_type: DataType,
+    /// Most wires the input accepts, any number if `None`.
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self {
+            name: "Input".to_string(),
+            kind: InputKind::Normal,
+            data_type: DataType::default(),
+            max_connections: None,
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize, Copy, PartialEq, Eq, Debug)]
+pub enum OutputKind {
+    Normal,
+    External,
+    Internal,
+}
+
+impl OutputKind {
+    pub const ALL: [OutputKind; 3] = [
+        OutputKind::Normal,
+        OutputKind::External,
+        OutputKind::Internal,
+    ];
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Output {
+    pub name: String,
+    pub kind: OutputKind,
+    #[serde(default)]
+    pub data_type: DataType,
+    /// Most wires the output drives, any number if `None`.
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            name: "Output".to_string(),
+            kind: OutputKind::Normal,
+            data_type: DataType::default(),
+            max_connections: None,
+        }
+    }
+}
+
+/// What a node stands for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum NodeVariant {
+    /// A block of the system, wired to the others.
+    #[default]
+    Block,
+    /// A free-floating note with no ports, ignored by checks and exports.
+    Comment,
+}
+
+/// What a node computes when the diagram is simulated, see [`crate::simulate`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum LogicOp {
+    /// Each output copies the input at the same index.
+    #[default]
+    Pass,
+    And,
+    Or,
+    /// Each output negates the input at the same index.
+    Not,
+    /// Every output holds the value, whatever the inputs.
+    Constant(bool),
+    /// Does something the simulator cannot compute: the outputs keep the values they had.
+    Custom,
+}
+
+impl LogicOp {
+    pub const ALL: [LogicOp; 7] = [
+        LogicOp::Pass,
+        LogicOp::And,
+        LogicOp::Or,
+        LogicOp::Not,
+        LogicOp::Constant(false),
+        LogicOp::Constant(true),
+        LogicOp::Custom,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LogicOp::Pass => "Pass-Through",
+            LogicOp::And => "AND",
+            LogicOp::Or => "OR",
+            LogicOp::Not => "NOT",
+            LogicOp::Constant(false) => "Constant 0",
+            LogicOp::Constant(true) => "Constant 1",
+            LogicOp::Custom => "Custom",
+        }
+    }
+
+    /// Short symbol shown in the header of the node.
+    pub fn glyph(self) -> &'static str {
+        match self {
+            LogicOp::Pass => "=",
+            LogicOp::And => "&",
+            LogicOp::Or => "≥1",
+            LogicOp::Not => "¬",
+            LogicOp::Constant(false) => "0",
+            LogicOp::Constant(true) => "1",
+            LogicOp::Custom => "ƒ",
+        }
+    }
+}
+
+/// Which way the wires of a subsystem flow, for its auto-layout and its pins.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum LayoutDirection {
+    #[default]
+    LeftToRight,
+    /// Mirrors every node of the level, see [`Node::mirrored`].
+    RightToLeft,
+    /// Stacks the layers of the auto-layout downwards. The pins stay on the sides of the nodes.
+    TopToBottom,
+}
+
+impl LayoutDirection {
+    pub const ALL: [LayoutDirection; 3] = [
+        LayoutDirection::LeftToRight,
+        LayoutDirection::RightToLeft,
+        LayoutDirection::TopToBottom,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LayoutDirection::LeftToRight => "Left to Right",
+            LayoutDirection::RightToLeft => "Right to Left",
+            LayoutDirection::TopToBottom => "Top to Bottom",
+        }
+    }
+}
+
+/// A block of the diagram.
+///
+/// Port indices are the pin indices used by the [`Snarl`] wires, so `inputs[n]` is the port
+/// behind `InPinId { input: n, .. }`.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Node {
+    /// Identity of the node across versions of the diagram, see [`crate::diff`]. Kept when the
+    /// node moves into a subsystem, given to nodes saved before ids existed by [`migrate`].
+    #[serde(default, deserialize_with = "nil_if_null")]
+    pub id: Uuid,
+    #[serde(default)]
+    pub kind: NodeVariant,
+    pub name: String,
+    /// Text of a comment node.
+    #[serde(default)]
+    pub text: String,
+    pub inputs: Vec<Input>,
+    pub outputs: Vec<Output>,
+    pub subsystem: Option<Rc<RefCell<Subsystem>>>,
+    /// Width of the name field in the header, which sets the width of the node.
+    #[serde(default = "Node::default_width")]
+    pub width: f32,
+    /// Background color, as sRGBA. The default one leaves the node with the background of the
+    /// style.
+    #[serde(default = "Node::default_color")]
+    pub color: [u8; 4],
+    /// Labels sorting nodes into categories, like "sensor" or "controller".
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Draw the inputs on the right edge and the outputs on the left one, for parts of a diagram
+    /// flowing right to left.
+    #[serde(default)]
+    pub mirrored: bool,
+    /// What the node computes when simulated, unless it holds a subsystem.
+    #[serde(default)]
+    pub op: LogicOp,
+    /// Show a subsystem node as its name and port counts, its pins stacked along the edges.
+    #[serde(default)]
+    pub collapsed: bool,
+    /// Keep the node where it is when dragged, for blocks placed with care. It can still be
+    /// wired and selected.
+    #[serde(default)]
+    pub locked: bool,
+    /// Render every pin even when there are more than the configured limit.
+    #[serde(skip)]
+    pub expand_pins: bool,
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            kind: NodeVariant::Block,
+            name: "Node".to_string(),
+            text: String::new(),
+            inputs: Vec::default(),
+            outputs: Vec::default(),
+            subsystem: None,
+            width: Self::default_width(),
+            color: Self::default_color(),
+            tags: Vec::new(),
+            mirrored: false,
+            op: LogicOp::Pass,
+            collapsed: false,
+            locked: false,
+            expand_pins: false,
+        }
+    }
+}
+
+impl Node {
+    /// An empty comment.
+    pub fn comment() -> Self {
+        Self {
+            kind: NodeVariant::Comment,
+            name: "Comment".to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_comment(&self) -> bool {
+        self.kind == NodeVariant::Comment
+    }
+
+    /// Gives the node an id of its own, for copies that must not pass for the original.
+    pub fn renew_id(&mut self) {
+        self.id = Uuid::new_v4();
+    }
+
+    /// Whether this is a subsystem node shown collapsed, see [`Node::collapsed`].
+    pub fn is_collapsed(&self) -> bool {
+        self.collapsed && self.subsystem.is_some()
+    }
+
+    /// Whether the subsystem of this node is also held by other nodes, so that editing its
+    /// boundary would change their interface too.
+    pub fn shares_subsystem(&self) -> bool {
+        self.subsystem
+            .as_ref()
+            .is_some_and(|inner| Rc::strong_count(inner) > 1)
+    }
+
+    /// Narrowest a node can be resized to.
+    pub const MIN_WIDTH: f32 = 120.0;
+
+    fn default_width() -> f32 {
+        200.0
+    }
+
+    pub fn default_color() -> [u8; 4] {
+        [30, 30, 30, 255]
+    }
+
+    /// Background color picked for this node, if any.
+    pub fn custom_color(&self) -> Option<Color32> {
+        let [r, g, b, a] = self.color;
+        (self.color != Self::default_color()).then(|| Color32::from_rgba_unmultiplied(r, g, b, a))
+    }
+
+    /// Number of subsystem levels nested below this node, 0 for a plain node.
+    pub fn nesting_depth(&self) -> usize {
+        self.subsystem
+            .as_ref()
+            .map_or(0, |subsystem| 1 + subsystem.borrow().nesting_depth())
+    }
+
+    /// Whether this node only exists to expose ports of the enclosing subsystem.
+    pub fn is_boundary(&self) -> bool {
+        (!self.inputs.is_empty() || !self.outputs.is_empty())
+            && self
+                .inputs
+                .iter()
+                .all(|input| input.kind == InputKind::External)
+            && self
+                .outputs
+                .iter()
+                .all(|output| output.kind == OutputKind::External)
+    }
+}
+
+/// Which side of a node a port is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PortSide {
+    Input,
+    Output,
+}
+
+/// A wire, identified by its two endpoints.
+pub type Wire = (OutPinId, InPinId);
+
+/// Wires whose ends moved to other ports, each with the wire it became, `None` if it was dropped
+/// along with its port. See [`Subsystem::rekey_wires`].
+pub type WireRemap = Vec<(Wire, Option<Wire>)>;
+
+/// (De)serializes maps keyed by [`Wire`] as lists of pairs, since JSON keys must be strings.
+mod wire_map {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Wire;
+
+    pub fn serialize<S: Serializer, T: Serialize>(
+        map: &HashMap<Wire, T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<Wire, T>, D::Error> {
+        Vec::<(Wire, T)>::deserialize(deserializer).map(|pairs| pairs.into_iter().collect())
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Subsystem {
+    pub snarl: Snarl<Node>,
+    /// Manual routing of wires, through these graph positions from output to input.
+    #[serde(default, with = "wire_map")]
+    pub waypoints: HashMap<Wire, Vec<Pos2>>,
+    /// Colors picked for wires, instead of the color of their data type.
+    #[serde(default, with = "wire_map")]
+    pub wire_colors: HashMap<Wire, Color32>,
+    /// Names given to wires, such as the signal they carry, shown halfway along them.
+    #[serde(default, with = "wire_map")]
+    pub wire_labels: HashMap<Wire, String>,
+    /// Key of a subsystem shared by several nodes. Each node saves its own copy, so this is
+    /// what makes them share it again once loaded, see [`relink_instances`].
+    #[serde(default)]
+    pub instance: Option<u64>,
+    /// Pan and zoom this subsystem was last shown with, if it ever was.
+    #[serde(default)]
+    pub view: Option<TSTransform>,
+    #[serde(default)]
+    pub layout_dir: LayoutDirection,
+    /// Table of boundary values this subsystem is checked against.
+    #[serde(default)]
+    pub test_vectors: Vec<TestVector>,
+    #[serde(default)]
+    pub groups: Vec<Group>,
+}
+
+/// Nodes boxed together under a name. Purely visual, the nodes keep their wires and level.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Group {
+    pub name: String,
+    pub color: Color32,
+    pub members: Vec<NodeId>,
+}
+
+/// Forgets the members of `groups` missing from `snarl`, and the groups left empty.
+pub fn prune_groups(groups: &mut Vec<Group>, snarl: &Snarl<Node>) {
+    groups.retain_mut(|group| {
+        group.members.retain(|&node| snarl.get_node(node).is_some());
+        !group.members.is_empty()
+    });
+}
+
+/// A row of the test table of a subsystem, run by [`crate::simulate::run_vector`].
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct TestVector {
+    /// Value driven into each boundary input, in the order of [`Subsystem::boundary_inputs`].
+    pub inputs: Vec<bool>,
+    /// Value expected at each boundary output, in the order of [`Subsystem::boundary_outputs`],
+    /// `None` when any value will do.
+    pub expected: Vec<Option<bool>>,
+}
+
+impl TestVector {
+    /// Whether `outputs` has every value expected.
+    pub fn passes(&self, outputs: &[bool]) -> bool {
+        self.expected
+            .iter()
+            .zip(outputs)
+            .all(|(expected, output)| expected.is_none_or(|expected| expected == *output))
+    }
+}
+
+impl Default for Subsystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Subsystem {
+    pub fn new() -> Self {
+        Self {
+            snarl: Snarl::new(),
+            waypoints: HashMap::default(),
+            wire_colors: HashMap::default(),
+            wire_labels: HashMap::default(),
+            instance: None,
+            view: None,
+            layout_dir: LayoutDirection::default(),
+            test_vectors: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+
+    /// Whether `node` of this subsystem has its inputs on the right and outputs on the left,
+    /// either on its own or because the level flows right to left.
+    pub fn is_mirrored(&self, node: &Node) -> bool {
+        node.mirrored != (self.layout_dir == LayoutDirection::RightToLeft)
+    }
+
+    /// Moves the waypoints, color and label of the wires in `remap` to the wires they became,
+    /// and forgets those of the dropped ones.
+    pub fn rekey_wires(&mut self, remap: &[(Wire, Option<Wire>)]) {
+        fn rekey<T>(data: &mut HashMap<Wire, T>, remap: &[(Wire, Option<Wire>)]) {
+            // Take everything out first, a wire may move to where another one just left
+            let moved = remap
+                .iter()
+                .filter_map(|&(old, new)| Some((new, data.remove(&old)?)))
+                .collect::<Vec<_>>();
+            data.extend(
+                moved
+                    .into_iter()
+                    .filter_map(|(new, value)| Some((new?, value))),
+            );
+        }
+        rekey(&mut self.waypoints, remap);
+        rekey(&mut self.wire_colors, remap);
+        rekey(&mut self.wire_labels, remap);
+    }
+
+    /// Forgets the data attached to wires that no longer exist.
+    pub fn prune_wire_data(&mut self) {
+        let wires = self.snarl.wires().collect::<HashSet<_>>();
+        self.waypoints.retain(|wire, _| wires.contains(wire));
+        self.wire_colors.retain(|wire, _| wires.contains(wire));
+        self.wire_labels.retain(|wire, _| wires.contains(wire));
+    }
+
+    /// Number of subsystem levels nested below this one.
+    pub fn nesting_depth(&self) -> usize {
+        self.snarl
+            .nodes()
+            .map(Node::nesting_depth)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Number of nodes and wires in this subsystem, including everything nested inside it.
+    pub fn total_counts(&self) -> (usize, usize) {
+        self.snarl
+            .nodes()
+            .filter_map(|node| node.subsystem.as_ref())
+            .map(|subsystem| subsystem.borrow().total_counts())
+            .fold(
+                (self.snarl.nodes().count(), self.snarl.wires().count()),
+                |(nodes, wires), (inner_nodes, inner_wires)| {
+                    (nodes + inner_nodes, wires + inner_wires)
+                },
+            )
+    }
+
+    /// Size figures of this subsystem, including everything nested inside it. Shared subsystems
+    /// count once per instance, as they would once expanded.
+    pub fn statistics(&self) -> Statistics {
+        let (nodes, wires) = self.total_counts();
+        let mut stats = Statistics {
+            nodes,
+            wires,
+            subsystems: 0,
+            depth: self.nesting_depth(),
+            unconnected_ports: unconnected_ports(&self.snarl).len(),
+        };
+        for inner in self
+            .snarl
+            .nodes()
+            .filter_map(|node| node.subsystem.as_ref())
+        {
+            let inner = inner.borrow().statistics();
+            stats.subsystems += 1 + inner.subsystems;
+            stats.unconnected_ports += inner.unconnected_ports;
+        }
+        stats
+    }
+
+    /// The `External` outputs feeding the inside of this subsystem, in the order of the inputs
+    /// of the node owning it: the n-th one is driven by the n-th input of that node.
+    pub fn boundary_inputs(&self) -> Vec<OutPinId> {
+        let mut pins = self
+            .snarl
+            .node_ids()
+            .flat_map(|(node_id, node)| {
+                node.outputs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, output)| output.kind == OutputKind::External)
+                    .map(move |(output, _)| OutPinId {
+                        node: node_id,
+                        output,
+                    })
+            })
+            .collect::<Vec<_>>();
+        pins.sort();
+        pins
+    }
+
+    /// The `External` inputs collecting the results of this subsystem, in the order of the
+    /// outputs of the node owning it: the n-th one drives the n-th output of that node.
+    pub fn boundary_outputs(&self) -> Vec<InPinId> {
+        let mut pins = self
+            .snarl
+            .node_ids()
+            .flat_map(|(node_id, node)| {
+                node.inputs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, input)| input.kind == InputKind::External)
+                    .map(move |(input, _)| InPinId {
+                        node: node_id,
+                        input,
+                    })
+            })
+            .collect::<Vec<_>>();
+        pins.sort();
+        pins
+    }
+}
+
+/// Version of the format diagrams are saved in, bumped whenever older versions need
+/// [`migrate`] to be read.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// A diagram as saved to a file, tagged with the version of the format.
+#[derive(serde::Serialize)]
+pub struct SavedDocument<'a> {
+    pub version: u32,
+    pub data: &'a Subsystem,
+}
+
+impl<'a> SavedDocument<'a> {
+    pub fn new(data: &'a Subsystem) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            data,
+        }
+    }
+}
+
+/// Reads a diagram saved in any version of the format, upgrading it to the current one.
+///
+/// Diagrams saved before the format was versioned hold the top level directly and count as
+/// version 0. Wires saved more than once come back as one, see [`duplicate_wires`].
+pub fn migrate(raw: serde_json::Value) -> Result<Subsystem, String> {
+    let (version, data) = match raw {
+        serde_json::Value::Object(mut fields) if fields.contains_key("version") => {
+            let version = fields
+                .get("version")
+                .and_then(serde_json::Value::as_u64)
+                .ok_or("The format version is not a number")?;
+            let data = fields.remove("data").ok_or("The diagram is missing")?;
+            (version, data)
+        }
+        raw => (0, raw),
+    };
+
+    if version > u64::from(FORMAT_VERSION) {
+        return Err(format!(
+            "Saved in format version {version}, newer than this editor understands"
+        ));
+    }
+
+    // The snarl keeps its wires in a set, so the copies of a wire merge as it is read
+    let duplicates = duplicate_wires(&data);
+    if duplicates > 0 {
+        log::warn!("Merged {duplicates} duplicate wire(s) while loading");
+    }
+
+    // Version 0 predates the node width and color, tags, comments, port data types, shared
+    // subsystems and saved views, which all read back as their defaults when missing
+    let mut subsystem: Subsystem = serde_json::from_value(data).map_err(|e| e.to_string())?;
+    if version < 2 {
+        assign_ids(&mut subsystem);
+    }
+    Ok(subsystem)
+}
+
+/// Number of wires listed more than once in the levels of `raw`, a saved subsystem, counting
+/// every level nested inside it. Legacy and hand-edited files may have them.
+pub fn duplicate_wires(raw: &serde_json::Value) -> usize {
+    match raw {
+        serde_json::Value::Object(fields) => {
+            let wires = fields
+                .get("snarl")
+                .and_then(|snarl| snarl.get("wires"))
+                .and_then(serde_json::Value::as_array);
+            let here = wires.map_or(0, |wires| {
+                let mut seen = HashSet::new();
+                wires
+                    .iter()
+                    .filter(|wire| !seen.insert(wire.to_string()))
+                    .count()
+            });
+            here + fields.values().map(duplicate_wires).sum::<usize>()
+        }
+        serde_json::Value::Array(items) => items.iter().map(duplicate_wires).sum(),
+        _ => 0,
+    }
+}
+
+/// Gives the nodes of `root` without an id one derived from where they are in the diagram, so
+/// loading the same file again gives them the same ids. The nodes of a shared subsystem get the
+/// same ids in every instance, through its instance key.
+pub fn assign_ids(root: &mut Subsystem) {
+    fn assign(snarl: &mut Snarl<Node>, prefix: &str) {
+        for (node_id, node) in snarl.nodes_ids_mut() {
+            let key = format!("{prefix}/{}", node_id.0);
+            if node.id.is_nil() {
+                node.id = Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes());
+            }
+            if let Some(inner) = node.subsystem.as_ref() {
+                let mut inner = inner.borrow_mut();
+                let prefix = match inner.instance {
+                    Some(instance) => format!("instance {instance}"),
+                    None => key,
+                };
+                assign(&mut inner.snarl, &prefix);
+            }
+        }
+    }
+
+    assign(&mut root.snarl, "");
+    log::info!("Gave ids to the nodes saved without one");
+}
+
+/// Reads a missing id saved as `null` as the nil id, left for [`assign_ids`] to replace.
+fn nil_if_null<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Uuid, D::Error> {
+    use serde::Deserialize;
+    Ok(Option::<Uuid>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Number of wires attached to any pin of `node_id`.
+pub fn boundary_wire_count(snarl: &Snarl<Node>, node_id: NodeId) -> usize {
+    snarl
+        .wires()
+        .filter(|(pin_out, pin_in)| pin_out.node == node_id || pin_in.node == node_id)
+        .count()
+}
+
+/// Inserts `node` at `pos`.
+pub fn add_node(snarl: &mut Snarl<Node>, pos: Pos2, node: Node) -> NodeId {
+    let node_id = snarl.insert_node(pos, node);
+    log::info!("Added node {node_id:?} at {pos:?}");
+    node_id
+}
+
+/// Removes `node_id` along with all its wires.
+pub fn remove_node(snarl: &mut Snarl<Node>, node_id: NodeId) -> Node {
+    let node = snarl.remove_node(node_id);
+    log::info!("Removed node {node_id:?}");
+    node
+}
+
+/// Appends `input` to the inputs of `node_id`, returning its index.
+pub fn add_input(snarl: &mut Snarl<Node>, node_id: NodeId, input: Input) -> usize {
+    let node = &mut snarl[node_id];
+    node.inputs.push(input);
+    log::info!("Added input {} to node {node_id:?}", node.inputs.len() - 1);
+    node.inputs.len() - 1
+}
+
+/// Appends `output` to the outputs of `node_id`, returning its index.
+pub fn add_output(snarl: &mut Snarl<Node>, node_id: NodeId, output: Output) -> usize {
+    let node = &mut snarl[node_id];
+    node.outputs.push(output);
+    log::info!(
+        "Added output {} to node {node_id:?}",
+        node.outputs.len() - 1
+    );
+    node.outputs.len() - 1
+}
+
+/// Appends `count` default inputs to `node_id`, numbered after the existing ones.
+pub fn add_inputs(snarl: &mut Snarl<Node>, node_id: NodeId, count: usize) {
+    let node = &mut snarl[node_id];
+    let existing = node.inputs.iter().map(|input| input.name.as_str());
+    let names = numbered_names("Input", existing, node.inputs.len() + 1, count);
+    node.inputs.extend(names.into_iter().map(|name| Input {
+        name,
+        ..Input::default()
+    }));
+    log::info!("Added {count} input(s) to node {node_id:?}");
+}
+
+/// Appends `count` default outputs to `node_id`, numbered after the existing ones.
+pub fn add_outputs(snarl: &mut Snarl<Node>, node_id: NodeId, count: usize) {
+    let node = &mut snarl[node_id];
+    let existing = node.outputs.iter().map(|output| output.name.as_str());
+    let names = numbered_names("Output", existing, node.outputs.len() + 1, count);
+    node.outputs.extend(names.into_iter().map(|name| Output {
+        name,
+        ..Output::default()
+    }));
+    log::info!("Added {count} output(s) to node {node_id:?}");
+}
+
+/// `count` names made of `prefix` and a number counting up from `first`, skipping the
+/// `existing` ones.
+fn numbered_names<'a>(
+    prefix: &str,
+    existing: impl Iterator<Item = &'a str>,
+    first: usize,
+    count: usize,
+) -> Vec<String> {
+    let existing = existing.collect::<HashSet<_>>();
+    (first..)
+        .map(|n| format!("{prefix} {n}"))
+        .filter(|name| !existing.contains(name.as_str()))
+        .take(count)
+        .collect()
+}
+
+/// `base` followed by the lowest number giving a name not in `existing`, like "Node 3".
+pub fn unique_name(base: &str, existing: &HashSet<String>) -> String {
+    (1..)
+        .map(|n| format!("{base} {n}"))
+        .find(|name| !existing.contains(name))
+        .unwrap()
+}
+
+/// Names of the nodes of `snarl`, leaving out comments.
+pub fn node_names(snarl: &Snarl<Node>) -> HashSet<String> {
+    snarl
+        .nodes()
+        .filter(|node| !node.is_comment())
+        .map(|node| node.name.clone())
+        .collect()
+}
+
+/// Renames `node` with [`unique_name`] if its name is already `taken`, then takes it. Comments
+/// keep their name.
+pub fn claim_unique_name(node: &mut Node, taken: &mut HashSet<String>) {
+    if node.is_comment() {
+        return;
+    }
+    if taken.contains(&node.name) {
+        node.name = unique_name(&node.name, taken);
+    }
+    taken.insert(node.name.clone());
+}
+
+/// Removes the `index`-th input or output of `node_id` along with its wires.
+///
+/// Wires attached to the ports after it are moved down by one, so they stay on the same port,
+/// and the returned remap tells where they went so their data can follow, see
+/// [`Subsystem::rekey_wires`]. If `node_id` holds a subsystem, the boundary port bound to the
+/// removed one goes too, along with its node once it has no port left, unless the ports of
+/// the node have drifted from the boundary, see [`interface_drift`]. Returns `None` if there is
+/// no such port, or if the subsystem is shared with other nodes, whose ports would no longer
+/// match it.
+pub fn remove_port(
+    snarl: &mut Snarl<Node>,
+    node_id: NodeId,
+    side: PortSide,
+    index: usize,
+) -> Option<WireRemap> {
+    let node = snarl.get_node_mut(node_id)?;
+    if node.shares_subsystem() {
+        log::warn!("Not removing {side:?} {index} of node {node_id:?}, its subsystem is shared");
+        return None;
+    }
+    let drifted = interface_drift(node);
+    let shift = |count: usize| {
+        (0..count)
+            .map(|n| match n.cmp(&index) {
+                Ordering::Less => Some(n),
+                Ordering::Equal => None,
+                Ordering::Greater => Some(n - 1),
+            })
+            .collect::<Vec<_>>()
+    };
+    let remap = match side {
+        PortSide::Input if index < node.inputs.len() => {
+            let remap = PortRemap {
+                inputs: shift(node.inputs.len()),
+                outputs: (0..node.outputs.len()).map(Some).collect(),
+            };
+            node.inputs.remove(index);
+            remap
+        }
+        PortSide::Output if index < node.outputs.len() => {
+            let remap = PortRemap {
+                inputs: (0..node.inputs.len()).map(Some).collect(),
+                outputs: shift(node.outputs.len()),
+            };
+            node.outputs.remove(index);
+            remap
+        }
+        _ => return None,
+    };
+
+    if let Some(inner) = node.subsystem.clone() {
+        if drifted {
+            // The index no longer tells which boundary port the removed one stood for
+            log::warn!("Left the boundary of node {node_id:?} alone, its ports have drifted");
+        } else {
+            remove_boundary_port(&mut inner.borrow_mut(), side, index);
+        }
+    }
+
+    log::info!("Removed {side:?} {index} of node {node_id:?}");
+    Some(move_wires(snarl, node_id, &remap))
+}
+
+/// Removes the boundary port of `subsystem` bound to the `index`-th port on `side` of the node
+/// owning it, and its node if that was the last port.
+fn remove_boundary_port(subsystem: &mut Subsystem, side: PortSide, index: usize) {
+    let (node_id, inner_side, inner_index) = match side {
+        PortSide::Input => match subsystem.boundary_inputs().get(index) {
+            Some(pin) => (pin.node, PortSide::Output, pin.output),
+            None => return,
+        },
+        PortSide::Output => match subsystem.boundary_outputs().get(index) {
+            Some(pin) => (pin.node, PortSide::Input, pin.input),
+            None => return,
+        },
+    };
+
+    if let Some(remap) = remove_port(&mut subsystem.snarl, node_id, inner_side, inner_index) {
+        subsystem.rekey_wires(&remap);
+    }
+    let node = &subsystem.snarl[node_id];
+    if node.inputs.is_empty() && node.outputs.is_empty() {
+        remove_node(&mut subsystem.snarl, node_id);
+    }
+    subsystem.prune_wire_data();
+}
+
+/// Whether the `Internal` ports of `node` no longer match the boundary of the subsystem it
+/// holds, see [`sync_interface`].
+pub fn interface_drift(node: &Node) -> bool {
+    let Some(inner) = node.subsystem.as_ref() else {
+        return false;
+    };
+    let inner = inner.borrow();
+    let inputs = node
+        .inputs
+        .iter()
+        .filter(|input| input.kind == InputKind::Internal)
+        .count();
+    let outputs = node
+        .outputs
+        .iter()
+        .filter(|output| output.kind == OutputKind::Internal)
+        .count();
+    inputs != inner.boundary_inputs().len() || outputs != inner.boundary_outputs().len()
+}
+
+/// Where the ports of a node went after [`sync_interface`] or a removal: the n-th entry is the new index of
+/// the n-th old port, `None` if it was removed.
+pub struct PortRemap {
+    pub inputs: Vec<Option<usize>>,
+    pub outputs: Vec<Option<usize>>,
+}
+
+/// Rebuilds the `Internal` ports of `node` from the `External` boundary ports of the subsystem
+/// it holds, named and typed after them. The ports keep their wires by name, and the rest in
+/// order. Ports of other kinds are kept after them.
+pub fn sync_interface(node: &mut Node) -> Option<PortRemap> {
+    let inner = node.subsystem.clone()?;
+    let inner = inner.borrow();
+
+    let bound_inputs = inner
+        .boundary_inputs()
+        .iter()
+        .map(|pin| {
+            let output = &inner.snarl[pin.node].outputs[pin.output];
+            Input {
+                name: output.name.clone(),
+                kind: InputKind::Internal,
+                data_type: output.data_type,
+                max_connections: None,
+            }
+        })
+        .collect();
+    let (inputs, input_map) = rebuild_ports(&node.inputs, bound_inputs, |input| {
+        (&input.name, input.kind == InputKind::Internal)
+    });
+
+    let bound_outputs = inner
+        .boundary_outputs()
+        .iter()
+        .map(|pin| {
+            let input = &inner.snarl[pin.node].inputs[pin.input];
+            Output {
+                name: input.name.clone(),
+                kind: OutputKind::Internal,
+                data_type: input.data_type,
+                max_connections: None,
+            }
+        })
+        .collect();
+    let (outputs, output_map) = rebuild_ports(&node.outputs, bound_outputs, |output| {
+        (&output.name, output.kind == OutputKind::Internal)
+    });
+
+    node.inputs = inputs;
+    node.outputs = outputs;
+    Some(PortRemap {
+        inputs: input_map,
+        outputs: output_map,
+    })
+}
+
+/// Puts `bound` in place of the ports of `old` that `info` tells are bound to a subsystem,
+/// returning the new ports along with where each old one went.
+fn rebuild_ports<P: Clone>(
+    old: &[P],
+    bound: Vec<P>,
+    info: impl Fn(&P) -> (&String, bool),
+) -> (Vec<P>, Vec<Option<usize>>) {
+    let mut map = vec![None; old.len()];
+    let mut taken = vec![false; bound.len()];
+
+    // Same name first, so reordered ports keep their wires
+    for (n, port) in old.iter().enumerate() {
+        let (name, internal) = info(port);
+        if !internal {
+            continue;
+        }
+        let found = (0..bound.len()).find(|&m| !taken[m] && info(&bound[m]).0 == name);
+        if let Some(m) = found {
+            taken[m] = true;
+            map[n] = Some(m);
+        }
+    }
+
+    // Then in order, so renamed ports keep theirs
+    for (n, port) in old.iter().enumerate() {
+        if !info(port).1 || map[n].is_some() {
+            continue;
+        }
+        if let Some(m) = (0..bound.len()).find(|&m| !taken[m]) {
+            taken[m] = true;
+            map[n] = Some(m);
+        }
+    }
+
+    let mut ports = bound;
+    for (n, port) in old.iter().enumerate() {
+        if !info(port).1 {
+            map[n] = Some(ports.len());
+            ports.push(port.clone());
+        }
+    }
+    (ports, map)
+}
+
+/// Syncs the interface of the subsystem node `node_id`, see [`sync_interface`], moving its
+/// wires along with its ports. Returns where the wires went, or `None` if the node holds no
+/// subsystem.
+pub fn sync_node_interface(snarl: &mut Snarl<Node>, node_id: NodeId) -> Option<WireRemap> {
+    let remap = sync_interface(snarl.get_node_mut(node_id)?)?;
+    let moved = move_wires(snarl, node_id, &remap);
+
+    let dropped = moved.iter().filter(|(_, new)| new.is_none()).count();
+    log::info!("Synced the interface of node {node_id:?}, dropping {dropped} wire(s)");
+    Some(moved)
+}
+
+/// Moves the wires of `node_id` to the ports its old ones went to in `remap`, dropping those of
+/// removed ports.
+fn move_wires(snarl: &mut Snarl<Node>, node_id: NodeId, remap: &PortRemap) -> WireRemap {
+    let attached = snarl
+        .wires()
+        .filter(|(pin_out, pin_in)| pin_out.node == node_id || pin_in.node == node_id)
+        .collect::<Vec<_>>();
+    // All of them go first, lest a moved wire land on one not yet moved and be dropped with it
+    for &(pin_out, pin_in) in &attached {
+        snarl.disconnect(pin_out, pin_in);
+    }
+
+    let mut moved = Vec::new();
+    for (pin_out, pin_in) in attached {
+        let output = if pin_out.node == node_id {
+            remap.outputs.get(pin_out.output).copied().flatten()
+        } else {
+            Some(pin_out.output)
+        };
+        let input = if pin_in.node == node_id {
+            remap.inputs.get(pin_in.input).copied().flatten()
+        } else {
+            Some(pin_in.input)
+        };
+
+        let new = match (output, input) {
+            (Some(output), Some(input)) => {
+                let new = (OutPinId { output, ..pin_out }, InPinId { input, ..pin_in });
+                snarl.connect(new.0, new.1);
+                Some(new)
+            }
+            _ => None,
+        };
+        if new != Some((pin_out, pin_in)) {
+            moved.push(((pin_out, pin_in), new));
+        }
+    }
+    moved
+}
+
+/// Connects `from` to `to`, provided both pins exist.
+///
+/// Returns `true` if a new wire was created.
+pub fn connect(snarl: &mut Snarl<Node>, from: OutPinId, to: InPinId) -> bool {
+    let from_exists = snarl
+        .get_node(from.node)
+        .is_some_and(|node| from.output < node.outputs.len());
+    let to_exists = snarl
+        .get_node(to.node)
+        .is_some_and(|node| to.input < node.inputs.len());
+
+    if !from_exists || !to_exists || !snarl.connect(from, to) {
+        return false;
+    }
+
+    log::info!("Connected wire {from:?} -> {to:?}");
+    true
+}
+
+/// Splits the wire from `from` to `to` with a new pass-through node at `pos`, whose ports are
+/// named and typed after the ends of the wire. Returns the id of that node.
+pub fn insert_node_on_wire(
+    snarl: &mut Snarl<Node>,
+    from: OutPinId,
+    to: InPinId,
+    pos: Pos2,
+) -> NodeId {
+    let output = snarl[from.node]
+        .outputs
+        .get(from.output)
+        .cloned()
+        .unwrap_or_default();
+    let input = snarl[to.node]
+        .inputs
+        .get(to.input)
+        .cloned()
+        .unwrap_or_default();
+
+    let node_id = add_node(
+        snarl,
+        pos,
+        Node {
+            inputs: vec![Input {
+                name: output.name,
+                kind: InputKind::Normal,
+                data_type: output.data_type,
+                max_connections: None,
+            }],
+            outputs: vec![Output {
+                name: input.name,
+                kind: OutputKind::Normal,
+                data_type: input.data_type,
+                max_connections: None,
+            }],
+            ..Node::default()
+        },
+    );
+
+    disconnect(snarl, from, to);
+    connect(
+        snarl,
+        from,
+        InPinId {
+            node: node_id,
+            input: 0,
+        },
+    );
+    connect(
+        snarl,
+        OutPinId {
+            node: node_id,
+            output: 0,
+        },
+        to,
+    );
+    node_id
+}
+
+/// Moves the inputs of `node_id` from `inputs` on and its outputs from `outputs` on to a new
+/// node at `pos`, along with their wires. The new node is a copy of the old one under a name of
+/// its own. With `link`, a new output of the old node is wired to a new input of the new one.
+///
+/// Returns the id of the new node along with where the moved wires went, see
+/// [`Subsystem::rekey_wires`], or `None` for comments and subsystem nodes, whose ports are bound
+/// to what they hold.
+pub fn split_node(
+    snarl: &mut Snarl<Node>,
+    node_id: NodeId,
+    inputs: usize,
+    outputs: usize,
+    pos: Pos2,
+    link: bool,
+) -> Option<(NodeId, WireRemap)> {
+    let node = snarl.get_node(node_id)?;
+    if node.is_comment() || node.subsystem.is_some() {
+        return None;
+    }
+    let inputs = inputs.min(node.inputs.len());
+    let outputs = outputs.min(node.outputs.len());
+
+    let moved = snarl
+        .wires()
+        .filter(|(pin_out, pin_in)| {
+            (pin_out.node == node_id && pin_out.output >= outputs)
+                || (pin_in.node == node_id && pin_in.input >= inputs)
+        })
+        .collect::<Vec<_>>();
+    for &(pin_out, pin_in) in &moved {
+        snarl.disconnect(pin_out, pin_in);
+    }
+
+    let name = unique_name(&snarl[node_id].name, &node_names(snarl));
+    let node = &mut snarl[node_id];
+    let mut half = Node {
+        name,
+        inputs: node.inputs.split_off(inputs),
+        outputs: node.outputs.split_off(outputs),
+        ..node.clone()
+    };
+    half.renew_id();
+    let half_id = add_node(snarl, pos, half);
+
+    let mut remap = WireRemap::new();
+    for (old_out, old_in) in moved {
+        let pin_out = if old_out.node == node_id && old_out.output >= outputs {
+            OutPinId {
+                node: half_id,
+                output: old_out.output - outputs,
+            }
+        } else {
+            old_out
+        };
+        let pin_in = if old_in.node == node_id && old_in.input >= inputs {
+            InPinId {
+                node: half_id,
+                input: old_in.input - inputs,
+            }
+        } else {
+            old_in
+        };
+        snarl.connect(pin_out, pin_in);
+        remap.push(((old_out, old_in), Some((pin_out, pin_in))));
+    }
+
+    if link {
+        let names = snarl[node_id]
+            .outputs
+            .iter()
+            .map(|output| output.name.clone())
+            .collect();
+        let output = Output {
+            name: unique_name("Link", &names),
+            ..Output::default()
+        };
+        let names = snarl[half_id]
+            .inputs
+            .iter()
+            .map(|input| input.name.clone())
+            .collect();
+        let input = Input {
+            name: unique_name("Link", &names),
+            ..Input::default()
+        };
+        let output = add_output(snarl, node_id, output);
+        let input = add_input(snarl, half_id, input);
+        connect(
+            snarl,
+            OutPinId {
+                node: node_id,
+                output,
+            },
+            InPinId {
+                node: half_id,
+                input,
+            },
+        );
+    }
+
+    log::info!("Split node {node_id:?} after input {inputs} and output {outputs} into {half_id:?}");
+    Some((half_id, remap))
+}
+
+/// Wires that must go for a new wire from `from` to `to` to keep both ends within their
+/// `max_connections`, the first ones attached to each end. Empty if there is room.
+pub fn wires_over_limit(snarl: &Snarl<Node>, from: OutPinId, to: InPinId) -> Vec<Wire> {
+    let output = snarl
+        .get_node(from.node)
+        .and_then(|node| node.outputs.get(from.output));
+    let input = snarl
+        .get_node(to.node)
+        .and_then(|node| node.inputs.get(to.input));
+    let (Some(output), Some(input)) = (output, input) else {
+        return Vec::new();
+    };
+
+    // Room for the new wire once the excess is gone
+    let excess =
+        |wires: usize, max: Option<usize>| max.map_or(0, |max| (wires + 1).saturating_sub(max));
+    let out_remotes = snarl.out_pin(from).remotes;
+    let in_remotes = snarl.in_pin(to).remotes;
+
+    let mut wires = out_remotes
+        .iter()
+        .take(excess(out_remotes.len(), output.max_connections))
+        .map(|&remote| (from, remote))
+        .collect::<Vec<_>>();
+    for &remote in in_remotes
+        .iter()
+        .take(excess(in_remotes.len(), input.max_connections))
+    {
+        if !wires.contains(&(remote, to)) {
+            wires.push((remote, to));
+        }
+    }
+    wires
+}
+
+/// Connects `from` to `to` like [`connect`], making room first at the ends already carrying their
+/// `max_connections`: the wires [`wires_over_limit`] lists go if `replace_at_limit` is set, and
+/// the new wire is refused otherwise.
+///
+/// Returns `true` if a new wire was created.
+pub fn connect_within_limit(
+    snarl: &mut Snarl<Node>,
+    from: OutPinId,
+    to: InPinId,
+    replace_at_limit: bool,
+) -> bool {
+    if snarl.in_pin(to).remotes.contains(&from) {
+        return false;
+    }
+    let over_limit = wires_over_limit(snarl, from, to);
+    if !over_limit.is_empty() && !replace_at_limit {
+        log::info!("Refused wire {from:?} -> {to:?} past the most wires of a port");
+        return false;
+    }
+
+    for (old_from, old_to) in over_limit {
+        disconnect(snarl, old_from, old_to);
+    }
+    connect(snarl, from, to)
+}
+
+/// Whether the data types of `from` and `to` allow wiring them together.
+pub fn types_compatible(snarl: &Snarl<Node>, from: OutPinId, to: InPinId) -> bool {
+    let output = snarl
+        .get_node(from.node)
+        .and_then(|node| node.outputs.get(from.output));
+    let input = snarl
+        .get_node(to.node)
+        .and_then(|node| node.inputs.get(to.input));
+
+    match (output, input) {
+        (Some(output), Some(input)) => output.data_type.connects_to(input.data_type),
+        _ => false,
+    }
+}
+
+/// Moves the wire `old` to connect `new` instead, keeping its color. Its waypoints and label are
+/// dropped, having been laid out and named for the old ends.
+///
+/// Returns `false`, leaving `old` in place, if `new` already exists, joins incompatible types,
+/// would close a loop without `allow_cycles` or carry a port past its most wires without
+/// `replace_at_limit`, see [`connect_within_limit`].
+pub fn reconnect(
+    subsystem: &mut Subsystem,
+    old: Wire,
+    new: Wire,
+    allow_cycles: bool,
+    replace_at_limit: bool,
+) -> bool {
+    let snarl = &mut subsystem.snarl;
+    if snarl.wires().any(|wire| wire == new) || !types_compatible(snarl, new.0, new.1) {
+        return false;
+    }
+
+    snarl.disconnect(old.0, old.1);
+    if !allow_cycles && would_create_cycle(snarl, new.0, new.1)
+        || !connect_within_limit(snarl, new.0, new.1, replace_at_limit)
+    {
+        snarl.connect(old.0, old.1);
+        return false;
+    }
+
+    if let Some(color) = subsystem.wire_colors.remove(&old) {
+        subsystem.wire_colors.insert(new, color);
+    }
+    subsystem.waypoints.remove(&old);
+    subsystem.wire_labels.remove(&old);
+    log::info!("Moved wire {old:?} to {new:?}");
+    true
+}
+
+/// Why [`reverse_wire`] left a wire as it was.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReverseError {
+    /// One end is bound to a subsystem boundary, which sets its direction.
+    Bound,
+    /// The reversed wire would close a loop.
+    Cycle,
+    /// The ports at the reversed ends carry incompatible types.
+    Types,
+    /// The reversed wire already exists.
+    Exists,
+    /// An end of the reversed wire already carries the most wires its port takes.
+    Limit,
+}
+
+impl ReverseError {
+    pub fn message(self) -> &'static str {
+        match self {
+            ReverseError::Bound => {
+                "An end is bound to a subsystem boundary, which sets its direction"
+            }
+            ReverseError::Cycle => "Reversed, the wire would close a loop",
+            ReverseError::Types => "The ports at the reversed ends carry incompatible types",
+            ReverseError::Exists => "The reversed wire already exists",
+            ReverseError::Limit => "An end of the reversed wire already has all the wires it takes",
+        }
+    }
+}
+
+/// Replaces `wire` by one going the other way: from the output of the node at its input end
+/// named after that input, to the input of the node at its output end named after that output.
+/// The ports are added, named and typed after the old ends, if the nodes have none by that name.
+///
+/// Returns the new wire, or why `wire` stays, which it does when an end is not a `Normal` port
+/// or when the new wire would join incompatible types, close a loop without `allow_cycles` or
+/// carry a port past its most wires without `replace_at_limit`.
+pub fn reverse_wire(
+    snarl: &mut Snarl<Node>,
+    (from, to): Wire,
+    allow_cycles: bool,
+    replace_at_limit: bool,
+) -> Result<Wire, ReverseError> {
+    let output = snarl[from.node].outputs[from.output].clone();
+    let input = snarl[to.node].inputs[to.input].clone();
+    if output.kind != OutputKind::Normal || input.kind != InputKind::Normal {
+        return Err(ReverseError::Bound);
+    }
+
+    // The node at the input end gets an output named after the input, and the other way round
+    let new_output = snarl[to.node]
+        .outputs
+        .iter()
+        .position(|port| port.kind == OutputKind::Normal && port.name == input.name);
+    let new_input = snarl[from.node]
+        .inputs
+        .iter()
+        .position(|port| port.kind == InputKind::Normal && port.name == output.name);
+    if let (Some(new_output), Some(new_input)) = (new_output, new_input) {
+        let reversed = (
+            OutPinId {
+                node: to.node,
+                output: new_output,
+            },
+            InPinId {
+                node: from.node,
+                input: new_input,
+            },
+        );
+        if snarl.wires().any(|wire| wire == reversed) {
+            return Err(ReverseError::Exists);
+        }
+    }
+    let output_type = new_output.map_or(input.data_type, |n| snarl[to.node].outputs[n].data_type);
+    let input_type = new_input.map_or(output.data_type, |n| snarl[from.node].inputs[n].data_type);
+    if !output_type.connects_to(input_type) {
+        return Err(ReverseError::Types);
+    }
+
+    // Ports added for the new wire take any number of wires
+    let output_full = new_output.is_some_and(|output| {
+        let wires = snarl
+            .out_pin(OutPinId {
+                node: to.node,
+                output,
+            })
+            .remotes
+            .len();
+        snarl[to.node].outputs[output]
+            .max_connections
+            .is_some_and(|max| wires >= max)
+    });
+    let input_full = new_input.is_some_and(|input| {
+        let wires = snarl
+            .in_pin(InPinId {
+                node: from.node,
+                input,
+            })
+            .remotes
+            .len();
+        snarl[from.node].inputs[input]
+            .max_connections
+            .is_some_and(|max| wires >= max)
+    });
+    if (output_full || input_full) && !replace_at_limit {
+        return Err(ReverseError::Limit);
+    }
+
+    // Loops only depend on the nodes, so the ports need not exist yet
+    snarl.disconnect(from, to);
+    let probe_from = OutPinId {
+        node: to.node,
+        output: 0,
+    };
+    let probe_to = InPinId {
+        node: from.node,
+        input: 0,
+    };
+    if !allow_cycles && would_create_cycle(snarl, probe_from, probe_to) {
+        snarl.connect(from, to);
+        return Err(ReverseError::Cycle);
+    }
+
+    let new_output = new_output.unwrap_or_else(|| {
+        add_output(
+            snarl,
+            to.node,
+            Output {
+                name: input.name,
+                kind: OutputKind::Normal,
+                data_type: input.data_type,
+                max_connections: None,
+            },
+        )
+    });
+    let new_input = new_input.unwrap_or_else(|| {
+        add_input(
+            snarl,
+            from.node,
+            Input {
+                name: output.name,
+                kind: InputKind::Normal,
+                data_type: output.data_type,
+                max_connections: None,
+            },
+        )
+    });
+    let reversed = (
+        OutPinId {
+            node: to.node,
+            output: new_output,
+        },
+        InPinId {
+            node: from.node,
+            input: new_input,
+        },
+    );
+    connect_within_limit(snarl, reversed.0, reversed.1, true);
+    log::info!("Reversed wire {from:?} -> {to:?}");
+    Ok(reversed)
+}
+
+/// Whether a wire from `from` to `to` would close a loop, with `to` already leading back to
+/// `from` through existing wires.
+pub fn would_create_cycle(snarl: &Snarl<Node>, from: OutPinId, to: InPinId) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack = vec![to.node];
+    while let Some(node_id) = stack.pop() {
+        if node_id == from.node {
+            return true;
+        }
+        if visited.insert(node_id) {
+            stack.extend(
+                snarl
+                    .wires()
+                    .filter(|(pin_out, _)| pin_out.node == node_id)
+                    .map(|(_, pin_in)| pin_in.node),
+            );
+        }
+    }
+    false
+}
+
+/// Why [`topo_order`] found no order: some wires loop back.
+#[derive(Clone, Debug)]
+pub struct CycleError {
+    /// Nodes sorted before the loops got in the way.
+    pub sorted: Vec<NodeId>,
+    /// Nodes left out of `sorted`: the ones on loops and the ones they drive, by id.
+    pub stuck: Vec<NodeId>,
+    /// Nodes of `stuck` that are on a loop.
+    pub cycle: Vec<NodeId>,
+}
+
+/// Nodes of `snarl` sorted so that each comes after the ones driving it, which is an order
+/// they can be evaluated in. Comments are left out. Ties go to the lowest id, so the order only
+/// changes with the wires.
+pub fn topo_order(snarl: &Snarl<Node>) -> Result<Vec<NodeId>, CycleError> {
+    let mut nodes = snarl
+        .node_ids()
+        .filter(|(_, node)| !node.is_comment())
+        .map(|(node_id, _)| node_id)
+        .collect::<Vec<_>>();
+    nodes.sort();
+    let edges = snarl
+        .wires()
+        .map(|(pin_out, pin_in)| (pin_out.node, pin_in.node))
+        .collect::<HashSet<_>>();
+
+    let mut incoming = nodes
+        .iter()
+        .map(|node_id| {
+            let count = edges.iter().filter(|(_, to)| to == node_id).count();
+            (*node_id, count)
+        })
+        .collect::<HashMap<_, _>>();
+    let mut sorted = Vec::new();
+    let mut ready = nodes
+        .iter()
+        .copied()
+        .filter(|node_id| incoming[node_id] == 0)
+        .collect::<std::collections::VecDeque<_>>();
+    while let Some(node_id) = ready.pop_front() {
+        sorted.push(node_id);
+        let mut next = edges
+            .iter()
+            .filter(|(from, _)| *from == node_id)
+            .map(|(_, to)| *to)
+            .collect::<Vec<_>>();
+        next.sort();
+        for to in next {
+            let Some(count) = incoming.get_mut(&to) else {
+                continue;
+            };
+            *count -= 1;
+            if *count == 0 {
+                ready.push_back(to);
+            }
+        }
+    }
+
+    let stuck = nodes
+        .into_iter()
+        .filter(|node_id| incoming[node_id] > 0)
+        .collect::<Vec<_>>();
+    if stuck.is_empty() {
+        return Ok(sorted);
+    }
+
+    // A stuck node is on a loop when one of the nodes it drives leads back to it
+    let cycle = stuck
+        .iter()
+        .copied()
+        .filter(|&node_id| {
+            edges
+                .iter()
+                .filter(|(from, _)| *from == node_id)
+                .any(|&(_, to)| reachable_nodes(snarl, to, Reach::Downstream).contains(&node_id))
+        })
+        .collect();
+    Err(CycleError {
+        sorted,
+        stuck,
+        cycle,
+    })
+}
+
+/// Which wires to follow when walking the graph from a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reach {
+    /// From inputs back to the outputs feeding them.
+    Upstream,
+    /// From outputs on to the inputs they feed.
+    Downstream,
+    /// Along wires either way.
+    Connected,
+}
+
+/// The nodes of `snarl` reached from `start` by following wires as told by `reach`, `start`
+/// included.
+pub fn reachable_nodes(snarl: &Snarl<Node>, start: NodeId, reach: Reach) -> HashSet<NodeId> {
+    let wires = snarl.wires().collect::<Vec<_>>();
+    let mut visited = HashSet::new();
+    let mut stack = vec![start];
+    while let Some(node_id) = stack.pop() {
+        if !visited.insert(node_id) {
+            continue;
+        }
+        for (pin_out, pin_in) in &wires {
+            if reach != Reach::Upstream && pin_out.node == node_id {
+                stack.push(pin_in.node);
+            }
+            if reach != Reach::Downstream && pin_in.node == node_id {
+                stack.push(pin_out.node);
+            }
+        }
+    }
+    visited
+}
+
+/// The nodes of `snarl` not in `selected`.
+pub fn invert_selection(snarl: &Snarl<Node>, selected: &[NodeId]) -> Vec<NodeId> {
+    let mut inverted = snarl
+        .node_ids()
+        .map(|(node_id, _)| node_id)
+        .filter(|node_id| !selected.contains(node_id))
+        .collect::<Vec<_>>();
+    inverted.sort();
+    inverted
+}
+
+/// `selected` followed by the nodes of `snarl` wired directly to any of them.
+pub fn grow_selection(snarl: &Snarl<Node>, selected: &[NodeId]) -> Vec<NodeId> {
+    let mut added = snarl
+        .wires()
+        .filter_map(|(pin_out, pin_in)| {
+            match (
+                selected.contains(&pin_out.node),
+                selected.contains(&pin_in.node),
+            ) {
+                (true, false) => Some(pin_in.node),
+                (false, true) => Some(pin_out.node),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    added.sort();
+    added.dedup();
+    [selected, &added].concat()
+}
+
+/// The nodes of `selected` without the ones on its boundary, wired directly to a node of
+/// `snarl` outside of it.
+pub fn shrink_selection(snarl: &Snarl<Node>, selected: &[NodeId]) -> Vec<NodeId> {
+    let boundary = snarl
+        .wires()
+        .filter_map(|(pin_out, pin_in)| {
+            match (
+                selected.contains(&pin_out.node),
+                selected.contains(&pin_in.node),
+            ) {
+                (true, false) => Some(pin_out.node),
+                (false, true) => Some(pin_in.node),
+                _ => None,
+            }
+        })
+        .collect::<HashSet<_>>();
+    selected
+        .iter()
+        .copied()
+        .filter(|node_id| !boundary.contains(node_id))
+        .collect()
+}
+
+/// Renames the nodes of `snarl` sharing a name, and the ports of each node sharing a name, by
+/// appending `_2`, `_3`, … to all but the first. Returns the number of names changed.
+pub fn fix_duplicate_names(snarl: &mut Snarl<Node>) -> usize {
+    let mut nodes = snarl
+        .nodes_ids_mut()
+        .filter(|(_, node)| !node.is_comment())
+        .collect::<Vec<_>>();
+    nodes.sort_by_key(|(node_id, _)| *node_id);
+
+    let mut count = deduplicate(nodes.iter_mut().map(|(_, node)| &mut node.name));
+    for (_, node) in nodes {
+        count += deduplicate(node.inputs.iter_mut().map(|input| &mut input.name));
+        count += deduplicate(node.outputs.iter_mut().map(|output| &mut output.name));
+    }
+    log::info!("Renamed {count} duplicate name(s)");
+    count
+}
+
+/// Gives a unique suffix to each of `names` already seen earlier. Returns the number of names
+/// changed.
+fn deduplicate<'a>(names: impl Iterator<Item = &'a mut String>) -> usize {
+    let mut names = names.collect::<Vec<_>>();
+    let mut taken = names
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<HashSet<_>>();
+    let mut seen = HashSet::new();
+
+    let mut count = 0;
+    for name in &mut names {
+        if seen.insert(name.to_string()) {
+            continue;
+        }
+        let unique = (2..)
+            .map(|n| format!("{name}_{n}"))
+            .find(|candidate| !taken.contains(candidate))
+            .unwrap();
+        taken.insert(unique.clone());
+        **name = unique;
+        count += 1;
+    }
+    count
+}
+
+/// Size figures of a subsystem, counting everything nested inside it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Statistics {
+    /// Nodes, comments included.
+    pub nodes: usize,
+    pub wires: usize,
+    /// Nodes holding a subsystem.
+    pub subsystems: usize,
+    /// Levels of subsystems below this one.
+    pub depth: usize,
+    /// Ports without any wire, as listed by [`unconnected_ports`].
+    pub unconnected_ports: usize,
+}
+
+/// Every port of `snarl` without any wire, leaving out the boundary ports, which may be left
+/// open.
+pub fn unconnected_ports(snarl: &Snarl<Node>) -> Vec<(NodeId, PortSide, usize)> {
+    let mut ports = Vec::new();
+    for (node_id, node) in snarl.node_ids() {
+        for (input, port) in node.inputs.iter().enumerate() {
+            let pin = snarl.in_pin(InPinId {
+                node: node_id,
+                input,
+            });
+            if port.kind == InputKind::Normal && pin.remotes.is_empty() {
+                ports.push((node_id, PortSide::Input, input));
+            }
+        }
+        for (output, port) in node.outputs.iter().enumerate() {
+            let pin = snarl.out_pin(OutPinId {
+                node: node_id,
+                output,
+            });
+            if port.kind == OutputKind::Normal && pin.remotes.is_empty() {
+                ports.push((node_id, PortSide::Output, output));
+            }
+        }
+    }
+    ports.sort();
+    ports
+}
+
+/// Removes every wire attached to `node_id`, keeping its ports. Returns the number of wires
+/// removed.
+pub fn disconnect_all(snarl: &mut Snarl<Node>, node_id: NodeId) -> usize {
+    let Some(node) = snarl.get_node(node_id) else {
+        return 0;
+    };
+    let (inputs, outputs) = (node.inputs.len(), node.outputs.len());
+
+    let mut count = 0;
+    for input in 0..inputs {
+        count += snarl.drop_inputs(InPinId {
+            node: node_id,
+            input,
+        });
+    }
+    for output in 0..outputs {
+        count += snarl.drop_outputs(OutPinId {
+            node: node_id,
+            output,
+        });
+    }
+    log::info!("Removed {count} wire(s) of node {node_id:?}");
+    count
+}
+
+/// Connects each output of `from` to the input of `to` with the same name, ignoring case,
+/// skipping the wires that would join incompatible types or, unless `allow_cycles` is set,
+/// close a loop. Ports already carrying their most wires make room as [`connect_within_limit`]
+/// does. Returns the number of wires created.
+pub fn connect_by_name(
+    snarl: &mut Snarl<Node>,
+    from: NodeId,
+    to: NodeId,
+    allow_cycles: bool,
+    replace_at_limit: bool,
+) -> usize {
+    let (Some(source), Some(sink)) = (snarl.get_node(from), snarl.get_node(to)) else {
+        return 0;
+    };
+    let pairs = source
+        .outputs
+        .iter()
+        .enumerate()
+        .filter_map(|(output, port)| {
+            let name = port.name.to_lowercase();
+            let input = sink
+                .inputs
+                .iter()
+                .position(|input| input.name.to_lowercase() == name)?;
+            Some((OutPinId { node: from, output }, InPinId { node: to, input }))
+        })
+        .collect::<Vec<_>>();
+
+    let mut count = 0;
+    for (pin_out, pin_in) in pairs {
+        if !types_compatible(snarl, pin_out, pin_in)
+            || (!allow_cycles && would_create_cycle(snarl, pin_out, pin_in))
+        {
+            continue;
+        }
+        if connect_within_limit(snarl, pin_out, pin_in, replace_at_limit) {
+            count += 1;
+        }
+    }
+    log::info!("Connected {count} port(s) of node {from:?} to node {to:?} by name");
+    count
+}
+
+/// Removes the wire from `from` to `to`, returning `true` if it existed.
+pub fn disconnect(snarl: &mut Snarl<Node>, from: OutPinId, to: InPinId) -> bool {
+    if !snarl.disconnect(from, to) {
+        return false;
+    }
+
+    log::info!("Disconnected wire {from:?} -> {to:?}");
+    true
+}
+
+/// Moves the `selected` nodes of `parent` into a new subsystem, replaced by a single subsystem
+/// node inserted at `pos`. Returns the id of that node.
+///
+/// Wires inside the selection keep their waypoints and color, wires crossing its boundary keep
+/// their color on both sides of the subsystem node.
+pub fn convert_to_subsystem(parent: &mut Subsystem, selected: &[NodeId], pos: Pos2) -> NodeId {
+    let snarl = &mut parent.snarl;
+
+    // Ports that are not connected internally become part of the subsytem ports
+    // and are internally connected to an "external" port.
+    // If they were connected externally, we re-create this connection once again.
+    // If they were unconnected, we leave them unconnected externally.
+
+    let mut subsystem = Subsystem::default();
+
+    // List all the relevant connections
+    let wires = snarl
+        .wires()
+        .filter(|(pin_out, pin_in)| {
+            selected.contains(&pin_in.node) || selected.contains(&pin_out.node)
+        })
+        .collect::<Vec<_>>();
+
+    let internal_wires = wires
+        .iter()
+        .filter(|(pin_out, pin_in)| {
+            selected.contains(&pin_in.node) && selected.contains(&pin_out.node)
+        })
+        .collect::<Vec<_>>();
+    let external_inputs = wires
+        .iter()
+        .filter(|(pin_out, pin_in)| {
+            selected.contains(&pin_in.node) && !selected.contains(&pin_out.node)
+        })
+        .collect::<Vec<_>>();
+    let external_outputs = wires
+        .iter()
+        .filter(|(pin_out, pin_in)| {
+            !selected.contains(&pin_in.node) && selected.contains(&pin_out.node)
+        })
+        .collect::<Vec<_>>();
+
+    // Create external input nodes internally
+    let external_input_ports = external_inputs
+        .iter()
+        .map(|(_, pin_in)| {
+            snarl[pin_in.node]
+                .inputs
+                .get(pin_in.input)
+                .cloned()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let external_input_nodes = external_input_ports
+        .iter()
+        .map(|input| Output {
+            name: input.name.clone(),
+            kind: OutputKind::External,
+            data_type: input.data_type,
+            max_connections: None,
+        })
+        .enumerate()
+        .map(|(n, output)| {
+            subsystem.snarl.insert_node(
+                [0.0, n as f32 * 50.0].into(),
+                Node {
+                    name: format!("Ext{}", n + 1),
+                    inputs: Vec::default(),
+                    outputs: vec![output],
+                    ..Node::default()
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // Create external output nodes internally
+    let external_output_ports = external_outputs
+        .iter()
+        .map(|(pin_out, _)| {
+            snarl[pin_out.node]
+                .outputs
+                .get(pin_out.output)
+                .cloned()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let external_output_nodes = external_output_ports
+        .iter()
+        .map(|output| Input {
+            name: output.name.clone(),
+            kind: InputKind::External,
+            data_type: output.data_type,
+            max_connections: None,
+        })
+        .enumerate()
+        .map(|(n, input)| {
+            subsystem.snarl.insert_node(
+                [100.0, n as f32 * 50.0].into(),
+                Node {
+                    name: format!("Ext{}", n + 1),
+                    inputs: vec![input],
+                    outputs: Vec::default(),
+                    ..Node::default()
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // Map the old node IDs to the new ones
+    let mut node_map: HashMap<NodeId, NodeId> = HashMap::default();
+    for &node_id in selected {
+        let Some(node) = snarl.get_node_info(node_id) else {
+            continue;
+        };
+        let new_node_id = subsystem
+            .snarl
+            .insert_node(node.pos, snarl.remove_node(node_id));
+        node_map.insert(node_id, new_node_id);
+    }
+
+    // Re-create the internal connections
+    internal_wires
+        .iter()
+        .filter_map(|(pin_out, pin_in)| {
+            Some((
+                OutPinId {
+                    node: *node_map.get(&pin_out.node)?,
+                    output: pin_out.output,
+                },
+                InPinId {
+                    node: *node_map.get(&pin_in.node)?,
+                    input: pin_in.input,
+                },
+            ))
+        })
+        .for_each(|(pin_out, pin_in)| {
+            subsystem.snarl.connect(pin_out, pin_in);
+        });
+
+    // Create the external input connections internally
+    external_inputs
+        .iter()
+        .enumerate()
+        .map(|(n, (_, pin_in))| {
+            (
+                OutPinId {
+                    node: external_input_nodes[n],
+                    output: 0,
+                },
+                InPinId {
+                    node: *node_map
+                        .get(&pin_in.node)
+                        .expect("Old input pin node is mapped to new node"),
+                    input: pin_in.input,
+                },
+            )
+        })
+        .for_each(|(pin_out, pin_in)| {
+            subsystem.snarl.connect(pin_out, pin_in);
+        });
+
+    // Create the external output connections internally
+    external_outputs
+        .iter()
+        .enumerate()
+        .map(|(n, (pin_out, _))| {
+            (
+                OutPinId {
+                    node: *node_map
+                        .get(&pin_out.node)
+                        .expect("Old output pin node is mapped to new node"),
+                    output: pin_out.output,
+                },
+                InPinId {
+                    node: external_output_nodes[n],
+                    input: 0,
+                },
+            )
+        })
+        .for_each(|(pin_out, pin_in)| {
+            subsystem.snarl.connect(pin_out, pin_in);
+        });
+
+    // Create the external subsystem node
+    let mut new_node = Node {
+        name: "Subsystem".to_string(),
+        inputs: external_input_ports
+            .into_iter()
+            .map(|input| Input {
+                kind: InputKind::Internal,
+                ..input
+            })
+            .collect(),
+        outputs: external_output_ports
+            .into_iter()
+            .map(|output| Output {
+                kind: OutputKind::Internal,
+                ..output
+            })
+            .collect(),
+        ..Node::default()
+    };
+
+    // Add the unconnected inputs
+    subsystem
+        .snarl
+        .node_ids()
+        .flat_map(|(node_id, node)| {
+            node.inputs
+                .iter()
+                .enumerate()
+                .filter_map(|(n, input)| {
+                    let pin = subsystem.snarl.in_pin(InPinId {
+                        node: node_id,
+                        input: n,
+                    });
+                    if !pin.remotes.is_empty() {
+                        None
+                    } else {
+                        Some((
+                            node_id,
+                            n,
+                            Input {
+                                kind: InputKind::Internal,
+                                ..input.clone()
+                            },
+                        ))
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .enumerate()
+        .for_each(|(n, (node_id, port, input))| {
+            // Create new internal input nodes
+            let input_node_id = subsystem.snarl.insert_node(
+                [0.0, n as f32 * -150.0].into(),
+                Node {
+                    name: format!("ExtUC{}", n + 1),
+                    inputs: Vec::default(),
+                    outputs: vec![Output {
+                        name: input.name.clone(),
+                        kind: OutputKind::External,
+                        data_type: input.data_type,
+                        max_connections: None,
+                    }],
+                    ..Node::default()
+                },
+            );
+
+            subsystem.snarl.connect(
+                OutPinId {
+                    node: input_node_id,
+                    output: 0,
+                },
+                InPinId {
+                    node: node_id,
+                    input: port,
+                },
+            );
+
+            // Add it to the subsystem block
+            new_node.inputs.push(input);
+        });
+
+    // Add the unconnected outputs
+    subsystem
+        .snarl
+        .node_ids()
+        .flat_map(|(node_id, node)| {
+            node.outputs
+                .iter()
+                .enumerate()
+                .filter_map(|(n, output)| {
+                    let pin = subsystem.snarl.out_pin(OutPinId {
+                        node: node_id,
+                        output: n,
+                    });
+                    if !pin.remotes.is_empty() {
+                        None
+                    } else {
+                        Some((
+                            node_id,
+                            n,
+                            Output {
+                                kind: OutputKind::Internal,
+                                ..output.clone()
+                            },
+                        ))
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .enumerate()
+        .for_each(|(n, (node_id, port, output))| {
+            // Create new internal output nodes
+            let output_node_id = subsystem.snarl.insert_node(
+                [300.0, n as f32 * -150.0].into(),
+                Node {
+                    name: format!("ExtOutUC{}", n + 1),
+                    inputs: vec![Input {
+                        name: output.name.clone(),
+                        kind: InputKind::External,
+                        data_type: output.data_type,
+                        max_connections: None,
+                    }],
+                    outputs: Vec::default(),
+                    ..Node::default()
+                },
+            );
+
+            subsystem.snarl.connect(
+                OutPinId {
+                    node: node_id,
+                    output: port,
+                },
+                InPinId {
+                    node: output_node_id,
+                    input: 0,
+                },
+            );
+
+            // Add it to the subsystem block
+            new_node.outputs.push(output);
+        });
+
+    // Move the data of the wires now inside the subsystem
+    for &&(pin_out, pin_in) in &internal_wires {
+        let inner = (
+            OutPinId {
+                node: node_map[&pin_out.node],
+                ..pin_out
+            },
+            InPinId {
+                node: node_map[&pin_in.node],
+                ..pin_in
+            },
+        );
+        if let Some(route) = parent.waypoints.remove(&(pin_out, pin_in)) {
+            subsystem.waypoints.insert(inner, route);
+        }
+        if let Some(color) = parent.wire_colors.remove(&(pin_out, pin_in)) {
+            subsystem.wire_colors.insert(inner, color);
+        }
+        if let Some(label) = parent.wire_labels.remove(&(pin_out, pin_in)) {
+            subsystem.wire_labels.insert(inner, label);
+        }
+    }
+    for (n, &&(pin_out, pin_in)) in external_inputs.iter().enumerate() {
+        if let Some(&color) = parent.wire_colors.get(&(pin_out, pin_in)) {
+            let inner = (
+                OutPinId {
+                    node: external_input_nodes[n],
+                    output: 0,
+                },
+                InPinId {
+                    node: node_map[&pin_in.node],
+                    ..pin_in
+                },
+            );
+            subsystem.wire_colors.insert(inner, color);
+        }
+    }
+    for (n, &&(pin_out, pin_in)) in external_outputs.iter().enumerate() {
+        if let Some(&color) = parent.wire_colors.get(&(pin_out, pin_in)) {
+            let inner = (
+                OutPinId {
+                    node: node_map[&pin_out.node],
+                    ..pin_out
+                },
+                InPinId {
+                    node: external_output_nodes[n],
+                    input: 0,
+                },
+            );
+            subsystem.wire_colors.insert(inner, color);
+        }
+    }
+
+    new_node.subsystem = Some(Rc::new(RefCell::new(subsystem)));
+    let new_node_id = snarl.insert_node(pos, new_node);
+
+    // Connect the previously connected inputs and outputs to the new subsystem node
+    external_inputs
+        .iter()
+        .enumerate()
+        .map(|(n, (pin_out, _))| {
+            (
+                pin_out,
+                InPinId {
+                    node: new_node_id,
+                    input: n,
+                },
+            )
+        })
+        .for_each(|(pin_out, pin_in)| {
+            snarl.connect(*pin_out, pin_in);
+        });
+    external_outputs
+        .iter()
+        .enumerate()
+        .map(|(n, (_, pin_in))| {
+            (
+                OutPinId {
+                    node: new_node_id,
+                    output: n,
+                },
+                pin_in,
+            )
+        })
+        .for_each(|(pin_out, pin_in)| {
+            snarl.connect(pin_out, *pin_in);
+        });
+
+    // The wires crossing the boundary now end on the subsystem node
+    for (n, &&wire) in external_inputs.iter().enumerate() {
+        let outer = (
+            wire.0,
+            InPinId {
+                node: new_node_id,
+                input: n,
+            },
+        );
+        if let Some(color) = parent.wire_colors.remove(&wire) {
+            parent.wire_colors.insert(outer, color);
+        }
+        if let Some(label) = parent.wire_labels.remove(&wire) {
+            parent.wire_labels.insert(outer, label);
+        }
+    }
+    for (n, &&wire) in external_outputs.iter().enumerate() {
+        let outer = (
+            OutPinId {
+                node: new_node_id,
+                output: n,
+            },
+            wire.1,
+        );
+        if let Some(color) = parent.wire_colors.remove(&wire) {
+            parent.wire_colors.insert(outer, color);
+        }
+        if let Some(label) = parent.wire_labels.remove(&wire) {
+            parent.wire_labels.insert(outer, label);
+        }
+    }
+
+    log::info!(
+        "Converted {} node(s) into subsystem node {new_node_id:?} ({} external input(s), {} external output(s))",
+        node_map.len(),
+        external_inputs.len(),
+        external_outputs.len()
+    );
+    log::debug!("Subsystem node map: {node_map:?}");
+
+    // Every wire that crossed the selection boundary must now end on the subsystem node
+    let expected = external_inputs.len() + external_outputs.len();
+    let actual = boundary_wire_count(&parent.snarl, new_node_id);
+    if actual != expected {
+        log::error!(
+            "Subsystem node {new_node_id:?} has {actual} external wire(s), expected {expected}"
+        );
+    }
+    debug_assert_eq!(actual, expected, "Conversion lost or added external wires");
+
+    new_node_id
+}
+
+/// Replaces the subsystem node `node_id` of `parent` by the contents of its subsystem, the
+/// inverse of [`convert_to_subsystem`]. The boundary nodes disappear and the wires going through
+/// them are reconnected directly, leaving out those joining incompatible types or going past the
+/// most wires of a port. The moved nodes get new ids, and new names where theirs are taken.
+///
+/// Wires inside the subsystem keep their waypoints, color and label. Wires going through the
+/// boundary take the color and label of their outer part, or else of their inner part.
+///
+/// Returns the ids of the inserted nodes, or `None` if `node_id` is not a subsystem node.
+pub fn flatten(parent: &mut Subsystem, node_id: NodeId) -> Option<Vec<NodeId>> {
+    let snarl = &mut parent.snarl;
+    let node = snarl.get_node_mut(node_id)?;
+    node.subsystem.as_ref()?;
+    // The moved nodes would otherwise share their nested subsystems with the other instances
+    if node.shares_subsystem() {
+        unshare_subsystem(node);
+    }
+    let origin = snarl.get_node_info(node_id)?.pos.to_vec2();
+
+    // Remember the outer wires before removing the node drops them
+    let outer_inputs = snarl
+        .wires()
+        .filter(|(_, pin_in)| pin_in.node == node_id)
+        .collect::<Vec<_>>();
+    let outer_outputs = snarl
+        .wires()
+        .filter(|(pin_out, _)| pin_out.node == node_id)
+        .collect::<Vec<_>>();
+    let subsystem = remove_node(snarl, node_id).subsystem?;
+
+    let subsystem = subsystem.borrow();
+    let boundary_inputs = subsystem.boundary_inputs();
+    let boundary_outputs = subsystem.boundary_outputs();
+
+    // Copy the inner nodes, leaving the boundary nodes behind
+    let mut node_map: HashMap<NodeId, NodeId> = HashMap::default();
+    let mut names = node_names(snarl);
+    for (inner_id, pos, node) in subsystem.snarl.nodes_pos_ids() {
+        if node.is_boundary() {
+            continue;
+        }
+        let mut node = node.clone();
+        node.renew_id();
+        claim_unique_name(&mut node, &mut names);
+        node_map.insert(inner_id, snarl.insert_node(pos + origin, node));
+    }
+
+    // Outer wires into the n-th input, and out of the n-th output, of the subsystem node
+    let sources = |n: usize| {
+        outer_inputs
+            .iter()
+            .filter(move |(_, pin_in)| pin_in.input == n)
+            .copied()
+    };
+    let sinks = |n: usize| {
+        outer_outputs
+            .iter()
+            .filter(move |(pin_out, _)| pin_out.output == n)
+            .copied()
+    };
+
+    let mut wire_data = Vec::new();
+    for inner_wire @ (pin_out, pin_in) in subsystem.snarl.wires() {
+        // Each end along with the outer wire it goes through, if any
+        let from = match node_map.get(&pin_out.node) {
+            Some(&node) => vec![(
+                OutPinId {
+                    node,
+                    output: pin_out.output,
+                },
+                None,
+            )],
+            None => boundary_inputs
+                .iter()
+                .position(|&pin| pin == pin_out)
+                .map(|n| sources(n).map(|outer| (outer.0, Some(outer))).collect())
+                .unwrap_or_default(),
+        };
+        let to = match node_map.get(&pin_in.node) {
+            Some(&node) => vec![(
+                InPinId {
+                    node,
+                    input: pin_in.input,
+                },
+                None,
+            )],
+            None => boundary_outputs
+                .iter()
+                .position(|&pin| pin == pin_in)
+                .map(|n| sinks(n).map(|outer| (outer.1, Some(outer))).collect())
+                .unwrap_or_default(),
+        };
+
+        for &(from, outer_from) in &from {
+            for &(to, outer_to) in &to {
+                if !types_compatible(snarl, from, to) {
+                    log::info!("Left out wire {from:?} -> {to:?} joining incompatible types");
+                    continue;
+                }
+                if !connect_within_limit(snarl, from, to, false) {
+                    continue;
+                }
+
+                let outer = outer_from.or(outer_to);
+                let color = outer
+                    .and_then(|outer| parent.wire_colors.get(&outer))
+                    .or_else(|| subsystem.wire_colors.get(&inner_wire));
+                let label = outer
+                    .and_then(|outer| parent.wire_labels.get(&outer))
+                    .or_else(|| subsystem.wire_labels.get(&inner_wire));
+                // Waypoints were laid out for the inner ends only
+                let waypoints = subsystem
+                    .waypoints
+                    .get(&inner_wire)
+                    .filter(|_| outer.is_none())
+                    .map(|points| points.iter().map(|&point| point + origin).collect());
+                wire_data.push(((from, to), color.copied(), label.cloned(), waypoints));
+            }
+        }
+    }
+
+    // The outer wires are gone, and the new wires may reuse their ends
+    let dropped = outer_inputs
+        .iter()
+        .chain(&outer_outputs)
+        .map(|&outer| (outer, None))
+        .collect::<WireRemap>();
+    parent.rekey_wires(&dropped);
+    for (wire, color, label, waypoints) in wire_data {
+        if let Some(color) = color {
+            parent.wire_colors.insert(wire, color);
+        }
+        if let Some(label) = label {
+            parent.wire_labels.insert(wire, label);
+        }
+        if let Some(waypoints) = waypoints {
+            parent.waypoints.insert(wire, waypoints);
+        }
+    }
+
+    log::info!(
+        "Flattened subsystem node {node_id:?} into {} node(s)",
+        node_map.len()
+    );
+    Some(node_map.into_values().collect())
+}
+
+/// Nodes taken out of a subsystem along with the wires between them, for copying them
+/// elsewhere.
+///
+/// The nodes still share their nested subsystems with the originals: serialize the fragment
+/// to get an independent copy.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Fragment {
+    pub nodes: Vec<(NodeId, Pos2, Node)>,
+    pub wires: Vec<Wire>,
+}
+
+/// Whether `target` is `root` or nested anywhere inside it.
+///
+/// `target` is never borrowed, so it may be the subsystem currently being edited.
+pub fn contains_subsystem(root: &Subsystem, target: &Rc<RefCell<Subsystem>>) -> bool {
+    std::ptr::eq(root, target.as_ptr())
+        || root
+            .snarl
+            .nodes()
+            .filter_map(|node| node.subsystem.as_ref())
+            .any(|inner| Rc::ptr_eq(inner, target) || contains_subsystem(&inner.borrow(), target))
+}
+
+/// A node found by [`find_nodes`] or [`find_nodes_regex`].
+#[derive(Clone)]
+pub struct NodeMatch {
+    /// Subsystem nodes leading from the top level to the level of the node.
+    pub path: Vec<NodeId>,
+    /// Names of the nodes of `path`.
+    pub path_names: Vec<String>,
+    pub node_id: NodeId,
+    pub name: String,
+}
+
+/// Every node of `root` and the subsystems nested inside it whose name or one of its tags
+/// contains `query`, ignoring case, level by level.
+pub fn find_nodes(root: &Subsystem, query: &str) -> Vec<NodeMatch> {
+    let query = query.to_lowercase();
+    find_nodes_by(root, &|node| {
+        std::iter::once(&node.name)
+            .chain(&node.tags)
+            .any(|text| text.to_lowercase().contains(&query))
+    })
+}
+
+/// Every node of `root` and the subsystems nested inside it whose name `pattern` matches, level
+/// by level.
+pub fn find_nodes_regex(root: &Subsystem, pattern: &Regex) -> Vec<NodeMatch> {
+    find_nodes_by(root, &|node| pattern.is_match(&node.name))
+}
+
+fn find_nodes_by(root: &Subsystem, matches: &dyn Fn(&Node) -> bool) -> Vec<NodeMatch> {
+    fn search(
+        subsystem: &Subsystem,
+        matches: &dyn Fn(&Node) -> bool,
+        path: &mut Vec<(NodeId, String)>,
+        found: &mut Vec<NodeMatch>,
+    ) {
+        let mut nodes = subsystem.snarl.node_ids().collect::<Vec<_>>();
+        nodes.sort_by_key(|(node_id, _)| *node_id);
+
+        for &(node_id, node) in &nodes {
+            if matches(node) {
+                found.push(NodeMatch {
+                    path: path.iter().map(|(node_id, _)| *node_id).collect(),
+                    path_names: path.iter().map(|(_, name)| name.clone()).collect(),
+                    node_id,
+                    name: node.name.clone(),
+                });
+            }
+        }
+        for (node_id, node) in nodes {
+            if let Some(inner) = node.subsystem.as_ref() {
+                path.push((node_id, node.name.clone()));
+                search(&inner.borrow(), matches, path, found);
+                path.pop();
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    search(root, matches, &mut Vec::new(), &mut found);
+    found
+}
+
+/// Why [`find_unused_subsystems`] reported a subsystem node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unused {
+    /// Its subsystem holds nothing but boundary nodes and comments.
+    Empty,
+    /// None of its ports is wired, if it has any.
+    Unwired,
+}
+
+/// Every subsystem node of `root` and the levels nested inside it that does nothing, level by
+/// level: its subsystem is empty, or the node is wired to nothing.
+pub fn find_unused_subsystems(root: &Subsystem) -> Vec<(NodeMatch, Unused)> {
+    fn search(
+        subsystem: &Subsystem,
+        path: &mut Vec<(NodeId, String)>,
+        found: &mut Vec<(NodeMatch, Unused)>,
+    ) {
+        let snarl = &subsystem.snarl;
+        let mut nodes = snarl.node_ids().collect::<Vec<_>>();
+        nodes.sort_by_key(|(node_id, _)| *node_id);
+
+        for &(node_id, node) in &nodes {
+            let Some(inner) = node.subsystem.as_ref() else {
+                continue;
+            };
+            let empty = inner
+                .borrow()
+                .snarl
+                .nodes()
+                .all(|node| node.is_comment() || node.is_boundary());
+            let wired = snarl
+                .wires()
+                .any(|(pin_out, pin_in)| pin_out.node == node_id || pin_in.node == node_id);
+            let reason = if empty {
+                Unused::Empty
+            } else if !wired {
+                Unused::Unwired
+            } else {
+                continue;
+            };
+            found.push((
+                NodeMatch {
+                    path: path.iter().map(|(node_id, _)| *node_id).collect(),
+                    path_names: path.iter().map(|(_, name)| name.clone()).collect(),
+                    node_id,
+                    name: node.name.clone(),
+                },
+                reason,
+            ));
+        }
+        for (node_id, node) in nodes {
+            if let Some(inner) = node.subsystem.as_ref() {
+                path.push((node_id, node.name.clone()));
+                search(&inner.borrow(), path, found);
+                path.pop();
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    search(root, &mut Vec::new(), &mut found);
+    found
+}
+
+/// The subsystem `node` holds, given an empty one first if it has none, so that what is built
+/// inside it stays with the node.
+pub fn subsystem_of(node: &mut Node) -> Rc<RefCell<Subsystem>> {
+    node.subsystem.get_or_insert_default().clone()
+}
+
+/// A copy of `subsystem` sharing nothing with it: every nested subsystem is copied too, so
+/// editing one never changes the other.
+pub fn deep_clone(subsystem: &Subsystem) -> Subsystem {
+    let mut copy = subsystem.clone();
+    copy.instance = None;
+    for node in copy.snarl.nodes_mut() {
+        unshare_subsystem(node);
+    }
+    copy
+}
+
+/// Gives `node` its own copy of the subsystem it holds, if any.
+pub fn unshare_subsystem(node: &mut Node) {
+    if let Some(inner) = node.subsystem.as_mut() {
+        let copy = deep_clone(&inner.borrow());
+        *inner = Rc::new(RefCell::new(copy));
+    }
+}
+
+/// A subsystem of the diagram, as found by [`subsystem_uses`].
+pub struct SubsystemUse {
+    /// Names of the subsystem nodes leading to the first node holding it, that node included.
+    pub path: Vec<String>,
+    /// First node holding it.
+    pub node: Node,
+    /// Number of nodes holding it.
+    pub instances: usize,
+}
+
+impl SubsystemUse {
+    pub fn subsystem(&self) -> &Rc<RefCell<Subsystem>> {
+        self.node.subsystem.as_ref().unwrap()
+    }
+}
+
+/// Every distinct subsystem held by the nodes of `roots` and the levels below them, each given
+/// with the path of its level. Levels shared by several nodes are only visited once.
+pub fn subsystem_uses(roots: &[(Vec<String>, &Snarl<Node>)]) -> Vec<SubsystemUse> {
+    fn visit(snarl: &Snarl<Node>, path: &mut Vec<String>, found: &mut Vec<SubsystemUse>) {
+        let mut nodes = snarl.node_ids().collect::<Vec<_>>();
+        nodes.sort_by_key(|(node_id, _)| *node_id);
+
+        for (_, node) in nodes {
+            let Some(inner) = node.subsystem.as_ref() else {
+                continue;
+            };
+            if let Some(found) = found.iter_mut().find(|u| Rc::ptr_eq(u.subsystem(), inner)) {
+                found.instances += 1;
+                continue;
+            }
+
+            path.push(node.name.clone());
+            found.push(SubsystemUse {
+                path: path.clone(),
+                node: node.clone(),
+                instances: 1,
+            });
+            visit(&inner.borrow().snarl, path, found);
+            path.pop();
+        }
+    }
+
+    let mut found = Vec::new();
+    for (path, snarl) in roots {
+        visit(snarl, &mut path.clone(), &mut found);
+    }
+    found
+}
+
+/// Gives `subsystem` an instance key unused in `uses`, if it has none yet.
+pub fn mark_instance(subsystem: &Rc<RefCell<Subsystem>>, uses: &[SubsystemUse]) {
+    if subsystem.borrow().instance.is_some() {
+        return;
+    }
+    let key = uses
+        .iter()
+        .filter_map(|u| u.subsystem().borrow().instance)
+        .max()
+        .map_or(0, |key| key + 1);
+    subsystem.borrow_mut().instance = Some(key);
+}
+
+/// Makes the nodes holding subsystems with the same instance key share the first of them
+/// again, as they were before being saved.
+pub fn relink_instances(root: &mut Subsystem) {
+    relink(&mut root.snarl, &mut HashMap::new());
+}
+
+/// Makes the nodes of `snarl` share the subsystems of `uses` having the same instance key as
+/// the ones they hold.
+pub fn relink_instances_to(snarl: &mut Snarl<Node>, uses: &[SubsystemUse]) {
+    let mut shared = uses
+        .iter()
+        .filter_map(|found| {
+            let key = found.subsystem().borrow().instance?;
+            Some((key, found.subsystem().clone()))
+        })
+        .collect();
+    relink(snarl, &mut shared);
+}
+
+fn relink(snarl: &mut Snarl<Node>, shared: &mut HashMap<u64, Rc<RefCell<Subsystem>>>) {
+    for node in snarl.nodes_mut() {
+        let Some(inner) = node.subsystem.as_mut() else {
+            continue;
+        };
+        let key = inner.borrow().instance;
+        if let Some(key) = key {
+            match shared.get(&key) {
+                Some(first) if Rc::ptr_eq(first, inner) => continue,
+                Some(first) => {
+                    *inner = first.clone();
+                    continue;
+                }
+                None => {
+                    shared.insert(key, inner.clone());
+                }
+            }
+        }
+        relink(&mut inner.borrow_mut().snarl, shared);
+    }
+}
+
+/// Copies the `selected` nodes of `snarl` along with the wires connecting them together.
+pub fn copy_nodes(snarl: &Snarl<Node>, selected: &[NodeId]) -> Fragment {
+    let nodes = selected
+        .iter()
+        .filter_map(|&node_id| {
+            let info = snarl.get_node_info(node_id)?;
+            Some((node_id, info.pos, info.value.clone()))
+        })
+        .collect::<Vec<_>>();
+    let wires = snarl
+        .wires()
+        .filter(|(pin_out, pin_in)| {
+            selected.contains(&pin_out.node) && selected.contains(&pin_in.node)
+        })
+        .collect();
+
+    Fragment { nodes, wires }
+}
+
+/// Inserts the nodes of `fragment` into `snarl`, moved by `offset`, and reconnects them, leaving
+/// out the wires past the most a port takes. Returns the ids of the new nodes.
+pub fn paste_fragment(snarl: &mut Snarl<Node>, fragment: Fragment, offset: Vec2) -> Vec<NodeId> {
+    // Map the copied node IDs to the new ones
+    let mut node_map: HashMap<NodeId, NodeId> = HashMap::default();
+    let mut names = node_names(snarl);
+    for (node_id, pos, mut node) in fragment.nodes {
+        // Pasted subsystems are copies, not more instances of the copied ones
+        unshare_subsystem(&mut node);
+        node.renew_id();
+        claim_unique_name(&mut node, &mut names);
+        node_map.insert(node_id, snarl.insert_node(pos + offset, node));
+    }
+
+    for (pin_out, pin_in) in fragment.wires {
+        let (Some(&from), Some(&to)) = (node_map.get(&pin_out.node), node_map.get(&pin_in.node))
+        else {
+            continue;
+        };
+        connect_within_limit(
+            snarl,
+            OutPinId {
+                node: from,
+                output: pin_out.output,
+            },
+            InPinId {
+                node: to,
+                input: pin_in.input,
+            },
+            false,
+        );
+    }
+
+    log::info!("Pasted {} node(s)", node_map.len());
+    node_map.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A node named `name` with `inputs` and `outputs` default ports.
+    fn block(name: &str, inputs: usize, outputs: usize) -> Node {
+        Node {
+            name: name.to_string(),
+            inputs: vec![Input::default(); inputs],
+            outputs: vec![Output::default(); outputs],
+            ..Node::default()
+        }
+    }
+
+    fn wire(from: NodeId, output: usize, to: NodeId, input: usize) -> Wire {
+        (OutPinId { node: from, output }, InPinId { node: to, input })
+    }
+
+    fn wires(snarl: &Snarl<Node>) -> Vec<Wire> {
+        let mut wires = snarl.wires().collect::<Vec<_>>();
+        wires.sort();
+        wires
+    }
+
+    /// `subsystem` saved the way the editor saves it.
+    fn saved(subsystem: &Subsystem) -> serde_json::Value {
+        serde_json::to_value(SavedDocument::new(subsystem)).unwrap()
+    }
+
+    #[test]
+    fn duplicate_wires_collapse_on_load() {
+        let mut inner = Subsystem::new();
+        let a = inner.snarl.insert_node(Pos2::ZERO, block("a", 0, 1));
+        let b = inner.snarl.insert_node(Pos2::ZERO, block("b", 1, 0));
+        let (pin_out, pin_in) = wire(a, 0, b, 0);
+        inner.snarl.connect(pin_out, pin_in);
+
+        let mut top = Subsystem::new();
+        let from = top.snarl.insert_node(Pos2::ZERO, block("from", 0, 1));
+        let to = top.snarl.insert_node(
+            Pos2::ZERO,
+            Node {
+                subsystem: Some(Rc::new(RefCell::new(inner))),
+                ..block("to", 1, 0)
+            },
+        );
+        let (pin_out, pin_in) = wire(from, 0, to, 0);
+        top.snarl.connect(pin_out, pin_in);
+
+        // Write every wire twice, at the top level and inside the subsystem
+        let mut raw = saved(&top);
+        fn double_wires(value: &mut serde_json::Value) {
+            if let Some(wires) = value
+                .pointer_mut("/snarl/wires")
+                .and_then(serde_json::Value::as_array_mut)
+            {
+                wires.extend(wires.clone());
+            }
+            match value {
+                serde_json::Value::Object(fields) => fields.values_mut().for_each(double_wires),
+                serde_json::Value::Array(items) => items.iter_mut().for_each(double_wires),
+                _ => {}
+            }
+        }
+        double_wires(&mut raw);
+        assert_eq!(duplicate_wires(&raw), 2);
+
+        let loaded = migrate(raw).unwrap();
+        assert_eq!(wires(&loaded.snarl), vec![(pin_out, pin_in)]);
+        let inner = loaded.snarl[to].subsystem.clone().unwrap();
+        assert_eq!(inner.borrow().snarl.wires().count(), 1);
+    }
+
+    #[test]
+    fn removing_an_output_shifts_the_wires_after_it() {
+        let mut snarl = Snarl::new();
+        let a = snarl.insert_node(Pos2::ZERO, block("a", 0, 3));
+        let b = snarl.insert_node(Pos2::ZERO, block("b", 3, 0));
+        for index in 0..3 {
+            let (pin_out, pin_in) = wire(a, index, b, index);
+            snarl.connect(pin_out, pin_in);
+        }
+
+        assert!(remove_port(&mut snarl, a, PortSide::Output, 0).is_some());
+        assert_eq!(snarl[a].outputs.len(), 2);
+        assert_eq!(wires(&snarl), vec![wire(a, 0, b, 1), wire(a, 1, b, 2)]);
+    }
+
+    #[test]
+    fn ports_of_a_shared_subsystem_stay() {
+        let mut top = Subsystem::new();
+        let a = top.snarl.insert_node(Pos2::ZERO, block("a", 0, 2));
+        let b = top.snarl.insert_node(Pos2::ZERO, block("b", 2, 0));
+        for index in 0..2 {
+            let (pin_out, pin_in) = wire(a, index, b, index);
+            top.snarl.connect(pin_out, pin_in);
+        }
+        let node_id = convert_to_subsystem(&mut top, &[b], Pos2::ZERO);
+        let copy = top.snarl[node_id].clone();
+        let other = top.snarl.insert_node(Pos2::ZERO, copy);
+        assert!(top.snarl[node_id].shares_subsystem());
+
+        assert!(remove_port(&mut top.snarl, node_id, PortSide::Input, 0).is_none());
+        let boundary_inputs = |snarl: &Snarl<Node>, node_id: NodeId| {
+            snarl[node_id]
+                .subsystem
+                .as_ref()
+                .map(|inner| inner.borrow().boundary_inputs().len())
+        };
+        assert_eq!(boundary_inputs(&top.snarl, other), Some(2));
+        assert_eq!(top.snarl[node_id].inputs.len(), 2);
+        assert_eq!(top.snarl[other].inputs.len(), 2);
+
+        // Once it is the only one left, the boundary port goes with it
+        remove_node(&mut top.snarl, other);
+        assert!(remove_port(&mut top.snarl, node_id, PortSide::Input, 0).is_some());
+        assert_eq!(boundary_inputs(&top.snarl, node_id), Some(1));
+    }
+
+    #[test]
+    fn ports_of_a_drifted_node_leave_the_boundary_alone() {
+        let mut top = Subsystem::new();
+        let a = top.snarl.insert_node(Pos2::ZERO, block("a", 0, 2));
+        let b = top.snarl.insert_node(Pos2::ZERO, block("b", 2, 0));
+        for index in 0..2 {
+            let (pin_out, pin_in) = wire(a, index, b, index);
+            top.snarl.connect(pin_out, pin_in);
+        }
+        let node_id = convert_to_subsystem(&mut top, &[b], Pos2::ZERO);
+        let boundary_inputs = |snarl: &Snarl<Node>| {
+            snarl[node_id]
+                .subsystem
+                .as_ref()
+                .map(|inner| inner.borrow().boundary_inputs())
+                .unwrap()
+        };
+        let [first, second] = boundary_inputs(&top.snarl)[..] else {
+            panic!("two boundary inputs");
+        };
+
+        // An input added by hand, out of step with the boundary
+        top.snarl[node_id].inputs.insert(
+            0,
+            Input {
+                kind: InputKind::Internal,
+                ..Input::default()
+            },
+        );
+        assert!(interface_drift(&top.snarl[node_id]));
+
+        assert!(remove_port(&mut top.snarl, node_id, PortSide::Input, 0).is_some());
+        assert_eq!(top.snarl[node_id].inputs.len(), 2);
+        assert_eq!(boundary_inputs(&top.snarl), vec![first, second]);
+        assert!(!interface_drift(&top.snarl[node_id]));
+    }
+
+    #[test]
+    fn wire_data_follows_removed_ports() {
+        let mut top = Subsystem::new();
+        let a = top.snarl.insert_node(Pos2::ZERO, block("a", 0, 1));
+        let b = top.snarl.insert_node(Pos2::ZERO, block("b", 3, 0));
+        let colors = [Color32::RED, Color32::GREEN, Color32::BLUE];
+        for (index, color) in colors.into_iter().enumerate() {
+            let (pin_out, pin_in) = wire(a, 0, b, index);
+            top.snarl.connect(pin_out, pin_in);
+            top.wire_colors.insert((pin_out, pin_in), color);
+        }
+        top.wire_labels.insert(wire(a, 0, b, 2), "last".to_string());
+        top.waypoints
+            .insert(wire(a, 0, b, 1), vec![Pos2::new(1.0, 2.0)]);
+
+        let remap = remove_port(&mut top.snarl, b, PortSide::Input, 0).unwrap();
+        top.rekey_wires(&remap);
+        top.prune_wire_data();
+
+        assert_eq!(wires(&top.snarl), vec![wire(a, 0, b, 0), wire(a, 0, b, 1)]);
+        assert_eq!(
+            top.wire_colors,
+            HashMap::from([
+                (wire(a, 0, b, 0), Color32::GREEN),
+                (wire(a, 0, b, 1), Color32::BLUE),
+            ])
+        );
+        assert_eq!(
+            top.wire_labels,
+            HashMap::from([(wire(a, 0, b, 1), "last".to_string())])
+        );
+        assert_eq!(
+            top.waypoints,
+            HashMap::from([(wire(a, 0, b, 0), vec![Pos2::new(1.0, 2.0)])])
+        );
+    }
+
+    #[test]
+    fn split_halves_keep_their_wires() {
+        let mut top = Subsystem::new();
+        let source = top.snarl.insert_node(Pos2::ZERO, block("source", 0, 4));
+        let sink = top.snarl.insert_node(Pos2::ZERO, block("sink", 2, 0));
+        let gate = top.snarl.insert_node(Pos2::ZERO, block("gate", 4, 2));
+        for index in 0..4 {
+            let (pin_out, pin_in) = wire(source, index, gate, index);
+            top.snarl.connect(pin_out, pin_in);
+            top.wire_colors
+                .insert((pin_out, pin_in), Color32::from_gray(index as u8));
+        }
+        for index in 0..2 {
+            let (pin_out, pin_in) = wire(gate, index, sink, index);
+            top.snarl.connect(pin_out, pin_in);
+        }
+        top.wire_labels
+            .insert(wire(gate, 1, sink, 1), "out".to_string());
+
+        let (half, remap) = split_node(&mut top.snarl, gate, 2, 1, Pos2::ZERO, true).unwrap();
+        top.rekey_wires(&remap);
+        top.prune_wire_data();
+
+        assert_eq!(top.snarl[gate].inputs.len(), 2);
+        assert_eq!(top.snarl[half].inputs.len(), 3);
+        assert_ne!(top.snarl[half].id, top.snarl[gate].id);
+        let mut expected = vec![
+            wire(source, 0, gate, 0),
+            wire(source, 1, gate, 1),
+            wire(source, 2, half, 0),
+            wire(source, 3, half, 1),
+            wire(gate, 0, sink, 0),
+            wire(half, 0, sink, 1),
+            wire(gate, 1, half, 2),
+        ];
+        expected.sort();
+        assert_eq!(wires(&top.snarl), expected);
+
+        for index in 0..4 {
+            let moved = if index < 2 {
+                wire(source, index, gate, index)
+            } else {
+                wire(source, index, half, index - 2)
+            };
+            assert_eq!(top.wire_colors[&moved], Color32::from_gray(index as u8));
+        }
+        assert_eq!(top.wire_colors.len(), 4);
+        assert_eq!(
+            top.wire_labels,
+            HashMap::from([(wire(half, 0, sink, 1), "out".to_string())])
+        );
+    }
+
+    /// Two sources and a sink whose only input takes a single wire, already wired to the first.
+    fn single_input() -> (Subsystem, NodeId, NodeId, NodeId) {
+        let mut top = Subsystem::new();
+        let first = top.snarl.insert_node(Pos2::ZERO, block("first", 0, 1));
+        let second = top.snarl.insert_node(Pos2::ZERO, block("second", 0, 1));
+        let mut sink = block("sink", 1, 0);
+        sink.inputs[0].max_connections = Some(1);
+        let sink = top.snarl.insert_node(Pos2::ZERO, sink);
+        let (pin_out, pin_in) = wire(first, 0, sink, 0);
+        top.snarl.connect(pin_out, pin_in);
+        (top, first, second, sink)
+    }
+
+    #[test]
+    fn single_input_replaces_its_wire() {
+        let (mut top, first, second, sink) = single_input();
+        let (pin_out, pin_in) = wire(second, 0, sink, 0);
+        assert!(!connect_within_limit(
+            &mut top.snarl,
+            pin_out,
+            pin_in,
+            false
+        ));
+        assert_eq!(wires(&top.snarl), vec![wire(first, 0, sink, 0)]);
+
+        assert!(connect_within_limit(&mut top.snarl, pin_out, pin_in, true));
+        assert_eq!(wires(&top.snarl), vec![wire(second, 0, sink, 0)]);
+    }
+
+    #[test]
+    fn moved_wires_keep_to_the_limit() {
+        let (mut top, first, second, sink) = single_input();
+        let extra = top.snarl.insert_node(Pos2::ZERO, block("extra", 1, 0));
+        let (pin_out, pin_in) = wire(second, 0, extra, 0);
+        top.snarl.connect(pin_out, pin_in);
+
+        // Dragging the wire of `second` onto the input of `sink` as well
+        let moved = wire(second, 0, sink, 0);
+        assert!(!reconnect(&mut top, (pin_out, pin_in), moved, false, false));
+        assert!(reconnect(&mut top, (pin_out, pin_in), moved, false, true));
+        assert_eq!(wires(&top.snarl), vec![moved]);
+
+        top.snarl[first].outputs[0].name = "Input".to_string();
+        assert_eq!(
+            connect_by_name(&mut top.snarl, first, sink, false, false),
+            0
+        );
+        assert_eq!(connect_by_name(&mut top.snarl, first, sink, false, true), 1);
+        assert_eq!(wires(&top.snarl), vec![wire(first, 0, sink, 0)]);
+    }
+
+    /// `raw`, a saved subsystem, as version 0 saved it: without any of the fields added since.
+    fn strip_to_v0(raw: &mut serde_json::Value) {
+        match raw {
+            serde_json::Value::Object(fields) => {
+                let added: &[&str] = if fields.contains_key("snarl") {
+                    &[
+                        "waypoints",
+                        "wire_colors",
+                        "wire_labels",
+                        "instance",
+                        "view",
+                        "layout_dir",
+                        "test_vectors",
+                        "groups",
+                    ]
+                } else if fields.contains_key("inputs") {
+                    &[
+                        "id",
+                        "kind",
+                        "text",
+                        "width",
+                        "color",
+                        "tags",
+                        "mirrored",
+                        "op",
+                        "collapsed",
+                        "locked",
+                    ]
+                } else if fields.contains_key("kind") {
+                    &["data_type", "max_connections"]
+                } else {
+                    &[]
+                };
+                for field in added {
+                    fields.remove(*field);
+                }
+                fields.values_mut().for_each(strip_to_v0);
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(strip_to_v0),
+            _ => {}
+        }
+    }
+
+    fn migration_sample() -> (Subsystem, NodeId, NodeId) {
+        let mut top = Subsystem::new();
+        let a = top.snarl.insert_node(Pos2::ZERO, block("a", 0, 1));
+        let b = top.snarl.insert_node(Pos2::ZERO, block("b", 1, 0));
+        let (pin_out, pin_in) = wire(a, 0, b, 0);
+        top.snarl.connect(pin_out, pin_in);
+        (top, a, b)
+    }
+
+    #[test]
+    fn version_0_reads_with_defaults() {
+        let (top, a, b) = migration_sample();
+        let mut raw = serde_json::to_value(&top).unwrap();
+        strip_to_v0(&mut raw);
+        assert!(!raw.to_string().contains("\"width\""));
+
+        let loaded = migrate(raw.clone()).unwrap();
+        assert_eq!(wires(&loaded.snarl), vec![wire(a, 0, b, 0)]);
+        let node = &loaded.snarl[a];
+        assert_eq!(node.name, "a");
+        assert_eq!(node.width, Node::default_width());
+        assert_eq!(node.color, Node::default_color());
+        assert!(node.tags.is_empty());
+        assert!(node.outputs[0].data_type == DataType::default());
+        assert!(loaded.wire_colors.is_empty() && loaded.groups.is_empty());
+
+        // Nodes saved without ids get ones that stay the same from one load to the next
+        assert!(!node.id.is_nil());
+        assert_ne!(node.id, loaded.snarl[b].id);
+        assert_eq!(migrate(raw).unwrap().snarl[a].id, node.id);
+    }
+
+    #[test]
+    fn version_1_reads_and_saves_as_current() {
+        let (top, a, _) = migration_sample();
+        let mut data = serde_json::to_value(&top).unwrap();
+        if let Some(nodes) = data.pointer_mut("/snarl/nodes") {
+            strip_ids(nodes);
+        }
+        fn strip_ids(raw: &mut serde_json::Value) {
+            match raw {
+                serde_json::Value::Object(fields) => {
+                    if fields.contains_key("inputs") {
+                        fields.remove("id");
+                    }
+                    fields.values_mut().for_each(strip_ids);
+                }
+                serde_json::Value::Array(items) => items.iter_mut().for_each(strip_ids),
+                _ => {}
+            }
+        }
+        assert!(!data.to_string().contains("\"id\""));
+        let raw = serde_json::json!({ "version": 1, "data": data });
+
+        let loaded = migrate(raw).unwrap();
+        let id = loaded.snarl[a].id;
+        assert!(!id.is_nil());
+        assert_eq!(loaded.snarl.wires().count(), 1);
+
+        let saved = saved(&loaded);
+        assert_eq!(saved["version"], FORMAT_VERSION);
+        assert_eq!(migrate(saved).unwrap().snarl[a].id, id);
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        let (top, _, _) = migration_sample();
+        let mut raw = saved(&top);
+        raw["version"] = (FORMAT_VERSION + 1).into();
+        assert!(migrate(raw).is_err());
+    }
+
+    /// A chain `a -> b -> c` of single-port blocks.
+    fn chain() -> (Subsystem, [NodeId; 3]) {
+        let mut top = Subsystem::new();
+        let a = add_node(&mut top.snarl, Pos2::ZERO, block("a", 0, 1));
+        let b = add_node(&mut top.snarl, Pos2::new(100.0, 0.0), block("b", 1, 1));
+        let c = add_node(&mut top.snarl, Pos2::new(200.0, 0.0), block("c", 1, 0));
+        let (pin_out, pin_in) = wire(a, 0, b, 0);
+        assert!(connect(&mut top.snarl, pin_out, pin_in));
+        let (pin_out, pin_in) = wire(b, 0, c, 0);
+        assert!(connect(&mut top.snarl, pin_out, pin_in));
+        (top, [a, b, c])
+    }
+
+    #[test]
+    fn nodes_come_and_go_with_their_wires() {
+        let (mut top, [a, b, c]) = chain();
+        assert_eq!(
+            top.snarl.get_node_info(b).unwrap().pos,
+            Pos2::new(100.0, 0.0)
+        );
+        assert_eq!(boundary_wire_count(&top.snarl, b), 2);
+
+        let removed = remove_node(&mut top.snarl, b);
+        assert_eq!(removed.name, "b");
+        assert!(top.snarl.get_node(b).is_none());
+        assert!(wires(&top.snarl).is_empty());
+        assert_eq!(
+            node_names(&top.snarl),
+            HashSet::from(["a".into(), "c".into()])
+        );
+        assert_eq!(unique_name("a", &node_names(&top.snarl)), "a 1");
+        assert!(top.snarl.get_node(a).is_some() && top.snarl.get_node(c).is_some());
+    }
+
+    #[test]
+    fn ports_are_appended_and_numbered() {
+        let mut snarl = Snarl::new();
+        let node_id = add_node(&mut snarl, Pos2::ZERO, block("n", 1, 0));
+        let index = add_input(
+            &mut snarl,
+            node_id,
+            Input {
+                name: "Input 3".to_string(),
+                ..Input::default()
+            },
+        );
+        assert_eq!(index, 1);
+        add_inputs(&mut snarl, node_id, 2);
+        let names = snarl[node_id]
+            .inputs
+            .iter()
+            .map(|input| input.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Input", "Input 3", "Input 4", "Input 5"]);
+
+        assert_eq!(add_output(&mut snarl, node_id, Output::default()), 0);
+        add_outputs(&mut snarl, node_id, 1);
+        assert_eq!(snarl[node_id].outputs[1].name, "Output 2");
+    }
+
+    #[test]
+    fn wires_need_both_pins() {
+        let (mut top, [a, b, c]) = chain();
+        let (pin_out, pin_in) = wire(a, 0, c, 0);
+        assert!(connect(&mut top.snarl, pin_out, pin_in));
+        assert!(
+            !connect(&mut top.snarl, pin_out, pin_in),
+            "the wire exists already"
+        );
+        let (pin_out, pin_in) = wire(a, 1, c, 0);
+        assert!(
+            !connect(&mut top.snarl, pin_out, pin_in),
+            "a has a single output"
+        );
+
+        assert!(disconnect(
+            &mut top.snarl,
+            wire(a, 0, c, 0).0,
+            wire(a, 0, c, 0).1
+        ));
+        assert!(!disconnect(
+            &mut top.snarl,
+            wire(a, 0, c, 0).0,
+            wire(a, 0, c, 0).1
+        ));
+        assert_eq!(wires(&top.snarl), vec![wire(a, 0, b, 0), wire(b, 0, c, 0)]);
+    }
+
+    #[test]
+    fn a_node_inserted_on_a_wire_passes_it_through() {
+        let (mut top, [a, b, _]) = chain();
+        let (pin_out, pin_in) = wire(a, 0, b, 0);
+        let pass = insert_node_on_wire(&mut top.snarl, pin_out, pin_in, Pos2::ZERO);
+        assert_eq!(top.snarl[pass].inputs[0].name, "Output");
+        assert_eq!(top.snarl[pass].outputs[0].name, "Input");
+        let expected = [wire(a, 0, pass, 0), wire(pass, 0, b, 0)];
+        assert!(expected.iter().all(|wire| wires(&top.snarl).contains(wire)));
+        assert!(!wires(&top.snarl).contains(&(pin_out, pin_in)));
+    }
+
+    /// The id of the node of `snarl` named `name`.
+    fn named(snarl: &Snarl<Node>, name: &str) -> NodeId {
+        snarl
+            .node_ids()
+            .find(|(_, node)| node.name == name)
+            .unwrap()
+            .0
+    }
+
+    /// `source` -> `x` -> `y` -> `sink`, with `x` and `y` turned into a subsystem node whose
+    /// wires carry colors, labels and waypoints.
+    fn converted_pair() -> (Subsystem, NodeId) {
+        let mut top = Subsystem::new();
+        let source = top.snarl.insert_node(Pos2::ZERO, block("source", 0, 1));
+        let x = top
+            .snarl
+            .insert_node(Pos2::new(10.0, 0.0), block("x", 1, 2));
+        let y = top
+            .snarl
+            .insert_node(Pos2::new(20.0, 0.0), block("y", 2, 1));
+        let sink = top.snarl.insert_node(Pos2::ZERO, block("sink", 1, 0));
+        for (pin_out, pin_in) in [
+            wire(source, 0, x, 0),
+            wire(x, 0, y, 0),
+            wire(x, 1, y, 1),
+            wire(y, 0, sink, 0),
+        ] {
+            top.snarl.connect(pin_out, pin_in);
+        }
+        top.wire_colors.insert(wire(source, 0, x, 0), Color32::RED);
+        top.wire_labels
+            .insert(wire(source, 0, x, 0), "in".to_string());
+        top.wire_colors.insert(wire(x, 0, y, 0), Color32::BLUE);
+        top.wire_labels.insert(wire(x, 0, y, 0), "mid".to_string());
+        top.waypoints
+            .insert(wire(x, 1, y, 1), vec![Pos2::new(15.0, 5.0)]);
+
+        let node_id = convert_to_subsystem(&mut top, &[x, y], Pos2::ZERO);
+        (top, node_id)
+    }
+
+    #[test]
+    fn flattening_undoes_a_conversion() {
+        let (mut top, node_id) = converted_pair();
+        assert_eq!(top.snarl.nodes().count(), 3);
+        assert!(top.snarl.nodes().all(|node| node.name != "x"));
+        let inner = top.snarl[node_id].subsystem.clone().unwrap();
+        assert_eq!(inner.borrow().boundary_inputs().len(), 1);
+        assert_eq!(inner.borrow().boundary_outputs().len(), 1);
+        drop(inner);
+
+        let inserted = flatten(&mut top, node_id).unwrap();
+        assert_eq!(inserted.len(), 2);
+        assert_eq!(top.snarl.nodes().count(), 4);
+        let [source, x, y, sink] = ["source", "x", "y", "sink"].map(|name| named(&top.snarl, name));
+        let mut expected = vec![
+            wire(source, 0, x, 0),
+            wire(x, 0, y, 0),
+            wire(x, 1, y, 1),
+            wire(y, 0, sink, 0),
+        ];
+        expected.sort();
+        assert_eq!(wires(&top.snarl), expected);
+        assert_eq!(
+            top.snarl.get_node_info(x).unwrap().pos,
+            Pos2::new(10.0, 0.0)
+        );
+
+        assert_eq!(
+            top.wire_colors.get(&wire(source, 0, x, 0)),
+            Some(&Color32::RED)
+        );
+        assert_eq!(
+            top.wire_labels
+                .get(&wire(source, 0, x, 0))
+                .map(String::as_str),
+            Some("in")
+        );
+        assert_eq!(top.wire_colors.get(&wire(x, 0, y, 0)), Some(&Color32::BLUE));
+        assert_eq!(
+            top.wire_labels.get(&wire(x, 0, y, 0)).map(String::as_str),
+            Some("mid")
+        );
+        assert_eq!(
+            top.waypoints.get(&wire(x, 1, y, 1)),
+            Some(&vec![Pos2::new(15.0, 5.0)])
+        );
+        assert_eq!(top.wire_colors.len(), 2);
+        assert_eq!(top.wire_labels.len(), 2);
+        assert_eq!(top.waypoints.len(), 1);
+
+        assert!(flatten(&mut top, x).is_none(), "x holds no subsystem");
+    }
+
+    #[test]
+    fn flattening_a_shared_instance_leaves_the_others() {
+        let (mut top, node_id) = converted_pair();
+        let inner = top.snarl[node_id].subsystem.clone().unwrap();
+        {
+            let mut inner = inner.borrow_mut();
+            let y = named(&inner.snarl, "y");
+            inner.snarl[y].subsystem = Some(Rc::default());
+        }
+        let other = top
+            .snarl
+            .insert_node(Pos2::ZERO, top.snarl[node_id].clone());
+        top.snarl.insert_node(Pos2::ZERO, block("y", 0, 0));
+        let source = named(&top.snarl, "source");
+
+        flatten(&mut top, node_id).unwrap();
+        let x = named(&top.snarl, "x");
+        let y = named(&top.snarl, "y 1");
+        assert_ne!(
+            top.snarl[x].id,
+            inner.borrow().snarl[named(&inner.borrow().snarl, "x")].id
+        );
+        assert_eq!(
+            top.wire_colors.get(&wire(source, 0, x, 0)),
+            Some(&Color32::RED)
+        );
+        assert_eq!(top.wire_colors.get(&wire(x, 0, y, 0)), Some(&Color32::BLUE));
+        assert!(top.waypoints.contains_key(&wire(x, 1, y, 1)));
+
+        // The other instance keeps the subsystem, whose nested one is no longer shared
+        assert!(Rc::ptr_eq(
+            top.snarl[other].subsystem.as_ref().unwrap(),
+            &inner
+        ));
+        assert_eq!(inner.borrow().snarl.nodes().count(), 4);
+        let inner = inner.borrow();
+        let nested = inner.snarl[named(&inner.snarl, "y")]
+            .subsystem
+            .as_ref()
+            .unwrap();
+        assert!(!Rc::ptr_eq(
+            top.snarl[y].subsystem.as_ref().unwrap(),
+            nested
+        ));
+    }
+
+    #[test]
+    fn walks_follow_the_wires() {
+        let (mut top, [a, b, c]) = chain();
+        assert_eq!(topo_order(&top.snarl).ok(), Some(vec![a, b, c]));
+        assert_eq!(
+            reachable_nodes(&top.snarl, b, Reach::Upstream),
+            HashSet::from([a, b])
+        );
+        assert_eq!(
+            reachable_nodes(&top.snarl, b, Reach::Downstream),
+            HashSet::from([b, c])
+        );
+        assert_eq!(invert_selection(&top.snarl, &[b]), vec![a, c]);
+        assert_eq!(grow_selection(&top.snarl, &[b]), vec![b, a, c]);
+        assert_eq!(shrink_selection(&top.snarl, &[a, b]), vec![a]);
+
+        let (pin_out, pin_in) = wire(b, 0, b, 0);
+        top.snarl.connect(pin_out, pin_in);
+        assert!(topo_order(&top.snarl).is_err());
+    }
+
+    #[test]
+    fn pasted_nodes_are_new_copies() {
+        let (mut top, [a, b, c]) = chain();
+        let fragment = copy_nodes(&top.snarl, &[a, b]);
+        assert_eq!(fragment.wires, vec![wire(a, 0, b, 0)]);
+
+        let pasted = paste_fragment(&mut top.snarl, fragment, Vec2::new(0.0, 50.0));
+        assert_eq!(pasted.len(), 2);
+        assert!(pasted.iter().all(|node_id| ![a, b, c].contains(node_id)));
+        let names = pasted
+            .iter()
+            .map(|&node_id| top.snarl[node_id].name.clone())
+            .collect::<HashSet<_>>();
+        assert_eq!(names, HashSet::from(["a 1".into(), "b 1".into()]));
+        assert_eq!(top.snarl.wires().count(), 3);
+    }
+
+    #[test]
+    fn conversion_keeps_the_external_wires() {
+        let mut top = Subsystem::new();
+        let source = top.snarl.insert_node(Pos2::ZERO, block("source", 0, 2));
+        let x = top.snarl.insert_node(Pos2::ZERO, block("x", 3, 1));
+        let y = top.snarl.insert_node(Pos2::ZERO, block("y", 1, 2));
+        let sink = top.snarl.insert_node(Pos2::ZERO, block("sink", 3, 0));
+        let all = [
+            wire(source, 0, x, 0),
+            wire(source, 1, x, 1),
+            // Fanning out of the selection, and around it
+            wire(source, 1, sink, 2),
+            wire(x, 0, y, 0),
+            wire(y, 0, sink, 0),
+            wire(y, 1, sink, 1),
+            wire(x, 0, sink, 2),
+        ];
+        for (pin_out, pin_in) in all {
+            top.snarl.connect(pin_out, pin_in);
+        }
+        let external = all
+            .iter()
+            .filter(|(pin_out, pin_in)| {
+                [x, y].contains(&pin_out.node) != [x, y].contains(&pin_in.node)
+            })
+            .count();
+        assert_eq!(external, 5);
+
+        let node_id = convert_to_subsystem(&mut top, &[x, y], Pos2::ZERO);
+        assert_eq!(boundary_wire_count(&top.snarl, node_id), external);
+        assert!(wires(&top.snarl).contains(&wire(source, 1, sink, 2)));
+        assert_eq!(top.snarl.wires().count(), external + 1);
+
+        // The unwired input of `x` is part of the interface too
+        let inner = top.snarl[node_id].subsystem.clone().unwrap();
+        assert_eq!(inner.borrow().boundary_inputs().len(), 3);
+        assert_eq!(inner.borrow().boundary_outputs().len(), 3);
+    }
+
+    #[test]
+    fn removing_an_input_shifts_the_wires_after_it() {
+        let mut snarl = Snarl::new();
+        let source = snarl.insert_node(Pos2::ZERO, block("source", 0, 1));
+        let node_id = snarl.insert_node(Pos2::ZERO, block("node", 3, 0));
+        let (pin_out, pin_in) = wire(source, 0, node_id, 2);
+        snarl.connect(pin_out, pin_in);
+
+        let remap = remove_port(&mut snarl, node_id, PortSide::Input, 0).unwrap();
+        assert_eq!(snarl[node_id].inputs.len(), 2);
+        assert_eq!(wires(&snarl), vec![wire(source, 0, node_id, 1)]);
+        assert_eq!(
+            remap,
+            vec![((pin_out, pin_in), Some(wire(source, 0, node_id, 1)))]
+        );
+    }
+
+    #[test]
+    fn edits_inside_a_new_subsystem_stay() {
+        let mut top = Subsystem::new();
+        let node_id = top.snarl.insert_node(Pos2::ZERO, block("holder", 0, 0));
+
+        // Enter, add a node, then go back up and drop the level
+        let level = subsystem_of(&mut top.snarl[node_id]);
+        add_node(
+            &mut level.borrow_mut().snarl,
+            Pos2::ZERO,
+            block("child", 0, 0),
+        );
+        drop(level);
+
+        let inner = top.snarl[node_id].subsystem.clone().unwrap();
+        assert_eq!(inner.borrow().snarl.nodes().count(), 1);
+        assert!(Rc::ptr_eq(&subsystem_of(&mut top.snarl[node_id]), &inner));
+    }
+
+    #[test]
+    fn instances_are_refused_inside_themselves() {
+        // The top level holds `a`, which holds `b`
+        let mut top = Subsystem::new();
+        let a = Rc::new(RefCell::new(Subsystem::new()));
+        let b = Rc::new(RefCell::new(Subsystem::new()));
+        a.borrow_mut().snarl.insert_node(
+            Pos2::ZERO,
+            Node {
+                subsystem: Some(b.clone()),
+                ..block("b", 0, 0)
+            },
+        );
+        top.snarl.insert_node(
+            Pos2::ZERO,
+            Node {
+                subsystem: Some(a.clone()),
+                ..block("a", 0, 0)
+            },
+        );
+
+        // The subsystems offered by Insert Instance, checked against the level being edited
+        let uses = subsystem_uses(&[(Vec::new(), &top.snarl)]);
+        let [use_a, use_b] = [0, 1].map(|n| uses[n].subsystem().clone());
+        assert!(Rc::ptr_eq(&use_a, &a) && Rc::ptr_eq(&use_b, &b));
+        assert!(contains_subsystem(&use_a.borrow(), &b), "a holds b");
+        assert!(contains_subsystem(&use_a.borrow(), &a), "a is a");
+        assert!(contains_subsystem(&use_b.borrow(), &b), "b is b");
+        assert!(!contains_subsystem(&use_b.borrow(), &a));
+        let other = Rc::new(RefCell::new(Subsystem::new()));
+        assert!(!contains_subsystem(&use_a.borrow(), &other));
+    }
+
+    #[test]
+    fn loops_are_spotted_before_wiring() {
+        // Loops only depend on the nodes, whichever pins the wire joins
+        let (top, [a, b, c]) = chain();
+        let snarl = top.snarl;
+        let closing = wire(c, 0, a, 0);
+        assert!(would_create_cycle(&snarl, closing.0, closing.1));
+        let back = wire(c, 0, b, 0);
+        assert!(would_create_cycle(&snarl, back.0, back.1));
+        let shortcut = wire(a, 0, c, 0);
+        assert!(!would_create_cycle(&snarl, shortcut.0, shortcut.1));
+
+        let self_loop = wire(b, 0, b, 0);
+        assert!(would_create_cycle(&snarl, self_loop.0, self_loop.1));
+    }
+
+    #[test]
+    fn disconnecting_all_keeps_the_ports() {
+        let (mut top, [a, b, c]) = chain();
+        assert_eq!(disconnect_all(&mut top.snarl, b), 2);
+        assert!(wires(&top.snarl).is_empty());
+        assert_eq!(top.snarl[b].inputs.len(), 1);
+        assert_eq!(top.snarl[b].outputs.len(), 1);
+        assert!(top.snarl.get_node(a).is_some() && top.snarl.get_node(c).is_some());
+    }
+
+    #[test]
+    fn deep_copies_share_nothing() {
+        // A subsystem node holding a node that holds a subsystem of its own
+        let mut deepest = Subsystem::new();
+        let leaf = deepest.snarl.insert_node(Pos2::ZERO, block("leaf", 0, 0));
+        let mut inner = Subsystem::new();
+        let middle = inner.snarl.insert_node(
+            Pos2::ZERO,
+            Node {
+                subsystem: Some(Rc::new(RefCell::new(deepest))),
+                ..block("middle", 0, 0)
+            },
+        );
+        let mut top = Subsystem::new();
+        let original = top.snarl.insert_node(
+            Pos2::ZERO,
+            Node {
+                subsystem: Some(Rc::new(RefCell::new(inner))),
+                ..block("original", 0, 0)
+            },
+        );
+
+        let mut copy = top.snarl[original].clone();
+        unshare_subsystem(&mut copy);
+        copy.renew_id();
+        let copy = add_node(&mut top.snarl, Pos2::ZERO, copy);
+        assert_ne!(top.snarl[copy].id, top.snarl[original].id);
+
+        let copied = subsystem_of(&mut top.snarl[copy]);
+        copied.borrow_mut().snarl[middle].name = "edited".to_string();
+        let copied_deepest = subsystem_of(&mut copied.borrow_mut().snarl[middle]);
+        copied_deepest.borrow_mut().snarl[leaf].name = "edited".to_string();
+
+        let inner = subsystem_of(&mut top.snarl[original]);
+        assert!(!Rc::ptr_eq(&inner, &copied));
+        assert_eq!(inner.borrow().snarl[middle].name, "middle");
+        let deepest = subsystem_of(&mut inner.borrow_mut().snarl[middle]);
+        assert_eq!(deepest.borrow().snarl[leaf].name, "leaf");
+    }
+}
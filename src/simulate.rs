@@ -0,0 +1,347 @@
+//! Step-by-step simulation of diagrams as boolean logic.
+//!
+//! Each leaf node computes its outputs from its inputs with its [`LogicOp`], and each subsystem
+//! node runs the subsystem it holds, fed through its boundary. Wires closing a loop carry the
+//! value they had at the previous step, so feedback settles over several steps.
+
+use std::collections::{HashMap, VecDeque};
+
+use egui_snarl::{InPinId, NodeId, OutPinId, Snarl};
+
+use crate::model::{self, LogicOp, Node, OutputKind, Subsystem, Wire};
+
+/// Most steps [`run_vector`] waits for the values to settle.
+const SETTLE_STEPS: usize = 64;
+
+/// Number of steps a [`Watch`] remembers.
+pub const WATCH_HISTORY: usize = 32;
+
+/// Values of a simulated diagram.
+#[derive(Default)]
+pub struct Simulation {
+    /// Values driven into the top level through its `External` outputs.
+    pub inputs: HashMap<OutPinId, bool>,
+    /// Value of every output, by the subsystem nodes leading from the top level to its level.
+    /// Each instance of a shared subsystem has its own values.
+    pub levels: HashMap<Vec<NodeId>, HashMap<OutPinId, bool>>,
+    pub watches: Vec<Watch>,
+}
+
+/// A wire whose value is recorded at every step.
+pub struct Watch {
+    /// Subsystem nodes leading from the top level to the level of the wire.
+    pub path: Vec<NodeId>,
+    pub wire: Wire,
+    /// Values of the last [`WATCH_HISTORY`] steps, oldest first.
+    pub history: VecDeque<bool>,
+}
+
+impl Simulation {
+    /// Evaluates every node of `toplevel` once, in evaluation order.
+    pub fn step(&mut self, toplevel: &Subsystem) {
+        evaluate(toplevel, &mut Vec::new(), &self.inputs, &mut self.levels);
+        for watch in &mut self.watches {
+            let value = self
+                .levels
+                .get(&watch.path)
+                .and_then(|values| values.get(&watch.wire.0))
+                .copied()
+                .unwrap_or(false);
+            watch.history.push_back(value);
+            if watch.history.len() > WATCH_HISTORY {
+                watch.history.pop_front();
+            }
+        }
+        log::debug!("Simulated one step");
+    }
+
+    pub fn is_watched(&self, path: &[NodeId], wire: Wire) -> bool {
+        self.watches
+            .iter()
+            .any(|watch| watch.path == path && watch.wire == wire)
+    }
+
+    /// Starts recording the values of `wire` of the level at `path`, if it is not already.
+    pub fn watch(&mut self, path: Vec<NodeId>, wire: Wire) {
+        if !self.is_watched(&path, wire) {
+            self.watches.push(Watch {
+                path,
+                wire,
+                history: VecDeque::new(),
+            });
+        }
+    }
+
+    pub fn unwatch(&mut self, path: &[NodeId], wire: Wire) {
+        self.watches
+            .retain(|watch| watch.path != path || watch.wire != wire);
+    }
+
+    /// Values of the outputs of the level at `path`, if it was simulated.
+    pub fn values(&self, path: &[NodeId]) -> Option<&HashMap<OutPinId, bool>> {
+        self.levels.get(path)
+    }
+}
+
+/// Runs `subsystem` on its own with its boundary inputs driven by `inputs`, in the order of
+/// [`Subsystem::boundary_inputs`], and returns the values reaching its boundary outputs. Starts
+/// with every value off and steps until none changes, so loops can settle.
+pub fn run_vector(subsystem: &Subsystem, inputs: &[bool]) -> Vec<bool> {
+    let inputs = subsystem
+        .boundary_inputs()
+        .into_iter()
+        .zip(inputs.iter().copied())
+        .collect();
+    let mut levels = HashMap::new();
+    let mut outputs = Vec::new();
+    for _ in 0..SETTLE_STEPS {
+        let before = levels.clone();
+        outputs = evaluate(subsystem, &mut Vec::new(), &inputs, &mut levels);
+        if levels == before {
+            break;
+        }
+    }
+    outputs
+}
+
+/// Evaluates the level `subsystem` found at `path`, its `External` outputs driven by `inputs`.
+/// Returns the values reaching its `External` inputs, in the order of
+/// [`Subsystem::boundary_outputs`].
+fn evaluate(
+    subsystem: &Subsystem,
+    path: &mut Vec<NodeId>,
+    inputs: &HashMap<OutPinId, bool>,
+    levels: &mut HashMap<Vec<NodeId>, HashMap<OutPinId, bool>>,
+) -> Vec<bool> {
+    let snarl = &subsystem.snarl;
+    // Start from the previous step, which is what wires closing a loop carry
+    let mut values = levels.remove(path.as_slice()).unwrap_or_default();
+    let order = model::topo_order(snarl).unwrap_or_else(|e| [e.sorted, e.stuck].concat());
+
+    for node_id in order {
+        let node = &snarl[node_id];
+        let input_values = (0..node.inputs.len())
+            .map(|input| {
+                let pin = InPinId {
+                    node: node_id,
+                    input,
+                };
+                input_value(snarl, &values, pin)
+            })
+            .collect::<Vec<_>>();
+
+        let output_values = match node.subsystem.as_ref() {
+            Some(inner) => {
+                let inner = inner.borrow();
+                let boundary = inner
+                    .boundary_inputs()
+                    .into_iter()
+                    .zip(input_values)
+                    .collect();
+                path.push(node_id);
+                let outputs = evaluate(&inner, path, &boundary, levels);
+                path.pop();
+                outputs
+            }
+            None if node.op == LogicOp::Custom => (0..node.outputs.len())
+                .map(|output| {
+                    let pin = OutPinId {
+                        node: node_id,
+                        output,
+                    };
+                    values.get(&pin).copied().unwrap_or(false)
+                })
+                .collect(),
+            None => apply(node.op, &input_values, node.outputs.len()),
+        };
+
+        for (output, value) in output_values
+            .into_iter()
+            .enumerate()
+            .take(node.outputs.len())
+        {
+            values.insert(
+                OutPinId {
+                    node: node_id,
+                    output,
+                },
+                value,
+            );
+        }
+        boundary_values(node_id, node, inputs, &mut values);
+    }
+
+    let outputs = subsystem
+        .boundary_outputs()
+        .into_iter()
+        .map(|pin| input_value(snarl, &values, pin))
+        .collect();
+    levels.insert(path.clone(), values);
+    outputs
+}
+
+/// Gives the `External` outputs of `node` the values driven into the level.
+fn boundary_values(
+    node_id: NodeId,
+    node: &Node,
+    inputs: &HashMap<OutPinId, bool>,
+    values: &mut HashMap<OutPinId, bool>,
+) {
+    for (output, port) in node.outputs.iter().enumerate() {
+        if port.kind == OutputKind::External {
+            let pin = OutPinId {
+                node: node_id,
+                output,
+            };
+            values.insert(pin, inputs.get(&pin).copied().unwrap_or(false));
+        }
+    }
+}
+
+/// Value reaching `pin`: whether any output wired to it is on.
+fn input_value(snarl: &Snarl<Node>, values: &HashMap<OutPinId, bool>, pin: InPinId) -> bool {
+    snarl
+        .in_pin(pin)
+        .remotes
+        .iter()
+        .any(|remote| values.get(remote).copied().unwrap_or(false))
+}
+
+/// Outputs of a leaf node computing `op` on `inputs`. `Pass` and `Not` map each input to the
+/// output at the same index, the last input feeding the outputs past the end. `Custom` nodes
+/// keep their previous values, see [`evaluate`], and never get here.
+fn apply(op: LogicOp, inputs: &[bool], outputs: usize) -> Vec<bool> {
+    let nth = |n: usize| inputs.get(n.min(inputs.len().saturating_sub(1))).copied();
+    (0..outputs)
+        .map(|n| match op {
+            LogicOp::Pass => nth(n).unwrap_or(false),
+            LogicOp::Not => !nth(n).unwrap_or(false),
+            LogicOp::And => !inputs.is_empty() && inputs.iter().all(|value| *value),
+            LogicOp::Or => inputs.iter().any(|value| *value),
+            LogicOp::Constant(value) => value,
+            LogicOp::Custom => unreachable!("custom nodes keep their previous values"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Input, InputKind, Output, Pos2, TestVector};
+
+    /// A leaf node computing `op`, with `inputs` and `outputs` default ports.
+    fn gate(op: LogicOp, inputs: usize, outputs: usize) -> Node {
+        Node {
+            inputs: vec![Input::default(); inputs],
+            outputs: vec![Output::default(); outputs],
+            op,
+            ..Node::default()
+        }
+    }
+
+    fn pin(node: NodeId, output: usize) -> OutPinId {
+        OutPinId { node, output }
+    }
+
+    fn wire(from: NodeId, output: usize, to: NodeId, input: usize) -> Wire {
+        (pin(from, output), InPinId { node: to, input })
+    }
+
+    /// Two boundary inputs through an AND then a NOT to a boundary output, returning the
+    /// subsystem along with the node driving the boundary, the AND node and the NOT node.
+    fn nand() -> (Subsystem, [NodeId; 3]) {
+        let mut subsystem = Subsystem::new();
+        let snarl = &mut subsystem.snarl;
+        let external = Output {
+            kind: OutputKind::External,
+            ..Output::default()
+        };
+        let source = Node {
+            outputs: vec![external; 2],
+            ..Node::default()
+        };
+        let sink = Node {
+            inputs: vec![Input {
+                kind: InputKind::External,
+                ..Input::default()
+            }],
+            ..Node::default()
+        };
+        let source = snarl.insert_node(Pos2::ZERO, source);
+        let and = snarl.insert_node(Pos2::ZERO, gate(LogicOp::And, 2, 1));
+        let not = snarl.insert_node(Pos2::ZERO, gate(LogicOp::Not, 1, 1));
+        let sink = snarl.insert_node(Pos2::ZERO, sink);
+        for (from, to) in [
+            wire(source, 0, and, 0),
+            wire(source, 1, and, 1),
+            wire(and, 0, not, 0),
+            wire(not, 0, sink, 0),
+        ] {
+            snarl.connect(from, to);
+        }
+        (subsystem, [source, and, not])
+    }
+
+    #[test]
+    fn steps_evaluate_the_wired_nodes() {
+        let (top, [source, and, not]) = nand();
+        let mut simulation = Simulation::default();
+        simulation.watch(Vec::new(), wire(and, 0, not, 0));
+
+        for (a, b) in [(true, true), (true, false)] {
+            simulation.inputs = HashMap::from([(pin(source, 0), a), (pin(source, 1), b)]);
+            simulation.step(&top);
+            let values = simulation.values(&[]).unwrap();
+            assert_eq!(values[&pin(and, 0)], a && b);
+            assert_eq!(values[&pin(not, 0)], !(a && b));
+        }
+        let history = simulation.watches[0].history.iter().copied();
+        assert_eq!(history.collect::<Vec<_>>(), vec![true, false]);
+    }
+
+    #[test]
+    fn test_vectors_pass_and_fail() {
+        let (subsystem, _) = nand();
+        assert_eq!(run_vector(&subsystem, &[true, true]), vec![false]);
+        assert_eq!(run_vector(&subsystem, &[true, false]), vec![true]);
+
+        let vector = |expected| TestVector {
+            inputs: vec![true, true],
+            expected: vec![expected],
+        };
+        let outputs = run_vector(&subsystem, &vector(None).inputs);
+        assert!(vector(Some(false)).passes(&outputs));
+        assert!(!vector(Some(true)).passes(&outputs));
+        assert!(vector(None).passes(&outputs));
+    }
+
+    #[test]
+    fn loops_carry_the_previous_step() {
+        // An OR feeding itself holds on once set, a NOT feeding itself toggles
+        let (mut top, [source, ..]) = nand();
+        let latch = top.snarl.insert_node(Pos2::ZERO, gate(LogicOp::Or, 2, 1));
+        let toggle = top.snarl.insert_node(Pos2::ZERO, gate(LogicOp::Not, 1, 1));
+        for (from, to) in [
+            wire(source, 0, latch, 0),
+            wire(latch, 0, latch, 1),
+            wire(toggle, 0, toggle, 0),
+        ] {
+            top.snarl.connect(from, to);
+        }
+
+        let mut simulation = Simulation::default();
+        let mut step = |set: bool| {
+            simulation.inputs = HashMap::from([(pin(source, 0), set)]);
+            simulation.step(&top);
+            let values = simulation.values(&[]).unwrap();
+            (values[&pin(latch, 0)], values[&pin(toggle, 0)])
+        };
+        assert_eq!(step(false), (false, true));
+        assert_eq!(step(true), (true, false));
+        assert_eq!(step(false), (true, true));
+        assert_eq!(step(false), (true, false));
+
+        // A vector gives up on values that never settle instead of running forever
+        run_vector(&top, &[true, true]);
+    }
+}
@@ -0,0 +1,169 @@
+//! Undo and redo of the edits made to a diagram.
+//!
+//! Before an edit, the edited subsystem is serialized into a [`Snapshot`] along with where it
+//! sits in the hierarchy, so undoing can put it back and bring the user to it. The snapshot
+//! holds the whole subsystem: its nodes and wires, and what hangs off them, like wire colors,
+//! waypoints, groups and test vectors.
+
+use std::collections::VecDeque;
+
+use egui::Id;
+use egui_snarl::{NodeId, Snarl};
+
+use crate::model::{Node, Subsystem};
+
+/// State of one subsystem at some point in time.
+pub struct Snapshot {
+    /// Subsystem nodes leading from the top level to the subsystem.
+    pub path: Vec<NodeId>,
+    subsystem: String,
+}
+
+impl Snapshot {
+    /// Snapshot of `subsystem` with the nodes and wires of `snarl`, which stands in for its own
+    /// when that one is lent to the canvas.
+    pub fn new(path: Vec<NodeId>, subsystem: &Subsystem, snarl: &Snarl<Node>) -> Self {
+        let expect = "diagrams are always serializable";
+        let subsystem = if std::ptr::eq(&subsystem.snarl, snarl) {
+            serde_json::to_string(subsystem).expect(expect)
+        } else {
+            let mut value = serde_json::to_value(subsystem).expect(expect);
+            value["snarl"] = serde_json::to_value(snarl).expect(expect);
+            value.to_string()
+        };
+        Self { path, subsystem }
+    }
+
+    /// A fresh copy of the subsystem as it was.
+    pub fn subsystem(&self) -> Subsystem {
+        serde_json::from_str(&self.subsystem).expect("snapshots are serialized diagrams")
+    }
+}
+
+#[derive(Default)]
+pub struct UndoStack {
+    undo: VecDeque<Snapshot>,
+    redo: Vec<Snapshot>,
+    /// Key of the edit being coalesced into the last undo entry, if any.
+    coalescing: Option<Id>,
+}
+
+impl UndoStack {
+    /// Records the state before a new edit, keeping at most `limit` entries. This forgets
+    /// everything that could be redone.
+    pub fn record(&mut self, snapshot: Snapshot, limit: usize) {
+        self.coalescing = None;
+        self.redo.clear();
+        self.undo.push_back(snapshot);
+        while self.undo.len() > limit {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Whether edits made under `key` are currently merged into the last entry.
+    pub fn coalesces(&self, key: Id) -> bool {
+        self.coalescing == Some(key)
+    }
+
+    /// Merges the next edits made under `key` into the last entry, until another one is recorded
+    /// or [`UndoStack::end_coalescing`] is called.
+    pub fn start_coalescing(&mut self, key: Id) {
+        self.coalescing = Some(key);
+    }
+
+    pub fn end_coalescing(&mut self) {
+        self.coalescing = None;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Takes the entry to restore to undo the last edit.
+    pub fn take_undo(&mut self) -> Option<Snapshot> {
+        self.coalescing = None;
+        self.undo.pop_back()
+    }
+
+    /// Takes the entry to restore to redo the last undone edit.
+    pub fn take_redo(&mut self) -> Option<Snapshot> {
+        self.coalescing = None;
+        self.redo.pop()
+    }
+
+    /// Records the state an undo replaced, so it can be redone.
+    pub fn undone(&mut self, replaced: Snapshot) {
+        self.redo.push(replaced);
+    }
+
+    /// Records the state a redo replaced, so it can be undone again.
+    pub fn redone(&mut self, replaced: Snapshot) {
+        self.undo.push_back(replaced);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::{Color32, pos2};
+    use egui_snarl::{InPinId, OutPinId};
+
+    use super::*;
+    use crate::model::{Group, Input, Output};
+
+    #[test]
+    fn snapshot_keeps_what_hangs_off_the_wires() {
+        let mut subsystem = Subsystem::new();
+        let from = subsystem.snarl.insert_node(
+            pos2(0.0, 0.0),
+            Node {
+                outputs: vec![Output::default()],
+                ..Node::default()
+            },
+        );
+        let to = subsystem.snarl.insert_node(
+            pos2(100.0, 0.0),
+            Node {
+                inputs: vec![Input::default()],
+                ..Node::default()
+            },
+        );
+        let wire = (
+            OutPinId {
+                node: from,
+                output: 0,
+            },
+            InPinId { node: to, input: 0 },
+        );
+        subsystem.snarl.connect(wire.0, wire.1);
+        subsystem.wire_colors.insert(wire, Color32::RED);
+        subsystem.wire_labels.insert(wire, "clock".to_string());
+        subsystem.waypoints.insert(wire, vec![pos2(50.0, 20.0)]);
+        subsystem.groups.push(Group {
+            name: "Pair".to_string(),
+            color: Color32::BLUE,
+            members: vec![from, to],
+        });
+
+        // The snarl lent to the canvas stands in for the emptied one
+        let snarl = std::mem::take(&mut subsystem.snarl);
+        let restored = Snapshot::new(Vec::new(), &subsystem, &snarl).subsystem();
+
+        assert_eq!(restored.snarl.wires().collect::<Vec<_>>(), vec![wire]);
+        assert_eq!(restored.wire_colors.get(&wire), Some(&Color32::RED));
+        assert_eq!(
+            restored.wire_labels.get(&wire).map(String::as_str),
+            Some("clock")
+        );
+        assert_eq!(restored.waypoints.get(&wire), Some(&vec![pos2(50.0, 20.0)]));
+        assert_eq!(restored.groups.len(), 1);
+        assert_eq!(restored.groups[0].members, vec![from, to]);
+    }
+}
@@ -0,0 +1,654 @@
+//! Checks spotting diagrams that are likely broken.
+//!
+//! Every check is a [`Lint`] reporting [`Problem`]s instead of failing, so the whole diagram is
+//! inspected in one go and the results can be listed in the UI or printed for tooling. Adding a
+//! check means adding a [`Lint`] to [`LINTS`].
+
+use std::collections::HashSet;
+
+use egui_snarl::{InPinId, NodeId, OutPinId};
+
+use crate::model::{self, InputKind, OutputKind, Subsystem};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// An issue found in a diagram.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Names of the subsystem nodes leading from the top level to the level of the issue.
+    pub path: Vec<String>,
+    /// The subsystem nodes of `path` themselves.
+    #[serde(skip)]
+    pub node_path: Vec<NodeId>,
+    /// Node the issue is about, if it is about a single one.
+    pub node: Option<NodeId>,
+    pub message: String,
+}
+
+/// An issue found by a [`Lint`] in the level it was given.
+pub struct Problem {
+    pub severity: Severity,
+    /// Node the issue is about, if it is about a single one.
+    pub node: Option<NodeId>,
+    pub message: String,
+}
+
+/// A check run on every level of a diagram.
+pub trait Lint {
+    /// The issues of `subsystem`, leaving out the levels nested inside it.
+    fn run(&self, subsystem: &Subsystem) -> Vec<Problem>;
+}
+
+/// Every check [`validate`] runs.
+pub const LINTS: &[&dyn Lint] = &[
+    &Wires,
+    &Cycles,
+    &DuplicateNames,
+    &OrphanedPorts,
+    &Interfaces,
+    &EmptySubsystems,
+];
+
+/// Runs every check on `subsystem` and the subsystems nested inside it.
+pub fn validate(subsystem: &Subsystem) -> Vec<Diagnostic> {
+    validate_with(subsystem, LINTS)
+}
+
+/// Runs `lints` on `subsystem` and the subsystems nested inside it.
+pub fn validate_with(subsystem: &Subsystem, lints: &[&dyn Lint]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    validate_level(subsystem, lints, &mut Vec::new(), &mut diagnostics);
+    diagnostics
+}
+
+fn validate_level(
+    subsystem: &Subsystem,
+    lints: &[&dyn Lint],
+    path: &mut Vec<(NodeId, String)>,
+    out: &mut Vec<Diagnostic>,
+) {
+    for lint in lints {
+        out.extend(lint.run(subsystem).into_iter().map(|problem| Diagnostic {
+            severity: problem.severity,
+            path: path.iter().map(|(_, name)| name.clone()).collect(),
+            node_path: path.iter().map(|(node_id, _)| *node_id).collect(),
+            node: problem.node,
+            message: problem.message,
+        }));
+    }
+
+    let mut nodes = subsystem.snarl.node_ids().collect::<Vec<_>>();
+    nodes.sort_by_key(|(node_id, _)| *node_id);
+    for (node_id, node) in nodes {
+        if let Some(inner) = node.subsystem.as_ref() {
+            path.push((node_id, node.name.clone()));
+            validate_level(&inner.borrow(), lints, path, out);
+            path.pop();
+        }
+    }
+}
+
+/// Collects what the `report` closure given to `check` reports into [`Problem`]s.
+fn collect(check: impl FnOnce(&mut dyn FnMut(Severity, Option<NodeId>, String))) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    check(&mut |severity, node, message| {
+        problems.push(Problem {
+            severity,
+            node,
+            message,
+        })
+    });
+    problems
+}
+
+/// Wires must connect existing ports.
+struct Wires;
+
+impl Lint for Wires {
+    fn run(&self, subsystem: &Subsystem) -> Vec<Problem> {
+        collect(|report| check_wires(subsystem, report))
+    }
+}
+
+/// Wires should not loop back to a node they started from. Loops can be allowed in the
+/// preferences and the simulator carries the previous step around them, so they only warn.
+struct Cycles;
+
+impl Lint for Cycles {
+    fn run(&self, subsystem: &Subsystem) -> Vec<Problem> {
+        collect(|report| check_cycles(subsystem, report))
+    }
+}
+
+/// Nodes of a level, and ports of a node, should be told apart by their name.
+pub struct DuplicateNames;
+
+impl Lint for DuplicateNames {
+    fn run(&self, subsystem: &Subsystem) -> Vec<Problem> {
+        collect(|report| check_duplicate_names(subsystem, report))
+    }
+}
+
+/// Inputs should be driven by something, and the ports of a subsystem should be used inside it.
+struct OrphanedPorts;
+
+impl Lint for OrphanedPorts {
+    fn run(&self, subsystem: &Subsystem) -> Vec<Problem> {
+        collect(|report| check_orphaned_ports(subsystem, report))
+    }
+}
+
+/// The ports of a subsystem node must match the boundary of the subsystem it holds.
+struct Interfaces;
+
+impl Lint for Interfaces {
+    fn run(&self, subsystem: &Subsystem) -> Vec<Problem> {
+        collect(|report| check_interfaces(subsystem, report))
+    }
+}
+
+/// Subsystems should hold something besides their boundary.
+struct EmptySubsystems;
+
+impl Lint for EmptySubsystems {
+    fn run(&self, subsystem: &Subsystem) -> Vec<Problem> {
+        collect(|report| check_empty_subsystems(subsystem, report))
+    }
+}
+
+type Report<'a> = dyn FnMut(Severity, Option<NodeId>, String) + 'a;
+
+fn check_wires(subsystem: &Subsystem, report: &mut Report) {
+    let snarl = &subsystem.snarl;
+    for (pin_out, pin_in) in snarl.wires() {
+        let from = snarl.get_node(pin_out.node);
+        let to = snarl.get_node(pin_in.node);
+
+        if from.is_none_or(|node| pin_out.output >= node.outputs.len()) {
+            report(
+                Severity::Error,
+                Some(pin_out.node),
+                format!(
+                    "Wire starts from missing output {} of node {:?}",
+                    pin_out.output, pin_out.node
+                ),
+            );
+        }
+        if to.is_none_or(|node| pin_in.input >= node.inputs.len()) {
+            report(
+                Severity::Error,
+                Some(pin_in.node),
+                format!(
+                    "Wire ends at missing input {} of node {:?}",
+                    pin_in.input, pin_in.node
+                ),
+            );
+        }
+
+        let output = from.and_then(|node| node.outputs.get(pin_out.output));
+        let input = to.and_then(|node| node.inputs.get(pin_in.input));
+        if let (Some(output), Some(input)) = (output, input)
+            && !output.data_type.connects_to(input.data_type)
+        {
+            report(
+                Severity::Error,
+                Some(pin_in.node),
+                format!(
+                    "Wire connects {:?} output \"{}\" to {:?} input \"{}\"",
+                    output.data_type, output.name, input.data_type, input.name
+                ),
+            );
+        }
+    }
+}
+
+fn check_cycles(subsystem: &Subsystem, report: &mut Report) {
+    let snarl = &subsystem.snarl;
+    let Err(error) = model::topo_order(snarl) else {
+        return;
+    };
+
+    // Only the nodes on the loops, not the ones stuck behind them
+    let names = error
+        .cycle
+        .iter()
+        .map(|node_id| format!("\"{}\"", snarl[*node_id].name))
+        .collect::<Vec<_>>();
+    report(
+        Severity::Warning,
+        None,
+        format!("Wires form a loop through {}", names.join(", ")),
+    );
+}
+
+fn check_duplicate_names(subsystem: &Subsystem, report: &mut Report) {
+    let mut seen = HashSet::new();
+    let mut node_ids = subsystem.snarl.node_ids().collect::<Vec<_>>();
+    node_ids.sort_by_key(|(node_id, _)| *node_id);
+
+    for (node_id, node) in node_ids {
+        if node.is_comment() {
+            continue;
+        }
+        if !seen.insert(node.name.as_str()) {
+            report(
+                Severity::Warning,
+                Some(node_id),
+                format!("Another node is already named \"{}\"", node.name),
+            );
+        }
+
+        let inputs = node.inputs.iter().map(|input| input.name.as_str());
+        if let Some(name) = first_duplicate(inputs) {
+            report(
+                Severity::Warning,
+                Some(node_id),
+                format!("\"{}\" has several inputs named \"{name}\"", node.name),
+            );
+        }
+
+        let outputs = node.outputs.iter().map(|output| output.name.as_str());
+        if let Some(name) = first_duplicate(outputs) {
+            report(
+                Severity::Warning,
+                Some(node_id),
+                format!("\"{}\" has several outputs named \"{name}\"", node.name),
+            );
+        }
+    }
+}
+
+fn first_duplicate<'a>(mut names: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let mut seen = HashSet::new();
+    names.find(|name| !seen.insert(*name))
+}
+
+fn check_orphaned_ports(subsystem: &Subsystem, report: &mut Report) {
+    let snarl = &subsystem.snarl;
+    let driven = snarl
+        .wires()
+        .map(|(_, pin_in)| pin_in)
+        .collect::<HashSet<_>>();
+    let used = snarl
+        .wires()
+        .map(|(pin_out, _)| pin_out)
+        .collect::<HashSet<_>>();
+
+    for (node_id, node) in snarl.node_ids() {
+        for (input, port) in node.inputs.iter().enumerate() {
+            let pin = InPinId {
+                node: node_id,
+                input,
+            };
+            if !driven.contains(&pin) {
+                report(
+                    Severity::Warning,
+                    Some(node_id),
+                    format!(
+                        "Input \"{}\" of \"{}\" is not connected",
+                        port.name, node.name
+                    ),
+                );
+            }
+        }
+
+        for (output, port) in node.outputs.iter().enumerate() {
+            let pin = OutPinId {
+                node: node_id,
+                output,
+            };
+            if port.kind == OutputKind::External && !used.contains(&pin) {
+                report(
+                    Severity::Warning,
+                    Some(node_id),
+                    format!(
+                        "Subsystem input \"{}\" is not used inside the subsystem",
+                        port.name
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn check_interfaces(subsystem: &Subsystem, report: &mut Report) {
+    for (node_id, node) in subsystem.snarl.node_ids() {
+        let Some(inner) = node.subsystem.as_ref() else {
+            continue;
+        };
+        let inner = inner.borrow();
+
+        let inputs = inner.boundary_inputs().len();
+        if inputs != node.inputs.len() {
+            report(
+                Severity::Error,
+                Some(node_id),
+                format!(
+                    "\"{}\" has {} inputs but its subsystem expects {inputs}",
+                    node.name,
+                    node.inputs.len()
+                ),
+            );
+        }
+
+        let outputs = inner.boundary_outputs().len();
+        if outputs != node.outputs.len() {
+            report(
+                Severity::Error,
+                Some(node_id),
+                format!(
+                    "\"{}\" has {} outputs but its subsystem provides {outputs}",
+                    node.name,
+                    node.outputs.len()
+                ),
+            );
+        }
+
+        let outer_kinds = node
+            .inputs
+            .iter()
+            .all(|input| input.kind == InputKind::Internal)
+            && node
+                .outputs
+                .iter()
+                .all(|output| output.kind == OutputKind::Internal);
+        if !outer_kinds {
+            report(
+                Severity::Warning,
+                Some(node_id),
+                format!(
+                    "\"{}\" holds a subsystem but has ports not bound to it",
+                    node.name
+                ),
+            );
+        }
+
+        drop(inner);
+        if inputs == node.inputs.len()
+            && outputs == node.outputs.len()
+            && model::interface_drift(node)
+        {
+            report(
+                Severity::Warning,
+                Some(node_id),
+                format!(
+                    "The ports of \"{}\" no longer match its subsystem, Sync Interface updates them",
+                    node.name
+                ),
+            );
+        }
+    }
+}
+
+fn check_empty_subsystems(subsystem: &Subsystem, report: &mut Report) {
+    for (node_id, node) in subsystem.snarl.node_ids() {
+        let Some(inner) = node.subsystem.as_ref() else {
+            continue;
+        };
+        let empty = inner
+            .borrow()
+            .snarl
+            .nodes()
+            .all(|node| node.is_comment() || node.is_boundary());
+        if empty {
+            report(
+                Severity::Warning,
+                Some(node_id),
+                format!("The subsystem of \"{}\" holds nothing", node.name),
+            );
+        }
+    }
+}
+
+/// Whether any of `diagnostics` should block the diagram from being used.
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use egui::Pos2;
+    use egui_snarl::Snarl;
+
+    use super::*;
+    use crate::model::{DataType, Input, Node, Output};
+
+    /// A node named `name` with `inputs` and `outputs` default ports.
+    fn block(name: &str, inputs: usize, outputs: usize) -> Node {
+        Node {
+            name: name.to_string(),
+            inputs: vec![Input::default(); inputs],
+            outputs: vec![Output::default(); outputs],
+            ..Node::default()
+        }
+    }
+
+    fn connect(snarl: &mut Snarl<Node>, from: NodeId, output: usize, to: NodeId, input: usize) {
+        snarl.connect(OutPinId { node: from, output }, InPinId { node: to, input });
+    }
+
+    /// A level holding a subsystem node "sub", whose boundary has one input wired to nothing
+    /// and which holds two nodes named "gate".
+    fn diagram() -> (Subsystem, NodeId) {
+        let mut inner = Subsystem::new();
+        inner.snarl.insert_node(
+            Pos2::ZERO,
+            Node {
+                outputs: vec![Output {
+                    name: "a".to_string(),
+                    kind: OutputKind::External,
+                    ..Output::default()
+                }],
+                ..block("in", 0, 0)
+            },
+        );
+        let first = inner.snarl.insert_node(Pos2::ZERO, block("gate", 0, 1));
+        let second = inner.snarl.insert_node(Pos2::ZERO, block("gate", 1, 0));
+        connect(&mut inner.snarl, first, 0, second, 0);
+
+        let mut top = Subsystem::new();
+        let source = top.snarl.insert_node(Pos2::ZERO, block("source", 0, 1));
+        let sub = top.snarl.insert_node(
+            Pos2::ZERO,
+            Node {
+                inputs: vec![bound_input()],
+                subsystem: Some(Rc::new(RefCell::new(inner))),
+                ..block("sub", 0, 0)
+            },
+        );
+        connect(&mut top.snarl, source, 0, sub, 0);
+        (top, sub)
+    }
+
+    fn bound_input() -> Input {
+        Input {
+            kind: InputKind::Internal,
+            ..Input::default()
+        }
+    }
+
+    fn problems(lint: &dyn Lint, subsystem: &Subsystem) -> Vec<(Severity, String)> {
+        lint.run(subsystem)
+            .into_iter()
+            .map(|problem| (problem.severity, problem.message))
+            .collect()
+    }
+
+    #[test]
+    fn nested_levels_are_reported_with_their_path() {
+        let (mut top, sub) = diagram();
+        let diagnostics = validate(&top);
+        let found = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.severity,
+                    diagnostic.path.clone(),
+                    diagnostic.message.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    Severity::Warning,
+                    vec!["sub".to_string()],
+                    "Another node is already named \"gate\"",
+                ),
+                (
+                    Severity::Warning,
+                    vec!["sub".to_string()],
+                    "Subsystem input \"a\" is not used inside the subsystem",
+                ),
+            ]
+        );
+        assert_eq!(diagnostics[0].node_path, vec![sub]);
+        assert!(!has_errors(&diagnostics));
+
+        // A port the subsystem has no boundary for
+        top.snarl[sub].inputs.push(bound_input());
+        let diagnostics = validate(&top);
+        assert!(diagnostics.iter().any(|diagnostic| {
+            diagnostic.severity == Severity::Error
+                && diagnostic.path.is_empty()
+                && diagnostic.node == Some(sub)
+        }));
+        assert!(has_errors(&diagnostics));
+    }
+
+    #[test]
+    fn loops_only_warn() {
+        let mut top = Subsystem::new();
+        let a = top.snarl.insert_node(Pos2::ZERO, block("a", 1, 1));
+        let b = top.snarl.insert_node(Pos2::ZERO, block("b", 1, 1));
+        connect(&mut top.snarl, a, 0, b, 0);
+        connect(&mut top.snarl, b, 0, a, 0);
+
+        assert_eq!(
+            problems(&Cycles, &top),
+            vec![(
+                Severity::Warning,
+                "Wires form a loop through \"a\", \"b\"".to_string()
+            )]
+        );
+        assert!(!has_errors(&validate(&top)));
+    }
+
+    #[test]
+    fn wires_need_existing_ports_of_matching_types() {
+        let mut top = Subsystem::new();
+        let a = top.snarl.insert_node(Pos2::ZERO, block("a", 0, 1));
+        let b = top.snarl.insert_node(Pos2::ZERO, block("b", 1, 0));
+        top.snarl[a].outputs[0].data_type = DataType::Bool;
+        top.snarl[b].inputs[0].data_type = DataType::Int;
+        connect(&mut top.snarl, a, 0, b, 0);
+        connect(&mut top.snarl, a, 1, b, 0);
+
+        let mut found = problems(&Wires, &top);
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    Severity::Error,
+                    "Wire connects Bool output \"Output\" to Int input \"Input\"".to_string(),
+                ),
+                (
+                    Severity::Error,
+                    format!("Wire starts from missing output 1 of node {a:?}"),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn ports_sharing_a_name_warn() {
+        let mut top = Subsystem::new();
+        top.snarl.insert_node(Pos2::ZERO, block("a", 2, 2));
+
+        assert_eq!(
+            problems(&DuplicateNames, &top),
+            vec![
+                (
+                    Severity::Warning,
+                    "\"a\" has several inputs named \"Input\"".to_string()
+                ),
+                (
+                    Severity::Warning,
+                    "\"a\" has several outputs named \"Output\"".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn undriven_inputs_warn() {
+        let mut top = Subsystem::new();
+        top.snarl.insert_node(Pos2::ZERO, block("a", 1, 1));
+
+        assert_eq!(
+            problems(&OrphanedPorts, &top),
+            vec![(
+                Severity::Warning,
+                "Input \"Input\" of \"a\" is not connected".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn interfaces_follow_their_subsystem() {
+        let (mut top, sub) = diagram();
+        assert!(problems(&Interfaces, &top).is_empty());
+
+        top.snarl[sub].outputs.push(Output::default());
+        assert_eq!(
+            problems(&Interfaces, &top),
+            vec![
+                (
+                    Severity::Error,
+                    "\"sub\" has 1 outputs but its subsystem provides 0".to_string()
+                ),
+                (
+                    Severity::Warning,
+                    "\"sub\" holds a subsystem but has ports not bound to it".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn subsystems_holding_only_their_boundary_warn() {
+        let (top, sub) = diagram();
+        assert!(problems(&EmptySubsystems, &top).is_empty());
+
+        let inner = top.snarl[sub].subsystem.clone().unwrap();
+        let gates = inner
+            .borrow()
+            .snarl
+            .node_ids()
+            .filter(|(_, node)| node.name == "gate")
+            .map(|(node_id, _)| node_id)
+            .collect::<Vec<_>>();
+        for node_id in gates {
+            inner.borrow_mut().snarl.remove_node(node_id);
+        }
+        assert_eq!(
+            problems(&EmptySubsystems, &top),
+            vec![(
+                Severity::Warning,
+                "The subsystem of \"sub\" holds nothing".to_string()
+            )]
+        );
+    }
+}